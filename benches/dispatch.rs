@@ -0,0 +1,130 @@
+//! Measures the throughput and latency of events through an
+//! `EventStream`, from a fake interface.
+//!
+//! The batched benchmark reads events that are already queued, which
+//! measures the dispatch and parsing cost. The wakeup benchmark pushes
+//! each event from another thread, so the stream usually parks first
+//! and is woken up by the event loop, which measures its overhead. The
+//! multi-device benchmark does the same for four remotes streaming
+//! accelerometer samples at once, where the wake-ups of the streams
+//! contend in the event loop. At 200 Hz, each round of samples must be
+//! dispatched well within 5 ms.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::{executor, StreamExt};
+use std::sync::mpsc;
+use std::thread;
+use xwiimote::event::{Key, KeyState};
+use xwiimote::harness::{FakeEvent, FakeIface};
+use xwiimote::{Channels, ConnectOptions, Device};
+
+const BATCH: usize = 1000;
+
+fn connect(fake: &FakeIface) -> Device {
+    let options = ConnectOptions {
+        blocking: false,
+        ..Default::default()
+    };
+    let mut device = Device::connect_with(&fake.address(), &options).unwrap();
+    device
+        .open(Channels::CORE | Channels::ACCELEROMETER, false)
+        .unwrap();
+    device
+}
+
+fn batched(c: &mut Criterion) {
+    let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER).unwrap();
+    let device = connect(&fake);
+    let mut events = device.events().unwrap();
+
+    let mut group = c.benchmark_group("stream");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    group.bench_function("batched accelerometer", |b| {
+        b.iter(|| {
+            fake.push_batch((0..BATCH as i32).map(|i| FakeEvent::accelerometer(i, 0, 100)));
+            let read = executor::block_on((&mut events).take(BATCH).count());
+            assert_eq!(read, BATCH);
+        })
+    });
+    group.finish();
+}
+
+fn wakeup(c: &mut Criterion) {
+    let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER).unwrap();
+    let device = connect(&fake);
+    let mut events = device.events().unwrap();
+
+    let (requests, pending) = mpsc::channel::<()>();
+    let producer = thread::spawn(move || {
+        for () in pending {
+            fake.push(FakeEvent::key(Key::A, KeyState::Down));
+        }
+    });
+
+    c.bench_function("stream wakeup latency", |b| {
+        b.iter(|| {
+            requests.send(()).unwrap();
+            executor::block_on(events.next()).unwrap().unwrap();
+        })
+    });
+    drop(requests);
+    producer.join().unwrap();
+}
+
+const DEVICES: usize = 4;
+
+fn multi_device(c: &mut Criterion) {
+    let fakes: Vec<_> = (0..DEVICES)
+        .map(|_| FakeIface::new(Channels::CORE | Channels::ACCELEROMETER).unwrap())
+        .collect();
+    let (acks, received) = mpsc::channel::<()>();
+    let consumers: Vec<_> = fakes
+        .iter()
+        .map(|fake| {
+            let address = fake.address();
+            let acks = acks.clone();
+            thread::spawn(move || {
+                let options = ConnectOptions {
+                    blocking: false,
+                    ..Default::default()
+                };
+                let mut device = Device::connect_with(&address, &options).unwrap();
+                device
+                    .open(Channels::CORE | Channels::ACCELEROMETER, false)
+                    .unwrap();
+                let mut events = device.events().unwrap();
+                acks.send(()).unwrap();
+                // Stop once the benchmark stops listening.
+                while executor::block_on(events.next()).is_some() && acks.send(()).is_ok() {}
+            })
+        })
+        .collect();
+    drop(acks);
+    for _ in 0..DEVICES {
+        received.recv().unwrap();
+    }
+
+    let mut group = c.benchmark_group("stream");
+    group.throughput(Throughput::Elements(DEVICES as u64));
+    group.bench_function("4 devices accelerometer round", |b| {
+        b.iter(|| {
+            for (i, fake) in fakes.iter().enumerate() {
+                fake.push(FakeEvent::accelerometer(i as i32, 0, 100));
+            }
+            for _ in 0..DEVICES {
+                received.recv().unwrap();
+            }
+        })
+    });
+    group.finish();
+
+    drop(received);
+    for fake in &fakes {
+        fake.push(FakeEvent::accelerometer(0, 0, 100));
+    }
+    for consumer in consumers {
+        consumer.join().unwrap();
+    }
+}
+
+criterion_group!(benches, batched, wakeup, multi_device);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+//! Measures the per-event cost of parsing the hot motion events.
+//!
+//! A counting allocator asserts that parsing never allocates, which
+//! matters for the ~100 Hz accelerometer and Motion Plus streams.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use xwiimote::event::Event;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn raw_event(type_: libc::c_uint) -> xwiimote_sys::event {
+    let mut raw = xwiimote_sys::event {
+        type_,
+        ..Default::default()
+    };
+    unsafe {
+        raw.v.abs[0] = xwiimote_sys::event_abs {
+            x: 12,
+            y: -3,
+            z: 101,
+        };
+    }
+    raw
+}
+
+fn assert_no_alloc(raw: &xwiimote_sys::event) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..1000 {
+        black_box(unsafe { Event::from_raw(black_box(raw)) });
+    }
+    let after = ALLOCATIONS.load(Ordering::Relaxed);
+    assert_eq!(before, after, "event parsing allocated");
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, type_) in [
+        ("accelerometer", xwiimote_sys::EVENT_ACCEL),
+        ("motion_plus", xwiimote_sys::EVENT_MOTION_PLUS),
+    ] {
+        let raw = raw_event(type_);
+        assert_no_alloc(&raw);
+        c.bench_function(&format!("parse {}", name), |b| {
+            b.iter(|| unsafe { Event::from_raw(black_box(&raw)) })
+        });
+    }
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
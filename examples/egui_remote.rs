@@ -0,0 +1,83 @@
+//! Drives an egui application with the first connected Wii Remote.
+//!
+//! Point the remote at the sensor bar to move the pointer, and press A
+//! to click. Run with:
+//!
+//! ```sh
+//! cargo run --example egui_remote --features egui-example
+//! ```
+use eframe::egui;
+use std::io;
+use xwiimote::egui_input::{RemoteConfig, RemoteControl};
+use xwiimote::prelude::*;
+
+struct RemoteApp {
+    device: Device,
+    remote: RemoteControl,
+    clicks: usize,
+    volume: f32,
+}
+
+impl eframe::App for RemoteApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Wii Remote");
+            match self.remote.position() {
+                Some(position) => {
+                    ui.label(format!("Pointer at {:.0}, {:.0}", position.x, position.y))
+                }
+                None => ui.label("Point the remote at the sensor bar"),
+            };
+            if ui
+                .button(format!("Clicked {} times", self.clicks))
+                .clicked()
+            {
+                self.clicks += 1;
+            }
+            ui.add(egui::Slider::new(&mut self.volume, 0.0..=100.0).text("Volume"));
+            ui.label(format!("B held: {}", self.remote.is_pressed(Key::B)));
+        });
+        // Events arrive between frames, so keep repainting.
+        ctx.request_repaint();
+    }
+
+    fn raw_input_hook(&mut self, _ctx: &egui::Context, raw_input: &mut egui::RawInput) {
+        loop {
+            match self.device.try_next_event() {
+                Ok(Some(event)) => self.remote.update(&event),
+                Ok(None) => break,
+                Err(err) => {
+                    eprintln!("Cannot read events: {}", err);
+                    break;
+                }
+            }
+        }
+        self.remote.apply(raw_input);
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut monitor = Monitor::new(false)?;
+    let address = monitor
+        .next_address()?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no connected remote"))?;
+    let options = ConnectOptions {
+        blocking: false,
+        ..Default::default()
+    };
+    let mut device = Device::connect_with(&address, &options)?;
+    device.open(Channels::CORE | Channels::IR, false)?;
+
+    let app = RemoteApp {
+        device,
+        remote: RemoteControl::new(RemoteConfig::default()),
+        clicks: 0,
+        volume: 50.0,
+    };
+    eframe::run_native(
+        "xwiimote egui remote",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Ok(Box::new(app))),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,261 @@
+//! A device owned by a worker thread, controlled through a handle.
+//!
+//! A [`Device`] can't move between threads, and its [`EventStream`]
+//! borrows it, so applications that read events while changing the LED
+//! lights or rumble motor from elsewhere end up fighting the borrow
+//! checker. A [`DeviceActor`] connects to the device on its own thread,
+//! which reads the events and applies the requests of the handle. The
+//! handle is cheap to clone and can be sent between threads:
+//!
+//! ```no_run
+//! # use xwiimote::actor::DeviceActor;
+//! # use xwiimote::event::Key;
+//! # use xwiimote::{Address, Channels, Led};
+//! # use futures::StreamExt;
+//! # async fn run(address: Address) -> std::io::Result<()> {
+//! let actor = DeviceActor::spawn(&address, Channels::CORE)?;
+//! let mut presses = actor.press_stream();
+//! while let Some(press) = presses.next().await {
+//!     if press.key == Key::A {
+//!         actor.set_led(Led::One, true).await?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`EventStream`]: crate::event::EventStream
+use crate::control::Command;
+use crate::event::{Event, EventKind, Key, KeyState};
+use crate::motion::MotionState;
+use crate::press::{PressDetector, PressEvent};
+use crate::{Address, Channels, Device, Led, Result};
+use futures::channel::{mpsc, oneshot};
+use futures::{executor, future, stream, SinkExt, Stream, StreamExt};
+use std::io;
+use std::thread;
+
+/// The latest input of a device, as tracked by a [`DeviceActor`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct InputState {
+    /// The Wii Remote keys held down, in the order they were pressed.
+    pub keys: Vec<Key>,
+    /// The latest motion data.
+    pub motion: MotionState,
+    /// The channels available as of the last hot-plug event.
+    pub available: Channels,
+}
+
+impl InputState {
+    /// Updates the state with the given event.
+    fn update(&mut self, event: &Event) {
+        self.motion.update(event);
+        match event.kind {
+            EventKind::Key(key, KeyState::Down) if !self.keys.contains(&key) => self.keys.push(key),
+            EventKind::Key(key, KeyState::Up) => self.keys.retain(|&held| held != key),
+            EventKind::Other(watch) => self.available = watch.available_after,
+            _ => {}
+        }
+    }
+}
+
+/// A request sent by a handle to the worker thread.
+enum Request {
+    Command(Command, oneshot::Sender<Result<()>>),
+    State(oneshot::Sender<InputState>),
+    Subscribe(mpsc::UnboundedSender<PressEvent>),
+}
+
+/// An input of the worker loop.
+enum Input {
+    Event(Result<Event>),
+    Request(Request),
+    Stop,
+}
+
+/// A handle to a device owned by a worker thread.
+///
+/// The thread stops once every handle is dropped, once the device is
+/// disconnected, or once reading its events fails; the requests of the
+/// remaining handles then fail with [`io::ErrorKind::NotConnected`].
+#[derive(Clone, Debug)]
+pub struct DeviceActor {
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+impl DeviceActor {
+    /// Spawns a worker thread that connects to the device at the given
+    /// address, and opens the given channels in writable mode.
+    pub fn spawn(address: &Address, channels: Channels) -> Result<Self> {
+        let address = address.clone();
+        Self::spawn_with(move || {
+            let mut device = Device::connect(&address)?;
+            device.open(channels, true)?;
+            Ok(device)
+        })
+    }
+
+    /// Spawns a worker thread that calls `connect` to create the device,
+    /// e.g. to connect with custom options.
+    ///
+    /// Returns once the device is created, or with the error of
+    /// `connect`. The rumble motor requires the [`Channels::CORE`]
+    /// channel to be open in writable mode.
+    pub fn spawn_with<F>(connect: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Device> + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::unbounded();
+        let (connected, on_connected) = oneshot::channel();
+        thread::Builder::new()
+            .name("xwiimote-actor".to_string())
+            .spawn(move || {
+                let device = match connect() {
+                    Ok(device) => device,
+                    Err(err) => {
+                        let _ = connected.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = connected.send(Ok(()));
+                if let Err(err) = executor::block_on(run(&device, receiver)) {
+                    log::warn!("device actor stopped: {}", err);
+                }
+            })?;
+        executor::block_on(on_connected).map_err(|_| stopped())??;
+        Ok(Self { requests })
+    }
+
+    /// Sends a request, and waits for its answer.
+    async fn request<T>(&self, request: impl FnOnce(oneshot::Sender<T>) -> Request) -> Result<T> {
+        let (sender, answer) = oneshot::channel();
+        self.requests
+            .unbounded_send(request(sender))
+            .map_err(|_| stopped())?;
+        answer.await.map_err(|_| stopped())
+    }
+
+    /// Changes the state of a single LED light.
+    pub async fn set_led(&self, light: Led, enabled: bool) -> Result<()> {
+        self.request(|answer| Request::Command(Command::Led(light, enabled), answer))
+            .await?
+    }
+
+    /// Toggles the rumble motor.
+    pub async fn rumble(&self, enabled: bool) -> Result<()> {
+        self.request(|answer| Request::Command(Command::Rumble(enabled), answer))
+            .await?
+    }
+
+    /// Returns the input state as of the last event read.
+    pub async fn latest_state(&self) -> Result<InputState> {
+        self.request(Request::State).await
+    }
+
+    /// Returns a stream of the key presses read from now on, as detected
+    /// with the default [`PressConfig`](crate::press::PressConfig). The
+    /// stream ends once the worker thread stops.
+    pub fn press_stream(&self) -> impl Stream<Item = PressEvent> + Send + Unpin {
+        let (sender, presses) = mpsc::unbounded();
+        // The stream ends right away if the worker stopped.
+        let _ = self.requests.unbounded_send(Request::Subscribe(sender));
+        presses
+    }
+
+    /// Checks whether the worker thread stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.requests.is_closed()
+    }
+}
+
+/// Returns the error of requests to a stopped worker.
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "the device actor stopped")
+}
+
+/// Reads the events of the device and applies the requests, until every
+/// handle is dropped or the event stream ends.
+async fn run(device: &Device, requests: mpsc::UnboundedReceiver<Request>) -> Result<()> {
+    let mut control = device.control_sink();
+    let mut detector = PressDetector::default();
+    let mut state = InputState {
+        keys: Vec::new(),
+        motion: MotionState::default(),
+        available: device.available(),
+    };
+    let mut subscribers: Vec<mpsc::UnboundedSender<PressEvent>> = Vec::new();
+    let stop = || stream::once(future::ready(Input::Stop));
+    let mut inputs = stream::select(
+        device.events()?.map(Input::Event).chain(stop()),
+        requests.map(Input::Request).chain(stop()),
+    );
+    while let Some(input) = inputs.next().await {
+        match input {
+            Input::Event(event) => {
+                let event = event?;
+                state.update(&event);
+                if let Some(press) = detector.update(&event) {
+                    subscribers.retain(|subscriber| subscriber.unbounded_send(press).is_ok());
+                }
+            }
+            Input::Request(Request::Command(command, answer)) => {
+                let _ = answer.send(control.send(command).await);
+            }
+            Input::Request(Request::State(answer)) => {
+                let _ = answer.send(state.clone());
+            }
+            Input::Request(Request::Subscribe(subscriber)) => subscribers.push(subscriber),
+            Input::Stop => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "test-harness"))]
+mod tests {
+    use super::DeviceActor;
+    use crate::event::{Key, KeyState};
+    use crate::harness::{connect, connect_to, FakeEvent, FakeIface};
+    use crate::motion::Vector3;
+    use crate::press::Press;
+    use crate::{Channels, Led, Result};
+    use futures::{executor, StreamExt};
+    use std::io;
+
+    #[test]
+    fn serves_requests_from_actor_thread() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER)?;
+        let address = fake.address();
+        let actor = DeviceActor::spawn_with(move || {
+            let mut device = connect_to(&address)?;
+            device.open(Channels::CORE | Channels::ACCELEROMETER, true)?;
+            Ok(device)
+        })?;
+        let mut presses = actor.press_stream();
+        // Wait for the subscription before pushing events.
+        executor::block_on(actor.latest_state())?;
+
+        fake.push(FakeEvent::key(Key::A, KeyState::Down));
+        fake.push(FakeEvent::accelerometer(1, 2, 3));
+        fake.push(FakeEvent::key(Key::A, KeyState::Up));
+        let press = executor::block_on(presses.next()).unwrap();
+        assert_eq!((press.key, press.press), (Key::A, Press::Tap));
+        let state = executor::block_on(actor.latest_state())?;
+        assert!(state.keys.is_empty());
+        assert_eq!(
+            state.motion.accelerometer,
+            Some(Vector3 { x: 1, y: 2, z: 3 })
+        );
+
+        executor::block_on(actor.rumble(true))?;
+        assert!(fake.rumble());
+        executor::block_on(actor.set_led(Led::Three, true))?;
+        assert!(connect(&fake)?.led(Led::Three)?);
+
+        fake.push(FakeEvent::gone());
+        assert!(executor::block_on(presses.next()).is_none());
+        let err = executor::block_on(actor.rumble(false)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+//! Controller-agnostic analog axes.
+//!
+//! Each controller reports the positions of its sticks, triggers and
+//! bars in its own movement event and units. An [`AxisNormalizer`]
+//! converts them into [`EventKind::Axis`] events, with values from -1
+//! to 1 (or from 0 to 1 for [unipolar](AxisId::is_unipolar) axes), so
+//! the same code can handle any controller. Use [`normalized_axes`] to
+//! adapt an event stream.
+use crate::event::{AxisId, Event, EventKind};
+use crate::pro_controller::{AxisCalibration, StickCalibration};
+use crate::Result;
+use futures::{stream, Stream, TryStreamExt};
+
+/// The controllers whose movement events are normalized.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Controller {
+    /// A Wii U Pro Controller.
+    Pro,
+    /// A Classic Controller.
+    Classic,
+    /// A Nunchuk.
+    Nunchuk,
+    /// A guitar.
+    Guitar,
+}
+
+/// Converts the movement events of every controller into
+/// [`EventKind::Axis`] events.
+#[derive(Clone, Debug)]
+pub struct AxisNormalizer {
+    pro: [AxisCalibration; 4],
+    classic: [AxisCalibration; 6],
+    nunchuk: [AxisCalibration; 2],
+    guitar: [AxisCalibration; 4],
+}
+
+/// Returns the calibration of an axis centered at zero.
+const fn centered(range: i32) -> AxisCalibration {
+    AxisCalibration {
+        center: 0,
+        min: -range,
+        max: range,
+    }
+}
+
+/// Returns the calibration of an axis from `min` to `max`.
+const fn unipolar(min: i32, max: i32) -> AxisCalibration {
+    AxisCalibration {
+        center: min,
+        min,
+        max,
+    }
+}
+
+impl AxisNormalizer {
+    /// Creates a normalizer with the ranges advertised by the kernel
+    /// driver for each axis.
+    pub fn new() -> Self {
+        Self {
+            pro: StickCalibration::default().axes,
+            classic: [
+                centered(30),
+                centered(30),
+                centered(30),
+                centered(30),
+                unipolar(0, 63),
+                unipolar(0, 63),
+            ],
+            nunchuk: [centered(120), centered(120)],
+            guitar: [centered(32), centered(32), unipolar(0, 15), unipolar(0, 15)],
+        }
+    }
+
+    /// Creates a normalizer which uses the given calibration for the
+    /// sticks of a Pro Controller.
+    pub fn with_stick_calibration(calibration: &StickCalibration) -> Self {
+        let mut normalizer = Self::new();
+        normalizer.pro = calibration.axes;
+        normalizer
+    }
+
+    /// Sets the calibration of an axis of the given controller, in the
+    /// units of its movement events. The center of unipolar axes is
+    /// ignored. Axes the controller doesn't have are ignored.
+    pub fn set_calibration(
+        &mut self,
+        controller: Controller,
+        axis: AxisId,
+        calibration: AxisCalibration,
+    ) {
+        if let Some(index) = Self::axes(controller).iter().position(|&id| id == axis) {
+            self.calibrations(controller)[index] = calibration;
+        }
+    }
+
+    /// Returns the axes of the given controller, in the order of the
+    /// fields of its movement event.
+    fn axes(controller: Controller) -> &'static [AxisId] {
+        use AxisId::*;
+        match controller {
+            Controller::Pro => &[LeftStickX, LeftStickY, RightStickX, RightStickY],
+            Controller::Classic => &[
+                LeftStickX,
+                LeftStickY,
+                RightStickX,
+                RightStickY,
+                LeftTrigger,
+                RightTrigger,
+            ],
+            Controller::Nunchuk => &[LeftStickX, LeftStickY],
+            Controller::Guitar => &[LeftStickX, LeftStickY, WhammyBar, FretBar],
+        }
+    }
+
+    fn calibrations(&mut self, controller: Controller) -> &mut [AxisCalibration] {
+        match controller {
+            Controller::Pro => &mut self.pro,
+            Controller::Classic => &mut self.classic,
+            Controller::Nunchuk => &mut self.nunchuk,
+            Controller::Guitar => &mut self.guitar,
+        }
+    }
+
+    /// Converts the given movement event into an event for each axis.
+    /// Returns `None` for other events.
+    ///
+    /// The accelerations of [`EventKind::NunchukMove`] events are
+    /// discarded; see the [`motion`](crate::motion) module instead.
+    pub fn normalize(&self, event: &Event) -> Option<Vec<Event>> {
+        let (controller, raw, calibrations): (_, &[i32], &[AxisCalibration]) = match event.kind {
+            EventKind::ProControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            } => (
+                Controller::Pro,
+                &[left_x, left_y, right_x, right_y],
+                &self.pro,
+            ),
+            EventKind::ClassicControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+                left_trigger,
+                right_trigger,
+            } => (
+                Controller::Classic,
+                &[
+                    left_x,
+                    left_y,
+                    right_x,
+                    right_y,
+                    left_trigger.into(),
+                    right_trigger.into(),
+                ],
+                &self.classic,
+            ),
+            EventKind::NunchukMove { x, y, .. } => (Controller::Nunchuk, &[x, y], &self.nunchuk),
+            EventKind::GuitarMove {
+                x,
+                y,
+                whammy_bar,
+                fret_bar,
+            } => (
+                Controller::Guitar,
+                &[x, y, whammy_bar, fret_bar],
+                &self.guitar,
+            ),
+            _ => return None,
+        };
+
+        let events = Self::axes(controller)
+            .iter()
+            .zip(raw)
+            .zip(calibrations)
+            .map(|((&axis, &raw), calibration)| {
+                let value = if axis.is_unipolar() {
+                    let range = (calibration.max - calibration.min).max(1);
+                    ((raw - calibration.min) as f32 / range as f32).clamp(0.0, 1.0)
+                } else {
+                    calibration.normalize(raw)
+                };
+                Event {
+                    time: event.time,
+                    kind: EventKind::Axis { axis, value },
+                    key_code: None,
+                    sequence: None,
+                }
+            })
+            .collect();
+        Some(events)
+    }
+}
+
+impl Default for AxisNormalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Adapts a stream of events, replacing the movement events of every
+/// controller by [`EventKind::Axis`] events. Other events are yielded
+/// unchanged.
+pub fn normalized_axes<S>(
+    events: S,
+    normalizer: AxisNormalizer,
+) -> impl Stream<Item = Result<Event>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events
+        .map_ok(move |event| {
+            let events = normalizer.normalize(&event).unwrap_or_else(|| vec![event]);
+            stream::iter(events.into_iter().map(Ok))
+        })
+        .try_flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalized_axes, AxisNormalizer, Controller};
+    use crate::event::{AxisId, Event, EventKind, Key, KeyState};
+    use crate::pro_controller::AxisCalibration;
+    use futures::{executor, stream, TryStreamExt};
+    use std::time::SystemTime;
+
+    fn event(kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH, kind)
+    }
+
+    fn axes(events: &[Event]) -> Vec<(AxisId, f32)> {
+        events
+            .iter()
+            .map(|event| match event.kind {
+                EventKind::Axis { axis, value } => (axis, value),
+                kind => panic!("unexpected event {:?}", kind),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_classic_controller() {
+        let normalizer = AxisNormalizer::new();
+        let events = normalizer
+            .normalize(&event(EventKind::ClassicControllerMove {
+                left_x: 30,
+                left_y: -15,
+                right_x: 0,
+                right_y: -60,
+                left_trigger: 63,
+                right_trigger: 0,
+            }))
+            .unwrap();
+        assert_eq!(
+            axes(&events),
+            [
+                (AxisId::LeftStickX, 1.0),
+                (AxisId::LeftStickY, -0.5),
+                (AxisId::RightStickX, 0.0),
+                (AxisId::RightStickY, -1.0),
+                (AxisId::LeftTrigger, 1.0),
+                (AxisId::RightTrigger, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_calibration() {
+        let mut normalizer = AxisNormalizer::new();
+        normalizer.set_calibration(
+            Controller::Guitar,
+            AxisId::WhammyBar,
+            AxisCalibration {
+                center: 0,
+                min: 5,
+                max: 15,
+            },
+        );
+        let events = normalizer
+            .normalize(&event(EventKind::GuitarMove {
+                x: 0,
+                y: 0,
+                whammy_bar: 10,
+                fret_bar: 0,
+            }))
+            .unwrap();
+        assert_eq!(axes(&events)[2], (AxisId::WhammyBar, 0.5));
+    }
+
+    #[test]
+    fn passes_other_events() {
+        let events = stream::iter([
+            Ok(event(EventKind::Key(Key::A, KeyState::Down))),
+            Ok(event(EventKind::NunchukMove {
+                x: 60,
+                y: 0,
+                x_acceleration: 0,
+                y_acceleration: 0,
+            })),
+        ]);
+        let events: Vec<_> =
+            executor::block_on(normalized_axes(events, AxisNormalizer::new()).try_collect())
+                .unwrap();
+        assert!(matches!(events[0].kind, EventKind::Key(Key::A, _)));
+        assert_eq!(
+            axes(&events[1..]),
+            [(AxisId::LeftStickX, 0.5), (AxisId::LeftStickY, 0.0)]
+        );
+    }
+}
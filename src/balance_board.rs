@@ -0,0 +1,380 @@
+//! Data of the Wii Balance Board not decoded by the kernel driver.
+//!
+//! The board reports its four weight sensors as an extension, followed
+//! by a temperature reading and its own battery level, since it runs on
+//! four AA batteries rather than the two of a Wii Remote. The kernel
+//! only decodes the weights into [`EventKind::BalanceBoard`] events, so
+//! the remaining bytes are read from the raw input reports through the
+//! hidraw node of the device.
+//!
+//! The factory calibration of the weight sensors drifts as boards age.
+//! A [`WeightCalibration`] corrects each sensor with a scale factor,
+//! measured by placing a known mass over it. See
+//! [`BalanceBoard::calibrate_with_reference`].
+use crate::event::{Event, EventKind};
+use crate::profile::{Profile, ProfileStore};
+use crate::{input, Device, Result};
+use futures::{Stream, StreamExt};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// The temperature and battery level reported by a Balance Board.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BoardStatus {
+    /// The raw temperature reading. It increases with temperature, but
+    /// its scale is undocumented; the weight sensors were calibrated
+    /// at the reading stored in the board.
+    pub temperature: u8,
+    /// The raw battery reading.
+    pub raw_battery: u8,
+}
+
+impl BoardStatus {
+    /// The raw battery reading of fresh batteries.
+    const BATTERY_FULL: u8 = 0x82;
+    /// The raw battery reading below which the board stops working.
+    const BATTERY_EMPTY: u8 = 0x69;
+
+    /// Parses an input report, including its report identifier.
+    ///
+    /// Returns `None` if the report doesn't carry the complete
+    /// extension data.
+    pub fn parse(report: &[u8]) -> Option<Self> {
+        // The offset of the extension bytes in each report with at
+        // least 11 extension bytes, after the identifier.
+        let offset = match report.first()? {
+            // Core keys and 19 extension bytes.
+            0x34 => 3,
+            // Core keys, accelerometer and 16 extension bytes.
+            0x35 => 6,
+            // 21 extension bytes.
+            0x3d => 1,
+            _ => return None,
+        };
+        let extension = report.get(offset..offset + 11)?;
+        Some(Self {
+            temperature: extension[8],
+            raw_battery: extension[10],
+        })
+    }
+
+    /// Returns the battery level as a percentage from 0 to 100%.
+    pub fn battery(&self) -> u8 {
+        let range = (Self::BATTERY_FULL - Self::BATTERY_EMPTY) as u32;
+        let level = self
+            .raw_battery
+            .clamp(Self::BATTERY_EMPTY, Self::BATTERY_FULL)
+            - Self::BATTERY_EMPTY;
+        (level as u32 * 100 / range) as u8
+    }
+}
+
+/// Scale factors correcting the weights reported by each sensor of a
+/// Balance Board, in the order of [`EventKind::BalanceBoard`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WeightCalibration {
+    /// The factor by which to multiply the weight of each sensor.
+    pub scales: [f32; 4],
+}
+
+impl WeightCalibration {
+    /// The kernel reports weights in units of 10 grams.
+    const UNITS_PER_KG: f32 = 100.0;
+    /// The profile value storing the scale factors.
+    const PROFILE_KEY: &'static str = "balance_board.scales";
+
+    /// Converts the weights of an [`EventKind::BalanceBoard`] event to
+    /// calibrated weights, in kilograms.
+    pub fn apply(&self, weights: [i32; 4]) -> [f32; 4] {
+        let mut kg = [0.0; 4];
+        for (i, weight) in kg.iter_mut().enumerate() {
+            *weight = weights[i] as f32 * self.scales[i] / Self::UNITS_PER_KG;
+        }
+        kg
+    }
+
+    /// Reads the calibration stored in the profile, if any.
+    pub fn from_profile(profile: &Profile) -> Result<Option<Self>> {
+        let value = match profile.values.get(Self::PROFILE_KEY) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid weight calibration: {}", value),
+            )
+        };
+        let scales: Vec<f32> = value
+            .split_whitespace()
+            .map(|scale| scale.parse().map_err(|_| invalid()))
+            .collect::<Result<_>>()?;
+        let scales = scales.try_into().map_err(|_| invalid())?;
+        Ok(Some(Self { scales }))
+    }
+
+    /// Stores the calibration in the profile.
+    pub fn store(&self, profile: &mut Profile) {
+        let scales: Vec<String> = self.scales.iter().map(f32::to_string).collect();
+        profile
+            .values
+            .insert(Self::PROFILE_KEY.to_string(), scales.join(" "));
+    }
+
+    /// Computes the scale factor of a sensor from the weights reported
+    /// with a reference mass of `kg` kilograms over it.
+    fn scale(kg: f32, sensor: usize, samples: &[[i32; 4]]) -> Result<f32> {
+        let mut totals = [0i64; 4];
+        for sample in samples {
+            for (total, &weight) in totals.iter_mut().zip(sample) {
+                *total += weight as i64;
+            }
+        }
+        // Most of the mass must rest on the sensor being calibrated.
+        let sum: i64 = totals.iter().sum();
+        if totals[sensor] <= 0 || totals[sensor] * 2 < sum {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("reference mass is not over sensor {}", sensor),
+            ));
+        }
+        let reading = totals[sensor] as f32 / samples.len() as f32;
+        Ok(kg * Self::UNITS_PER_KG / reading)
+    }
+}
+
+impl Default for WeightCalibration {
+    fn default() -> Self {
+        Self { scales: [1.0; 4] }
+    }
+}
+
+/// A connected Wii Balance Board.
+pub struct BalanceBoard<'a> {
+    device: &'a Device,
+}
+
+impl<'a> BalanceBoard<'a> {
+    /// How long to wait for an input report with extension data.
+    const REPORT_TIMEOUT: Duration = Duration::from_secs(1);
+    /// The number of events to skip while the reference mass settles.
+    const SETTLE_SAMPLES: usize = 100;
+    /// The number of events to average for each sensor.
+    const REFERENCE_SAMPLES: usize = 200;
+
+    /// Wraps the given device, which should be a Balance Board.
+    pub fn new(device: &'a Device) -> Self {
+        Self { device }
+    }
+
+    /// Returns the underlying device.
+    pub fn device(&self) -> &'a Device {
+        self.device
+    }
+
+    /// Reads the temperature and battery level from the next input
+    /// report of the board.
+    ///
+    /// The board only sends reports while the
+    /// [`Channels::BALANCE_BOARD`](crate::Channels::BALANCE_BOARD)
+    /// channel is open. Fails with [`io::ErrorKind::TimedOut`] if no
+    /// report is received. Requires read access to the hidraw node of
+    /// the device.
+    pub fn status(&self) -> Result<BoardStatus> {
+        let devnode = input::hidraw_node(&self.device.syspath())?;
+        let mut file = File::options()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(devnode)?;
+
+        let deadline = Instant::now() + Self::REPORT_TIMEOUT;
+        let mut report = [0; 22];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no balance board report received",
+                ));
+            }
+
+            let mut pollfd = libc::pollfd {
+                fd: file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let res_code = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as _) };
+            crate::bail_if!(res_code == -1);
+            match file.read(&mut report) {
+                Ok(len) => {
+                    if let Some(status) = BoardStatus::parse(&report[..len]) {
+                        return Ok(status);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reads the raw temperature of the board.
+    /// See [`BalanceBoard::status`].
+    pub fn temperature(&self) -> Result<u8> {
+        self.status().map(|status| status.temperature)
+    }
+
+    /// Reads the battery level of the board, as a percentage from
+    /// 0 to 100%. See [`BalanceBoard::status`].
+    ///
+    /// Unlike [`Device::battery`], this is based on the level reported
+    /// along with the weights, which accounts for the batteries of
+    /// the board.
+    pub fn battery(&self) -> Result<u8> {
+        self.status().map(|status| status.battery())
+    }
+
+    /// Measures the scale factor of each sensor with a reference mass
+    /// of `kg` kilograms, and saves them to the profile of the board in
+    /// the [user store](ProfileStore::user).
+    ///
+    /// For each sensor, `prompt` is called with its index in the order
+    /// of [`EventKind::BalanceBoard`], after which the user should place
+    /// the mass over that sensor. The weights are then read from
+    /// `events`, which must have [`Channels::BALANCE_BOARD`] open, so
+    /// the mass should be in place within a second.
+    ///
+    /// [`Channels::BALANCE_BOARD`]: crate::Channels::BALANCE_BOARD
+    pub async fn calibrate_with_reference<S, F>(
+        &self,
+        kg: f32,
+        events: &mut S,
+        mut prompt: F,
+    ) -> Result<WeightCalibration>
+    where
+        S: Stream<Item = Result<Event>> + Unpin,
+        F: FnMut(usize),
+    {
+        let mut calibration = WeightCalibration::default();
+        for (sensor, scale) in calibration.scales.iter_mut().enumerate() {
+            prompt(sensor);
+            let mut samples = Vec::with_capacity(Self::REFERENCE_SAMPLES);
+            let mut skipped = 0;
+            while samples.len() < Self::REFERENCE_SAMPLES {
+                let event = match events.next().await {
+                    Some(event) => event?,
+                    None => return Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+                if let EventKind::BalanceBoard(weights) = event.kind {
+                    if skipped < Self::SETTLE_SAMPLES {
+                        skipped += 1;
+                    } else {
+                        samples.push(weights);
+                    }
+                }
+            }
+            *scale = WeightCalibration::scale(kg, sensor, &samples)?;
+        }
+        self.save_calibration(&calibration)?;
+        Ok(calibration)
+    }
+
+    /// Saves the calibration to the profile of the board in the
+    /// [user store](ProfileStore::user).
+    pub fn save_calibration(&self, calibration: &WeightCalibration) -> Result<()> {
+        let store = ProfileStore::user()?;
+        let mac = self.device.mac_address()?;
+        let mut profile = match store.load(&mac)? {
+            Some(profile) => profile,
+            None => Profile::capture(self.device)?,
+        };
+        calibration.store(&mut profile);
+        store.save(&mac, &profile)
+    }
+
+    /// Loads the calibration from the profile of the board in the
+    /// [user store](ProfileStore::user), if any.
+    pub fn load_calibration(&self) -> Result<Option<WeightCalibration>> {
+        let mac = self.device.mac_address()?;
+        match ProfileStore::user()?.load(&mac)? {
+            Some(profile) => WeightCalibration::from_profile(&profile),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BoardStatus, WeightCalibration};
+    use crate::profile::Profile;
+    use crate::Result;
+
+    #[test]
+    fn parses_extension_reports() {
+        // Core keys, the four 16-bit weights, temperature and battery.
+        let mut report = [0; 22];
+        report[..3].copy_from_slice(&[0x34, 0x00, 0x00]);
+        report[3 + 8] = 0x19;
+        report[3 + 10] = 0x82;
+
+        let status = BoardStatus::parse(&report).unwrap();
+        assert_eq!(status.temperature, 0x19);
+        assert_eq!(status.battery(), 100);
+        assert_eq!(BoardStatus::parse(&report[..10]), None);
+
+        // Status reports don't carry extension data.
+        report[0] = 0x20;
+        assert_eq!(BoardStatus::parse(&report), None);
+    }
+
+    #[test]
+    fn scales_battery() {
+        let status = |raw_battery| BoardStatus {
+            temperature: 0,
+            raw_battery,
+        };
+        assert_eq!(status(0x20).battery(), 0);
+        assert_eq!(status(0x75).battery(), 48);
+        assert_eq!(status(0xff).battery(), 100);
+    }
+
+    #[test]
+    fn scales_from_reference() -> Result<()> {
+        // A 10 kg mass over the second sensor, which reads 5% low.
+        let samples = [[10, 950, 20, 0], [10, 950, 0, 20]];
+        let scale = WeightCalibration::scale(10.0, 1, &samples)?;
+        assert!((scale - 1000.0 / 950.0).abs() < 1e-6);
+        assert!(WeightCalibration::scale(10.0, 0, &samples).is_err());
+
+        let calibration = WeightCalibration {
+            scales: [1.0, scale, 1.0, 0.5],
+        };
+        let kg = calibration.apply([100, 950, 0, 200]);
+        assert!((kg[1] - 10.0).abs() < 1e-4);
+        assert_eq!(kg[3], 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn stores_in_profile() -> Result<()> {
+        let mut profile = Profile::default();
+        assert_eq!(WeightCalibration::from_profile(&profile)?, None);
+
+        let calibration = WeightCalibration {
+            scales: [1.0, 1.0526, 0.98, 1.5],
+        };
+        calibration.store(&mut profile);
+        assert_eq!(
+            WeightCalibration::from_profile(&profile)?,
+            Some(calibration)
+        );
+
+        profile
+            .values
+            .insert("balance_board.scales".to_string(), "1 2".to_string());
+        assert!(WeightCalibration::from_profile(&profile).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Smoothed battery levels.
+//!
+//! The battery level reported by a device bounces by several points
+//! between consecutive readings, so warning about a low battery from
+//! the raw readings makes applications flap between states. A
+//! [`BatteryEstimator`] smooths the readings and applies hysteresis to
+//! the [`BatteryState`] thresholds. Use [`Device::battery_levels`] to
+//! read the battery periodically, and [`smoothed_battery`] to adapt
+//! the readings.
+use crate::io_blocker::IoBlocker;
+use crate::timer::Timer;
+use crate::{Device, Result};
+use futures::{future, Stream, TryStreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// A [`Stream`] that reads the battery level of a device periodically.
+/// The first level is read immediately.
+///
+/// See [`Device::battery_levels`].
+pub struct BatteryLevels<'a> {
+    device: &'a Device,
+    interval: Duration,
+    next: Instant,
+    // Wakes the stream once the next reading is due.
+    timer: Timer,
+}
+
+impl<'a> BatteryLevels<'a> {
+    pub(crate) fn new(device: &'a Device, blocker: Arc<IoBlocker>, interval: Duration) -> Self {
+        Self {
+            device,
+            interval,
+            next: Instant::now(),
+            timer: Timer::new(blocker),
+        }
+    }
+}
+
+impl Stream for BatteryLevels<'_> {
+    type Item = Result<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let now = Instant::now();
+        if now < this.next {
+            if let Err(err) = this.timer.wake_at(this.next, cx.waker()) {
+                return Poll::Ready(Some(Err(err)));
+            }
+            return Poll::Pending;
+        }
+        this.next = now + this.interval;
+        Poll::Ready(Some(this.device.battery()))
+    }
+}
+
+/// The state of a battery, relative to the thresholds of a
+/// [`BatteryConfig`].
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum BatteryState {
+    /// The battery is at or below the critical threshold.
+    Critical,
+    /// The battery is at or below the low threshold.
+    Low,
+    /// The battery is above the low threshold.
+    Normal,
+}
+
+/// The parameters of a [`BatteryEstimator`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BatteryConfig {
+    /// The weight of each new reading in the smoothed level, from 0
+    /// (ignore new readings) to 1 (no smoothing).
+    pub smoothing: f32,
+    /// The percentage at or below which the battery is low.
+    pub low: u8,
+    /// The percentage at or below which the battery is critical.
+    pub critical: u8,
+    /// The percentage points the level must rise above a threshold
+    /// to leave the state it entered by crossing it.
+    pub hysteresis: u8,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.2,
+            low: 20,
+            critical: 10,
+            hysteresis: 5,
+        }
+    }
+}
+
+/// A smoothed battery level.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BatteryReport {
+    /// The smoothed level, as a percentage from 0 to 100%.
+    pub percentage: u8,
+    /// The state of the battery.
+    pub state: BatteryState,
+}
+
+/// Smooths battery readings and tracks the [`BatteryState`].
+#[derive(Clone, Debug)]
+pub struct BatteryEstimator {
+    config: BatteryConfig,
+    level: Option<f32>,
+    state: BatteryState,
+    last_report: Option<BatteryReport>,
+}
+
+impl BatteryEstimator {
+    /// Creates an estimator with the given parameters.
+    pub fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            level: None,
+            state: BatteryState::Normal,
+            last_report: None,
+        }
+    }
+
+    /// Returns the smoothed level, as a percentage from 0 to 100%, or
+    /// `None` before the first reading.
+    pub fn level(&self) -> Option<f32> {
+        self.level
+    }
+
+    /// Returns the current state of the battery.
+    pub fn state(&self) -> BatteryState {
+        self.state
+    }
+
+    /// Updates the estimator with a raw reading, and returns the new
+    /// report if the rounded level or the state changed.
+    pub fn update(&mut self, percentage: u8) -> Option<BatteryReport> {
+        let reading = percentage.min(100) as f32;
+        let level = match self.level {
+            Some(level) => level + self.config.smoothing * (reading - level),
+            None => reading,
+        };
+        self.level = Some(level);
+        self.state = self.next_state(level);
+
+        let report = BatteryReport {
+            percentage: level.round() as u8,
+            state: self.state,
+        };
+        if self.last_report == Some(report) {
+            return None;
+        }
+        self.last_report = Some(report);
+        Some(report)
+    }
+
+    fn next_state(&self, level: f32) -> BatteryState {
+        let config = &self.config;
+        let at_or_below = |threshold: u8| level <= threshold as f32;
+        let rose_above = |threshold: u8| level > threshold.saturating_add(config.hysteresis) as f32;
+        match self.state {
+            _ if at_or_below(config.critical) => BatteryState::Critical,
+            BatteryState::Normal if at_or_below(config.low) => BatteryState::Low,
+            BatteryState::Low | BatteryState::Critical if rose_above(config.low) => {
+                BatteryState::Normal
+            }
+            BatteryState::Critical if rose_above(config.critical) => BatteryState::Low,
+            state => state,
+        }
+    }
+}
+
+impl Default for BatteryEstimator {
+    fn default() -> Self {
+        Self::new(BatteryConfig::default())
+    }
+}
+
+/// Adapts a stream of battery readings, e.g. [`BatteryLevels`], into a
+/// stream of the reports of `estimator`.
+pub fn smoothed_battery<S>(
+    levels: S,
+    mut estimator: BatteryEstimator,
+) -> impl Stream<Item = Result<BatteryReport>>
+where
+    S: Stream<Item = Result<u8>>,
+{
+    levels.try_filter_map(move |level| future::ready(Ok(estimator.update(level))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{smoothed_battery, BatteryConfig, BatteryEstimator, BatteryState};
+    use futures::{executor, stream, TryStreamExt};
+
+    #[test]
+    fn smooths_readings() {
+        let mut estimator = BatteryEstimator::new(BatteryConfig {
+            smoothing: 0.5,
+            ..Default::default()
+        });
+        assert_eq!(estimator.update(80).unwrap().percentage, 80);
+        assert_eq!(estimator.update(60).unwrap().percentage, 70);
+        assert_eq!(estimator.update(70), None);
+        assert_eq!(estimator.level(), Some(70.0));
+    }
+
+    #[test]
+    fn does_not_flap_between_states() {
+        let readings = [25, 15, 22, 15, 22, 15, 22, 15, 22, 15];
+        let reports: Vec<_> = executor::block_on(
+            smoothed_battery(
+                stream::iter(readings.map(Ok)),
+                BatteryEstimator::new(BatteryConfig {
+                    smoothing: 1.0,
+                    ..Default::default()
+                }),
+            )
+            .try_collect(),
+        )
+        .unwrap();
+        let states: Vec<_> = reports.iter().map(|report| report.state).collect();
+        assert!(states[1..].iter().all(|&state| state == BatteryState::Low));
+
+        let mut estimator = BatteryEstimator::default();
+        estimator.update(5);
+        assert_eq!(estimator.state(), BatteryState::Critical);
+        for _ in 0..50 {
+            estimator.update(100);
+        }
+        assert_eq!(estimator.state(), BatteryState::Normal);
+    }
+}
@@ -0,0 +1,634 @@
+//! Fan-out of a single event stream to multiple consumers.
+//!
+//! The events of a device can only be read once, so two subsystems
+//! reading the same [`EventStream`](crate::event::EventStream) would
+//! steal each other's events. An [`EventBroadcaster`] wraps the stream
+//! and hands out [`Subscription`]s instead, each of which receives every
+//! event read after its creation.
+//!
+//! The stream is driven by the subscriptions themselves: whichever is
+//! polled reads new events and queues them for the others. Each queue
+//! is bounded, so a subscription that is not polled often enough loses
+//! events rather than growing without limit, or stalling the others.
+//! Which events are lost is chosen by its [`Backpressure`] policy.
+//!
+//! The capacity and policy of the queues are set for the whole
+//! broadcaster by a [`QueueConfig`], and can be overridden for each
+//! subscription. Latency-sensitive consumers, e.g. those producing audio
+//! or MIDI, should use small queues: a subscription with a capacity of 1
+//! that drops its oldest event always reads the latest one. The
+//! [`QueueStats`] of each subscription count the events it lost.
+use crate::event::{Event, EventKind};
+use crate::Result;
+use futures::task::{waker, ArcWake};
+use futures::{Stream, StreamExt};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// What a subscription does with new events when its queue is full.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Backpressure {
+    /// Drops the oldest queued event.
+    #[default]
+    DropOldest,
+    /// Drops the new event.
+    DropNewest,
+    /// Removes the newest queued event of the same kind if the new
+    /// event carries motion data, e.g. [`EventKind::Accelerometer`],
+    /// since only the latest reading matters to most consumers.
+    /// Otherwise drops the oldest queued event. The new event is queued
+    /// last either way, so events are yielded in order.
+    CoalesceMotion,
+    /// Drops the new event, and yields an error of kind
+    /// [`io::ErrorKind::Other`] before the next event, reporting the
+    /// number of dropped events.
+    Error,
+}
+
+/// The capacity and overflow policy of the queue of a subscription.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct QueueConfig {
+    /// The maximum number of unread events. Must be positive.
+    pub capacity: usize,
+    /// What to do with new events when the queue is full.
+    pub policy: Backpressure,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+            policy: Backpressure::DropOldest,
+        }
+    }
+}
+
+/// The counters of the queue of a subscription, or the totals of a
+/// broadcaster.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct QueueStats {
+    /// The number of unread events.
+    pub queued: usize,
+    /// The number of events dropped because the queue was full.
+    pub dropped: u64,
+    /// The number of events replaced by a newer one of the same kind,
+    /// with the [`Backpressure::CoalesceMotion`] policy.
+    pub coalesced: u64,
+}
+
+impl QueueStats {
+    fn add(&mut self, other: &QueueStats) {
+        self.queued += other.queued;
+        self.dropped += other.dropped;
+        self.coalesced += other.coalesced;
+    }
+}
+
+/// Returns `true` if events of this kind report the latest value of
+/// a continuous input, rather than a discrete change.
+fn is_motion(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Accelerometer { .. }
+            | EventKind::Ir(_)
+            | EventKind::BalanceBoard(_)
+            | EventKind::MotionPlus { .. }
+            | EventKind::ProControllerMove { .. }
+            | EventKind::ClassicControllerMove { .. }
+            | EventKind::NunchukMove { .. }
+            | EventKind::DrumsMove { .. }
+            | EventKind::GuitarMove { .. }
+    )
+}
+
+/// The events pending to be read by a subscription.
+struct Queue {
+    items: VecDeque<Result<Event>>,
+    config: QueueConfig,
+    dropped: u64,
+    coalesced: u64,
+    // The number of events dropped since the last lag error.
+    unreported: u64,
+}
+
+impl Queue {
+    fn new(config: QueueConfig) -> Self {
+        assert!(config.capacity > 0, "capacity must be positive");
+        Self {
+            items: VecDeque::new(),
+            config,
+            dropped: 0,
+            coalesced: 0,
+            unreported: 0,
+        }
+    }
+
+    fn stats(&self) -> QueueStats {
+        QueueStats {
+            queued: self.items.len(),
+            dropped: self.dropped,
+            coalesced: self.coalesced,
+        }
+    }
+
+    fn push(&mut self, item: Result<Event>) {
+        if self.items.len() < self.config.capacity {
+            self.items.push_back(item);
+            return;
+        }
+        match self.config.policy {
+            Backpressure::DropOldest => {
+                self.dropped += 1;
+                self.items.pop_front();
+                self.items.push_back(item);
+            }
+            Backpressure::DropNewest => self.dropped += 1,
+            Backpressure::CoalesceMotion => {
+                let kind = match &item {
+                    Ok(event) if is_motion(&event.kind) => Some(mem::discriminant(&event.kind)),
+                    _ => None,
+                };
+                // The reading moves to the back, so the events stay in order.
+                let queued = kind.and_then(|kind| {
+                    self.items.iter().rposition(|queued| {
+                        matches!(queued, Ok(queued) if mem::discriminant(&queued.kind) == kind)
+                    })
+                });
+                match queued {
+                    Some(index) => {
+                        self.coalesced += 1;
+                        self.items.remove(index);
+                    }
+                    None => {
+                        self.dropped += 1;
+                        self.items.pop_front();
+                    }
+                }
+                self.items.push_back(item);
+            }
+            Backpressure::Error => {
+                self.dropped += 1;
+                self.unreported += 1;
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Result<Event>> {
+        if self.unreported > 0 {
+            let message = format!("subscription lagged behind by {} events", self.unreported);
+            self.unreported = 0;
+            return Some(Err(io::Error::other(message)));
+        }
+        self.items.pop_front()
+    }
+}
+
+/// The state shared by the subscriptions of a broadcaster.
+struct Shared<S> {
+    events: S,
+    ended: bool,
+    next_id: usize,
+    queues: HashMap<usize, Queue>,
+    // The counters of the dropped subscriptions.
+    closed: QueueStats,
+}
+
+/// Wakes up every subscription waiting for events.
+///
+/// The underlying stream only stores the waker of the subscription that
+/// polled it last, so it is given this waker instead. Kept separate from
+/// [`Shared`], since the stream may wake it up while being polled.
+#[derive(Default)]
+struct FanOut {
+    wakers: Mutex<HashMap<usize, Waker>>,
+}
+
+impl FanOut {
+    fn register(&self, id: usize, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        match wakers.get_mut(&id) {
+            Some(current) if current.will_wake(waker) => {}
+            Some(current) => current.clone_from(waker),
+            None => {
+                wakers.insert(id, waker.clone());
+            }
+        }
+    }
+
+    fn wake_others(&self, id: usize) {
+        for (_, waker) in self.wakers.lock().unwrap().iter().filter(|(&i, _)| i != id) {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl ArcWake for FanOut {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        for waker in arc_self.wakers.lock().unwrap().values() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Shares an event stream between multiple [`Subscription`]s.
+pub struct EventBroadcaster<S> {
+    shared: Arc<Mutex<Shared<S>>>,
+    fan_out: Arc<FanOut>,
+    config: QueueConfig,
+}
+
+impl<S> EventBroadcaster<S>
+where
+    S: Stream<Item = Result<Event>> + Unpin,
+{
+    /// Wraps the given stream. Each subscription queues up to `capacity`
+    /// unread events.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(events: S, capacity: usize) -> Self {
+        Self::with_config(
+            events,
+            QueueConfig {
+                capacity,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Wraps the given stream. Each subscription queues unread events
+    /// as configured by `config`, unless subscribed with another one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is zero.
+    pub fn with_config(events: S, config: QueueConfig) -> Self {
+        assert!(config.capacity > 0, "capacity must be positive");
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                events,
+                ended: false,
+                next_id: 0,
+                queues: HashMap::new(),
+                closed: QueueStats::default(),
+            })),
+            fan_out: Arc::default(),
+            config,
+        }
+    }
+
+    /// Returns the queue configuration of new subscriptions.
+    pub fn config(&self) -> QueueConfig {
+        self.config
+    }
+
+    /// Sets the queue configuration of the subscriptions created from
+    /// now on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is zero.
+    pub fn set_config(&mut self, config: QueueConfig) {
+        assert!(config.capacity > 0, "capacity must be positive");
+        self.config = config;
+    }
+
+    /// Creates a subscription receiving the events read from now on,
+    /// with the queue configuration of the broadcaster.
+    pub fn subscribe(&self) -> Subscription<S> {
+        self.subscribe_with_config(self.config)
+    }
+
+    /// Creates a subscription receiving the events read from now on,
+    /// with the given policy for when its queue is full.
+    pub fn subscribe_with(&self, policy: Backpressure) -> Subscription<S> {
+        self.subscribe_with_config(QueueConfig {
+            policy,
+            ..self.config
+        })
+    }
+
+    /// Creates a subscription receiving the events read from now on,
+    /// with the given queue configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is zero.
+    pub fn subscribe_with_config(&self, config: QueueConfig) -> Subscription<S> {
+        Subscription::new(Arc::clone(&self.shared), Arc::clone(&self.fan_out), config)
+    }
+
+    /// Returns the totals of the counters of every subscription,
+    /// including those dropped.
+    pub fn stats(&self) -> QueueStats {
+        let state = self.shared.lock().unwrap();
+        let mut stats = state.closed;
+        for queue in state.queues.values() {
+            stats.add(&queue.stats());
+        }
+        stats
+    }
+}
+
+/// A stream of the events of an [`EventBroadcaster`].
+///
+/// Cloning a subscription creates a new subscription with the same
+/// policy, which receives the events read from then on.
+pub struct Subscription<S> {
+    id: usize,
+    shared: Arc<Mutex<Shared<S>>>,
+    fan_out: Arc<FanOut>,
+}
+
+impl<S> Subscription<S> {
+    fn new(shared: Arc<Mutex<Shared<S>>>, fan_out: Arc<FanOut>, config: QueueConfig) -> Self {
+        let queue = Queue::new(config);
+        let id = {
+            let mut state = shared.lock().unwrap();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.queues.insert(id, queue);
+            id
+        };
+        Self {
+            id,
+            shared,
+            fan_out,
+        }
+    }
+
+    /// Returns the number of events this subscription has dropped or
+    /// coalesced because its queue was full.
+    pub fn lagged(&self) -> u64 {
+        let stats = self.stats();
+        stats.dropped + stats.coalesced
+    }
+
+    /// Returns the counters of the queue of this subscription.
+    pub fn stats(&self) -> QueueStats {
+        self.shared.lock().unwrap().queues[&self.id].stats()
+    }
+
+    /// Returns the queue configuration of this subscription.
+    pub fn config(&self) -> QueueConfig {
+        self.shared.lock().unwrap().queues[&self.id].config
+    }
+}
+
+impl<S> Clone for Subscription<S> {
+    fn clone(&self) -> Self {
+        Self::new(
+            Arc::clone(&self.shared),
+            Arc::clone(&self.fan_out),
+            self.config(),
+        )
+    }
+}
+
+impl<S> Stream for Subscription<S>
+where
+    S: Stream<Item = Result<Event>> + Unpin,
+{
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.fan_out.register(self.id, cx.waker());
+        let mut state = self.shared.lock().unwrap();
+        let state = &mut *state;
+        let mut delivered = false;
+        let poll = loop {
+            if let Some(item) = state.queues.get_mut(&self.id).unwrap().pop() {
+                break Poll::Ready(Some(item));
+            }
+            if state.ended {
+                break Poll::Ready(None);
+            }
+
+            let fan_out = waker(Arc::clone(&self.fan_out));
+            match state
+                .events
+                .poll_next_unpin(&mut Context::from_waker(&fan_out))
+            {
+                Poll::Ready(Some(item)) => {
+                    for queue in state.queues.values_mut() {
+                        // `io::Error` is not `Clone`, so copy its contents.
+                        let item = match &item {
+                            Ok(event) => Ok(*event),
+                            Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+                        };
+                        queue.push(item);
+                    }
+                    delivered = true;
+                }
+                Poll::Ready(None) => state.ended = true,
+                Poll::Pending => break Poll::Pending,
+            }
+        };
+        if delivered || state.ended {
+            self.fan_out.wake_others(self.id);
+        }
+        poll
+    }
+}
+
+impl<S> Drop for Subscription<S> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.lock() {
+            if let Some(queue) = state.queues.remove(&self.id) {
+                let stats = QueueStats {
+                    queued: 0,
+                    ..queue.stats()
+                };
+                state.closed.add(&stats);
+            }
+        }
+        if let Ok(mut wakers) = self.fan_out.wakers.lock() {
+            wakers.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backpressure, EventBroadcaster, QueueConfig};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use crate::Result;
+    use futures::channel::mpsc;
+    use futures::task::{waker, ArcWake};
+    use futures::{executor, stream, StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::SystemTime;
+
+    fn event(key: Key) -> Result<Event> {
+        Ok(Event::at(
+            SystemTime::UNIX_EPOCH,
+            EventKind::Key(key, KeyState::Down),
+        ))
+    }
+
+    fn key(item: Option<Result<Event>>) -> Key {
+        match item.unwrap().unwrap().kind {
+            EventKind::Key(key, _) => key,
+            kind => panic!("unexpected event {:?}", kind),
+        }
+    }
+
+    #[derive(Default)]
+    struct CountWaker(AtomicUsize);
+
+    impl ArcWake for CountWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn subscriptions_receive_all_events() {
+        let events = stream::iter([event(Key::A), event(Key::B)]);
+        let broadcaster = EventBroadcaster::new(events, 8);
+        let (mut first, mut second) = (broadcaster.subscribe(), broadcaster.subscribe());
+
+        executor::block_on(async {
+            assert_eq!(key(first.next().await), Key::A);
+            assert_eq!(key(second.next().await), Key::A);
+            assert_eq!(key(second.next().await), Key::B);
+            assert_eq!(key(first.next().await), Key::B);
+            assert!(first.next().await.is_none());
+            assert!(second.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn slow_subscription_lags() {
+        let keys = [Key::A, Key::B, Key::Home, Key::One, Key::Two];
+        let broadcaster = EventBroadcaster::new(stream::iter(keys.map(event)), 2);
+        let (fast, mut slow) = (broadcaster.subscribe(), broadcaster.subscribe());
+
+        executor::block_on(async {
+            assert_eq!(fast.count().await, 5);
+            assert_eq!(key(slow.next().await), Key::One);
+            assert_eq!(key(slow.next().await), Key::Two);
+        });
+        assert_eq!(slow.lagged(), 3);
+        assert_eq!(slow.stats().dropped, 3);
+    }
+
+    #[test]
+    fn wakes_waiting_subscriptions() {
+        let (sender, receiver) = mpsc::unbounded();
+        let broadcaster = EventBroadcaster::new(receiver, 8);
+        let (mut first, mut second) = (broadcaster.subscribe(), broadcaster.subscribe());
+
+        let wakers = [Arc::new(CountWaker::default()), Arc::default()];
+        for (subscription, count) in [(&mut first, &wakers[0]), (&mut second, &wakers[1])] {
+            let waker = waker(Arc::clone(count));
+            let poll = subscription.poll_next_unpin(&mut Context::from_waker(&waker));
+            assert!(poll.is_pending());
+        }
+
+        // Only the last subscription polled the channel.
+        sender.unbounded_send(event(Key::A)).unwrap();
+        assert!(wakers
+            .iter()
+            .all(|count| count.0.load(Ordering::SeqCst) > 0));
+
+        let waker = waker(Arc::clone(&wakers[0]));
+        let poll = second.poll_next_unpin(&mut Context::from_waker(&waker));
+        assert!(matches!(poll, Poll::Ready(Some(Ok(_)))));
+        assert_eq!(key(executor::block_on(first.next())), Key::A);
+
+        // Clones only receive new events.
+        let mut clone = first.clone();
+        sender.unbounded_send(event(Key::B)).unwrap();
+        drop(sender);
+        assert_eq!(key(executor::block_on(clone.next())), Key::B);
+        assert_eq!(executor::block_on(first.count()), 1);
+    }
+
+    fn accel(x: i32) -> Result<Event> {
+        Ok(Event::at(
+            SystemTime::UNIX_EPOCH,
+            EventKind::Accelerometer { x, y: 0, z: 0 },
+        ))
+    }
+
+    /// Reads all events through a fast subscription, then returns the
+    /// items received by a slow one with the given policy.
+    fn drain_slow(items: Vec<Result<Event>>, policy: Backpressure) -> Vec<Result<Event>> {
+        let broadcaster = EventBroadcaster::new(stream::iter(items), 3);
+        let (fast, slow) = (broadcaster.subscribe(), broadcaster.subscribe_with(policy));
+        executor::block_on(async {
+            fast.count().await;
+            slow.collect().await
+        })
+    }
+
+    #[test]
+    fn drop_newest_keeps_first_events() {
+        let keys = [Key::A, Key::B, Key::Home, Key::One, Key::Two];
+        let items = drain_slow(keys.map(event).into(), Backpressure::DropNewest);
+        let keys: Vec<Key> = items.into_iter().map(|item| key(Some(item))).collect();
+        assert_eq!(keys, [Key::A, Key::B, Key::Home]);
+    }
+
+    #[test]
+    fn coalesces_motion() {
+        let items = vec![event(Key::A), accel(1), event(Key::B), accel(2), accel(3)];
+        let items = drain_slow(items, Backpressure::CoalesceMotion);
+
+        // The key events are kept, and only the latest reading.
+        let kinds: Vec<EventKind> = items.into_iter().map(|item| item.unwrap().kind).collect();
+        assert!(matches!(
+            kinds[..],
+            [
+                EventKind::Key(Key::A, _),
+                EventKind::Key(Key::B, _),
+                EventKind::Accelerometer { x: 3, .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn reports_lag_errors() {
+        let items = (0..5).map(accel).collect();
+        let items = drain_slow(items, Backpressure::Error);
+
+        assert_eq!(items.len(), 4);
+        let err = items[0].as_ref().unwrap_err();
+        assert_eq!(err.to_string(), "subscription lagged behind by 2 events");
+        assert!(items[1..].iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn configures_queues_per_subscription() {
+        let items: Vec<_> = (0..5).map(accel).collect();
+        let config = QueueConfig {
+            capacity: 4,
+            policy: Backpressure::CoalesceMotion,
+        };
+        let broadcaster = EventBroadcaster::with_config(stream::iter(items), config);
+        let fast = broadcaster.subscribe();
+        let latest = broadcaster.subscribe_with_config(QueueConfig {
+            capacity: 1,
+            policy: Backpressure::DropOldest,
+        });
+        let coalescing = broadcaster.subscribe();
+        assert_eq!(coalescing.config(), config);
+
+        executor::block_on(fast.count());
+        assert_eq!(latest.stats().dropped, 4);
+        let stats = coalescing.stats();
+        assert_eq!((stats.queued, stats.coalesced), (4, 1));
+
+        drop(latest);
+        let totals = broadcaster.stats();
+        assert_eq!((totals.queued, totals.dropped, totals.coalesced), (4, 4, 1));
+    }
+}
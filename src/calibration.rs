@@ -0,0 +1,219 @@
+//! Factory calibration of the Wii Remote accelerometer.
+//!
+//! Each Wii Remote stores the accelerometer readings at rest (zero
+//! acceleration) and under 1 g of gravity in its EEPROM. Applying them
+//! converts the raw [`EventKind::Accelerometer`] values into units of g,
+//! which are consistent across devices.
+use crate::event::{Event, EventKind};
+use crate::motion::Vector3;
+use crate::{Device, Result};
+use futures::{Stream, StreamExt};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+/// The accelerometer calibration values, in the same units as
+/// [`EventKind::Accelerometer`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct AccelCalibration {
+    /// The readings with zero acceleration.
+    pub zero: Vector3,
+    /// The readings with 1 g of acceleration along each axis.
+    pub gravity: Vector3,
+}
+
+impl AccelCalibration {
+    /// The EEPROM offset of the calibration block.
+    const EEPROM_OFFSET: u64 = 0x16;
+    /// The kernel reports the 10-bit readings centered at zero.
+    const CENTER: i32 = 0x200;
+
+    /// Typical calibration values, used for devices without valid
+    /// calibration data.
+    pub const NOMINAL: Self = Self {
+        zero: Vector3 { x: 0, y: 0, z: 0 },
+        gravity: Vector3 {
+            x: 100,
+            y: 100,
+            z: 100,
+        },
+    };
+
+    /// Reads the factory calibration from the device EEPROM.
+    ///
+    /// The EEPROM is exposed by the kernel driver through debugfs, which
+    /// must be mounted at `/sys/kernel/debug`. Reading it usually requires
+    /// root privileges.
+    ///
+    /// Many third-party remotes store no valid calibration. If the
+    /// device is [clone-friendly](crate::ConnectOptions::clone_friendly),
+    /// [`AccelCalibration::NOMINAL`] is returned when the stored block
+    /// is missing or invalid.
+    pub fn read(device: &Device) -> Result<Self> {
+        match Self::read_eeprom(device) {
+            Err(err)
+                if device.is_clone_friendly() && err.kind() != io::ErrorKind::PermissionDenied =>
+            {
+                log::debug!("using nominal accelerometer calibration: {}", err);
+                Ok(Self::NOMINAL)
+            }
+            result => result,
+        }
+    }
+
+    pub(crate) fn read_eeprom(device: &Device) -> Result<Self> {
+        let syspath = device.syspath();
+        let hid_name = syspath
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "invalid device path"))?;
+        let path = Path::new("/sys/kernel/debug/hid")
+            .join(hid_name)
+            .join("eeprom");
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(Self::EEPROM_OFFSET))?;
+        let mut block = [0; 10];
+        file.read_exact(&mut block)?;
+        Self::parse(&block)
+    }
+
+    /// Parses the 10-byte EEPROM calibration block.
+    ///
+    /// Each reading is stored as its 8 most significant bits, followed
+    /// by a byte with the 2 least significant bits of each axis, and the
+    /// block ends with a checksum.
+    pub fn parse(block: &[u8; 10]) -> Result<Self> {
+        let checksum = block[..9]
+            .iter()
+            .fold(0x55u8, |sum, &byte| sum.wrapping_add(byte));
+        if checksum != block[9] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid calibration checksum",
+            ));
+        }
+
+        let reading = |bytes: &[u8]| {
+            let low = bytes[3] as i32;
+            Vector3 {
+                x: ((bytes[0] as i32) << 2 | (low >> 4) & 0x3) - Self::CENTER,
+                y: ((bytes[1] as i32) << 2 | (low >> 2) & 0x3) - Self::CENTER,
+                z: ((bytes[2] as i32) << 2 | low & 0x3) - Self::CENTER,
+            }
+        };
+        Ok(Self {
+            zero: reading(&block[0..4]),
+            gravity: reading(&block[4..8]),
+        })
+    }
+
+    /// Converts the raw accelerometer readings to units of g.
+    pub fn apply(&self, raw: Vector3) -> Acceleration {
+        let scale = |value: i32, zero: i32, gravity: i32| {
+            // A broken calibration would divide by zero.
+            let range = (gravity - zero).max(1);
+            (value - zero) as f32 / range as f32
+        };
+        Acceleration {
+            x: scale(raw.x, self.zero.x, self.gravity.x),
+            y: scale(raw.y, self.zero.y, self.gravity.y),
+            z: scale(raw.z, self.zero.z, self.gravity.z),
+        }
+    }
+}
+
+/// A calibrated acceleration, in units of g.
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct Acceleration {
+    /// The x-axis acceleration.
+    pub x: f32,
+    /// The y-axis acceleration.
+    pub y: f32,
+    /// The z-axis acceleration.
+    pub z: f32,
+}
+
+/// Adapts a stream of events into a stream of calibrated accelerations,
+/// together with the time of the event.
+///
+/// Events other than [`EventKind::Accelerometer`] are skipped.
+pub struct CalibratedAccel<S> {
+    events: S,
+    calibration: AccelCalibration,
+}
+
+impl<S> CalibratedAccel<S> {
+    /// Creates the adapter over the given events.
+    pub fn new(events: S, calibration: AccelCalibration) -> Self {
+        Self {
+            events,
+            calibration,
+        }
+    }
+}
+
+impl<S> Stream for CalibratedAccel<S>
+where
+    S: Stream<Item = Result<Event>> + Unpin,
+{
+    type Item = Result<(SystemTime, Acceleration)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let event = match self.events.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(event))) => event,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            if let EventKind::Accelerometer { x, y, z } = event.kind {
+                let acceleration = self.calibration.apply(Vector3 { x, y, z });
+                return Poll::Ready(Some(Ok((event.time, acceleration))));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccelCalibration;
+    use crate::motion::Vector3;
+
+    // Taken from the EEPROM dump in the xwiimote documentation.
+    const BLOCK: [u8; 10] = [0x84, 0x82, 0x86, 0x30, 0x9f, 0x9d, 0x9f, 0x02, 0x40, 0x2e];
+
+    #[test]
+    fn parses_eeprom_block() {
+        let calibration = AccelCalibration::parse(&BLOCK).unwrap();
+        assert_eq!(calibration.zero, Vector3 { x: 19, y: 8, z: 24 });
+        assert_eq!(
+            calibration.gravity,
+            Vector3 {
+                x: 124,
+                y: 116,
+                z: 126
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut block = BLOCK;
+        block[9] ^= 1;
+        assert!(AccelCalibration::parse(&block).is_err());
+    }
+
+    #[test]
+    fn converts_to_g() {
+        let calibration = AccelCalibration::parse(&BLOCK).unwrap();
+        let acc = calibration.apply(Vector3 {
+            x: 19,
+            y: 116,
+            z: 24 - 102,
+        });
+        assert_eq!((acc.x, acc.y, acc.z), (0.0, 1.0, -1.0));
+    }
+}
@@ -0,0 +1,387 @@
+//! A C API over the high-level layers of the crate.
+//!
+//! Build it as a shared library and header with [cargo-c], which enables
+//! the `capi` feature:
+//!
+//! ```text
+//! cargo cinstall --release --prefix=/usr/local
+//! ```
+//!
+//! The library is named `xwiimote_rs`, and its header `xwiimote_rs.h`.
+//! A `xwiirs_device` reads the events of a remote without blocking, and
+//! tracks a snapshot of its state: the pressed keys, the calibrated
+//! acceleration, the orientation fused from the Motion Plus and the
+//! accelerometer, and the IR pointer. Poll the file descriptor of the
+//! device, and call `xwiirs_device_dispatch` once it is readable:
+//!
+//! ```c
+//! struct xwiirs_device *dev = xwiirs_device_connect(path, XWII_IFACE_CORE |
+//!         XWII_IFACE_ACCEL | XWII_IFACE_IR | XWII_IFACE_MOTION_PLUS);
+//! struct pollfd fds = { .fd = xwiirs_device_get_fd(dev), .events = POLLIN };
+//! while (poll(&fds, 1, -1) > 0 && xwiirs_device_dispatch(dev) >= 0) {
+//!         struct xwiirs_state state;
+//!         xwiirs_device_get_state(dev, &state);
+//!         if (state.keys & (1 << XWII_KEY_HOME))
+//!                 break;
+//! }
+//! xwiirs_device_free(dev);
+//! ```
+//!
+//! Like `libxwiimote`, the functions returning an `int` return a
+//! negative error code on failure, and those returning a pointer return
+//! `NULL` and set `errno`. The strings returned by the library are
+//! freed with `xwiirs_string_free`.
+//!
+//! [cargo-c]: https://github.com/lu-zero/cargo-c
+#![allow(non_camel_case_types)]
+use crate::calibration::AccelCalibration;
+use crate::event::{EventKind, Key, KeyState};
+use crate::fusion::{AccelCorrection, FusionConfig, IntegratedAngles};
+use crate::ir::{Pointer, SensorBarConfig};
+use crate::motion::Vector3;
+use crate::{Address, Channels, ConnectOptions, Device, Led, Leds, Monitor};
+use std::ffi::{c_char, c_int, c_uint, CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+
+/// Converts an error to the negative error code returned to C.
+fn error_code(err: &io::Error) -> c_int {
+    -err.raw_os_error().unwrap_or(libc::EIO)
+}
+
+/// Sets `errno` to the code of the error, and returns `NULL`.
+fn null_with_errno<T>(err: &io::Error) -> *mut T {
+    unsafe { *crate::ffi::errno_location() = -error_code(err) };
+    ptr::null_mut()
+}
+
+/// Enumerates the connected remotes, and optionally discovers new ones.
+pub struct xwiirs_monitor {
+    monitor: Monitor,
+}
+
+/// Creates a monitor that returns the connected devices and, if
+/// `discover` is set, the devices connected later on.
+///
+/// # Safety
+///
+/// The monitor must be freed with `xwiirs_monitor_free`.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_monitor_new(discover: bool) -> *mut xwiirs_monitor {
+    match Monitor::new(discover) {
+        Ok(monitor) => Box::into_raw(Box::new(xwiirs_monitor { monitor })),
+        Err(err) => null_with_errno(&err),
+    }
+}
+
+/// Returns the sysfs path of the next device, without blocking, or
+/// `NULL` if there is none yet or on failure, which sets `errno`.
+///
+/// # Safety
+///
+/// The monitor must be valid. The path must be freed with
+/// `xwiirs_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_monitor_next(monitor: *mut xwiirs_monitor) -> *mut c_char {
+    match (*monitor).monitor.next_address() {
+        Ok(Some(Address(syspath))) => {
+            CString::new(syspath.as_os_str().as_bytes()).map_or(ptr::null_mut(), CString::into_raw)
+        }
+        Ok(None) => {
+            *crate::ffi::errno_location() = 0;
+            ptr::null_mut()
+        }
+        Err(err) => null_with_errno(&err),
+    }
+}
+
+/// Frees a monitor.
+///
+/// # Safety
+///
+/// The monitor must be valid or `NULL`, and is invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_monitor_free(monitor: *mut xwiirs_monitor) {
+    if !monitor.is_null() {
+        drop(Box::from_raw(monitor));
+    }
+}
+
+/// Frees a string returned by the library.
+///
+/// # Safety
+///
+/// The string must have been returned by the library, or be `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// A snapshot of the state of a remote.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct xwiirs_state {
+    /// The pressed keys of the remote, with the bit `1 << XWII_KEY_*`
+    /// set for each key.
+    pub keys: u32,
+    /// The calibrated acceleration, in g.
+    pub accel: [f32; 3],
+    /// The yaw, pitch and roll fused from the Motion Plus and the
+    /// accelerometer, in radians.
+    pub orientation: [f32; 3],
+    /// Whether the sensor bar is visible, in which case `pointer` is
+    /// set.
+    pub pointer_visible: bool,
+    /// The horizontal and vertical angles between the camera axis and
+    /// the sensor bar, in radians.
+    pub pointer: [f32; 2],
+    /// The distance to the sensor bar, in millimeters.
+    pub pointer_distance_mm: f32,
+}
+
+/// A connected remote, and the state tracked from its events.
+pub struct xwiirs_device {
+    device: Device,
+    calibration: AccelCalibration,
+    angles: IntegratedAngles,
+    pointer: Pointer,
+    state: xwiirs_state,
+}
+
+impl xwiirs_device {
+    fn connect(syspath: PathBuf, channels: Channels) -> io::Result<Self> {
+        let options = ConnectOptions {
+            blocking: false,
+            ..Default::default()
+        };
+        let mut device = Device::connect_with(&Address::from(syspath), &options)?;
+        device.open(channels, false)?;
+        // Reading the calibration usually requires root.
+        let calibration = AccelCalibration::read(&device).unwrap_or_else(|err| {
+            log::debug!("using nominal accelerometer calibration: {}", err);
+            AccelCalibration::NOMINAL
+        });
+        let fusion = FusionConfig {
+            accel_correction: Some(AccelCorrection {
+                calibration,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        Ok(Self {
+            device,
+            calibration,
+            angles: IntegratedAngles::new(fusion),
+            pointer: Pointer::new(SensorBarConfig::default()),
+            state: xwiirs_state::default(),
+        })
+    }
+
+    /// Reads the available events, and returns how many were read.
+    fn dispatch(&mut self) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(event) = self.device.try_next_event()? {
+            count += 1;
+            if let Some(angles) = self.angles.update(&event) {
+                self.state.orientation = [angles.yaw, angles.pitch, angles.roll];
+            }
+            match event.kind {
+                EventKind::Key(key, state) => {
+                    let bit = 1 << key as u32;
+                    if state == KeyState::Up {
+                        self.state.keys &= !bit;
+                    } else {
+                        self.state.keys |= bit;
+                    }
+                }
+                EventKind::Accelerometer { x, y, z } => {
+                    let accel = self.calibration.apply(Vector3 { x, y, z });
+                    self.state.accel = [accel.x, accel.y, accel.z];
+                }
+                EventKind::Ir(_) => match self.pointer.update(&event) {
+                    Some(sample) => {
+                        self.state.pointer_visible = true;
+                        self.state.pointer = [sample.pose.yaw, sample.pose.pitch];
+                        self.state.pointer_distance_mm = sample.pose.distance_mm;
+                    }
+                    None => self.state.pointer_visible = false,
+                },
+                _ => {}
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Connects to the device at the given sysfs path, and opens the given
+/// `XWII_IFACE_*` channels in read-only mode.
+///
+/// # Safety
+///
+/// The path must be a valid C string. The device must be freed with
+/// `xwiirs_device_free`.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_connect(
+    syspath: *const c_char,
+    channels: c_uint,
+) -> *mut xwiirs_device {
+    let syspath = PathBuf::from(std::ffi::OsStr::from_bytes(
+        CStr::from_ptr(syspath).to_bytes(),
+    ));
+    let channels = Channels::from_bits_truncate(channels);
+    match xwiirs_device::connect(syspath, channels) {
+        Ok(device) => Box::into_raw(Box::new(device)),
+        Err(err) => null_with_errno(&err),
+    }
+}
+
+/// Disconnects from a device, and frees it.
+///
+/// # Safety
+///
+/// The device must be valid or `NULL`, and is invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_free(device: *mut xwiirs_device) {
+    if !device.is_null() {
+        drop(Box::from_raw(device));
+    }
+}
+
+/// Returns the file descriptor to poll for readability, after which
+/// `xwiirs_device_dispatch` reads the new events.
+///
+/// # Safety
+///
+/// The device must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_get_fd(device: *const xwiirs_device) -> c_int {
+    (*device).device.fd()
+}
+
+/// Reads the available events without blocking, and updates the state.
+/// Returns the number of events read.
+///
+/// # Safety
+///
+/// The device must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_dispatch(device: *mut xwiirs_device) -> c_int {
+    match (*device).dispatch() {
+        Ok(count) => count.try_into().unwrap_or(c_int::MAX),
+        Err(err) => error_code(&err),
+    }
+}
+
+/// Copies the current state to `state`.
+///
+/// # Safety
+///
+/// The device must be valid, and `state` writable.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_get_state(
+    device: *const xwiirs_device,
+    state: *mut xwiirs_state,
+) {
+    *state = (*device).state;
+}
+
+/// Resets the orientation to zero, e.g. to recenter the yaw.
+///
+/// # Safety
+///
+/// The device must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_reset_orientation(device: *mut xwiirs_device) {
+    let device = &mut *device;
+    device.angles.reset();
+    device.state.orientation = [0.0; 3];
+}
+
+/// Sets the rumble intensity, from 0 (off) to 1 (full speed). Partial
+/// intensities are emulated by pulsing the motor.
+///
+/// # Safety
+///
+/// The device must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_rumble(device: *mut xwiirs_device, intensity: f32) -> c_int {
+    match (*device).device.set_rumble_intensity(intensity) {
+        Ok(()) => 0,
+        Err(err) => error_code(&err),
+    }
+}
+
+/// Turns on the LED lights set in `leds`, where bit `n` is the light
+/// `XWII_LED(n + 1)`, and turns off the rest.
+///
+/// # Safety
+///
+/// The device must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_device_set_leds(
+    device: *const xwiirs_device,
+    leds: c_uint,
+) -> c_int {
+    let leds = Leds::from_bits_truncate(leds as u8);
+    for light in [Led::One, Led::Two, Led::Three, Led::Four] {
+        if let Err(err) = (*device).device.set_led(light, leds.contains(light.into())) {
+            return error_code(&err);
+        }
+    }
+    0
+}
+
+/// Checks whether the given `XWII_KEY_*` key is set in the keys of a
+/// state.
+///
+/// # Safety
+///
+/// The state must be readable.
+#[no_mangle]
+pub unsafe extern "C" fn xwiirs_state_is_pressed(state: *const xwiirs_state, key: c_uint) -> bool {
+    key < 32 && (*state).keys & (1 << key) != 0
+}
+
+// The key bits are the kernel key codes, as exported by `libxwiimote`.
+const _: () = assert!(Key::Two as u32 == xwiimote_sys::KEY_TWO);
+
+#[cfg(all(test, feature = "test-harness"))]
+mod tests {
+    use super::*;
+    use crate::event::{Key, KeyState};
+    use crate::harness::{FakeEvent, FakeIface};
+    use crate::{Channels, Result};
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn exports_state_through_capi() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER)?;
+        let syspath = CString::new(fake.address().0.as_os_str().as_bytes()).unwrap();
+        let channels = Channels::CORE | Channels::ACCELEROMETER;
+        unsafe {
+            let device = xwiirs_device_connect(syspath.as_ptr(), channels.bits());
+            assert!(!device.is_null());
+            assert_eq!(fake.opened(), channels);
+
+            fake.push_batch([
+                FakeEvent::key(Key::A, KeyState::Down),
+                FakeEvent::accelerometer(0, 0, 100),
+            ]);
+            assert_eq!(xwiirs_device_dispatch(device), 2);
+            assert_eq!(xwiirs_device_dispatch(device), 0);
+            let mut state = xwiirs_state::default();
+            xwiirs_device_get_state(device, &mut state);
+            assert!(xwiirs_state_is_pressed(&state, Key::A as u32));
+            assert!(!xwiirs_state_is_pressed(&state, Key::B as u32));
+            // The nominal calibration, as the fake has no EEPROM.
+            assert_eq!(state.accel, [0.0, 0.0, 1.0]);
+            xwiirs_device_free(device);
+        }
+        Ok(())
+    }
+}
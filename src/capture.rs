@@ -0,0 +1,188 @@
+//! Synchronized recording of several devices.
+//!
+//! Research setups often record several devices at once, e.g. two
+//! Balance Boards, one per foot. A [`CaptureSession`] opens the same
+//! channels of every device together, moves the event times onto a
+//! common clock with the [`clock`](crate::clock) module, and writes the
+//! samples of every device to a single log, labeled by device:
+//!
+//! ```no_run
+//! # use xwiimote::capture::CaptureSession;
+//! # use xwiimote::logger::{CsvWriter, DataLogger};
+//! # use xwiimote::{Channels, Device};
+//! # use std::fs::File;
+//! # async fn run(left: &mut Device, right: &mut Device) -> std::io::Result<()> {
+//! let mut session = CaptureSession::new(Channels::BALANCE_BOARD);
+//! session.add_device("left", left);
+//! session.add_device("right", right);
+//!
+//! let file = File::create("capture.csv")?;
+//! let writer = CsvWriter::with_labels(file, &session.metadata()?, &session.labels())?;
+//! let mut logger = DataLogger::new(writer);
+//! session.start()?;
+//! session.record(&mut logger, futures::future::pending()).await?;
+//! session.stop()?;
+//! logger.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::clock::{synced_events, SkewConfig};
+use crate::logger::{DataLogger, SampleWriter, SessionMetadata};
+use crate::{Channels, Device, Result};
+use futures::future::{self, Either};
+use futures::{Future, StreamExt};
+
+/// Records the sensor data of several devices to a single log.
+pub struct CaptureSession<'a> {
+    channels: Channels,
+    skew: SkewConfig,
+    devices: Vec<(String, &'a mut Device)>,
+}
+
+impl<'a> CaptureSession<'a> {
+    /// Creates a session that records the given channels of each device.
+    pub fn new(channels: Channels) -> Self {
+        Self::with_skew_config(channels, SkewConfig::default())
+    }
+
+    /// Creates a session like [`CaptureSession::new`], which estimates
+    /// the clock offsets of the devices with the given parameters.
+    pub fn with_skew_config(channels: Channels, skew: SkewConfig) -> Self {
+        Self {
+            channels,
+            skew,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Adds a device, whose samples are labeled with `label`. The
+    /// [device index](crate::logger::Sample::device) of its samples is
+    /// the number of devices added before.
+    pub fn add_device(&mut self, label: impl Into<String>, device: &'a mut Device) {
+        self.devices.push((label.into(), device));
+    }
+
+    /// Returns the labels of the devices, by index.
+    pub fn labels(&self) -> Vec<String> {
+        self.devices
+            .iter()
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+
+    /// Returns the metadata of a session starting now, with the label,
+    /// kind and MAC address of each device.
+    pub fn metadata(&self) -> Result<SessionMetadata> {
+        let mut metadata = SessionMetadata::new();
+        for (index, (label, device)) in self.devices.iter().enumerate() {
+            metadata.insert(format!("device{}_label", index), label.as_str());
+            metadata.insert(format!("device{}_kind", index), device.kind()?);
+            metadata.insert(
+                format!("device{}_mac_address", index),
+                device.mac_address()?,
+            );
+        }
+        Ok(metadata)
+    }
+
+    /// Opens the recorded channels of every device.
+    ///
+    /// If a device fails, the channels opened on the others are closed
+    /// again, so that either all devices or none are recording.
+    pub fn start(&mut self) -> Result<()> {
+        for index in 0..self.devices.len() {
+            if let Err(err) = self.devices[index].1.open(self.channels, false) {
+                for (_, device) in &mut self.devices[..index] {
+                    let _ = device.close(self.channels);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the recorded channels of every device. Every device is
+    /// stopped even if stopping another fails, in which case the first
+    /// error is returned.
+    pub fn stop(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for (_, device) in &mut self.devices {
+            result = result.and(device.close(self.channels));
+        }
+        result
+    }
+
+    /// Logs the samples of every device, with times on a common clock,
+    /// until `stop` completes, every device is disconnected, or reading
+    /// the events of a device fails.
+    pub async fn record<W, F>(&self, logger: &mut DataLogger<W>, stop: F) -> Result<()>
+    where
+        W: SampleWriter,
+        F: Future<Output = ()>,
+    {
+        let streams = self
+            .devices
+            .iter()
+            .map(|(_, device)| device.events())
+            .collect::<Result<Vec<_>>>()?;
+        let mut events = synced_events(streams, self.skew);
+        futures::pin_mut!(stop);
+        loop {
+            match future::select(events.next(), &mut stop).await {
+                Either::Left((Some((_, item)), _)) => {
+                    let synced = item?;
+                    logger.log_from(synced.device, &synced.event)?;
+                }
+                Either::Left((None, _)) | Either::Right(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-harness"))]
+mod tests {
+    use super::CaptureSession;
+    use crate::harness::{connect, FakeEvent, FakeIface};
+    use crate::logger::{CsvWriter, DataLogger, SessionMetadata};
+    use crate::{Channels, Result};
+    use futures::executor;
+
+    #[test]
+    fn captures_several_devices() -> Result<()> {
+        let left = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER)?;
+        let right = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER)?;
+        let (mut left_device, mut right_device) = (connect(&left)?, connect(&right)?);
+        let mut session = CaptureSession::new(Channels::ACCELEROMETER);
+        session.add_device("left", &mut left_device);
+        session.add_device("right", &mut right_device);
+        session.start()?;
+        assert_eq!(left.opened(), Channels::ACCELEROMETER);
+        assert_eq!(right.opened(), Channels::ACCELEROMETER);
+
+        left.push(FakeEvent::accelerometer(1, 2, 3));
+        right.push(FakeEvent::accelerometer(4, 5, 6));
+        left.push(FakeEvent::gone());
+        right.push(FakeEvent::gone());
+        let writer =
+            CsvWriter::with_labels(Vec::new(), &SessionMetadata::new(), &session.labels())?;
+        let mut logger = DataLogger::new(writer);
+        executor::block_on(session.record(&mut logger, futures::future::pending()))?;
+        session.stop()?;
+        assert_eq!(left.opened(), Channels::empty());
+
+        let csv = String::from_utf8(logger.finish()?).unwrap();
+        let rows: Vec<_> = csv.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            rows[0],
+            "elapsed_ns,time_ns,device,source,value0,value1,value2,value3"
+        );
+        assert_eq!(rows.len(), 3);
+        assert!(rows
+            .iter()
+            .any(|row| row.ends_with(",left,accelerometer,1,2,3,")));
+        assert!(rows
+            .iter()
+            .any(|row| row.ends_with(",right,accelerometer,4,5,6,")));
+        Ok(())
+    }
+}
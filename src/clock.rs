@@ -0,0 +1,232 @@
+//! Event times of several devices on a common clock.
+//!
+//! The kernel timestamps each event with the wall clock when its report
+//! arrives. The reports of each remote are delayed differently by the
+//! Bluetooth link, and the wall clock can jump, e.g. when it is adjusted
+//! by NTP, so the times of the events of several remotes don't line up
+//! well enough for e.g. rhythm games or motion recordings.
+//!
+//! A [`SkewEstimator`] compares the time of each event of a device with
+//! the time it was read, on a [`MonotonicClock`] shared by every device.
+//! The smallest difference over a window is the offset of the device:
+//! adding it to the event times moves them onto the common clock, while
+//! keeping their spacing. The spread of the other differences is the
+//! jitter of the device. Use [`synced_events`] to merge the streams of
+//! several devices with corrected times.
+use crate::event::Event;
+use crate::Result;
+use futures::{stream, Stream, StreamExt};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A monotonic clock whose readings are expressed as wall clock times,
+/// anchored at its creation.
+///
+/// Copies of a clock read the same times, so they can be shared by the
+/// estimators of every device.
+#[derive(Copy, Clone, Debug)]
+pub struct MonotonicClock {
+    instant: Instant,
+    system: SystemTime,
+}
+
+impl MonotonicClock {
+    /// Creates a clock that reads the current wall clock time now.
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+        }
+    }
+
+    /// Returns the current time.
+    pub fn now(&self) -> SystemTime {
+        self.at(Instant::now())
+    }
+
+    /// Returns the time of the given instant.
+    pub fn at(&self, instant: Instant) -> SystemTime {
+        match instant.checked_duration_since(self.instant) {
+            Some(since) => self.system + since,
+            None => self.system - self.instant.duration_since(instant),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The parameters of a [`SkewEstimator`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SkewConfig {
+    /// The number of recent events over which the offset and jitter are
+    /// estimated. Larger windows are more likely to contain an event
+    /// that was read without delay, but adapt slower to clock jumps.
+    pub window: usize,
+}
+
+impl Default for SkewConfig {
+    fn default() -> Self {
+        Self { window: 200 }
+    }
+}
+
+/// The clock offset and jitter of a device.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SkewEstimate {
+    /// The time added to the event times to move them onto the common
+    /// clock, in seconds. Negative if the events seem to be read before
+    /// they happen, i.e. the wall clock is ahead.
+    pub offset: f64,
+    /// The mean delay of the events beyond the offset, in seconds.
+    pub jitter: f64,
+}
+
+/// Estimates the offset of the event times of a device from a common
+/// clock, and corrects them.
+#[derive(Clone, Debug)]
+pub struct SkewEstimator {
+    clock: MonotonicClock,
+    config: SkewConfig,
+    // The differences between the read and event times of the last
+    // events, in seconds.
+    samples: VecDeque<f64>,
+}
+
+/// Returns `a - b` in seconds.
+fn seconds_between(a: SystemTime, b: SystemTime) -> f64 {
+    match a.duration_since(b) {
+        Ok(later) => later.as_secs_f64(),
+        Err(earlier) => -earlier.duration().as_secs_f64(),
+    }
+}
+
+/// Returns `time + seconds`.
+fn shift(time: SystemTime, seconds: f64) -> SystemTime {
+    let duration = Duration::from_secs_f64(seconds.abs());
+    if seconds >= 0.0 {
+        time + duration
+    } else {
+        time - duration
+    }
+}
+
+impl SkewEstimator {
+    /// Creates an estimator for a device whose events are read on the
+    /// given clock.
+    pub fn new(clock: MonotonicClock, config: SkewConfig) -> Self {
+        Self {
+            clock,
+            config,
+            samples: VecDeque::with_capacity(config.window),
+        }
+    }
+
+    /// Returns the current estimate, if any event was received.
+    pub fn estimate(&self) -> Option<SkewEstimate> {
+        let offset = self.samples.iter().copied().reduce(f64::min)?;
+        let delays: f64 = self.samples.iter().map(|sample| sample - offset).sum();
+        Some(SkewEstimate {
+            offset,
+            jitter: delays / self.samples.len() as f64,
+        })
+    }
+
+    /// Updates the estimate with an event read at the given instant, and
+    /// returns the event with its time on the common clock.
+    pub fn update(&mut self, event: &Event, read: Instant) -> Event {
+        if self.samples.len() >= self.config.window.max(1) {
+            self.samples.pop_front();
+        }
+        self.samples
+            .push_back(seconds_between(self.clock.at(read), event.time));
+        let offset = self.estimate().map_or(0.0, |estimate| estimate.offset);
+        Event {
+            time: shift(event.time, offset),
+            ..*event
+        }
+    }
+}
+
+/// An event of one of the streams merged by [`synced_events`].
+#[derive(Copy, Clone, Debug)]
+pub struct SyncedEvent {
+    /// The index of the stream that yielded the event.
+    pub device: usize,
+    /// The event, with its time on the common clock.
+    pub event: Event,
+    /// The estimate of the device after the event.
+    pub estimate: SkewEstimate,
+}
+
+/// Merges the event streams of several devices, in the order the events
+/// are read, moving their times onto a common clock.
+///
+/// Events of different devices may be yielded slightly out of order of
+/// their corrected times. Errors are yielded with the index of their
+/// stream.
+pub fn synced_events<S>(
+    streams: impl IntoIterator<Item = S>,
+    config: SkewConfig,
+) -> impl Stream<Item = (usize, Result<SyncedEvent>)>
+where
+    S: Stream<Item = Result<Event>> + Unpin,
+{
+    let clock = MonotonicClock::new();
+    let streams: Vec<_> = streams
+        .into_iter()
+        .enumerate()
+        .map(|(device, events)| events.map(move |item| (device, item)))
+        .collect();
+    let mut estimators = vec![SkewEstimator::new(clock, config); streams.len()];
+    stream::select_all(streams).map(move |(device, item)| {
+        let read = Instant::now();
+        let item = item.map(|event| {
+            let estimator = &mut estimators[device];
+            let event = estimator.update(&event, read);
+            SyncedEvent {
+                device,
+                event,
+                estimate: estimator.estimate().expect("updated estimator"),
+            }
+        });
+        (device, item)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MonotonicClock, SkewConfig, SkewEstimator};
+    use crate::event::{Event, EventKind};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn corrects_offset_and_estimates_jitter() {
+        let clock = MonotonicClock::new();
+        let start = Instant::now();
+        let mut estimator = SkewEstimator::new(clock, SkewConfig { window: 3 });
+
+        // The wall clock is 1 s behind, and reports arrive 10 or 30 ms late.
+        let behind = clock.at(start) - Duration::from_secs(1);
+        let mut update = |millis: u64, delay: u64| {
+            let event = Event::at(
+                behind + Duration::from_millis(millis),
+                EventKind::Disconnected,
+            );
+            let read = start + Duration::from_millis(millis + delay);
+            estimator.update(&event, read).time
+        };
+        update(0, 30);
+        update(10, 10);
+        let corrected = update(20, 30);
+        let expected = clock.at(start) + Duration::from_millis(30);
+        assert!(corrected.duration_since(expected).unwrap() < Duration::from_micros(1));
+
+        let estimate = estimator.estimate().unwrap();
+        assert!((estimate.offset - 1.01).abs() < 1e-6);
+        assert!((estimate.jitter - 0.04 / 3.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,509 @@
+//! Detection of key chords and sequential key combos.
+//!
+//! A [`ComboDetector`] is fed with the events of a [`Device`](crate::Device)
+//! and reports the named combos completed by them. Use [`combos`] to
+//! adapt an event stream into a stream of combo events.
+//!
+//! The kernel driver sends no further events while keys are held down,
+//! unless [key repeat](crate::Device::set_key_repeat) is enabled, so
+//! chords complete on a timer rather than on an event. [`combos`] arms
+//! the timer itself. Callers of [`ComboDetector::update`] should call
+//! [`ComboDetector::update_time`] once [`ComboDetector::next_deadline`]
+//! passes.
+//!
+//! Only the Wii Remote [keys](Key) are considered, hence the
+//! [`Channels::CORE`](crate::Channels::CORE) channel must be open.
+use crate::event::{Event, EventKind, Key, KeyState};
+use crate::io_blocker::IoBlocker;
+use crate::timer::Timer;
+use crate::Result;
+use futures::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A combination of keys to detect.
+#[derive(Clone, Debug)]
+pub enum Combo {
+    /// The keys are held down simultaneously for at least the given duration.
+    Chord {
+        /// The keys to hold down.
+        keys: Vec<Key>,
+        /// The minimum duration to hold all keys down.
+        hold: Duration,
+    },
+    /// The keys are pressed in order, each within the given window
+    /// of time since the previous press.
+    Sequence {
+        /// The keys to press, in order.
+        keys: Vec<Key>,
+        /// The maximum time between two consecutive presses.
+        window: Duration,
+    },
+}
+
+/// A combo completed by a key event.
+#[derive(Clone, Debug)]
+pub struct ComboEvent<N> {
+    /// The name of the completed combo.
+    pub name: N,
+    /// The time of the event that completed the combo, or the time at
+    /// which the keys of a chord were held down for its duration.
+    pub time: SystemTime,
+}
+
+/// The detection progress of a combo.
+#[derive(Clone, Debug)]
+enum Progress {
+    /// The time at which all chord keys were held down, and whether
+    /// the chord was already reported during this hold.
+    Chord {
+        since: Option<SystemTime>,
+        reported: bool,
+    },
+    /// The number of matched sequence keys, and the time of the
+    /// last matched press.
+    Sequence {
+        matched: usize,
+        last: Option<SystemTime>,
+        // The length of the longest proper prefix of the first `i + 1`
+        // keys that is also their suffix, at index `i`.
+        fallback: Vec<usize>,
+    },
+}
+
+/// Detects named key combos from [`Event`]s.
+#[derive(Clone, Debug)]
+pub struct ComboDetector<N> {
+    combos: Vec<(N, Combo, Progress)>,
+    // The keys currently held down.
+    held: Vec<Key>,
+}
+
+impl<N: Clone> ComboDetector<N> {
+    /// Creates a detector with no combos.
+    pub fn new() -> Self {
+        Self {
+            combos: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+
+    /// Adds a combo to detect, reported with the given name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the combo has no keys.
+    pub fn with(mut self, name: N, combo: Combo) -> Self {
+        let (Combo::Chord { keys, .. } | Combo::Sequence { keys, .. }) = &combo;
+        assert!(!keys.is_empty(), "a combo must have at least one key");
+        let progress = match combo {
+            Combo::Chord { .. } => Progress::Chord {
+                since: None,
+                reported: false,
+            },
+            Combo::Sequence { ref keys, .. } => Progress::Sequence {
+                matched: 0,
+                last: None,
+                fallback: fallback(keys),
+            },
+        };
+        self.combos.push((name, combo, progress));
+        self
+    }
+
+    /// Adds a chord of keys held down simultaneously for `hold`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` is empty.
+    pub fn chord(self, name: N, keys: &[Key], hold: Duration) -> Self {
+        let keys = keys.to_vec();
+        self.with(name, Combo::Chord { keys, hold })
+    }
+
+    /// Adds a sequence of key presses, each within `window` of the previous.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` is empty.
+    pub fn sequence(self, name: N, keys: &[Key], window: Duration) -> Self {
+        let keys = keys.to_vec();
+        self.with(name, Combo::Sequence { keys, window })
+    }
+
+    /// Updates the detector with the given event, and returns the
+    /// combos completed by it.
+    pub fn update(&mut self, event: &Event) -> Vec<ComboEvent<N>> {
+        let (key, state) = match event.kind {
+            EventKind::Key(key, state) => (key, state),
+            _ => return Vec::new(),
+        };
+        match state {
+            KeyState::Down => {
+                if !self.held.contains(&key) {
+                    self.held.push(key);
+                }
+            }
+            KeyState::Up => self.held.retain(|&held| held != key),
+            KeyState::AutoRepeat => {}
+        }
+
+        let mut completed = Vec::new();
+        for (name, combo, progress) in &mut self.combos {
+            let done = match (combo, progress) {
+                (Combo::Chord { keys, hold }, Progress::Chord { since, reported }) => {
+                    if !keys.iter().all(|key| self.held.contains(key)) {
+                        *since = None;
+                        *reported = false;
+                        false
+                    } else {
+                        let start = *since.get_or_insert(event.time);
+                        let held_for = event.time.duration_since(start).unwrap_or_default();
+                        if !*reported && held_for >= *hold {
+                            *reported = true;
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                }
+                (
+                    Combo::Sequence { keys, window },
+                    Progress::Sequence {
+                        matched,
+                        last,
+                        fallback,
+                    },
+                ) => {
+                    if state != KeyState::Down {
+                        false
+                    } else {
+                        let in_window = last
+                            .and_then(|last| event.time.duration_since(last).ok())
+                            .is_some_and(|since| since <= *window);
+                        let mut next = if in_window { *matched } else { 0 };
+                        // On a mismatch, the latest presses may still match
+                        // a shorter prefix, e.g. Up, Up, Up for Up, Up, Down.
+                        while next > 0 && keys[next] != key {
+                            next = fallback[next - 1];
+                        }
+                        *matched = next + (keys[next] == key) as usize;
+                        *last = Some(event.time);
+
+                        if *matched == keys.len() {
+                            *matched = 0;
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                }
+                _ => unreachable!("combo progress mismatch"),
+            };
+            if done {
+                completed.push(ComboEvent {
+                    name: name.clone(),
+                    time: event.time,
+                });
+            }
+        }
+        completed
+    }
+
+    /// Returns the time at which the hold duration of a chord held down
+    /// elapses, if any.
+    pub fn next_deadline(&self) -> Option<SystemTime> {
+        self.combos
+            .iter()
+            .filter_map(|(_, combo, progress)| match (combo, progress) {
+                (
+                    Combo::Chord { hold, .. },
+                    Progress::Chord {
+                        since: Some(since),
+                        reported: false,
+                    },
+                ) => Some(*since + *hold),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Updates the detector with the current time, and returns the
+    /// chords whose hold duration elapsed without an event.
+    pub fn update_time(&mut self, now: SystemTime) -> Vec<ComboEvent<N>> {
+        let mut completed = Vec::new();
+        for (name, combo, progress) in &mut self.combos {
+            if let (
+                Combo::Chord { hold, .. },
+                Progress::Chord {
+                    since: Some(since),
+                    reported,
+                },
+            ) = (combo, progress)
+            {
+                let time = *since + *hold;
+                if !*reported && time <= now {
+                    *reported = true;
+                    completed.push(ComboEvent {
+                        name: name.clone(),
+                        time,
+                    });
+                }
+            }
+        }
+        completed
+    }
+}
+
+impl<N: Clone> Default for ComboDetector<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the Knuth-Morris-Pratt failure function of the keys.
+fn fallback(keys: &[Key]) -> Vec<usize> {
+    let mut fallback = vec![0; keys.len()];
+    let mut len = 0;
+    for i in 1..keys.len() {
+        while len > 0 && keys[i] != keys[len] {
+            len = fallback[len - 1];
+        }
+        if keys[i] == keys[len] {
+            len += 1;
+        }
+        fallback[i] = len;
+    }
+    fallback
+}
+
+/// Adapts a stream of events into a stream of the combos
+/// detected by `detector`.
+///
+/// Chords are reported once held down for their duration, even if no
+/// event arrives in the meantime.
+pub fn combos<N, S>(
+    events: S,
+    detector: ComboDetector<N>,
+) -> impl Stream<Item = Result<ComboEvent<N>>>
+where
+    N: Clone,
+    S: Stream<Item = Result<Event>>,
+{
+    Combos {
+        events: Box::pin(events),
+        detector,
+        completed: VecDeque::new(),
+        deadline: None,
+        timer: Timer::new(IoBlocker::get().clone()),
+    }
+}
+
+/// The stream returned by [`combos`].
+struct Combos<N, S> {
+    events: Pin<Box<S>>,
+    detector: ComboDetector<N>,
+    completed: VecDeque<ComboEvent<N>>,
+    // The next chord deadline, and the instant the timer is armed at.
+    deadline: Option<(SystemTime, Instant)>,
+    timer: Timer,
+}
+
+// The detector is never pinned.
+impl<N, S> Unpin for Combos<N, S> {}
+
+impl<N, S> Stream for Combos<N, S>
+where
+    N: Clone,
+    S: Stream<Item = Result<Event>>,
+{
+    type Item = Result<ComboEvent<N>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(combo) = this.completed.pop_front() {
+                return Poll::Ready(Some(Ok(combo)));
+            }
+            match this.events.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(event))) => {
+                    this.completed.extend(this.detector.update(&event));
+                    continue;
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => {}
+            }
+            let Some(deadline) = this.detector.next_deadline() else {
+                this.deadline = None;
+                this.timer.cancel();
+                return Poll::Pending;
+            };
+            let now = SystemTime::now();
+            let remaining = match deadline.duration_since(now) {
+                Ok(remaining) if !remaining.is_zero() => remaining,
+                _ => {
+                    this.completed.extend(this.detector.update_time(now));
+                    continue;
+                }
+            };
+            // Event times are on the wall clock, timers on the monotonic one.
+            let at = match this.deadline {
+                Some((armed, at)) if armed == deadline => at,
+                _ => Instant::now() + remaining,
+            };
+            this.deadline = Some((deadline, at));
+            if let Err(err) = this.timer.wake_at(at, cx.waker()) {
+                return Poll::Ready(Some(Err(err)));
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combos, ComboDetector};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use futures::{executor, stream, StreamExt};
+    use std::time::{Duration, SystemTime};
+
+    fn key(ms: u64, key: Key, state: KeyState) -> Event {
+        Event::at(
+            SystemTime::UNIX_EPOCH + Duration::from_millis(ms),
+            EventKind::Key(key, state),
+        )
+    }
+
+    fn names(detector: &mut ComboDetector<&'static str>, event: Event) -> Vec<&'static str> {
+        detector
+            .update(&event)
+            .into_iter()
+            .map(|combo| combo.name)
+            .collect()
+    }
+
+    #[test]
+    fn chord_held() {
+        let mut detector =
+            ComboDetector::new().chord("pair", &[Key::One, Key::Two], Duration::from_secs(1));
+
+        assert!(names(&mut detector, key(0, Key::One, KeyState::Down)).is_empty());
+        assert!(names(&mut detector, key(100, Key::Two, KeyState::Down)).is_empty());
+        assert!(names(&mut detector, key(600, Key::Two, KeyState::AutoRepeat)).is_empty());
+        assert_eq!(
+            names(&mut detector, key(1100, Key::One, KeyState::AutoRepeat)),
+            ["pair"]
+        );
+        // Reported once per hold.
+        assert!(names(&mut detector, key(1200, Key::One, KeyState::AutoRepeat)).is_empty());
+    }
+
+    #[test]
+    fn chord_released_early() {
+        let mut detector =
+            ComboDetector::new().chord("pair", &[Key::One, Key::Two], Duration::from_secs(1));
+
+        names(&mut detector, key(0, Key::One, KeyState::Down));
+        names(&mut detector, key(0, Key::Two, KeyState::Down));
+        names(&mut detector, key(500, Key::Two, KeyState::Up));
+        names(&mut detector, key(600, Key::Two, KeyState::Down));
+        assert!(names(&mut detector, key(1200, Key::Two, KeyState::AutoRepeat)).is_empty());
+        assert_eq!(
+            names(&mut detector, key(1600, Key::Two, KeyState::AutoRepeat)),
+            ["pair"]
+        );
+    }
+
+    #[test]
+    fn chord_held_without_events() {
+        let mut detector =
+            ComboDetector::new().chord("pair", &[Key::One, Key::Two], Duration::from_secs(1));
+
+        names(&mut detector, key(0, Key::One, KeyState::Down));
+        names(&mut detector, key(100, Key::Two, KeyState::Down));
+        let deadline = SystemTime::UNIX_EPOCH + Duration::from_millis(1100);
+        assert_eq!(detector.next_deadline(), Some(deadline));
+        assert!(detector
+            .update_time(deadline - Duration::from_millis(1))
+            .is_empty());
+
+        let completed = detector.update_time(deadline);
+        assert_eq!(completed.len(), 1);
+        assert_eq!((completed[0].name, completed[0].time), ("pair", deadline));
+        assert_eq!(detector.next_deadline(), None);
+    }
+
+    #[test]
+    fn stream_times_chords() {
+        let detector =
+            ComboDetector::new().chord("pair", &[Key::One, Key::Two], Duration::from_millis(20));
+        let now = SystemTime::now();
+        let events = [Key::One, Key::Two]
+            .map(|pressed| Ok(Event::at(now, EventKind::Key(pressed, KeyState::Down))));
+        // No event arrives while the keys are held.
+        let events = stream::iter(events).chain(stream::pending());
+
+        let combo = executor::block_on(combos(events, detector).next()).unwrap();
+        assert_eq!(combo.unwrap().name, "pair");
+        assert!(SystemTime::now() >= now + Duration::from_millis(20));
+    }
+
+    #[test]
+    fn sequence_within_window() {
+        let window = Duration::from_millis(300);
+        let mut detector =
+            ComboDetector::new().sequence("konami", &[Key::Up, Key::Up, Key::Down], window);
+
+        names(&mut detector, key(0, Key::Up, KeyState::Down));
+        names(&mut detector, key(100, Key::Up, KeyState::Up));
+        names(&mut detector, key(200, Key::Up, KeyState::Down));
+        assert_eq!(
+            names(&mut detector, key(400, Key::Down, KeyState::Down)),
+            ["konami"]
+        );
+    }
+
+    #[test]
+    fn sequence_restarts() {
+        let window = Duration::from_millis(300);
+        let mut detector = ComboDetector::new().sequence("ab", &[Key::A, Key::B], window);
+
+        // Too slow.
+        names(&mut detector, key(0, Key::A, KeyState::Down));
+        assert!(names(&mut detector, key(500, Key::B, KeyState::Down)).is_empty());
+        // Wrong key in between.
+        names(&mut detector, key(1000, Key::A, KeyState::Down));
+        names(&mut detector, key(1100, Key::Home, KeyState::Down));
+        assert!(names(&mut detector, key(1200, Key::B, KeyState::Down)).is_empty());
+        // A restarts the sequence.
+        names(&mut detector, key(2000, Key::A, KeyState::Down));
+        names(&mut detector, key(2100, Key::A, KeyState::Down));
+        assert_eq!(
+            names(&mut detector, key(2200, Key::B, KeyState::Down)),
+            ["ab"]
+        );
+    }
+    #[test]
+    fn sequence_overlapping_prefix() {
+        let window = Duration::from_millis(300);
+        let mut detector =
+            ComboDetector::new().sequence("konami", &[Key::Up, Key::Up, Key::Down], window);
+
+        names(&mut detector, key(0, Key::Up, KeyState::Down));
+        names(&mut detector, key(100, Key::Up, KeyState::Down));
+        names(&mut detector, key(200, Key::Up, KeyState::Down));
+        assert_eq!(
+            names(&mut detector, key(300, Key::Down, KeyState::Down)),
+            ["konami"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one key")]
+    fn rejects_empty_combo() {
+        ComboDetector::new().sequence("none", &[], Duration::from_millis(300));
+    }
+}
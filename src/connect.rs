@@ -0,0 +1,173 @@
+//! Connecting to devices without blocking.
+//!
+//! [`Device::connect_async`](crate::Device::connect_async) returns a
+//! future that connects to a device, waiting for it to settle without
+//! blocking the current thread, and a [`ConnectProgress`] stream that
+//! reports the [`ConnectState`] of the connection, e.g. to display it
+//! in a user interface.
+use crate::io_blocker::IoBlocker;
+use crate::timer::Timer;
+use crate::Result;
+use futures::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// The progress of a connection to a device, in order.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ConnectState {
+    /// Waiting for the newly discovered device to settle.
+    Resolving,
+    /// Opening the `xwiimote` interface of the device.
+    OpeningInterface,
+    /// Watching the device for hot-plug events.
+    Watching,
+    /// The device is connected.
+    Ready,
+}
+
+struct Watch {
+    state: ConnectState,
+    // Incremented on every change.
+    version: u64,
+    // Set once the connection succeeds or fails.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// A [`Stream`] of the states of a connection, see
+/// [`Device::connect_async`](crate::Device::connect_async).
+///
+/// Only the latest state is kept, so intermediate states are skipped if
+/// the stream is not polled in time. The stream ends once the device is
+/// [`ConnectState::Ready`], or once connecting fails.
+pub struct ConnectProgress {
+    watch: Arc<Mutex<Watch>>,
+    seen: u64,
+}
+
+impl ConnectProgress {
+    /// Returns the latest state of the connection.
+    pub fn state(&self) -> ConnectState {
+        self.watch.lock().unwrap().state
+    }
+}
+
+impl Stream for ConnectProgress {
+    type Item = ConnectState;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut watch = this.watch.lock().unwrap();
+        if watch.version > this.seen {
+            this.seen = watch.version;
+            return Poll::Ready(Some(watch.state));
+        }
+        if watch.closed {
+            return Poll::Ready(None);
+        }
+        watch.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Publishes the states of a connection to its [`ConnectProgress`].
+/// Dropping the reporter ends the stream.
+pub(crate) struct ProgressReporter {
+    watch: Arc<Mutex<Watch>>,
+}
+
+impl ProgressReporter {
+    /// Creates a reporter, starting at [`ConnectState::Resolving`].
+    pub fn new() -> (Self, ConnectProgress) {
+        let watch = Arc::new(Mutex::new(Watch {
+            state: ConnectState::Resolving,
+            version: 1,
+            closed: false,
+            waker: None,
+        }));
+        let progress = ConnectProgress {
+            watch: watch.clone(),
+            seen: 0,
+        };
+        (Self { watch }, progress)
+    }
+
+    pub fn report(&self, state: ConnectState) {
+        let mut watch = self.watch.lock().unwrap();
+        watch.state = state;
+        watch.version += 1;
+        if let Some(waker) = watch.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        let mut watch = self.watch.lock().unwrap();
+        watch.closed = true;
+        if let Some(waker) = watch.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A future that completes after a duration, woken by the event loop.
+pub(crate) struct Sleep {
+    end: Instant,
+    timer: Timer,
+}
+
+impl Sleep {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            end: Instant::now() + duration,
+            timer: Timer::new(IoBlocker::get().clone()),
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if Instant::now() >= this.end {
+            return Poll::Ready(Ok(()));
+        }
+        this.timer.wake_at(this.end, cx.waker())?;
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectState, ProgressReporter, Sleep};
+    use futures::{executor, StreamExt};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reports_latest_state() {
+        let (reporter, mut progress) = ProgressReporter::new();
+        assert_eq!(
+            executor::block_on(progress.next()),
+            Some(ConnectState::Resolving)
+        );
+        reporter.report(ConnectState::OpeningInterface);
+        reporter.report(ConnectState::Watching);
+        drop(reporter);
+
+        let states: Vec<_> = executor::block_on(progress.collect());
+        assert_eq!(states, [ConnectState::Watching]);
+    }
+
+    #[test]
+    fn sleeps() {
+        let start = Instant::now();
+        executor::block_on(Sleep::new(Duration::from_millis(20))).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
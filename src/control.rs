@@ -0,0 +1,124 @@
+//! Asynchronous control of the LED lights and rumble motor.
+//!
+//! A [`ControlSink`] accepts [`Command`]s, so that control traffic can
+//! be driven from async pipelines, e.g. forwarded from a channel or
+//! throttled by a single task. See [`Device::control_sink`].
+use crate::io_blocker::IoBlocker;
+use crate::timer::Timer;
+use crate::{Device, Led, Leds, Result};
+use futures::Sink;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// A request to change the state of a device output.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Command {
+    /// Changes the state of a single LED light.
+    Led(Led, bool),
+    /// Turns on the given LED lights, and turns off the rest.
+    Leds(Leds),
+    /// Toggles the rumble motor.
+    Rumble(bool),
+    /// Turns on the rumble motor for the given duration.
+    ///
+    /// The sink is not ready to accept further commands until the
+    /// motor is turned off again.
+    RumblePulse(Duration),
+}
+
+/// A rumble pulse in progress.
+struct Pulse {
+    end: Instant,
+    // Wakes the sink once the pulse ends.
+    timer: Timer,
+}
+
+/// A [`Sink`] that applies [`Command`]s to a device.
+///
+/// Rumble commands require the [`Channels::CORE`](crate::Channels::CORE)
+/// channel to be open in writable mode.
+pub struct ControlSink<'a> {
+    device: &'a Device,
+    blocker: Arc<IoBlocker>,
+    pulse: Option<Pulse>,
+}
+
+impl<'a> ControlSink<'a> {
+    pub(crate) fn new(device: &'a Device, blocker: Arc<IoBlocker>) -> Self {
+        Self {
+            device,
+            blocker,
+            pulse: None,
+        }
+    }
+
+    fn apply(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::Led(light, enabled) => self.device.set_led(light, enabled),
+            Command::Leds(leds) => {
+                for light in [Led::One, Led::Two, Led::Three, Led::Four] {
+                    self.device.set_led(light, leds.contains(light.into()))?;
+                }
+                Ok(())
+            }
+            Command::Rumble(enabled) => self.device.set_rumble(enabled),
+            Command::RumblePulse(duration) => {
+                self.pulse = Some(Pulse {
+                    end: Instant::now() + duration,
+                    timer: Timer::new(self.blocker.clone()),
+                });
+                self.device.set_rumble(true)
+            }
+        }
+    }
+
+    /// Turns off the rumble motor once the current pulse ends, if any.
+    fn poll_pulse(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let pulse = match &mut self.pulse {
+            Some(pulse) => pulse,
+            None => return Poll::Ready(Ok(())),
+        };
+        if Instant::now() < pulse.end {
+            pulse.timer.wake_at(pulse.end, cx.waker())?;
+            return Poll::Pending;
+        }
+        Poll::Ready(self.end_pulse())
+    }
+
+    fn end_pulse(&mut self) -> Result<()> {
+        match self.pulse.take() {
+            Some(_) => self.device.set_rumble(false),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Sink<Command> for ControlSink<'_> {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_pulse(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, command: Command) -> Result<()> {
+        self.get_mut().apply(command)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        // Commands are applied immediately, except for the end of a pulse.
+        self.get_mut().poll_pulse(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_pulse(cx)
+    }
+}
+
+impl Drop for ControlSink<'_> {
+    fn drop(&mut self) {
+        // Don't leave the motor running if the pulse was cut short.
+        let _ = self.end_pulse();
+    }
+}
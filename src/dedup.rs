@@ -0,0 +1,198 @@
+//! Suppression of repeated movement events.
+//!
+//! The high-rate channels report about 100 movement events per second,
+//! even if the controller lies still and every event repeats the same
+//! positions. A [`Dedup`] drops the movement events whose values didn't
+//! change by more than an epsilon since the last event passed on, so
+//! idle controllers cost next to nothing downstream. Key and other
+//! events are always passed on.
+//!
+//! Add it to a device as a [layer](crate::layer), or use
+//! [`deduplicated`] to adapt a stream.
+use crate::event::{Event, EventKind};
+use crate::layer::EventLayer;
+use crate::{Channels, Result};
+use futures::{future, Stream, TryStreamExt};
+use std::collections::HashMap;
+
+/// The most values reported by a movement event, i.e. the coordinates
+/// of the four IR sources.
+const MAX_VALUES: usize = 8;
+
+/// Returns the values of a movement event, padded with zeros.
+fn values(kind: &EventKind) -> Option<[i32; MAX_VALUES]> {
+    let mut values = [0; MAX_VALUES];
+    match *kind {
+        EventKind::Accelerometer { x, y, z } | EventKind::MotionPlus { x, y, z } => {
+            fill(&mut values, 0, &[x, y, z])
+        }
+        EventKind::Ir(sources) => {
+            for (ix, source) in sources.iter().enumerate() {
+                // Missing sources are out of the range of the camera.
+                let (x, y) = source.map_or((-1, -1), |source| (source.x, source.y));
+                fill(&mut values, ix * 2, &[x, y]);
+            }
+        }
+        EventKind::BalanceBoard(weights) => fill(&mut values, 0, &weights),
+        EventKind::ProControllerMove {
+            left_x,
+            left_y,
+            right_x,
+            right_y,
+        } => fill(&mut values, 0, &[left_x, left_y, right_x, right_y]),
+        EventKind::ClassicControllerMove {
+            left_x,
+            left_y,
+            right_x,
+            right_y,
+            left_trigger,
+            right_trigger,
+        } => fill(
+            &mut values,
+            0,
+            &[
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+                left_trigger.into(),
+                right_trigger.into(),
+            ],
+        ),
+        EventKind::NunchukMove {
+            x,
+            y,
+            x_acceleration,
+            y_acceleration,
+        } => fill(&mut values, 0, &[x, y, x_acceleration, y_acceleration]),
+        EventKind::GuitarMove {
+            x,
+            y,
+            whammy_bar,
+            fret_bar,
+        } => fill(&mut values, 0, &[x, y, whammy_bar, fret_bar]),
+        _ => return None,
+    }
+    Some(values)
+}
+
+/// Copies reported values into `values`, from `start` on.
+fn fill(values: &mut [i32; MAX_VALUES], start: usize, reported: &[i32]) {
+    values[start..start + reported.len()].copy_from_slice(reported);
+}
+
+/// Drops movement events that repeat the values of the last one passed
+/// on, per channel.
+#[derive(Clone, Default, Debug)]
+pub struct Dedup {
+    // The epsilon of each channel, if not zero; `None` disables the
+    // suppression.
+    epsilons: HashMap<Channels, Option<i32>>,
+    // The values of the last movement event passed on, per channel.
+    last: HashMap<Channels, [i32; MAX_VALUES]>,
+}
+
+impl Dedup {
+    /// Creates a filter that drops exact repeats on every channel.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the epsilon of the given channels: events are dropped if no
+    /// value changed by more than `epsilon`, in the units of the event.
+    /// `None` passes on every event of the channels.
+    pub fn set_epsilon(&mut self, channels: Channels, epsilon: Option<i32>) {
+        for bit in 0..u32::BITS {
+            let channel = Channels::from_bits_truncate(1 << bit);
+            if !channel.is_empty() && channels.contains(channel) {
+                self.epsilons.insert(channel, epsilon);
+                self.last.remove(&channel);
+            }
+        }
+    }
+
+    /// Returns the epsilon of the given channel.
+    pub fn epsilon(&self, channel: Channels) -> Option<i32> {
+        self.epsilons.get(&channel).copied().unwrap_or(Some(0))
+    }
+
+    /// Checks whether the given event should be passed on, updating the
+    /// last values of its channel if so.
+    pub fn update(&mut self, event: &Event) -> bool {
+        let (channel, values) = match (event.kind.channel(), values(&event.kind)) {
+            (Some(channel), Some(values)) => (channel, values),
+            _ => return true,
+        };
+        let epsilon = match self.epsilon(channel) {
+            Some(epsilon) => epsilon,
+            None => return true,
+        };
+        if let Some(last) = self.last.get(&channel) {
+            let changed = last
+                .iter()
+                .zip(&values)
+                .any(|(last, value)| (value - last).abs() > epsilon);
+            if !changed {
+                return false;
+            }
+        }
+        self.last.insert(channel, values);
+        true
+    }
+}
+
+impl EventLayer for Dedup {
+    fn handle(&mut self, event: Event, emit: &mut dyn FnMut(Event)) {
+        if self.update(&event) {
+            emit(event);
+        }
+    }
+}
+
+/// Adapts a stream of events, dropping those suppressed by `dedup`.
+pub fn deduplicated<S>(events: S, mut dedup: Dedup) -> impl Stream<Item = Result<Event>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter(move |event| future::ready(dedup.update(event)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dedup;
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use crate::Channels;
+    use std::time::SystemTime;
+
+    fn event(kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH, kind)
+    }
+
+    fn accel(x: i32) -> Event {
+        event(EventKind::Accelerometer { x, y: 0, z: 100 })
+    }
+
+    #[test]
+    fn drops_repeats_within_epsilon() {
+        let mut dedup = Dedup::new();
+        assert!(dedup.update(&accel(0)));
+        assert!(!dedup.update(&accel(0)));
+        assert!(dedup.update(&accel(1)));
+
+        dedup.set_epsilon(Channels::ACCELEROMETER, Some(2));
+        assert!(dedup.update(&accel(1)));
+        assert!(!dedup.update(&accel(3)));
+        // Compared to the last event passed on, so slow drifts get through.
+        assert!(dedup.update(&accel(4)));
+
+        let key = event(EventKind::Key(Key::A, KeyState::Down));
+        assert!(dedup.update(&key));
+        assert!(dedup.update(&key));
+
+        dedup.set_epsilon(Channels::ACCELEROMETER | Channels::IR, None);
+        assert!(dedup.update(&accel(4)));
+        assert!(dedup.update(&accel(4)));
+        assert_eq!(dedup.epsilon(Channels::IR), None);
+        assert_eq!(dedup.epsilon(Channels::MOTION_PLUS), Some(0));
+    }
+}
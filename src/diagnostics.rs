@@ -0,0 +1,255 @@
+//! Latency measurements, to tell delays added by the Bluetooth stack
+//! from those of the application.
+//!
+//! [`measure_latency`] times requests to a remote: reading the battery
+//! level makes the kernel driver request a status report and wait for
+//! the answer, which takes a round trip over the link. Each round first
+//! toggles an LED light, whose report is queued before the request.
+//! Round trips of a few milliseconds are typical; tens of milliseconds
+//! usually mean a congested link or aggressive power saving.
+//!
+//! The kernel timestamps each event once its report arrives, so the
+//! delay until the event is read is spent in the kernel and in the
+//! application. A [`LatencyMeter`] tracks it over the recent events.
+use crate::event::Event;
+use crate::{Device, Led, Result};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, io};
+
+/// Summary statistics of a set of latency samples.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LatencyStats {
+    /// The number of samples.
+    pub samples: usize,
+    /// The smallest sample.
+    pub min: Duration,
+    /// The mean of the samples.
+    pub mean: Duration,
+    /// The 95th percentile of the samples.
+    pub p95: Duration,
+    /// The largest sample.
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Computes the statistics of the given samples, or returns `None`
+    /// if there are none.
+    pub fn of(samples: impl IntoIterator<Item = Duration>) -> Option<Self> {
+        let mut sorted: Vec<_> = samples.into_iter().collect();
+        sorted.sort_unstable();
+        let (&min, &max) = (sorted.first()?, sorted.last()?);
+        let total: Duration = sorted.iter().sum();
+        // The nearest-rank percentile.
+        let rank = (sorted.len() * 95).div_ceil(100);
+        Some(Self {
+            samples: sorted.len(),
+            min,
+            mean: total / sorted.len() as u32,
+            p95: sorted[rank.max(1) - 1],
+            max,
+        })
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1e3;
+        write!(
+            f,
+            "min {:.1} ms, mean {:.1} ms, p95 {:.1} ms, max {:.1} ms ({} samples)",
+            millis(self.min),
+            millis(self.mean),
+            millis(self.p95),
+            millis(self.max),
+            self.samples
+        )
+    }
+}
+
+/// The parameters of [`measure_latency_with`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LatencyConfig {
+    /// The number of measured round trips.
+    pub rounds: usize,
+    /// The pause between consecutive rounds, which keeps the requests
+    /// from queuing up behind each other.
+    pub interval: Duration,
+    /// The LED light toggled on each round.
+    pub light: Led,
+}
+
+impl Default for LatencyConfig {
+    fn default() -> Self {
+        Self {
+            rounds: 20,
+            interval: Duration::from_millis(20),
+            light: Led::One,
+        }
+    }
+}
+
+/// The latencies measured by [`measure_latency`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LatencyReport {
+    /// The time from toggling the LED light until the status report
+    /// requested after it arrives.
+    pub round_trip: LatencyStats,
+    /// The time taken to hand the LED report to the kernel driver.
+    pub output: LatencyStats,
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "round trip: {}; output: {}",
+            self.round_trip, self.output
+        )
+    }
+}
+
+/// Measures the latency of the link to the device with the default
+/// [`LatencyConfig`], which takes about half a second.
+///
+/// Blocks until done. The LED light is restored afterwards. Reading the
+/// battery level may require root.
+pub fn measure_latency(device: &Device) -> Result<LatencyReport> {
+    measure_latency_with(device, &LatencyConfig::default())
+}
+
+/// Measures the latency of the link to the device, with the given
+/// parameters. See [`measure_latency`].
+pub fn measure_latency_with(device: &Device, config: &LatencyConfig) -> Result<LatencyReport> {
+    if config.rounds == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one round is required",
+        ));
+    }
+    let initial = device.led(config.light)?;
+    let result = (|| {
+        let mut round_trips = Vec::with_capacity(config.rounds);
+        let mut outputs = Vec::with_capacity(config.rounds);
+        let mut enabled = initial;
+        for round in 0..config.rounds {
+            if round > 0 {
+                std::thread::sleep(config.interval);
+            }
+            enabled = !enabled;
+            let start = Instant::now();
+            device.set_led(config.light, enabled)?;
+            outputs.push(start.elapsed());
+            device.battery()?;
+            round_trips.push(start.elapsed());
+        }
+        Ok(LatencyReport {
+            round_trip: LatencyStats::of(round_trips).expect("measured rounds"),
+            output: LatencyStats::of(outputs).expect("measured rounds"),
+        })
+    })();
+    device.set_led(config.light, initial)?;
+    result
+}
+
+/// Tracks the delay between the kernel receiving the reports of a
+/// device and the application reading their events.
+///
+/// The delay is computed on the wall clock, so it is skewed while the
+/// clock is adjusted; negative delays are ignored.
+#[derive(Clone, Debug)]
+pub struct LatencyMeter {
+    window: usize,
+    samples: VecDeque<Duration>,
+}
+
+impl LatencyMeter {
+    /// Creates a meter that keeps the delays of the last `window` events.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Records an event read at the given time, usually
+    /// [`SystemTime::now`], and returns its delay.
+    pub fn update(&mut self, event: &Event, read: SystemTime) -> Option<Duration> {
+        let delay = read.duration_since(event.time).ok()?;
+        if self.samples.len() >= self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(delay);
+        Some(delay)
+    }
+
+    /// Returns the statistics of the recorded delays, if any.
+    pub fn stats(&self) -> Option<LatencyStats> {
+        LatencyStats::of(self.samples.iter().copied())
+    }
+}
+
+impl Default for LatencyMeter {
+    fn default() -> Self {
+        Self::new(200)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyMeter, LatencyStats};
+    use crate::event::{Event, EventKind};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn summarizes_samples() {
+        let samples = (1..=20).map(Duration::from_millis);
+        let stats = LatencyStats::of(samples).unwrap();
+        assert_eq!(stats.samples, 20);
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.mean, Duration::from_micros(10_500));
+        assert_eq!(stats.p95, Duration::from_millis(19));
+        assert_eq!(stats.max, Duration::from_millis(20));
+        assert_eq!(LatencyStats::of([]), None);
+    }
+
+    #[test]
+    fn keeps_recent_delays() {
+        let mut meter = LatencyMeter::new(2);
+        let start = SystemTime::now();
+        let event = Event::at(start, EventKind::Disconnected);
+        for millis in [30, 10, 20] {
+            let read = start + Duration::from_millis(millis);
+            assert_eq!(
+                meter.update(&event, read),
+                Some(Duration::from_millis(millis))
+            );
+        }
+        assert_eq!(meter.update(&event, start - Duration::from_millis(1)), None);
+
+        let stats = meter.stats().unwrap();
+        assert_eq!((stats.samples, stats.min), (2, Duration::from_millis(10)));
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn measures_latency_and_restores_led() -> crate::Result<()> {
+        use crate::diagnostics::{self, LatencyConfig};
+        use crate::harness::{connect, FakeIface};
+        use crate::{Channels, Led};
+
+        let fake = FakeIface::new(Channels::CORE)?;
+        let device = connect(&fake)?;
+        device.set_led(Led::Two, true)?;
+        let config = LatencyConfig {
+            rounds: 3,
+            interval: Duration::ZERO,
+            light: Led::Two,
+        };
+        let report = diagnostics::measure_latency_with(&device, &config)?;
+        assert_eq!(report.round_trip.samples, 3);
+        assert!(report.output.max <= report.round_trip.max);
+        assert!(device.led(Led::Two)?);
+        Ok(())
+    }
+}
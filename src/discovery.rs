@@ -0,0 +1,152 @@
+//! Devices found by a [`Monitor`], before connecting to them.
+//!
+//! [`Monitor::devices`] yields a [`DiscoveredDevice`] for each connected
+//! or discovered remote, which describes it from the kernel's `uevent`
+//! properties, as seen by udev, and from its sysfs attributes. This lets
+//! applications choose which devices to connect to, e.g. by kind, before
+//! opening them.
+//!
+//! [`Monitor`]: crate::Monitor
+//! [`Monitor::devices`]: crate::Monitor::devices
+use crate::{Address, ConnectOptions, Device, Result};
+use std::fs;
+use std::path::Path;
+
+/// Returns the value of the given property of the `uevent` file of the
+/// device at the given sysfs path, if any.
+pub(crate) fn uevent_property(syspath: &Path, key: &str) -> Result<Option<String>> {
+    let uevent = fs::read_to_string(syspath.join("uevent"))?;
+    Ok(uevent
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value.to_string()))
+}
+
+/// A connected remote, found by a [`Monitor`](crate::Monitor).
+#[derive(Clone, Debug)]
+pub struct DiscoveredDevice {
+    address: Address,
+    name: Option<String>,
+    mac_address: Option<String>,
+    product_id: Option<u16>,
+}
+
+impl DiscoveredDevice {
+    /// Describes the device at the given address.
+    ///
+    /// The properties of devices that are gone, or whose properties
+    /// can't be read, are `None`.
+    pub fn new(address: Address) -> Self {
+        let property = |key| {
+            uevent_property(&address.0, key)
+                .unwrap_or_else(|err| {
+                    log::debug!("failed to read {:?} properties: {}", address, err);
+                    None
+                })
+                .filter(|value| !value.is_empty())
+        };
+        // The ID is `bus:vendor:product`, e.g. `0005:0000057E:00000306`.
+        let product_id =
+            property("HID_ID").and_then(|id| u16::from_str_radix(id.rsplit(':').next()?, 16).ok());
+        Self {
+            name: property("HID_NAME"),
+            mac_address: property("HID_UNIQ").map(|mac| mac.to_ascii_lowercase()),
+            product_id,
+            address,
+        }
+    }
+
+    /// Returns the address of the device.
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Returns the sysfs path of the device.
+    pub fn syspath(&self) -> &Path {
+        &self.address.0
+    }
+
+    /// Returns the name reported by the device, e.g.
+    /// `Nintendo RVL-CNT-01-TR`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the Bluetooth address of the device, e.g.
+    /// `00:1f:32:aa:bb:cc`.
+    pub fn mac_address(&self) -> Option<&str> {
+        self.mac_address.as_deref()
+    }
+
+    /// Returns the HID product ID reported by the device, e.g. `0x0306`
+    /// for the original Wii Remote.
+    pub fn product_id(&self) -> Option<u16> {
+        self.product_id
+    }
+
+    /// Returns the device type identifier, as [`Device::kind`] would.
+    pub fn kind(&self) -> Result<String> {
+        self.attribute("devtype")
+    }
+
+    /// Returns the current extension type identifier, as
+    /// [`Device::extension`] would.
+    pub fn extension(&self) -> Result<String> {
+        self.attribute("extension")
+    }
+
+    fn attribute(&self, name: &str) -> Result<String> {
+        let value = fs::read_to_string(self.syspath().join(name))?;
+        Ok(value.trim_end().to_string())
+    }
+
+    /// Connects to the device.
+    pub fn connect(&self) -> Result<Device> {
+        Device::connect(&self.address)
+    }
+
+    /// Connects to the device with the given options.
+    pub fn connect_with(&self, options: &ConnectOptions) -> Result<Device> {
+        Device::connect_with(&self.address, options)
+    }
+}
+
+impl From<DiscoveredDevice> for Address {
+    fn from(device: DiscoveredDevice) -> Self {
+        device.address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiscoveredDevice;
+    use crate::Address;
+    use std::fs;
+
+    #[test]
+    fn reads_properties() -> std::io::Result<()> {
+        let syspath = std::env::temp_dir().join(format!("xwiimote-hid-{}", std::process::id()));
+        fs::create_dir_all(&syspath)?;
+        fs::write(
+            syspath.join("uevent"),
+            "DRIVER=wiimote\nHID_ID=0005:0000057E:00000330\n\
+             HID_NAME=Nintendo RVL-CNT-01-UC\nHID_UNIQ=00:1F:32:AA:BB:CC\n",
+        )?;
+        fs::write(syspath.join("devtype"), "gen20\n")?;
+
+        let device = DiscoveredDevice::new(Address::from(syspath.clone()));
+        assert_eq!(device.name(), Some("Nintendo RVL-CNT-01-UC"));
+        assert_eq!(device.mac_address(), Some("00:1f:32:aa:bb:cc"));
+        assert_eq!(device.product_id(), Some(0x0330));
+        assert_eq!(device.kind()?, "gen20");
+        assert_eq!(
+            device.extension().unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+
+        let gone = DiscoveredDevice::new(Address::from(syspath.join("gone")));
+        assert_eq!(gone.name(), None);
+        fs::remove_dir_all(&syspath)
+    }
+}
@@ -0,0 +1,271 @@
+//! Wii Remote input for egui.
+//!
+//! A [`RemoteControl`] turns the events of a remote into egui input:
+//! the IR pointer moves the egui pointer, and the keys of the remote
+//! click or press keyboard keys, as given by a [`RemoteConfig`]. Feed
+//! it the events of the remote, and add its input to the raw input of
+//! each frame, e.g. from `eframe::App::raw_input_hook`:
+//!
+//! ```no_run
+//! # use xwiimote::egui_input::RemoteControl;
+//! # use xwiimote::Device;
+//! # fn hook(device: &mut Device, remote: &mut RemoteControl, raw_input: &mut egui::RawInput)
+//! # -> std::io::Result<()> {
+//! while let Some(event) = device.try_next_event()? {
+//!     remote.update(&event);
+//! }
+//! remote.apply(raw_input);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! See the `egui_remote` example for a complete application. Requires
+//! the `egui` feature.
+use crate::event::{Event, EventKind, Key, KeyState};
+use crate::filter::{OneEuroConfig, PointerFilter};
+use crate::ir::{Pointer, SensorBarConfig};
+use ::egui::{Modifiers, PointerButton, Pos2, RawInput, Rect};
+use std::collections::{HashMap, HashSet};
+
+/// How the pointer and keys of a remote drive egui.
+#[derive(Clone, Debug)]
+pub struct RemoteConfig {
+    /// The layout of the sensor bar.
+    pub sensor_bar: SensorBarConfig,
+    /// The horizontal rotation of the remote, in radians, that sweeps
+    /// the pointer across the width of the screen. The pointer moves by
+    /// the same distance per radian vertically.
+    pub reach: f32,
+    /// The filter of the pointer position, in points, or `None` to move
+    /// the pointer by the raw samples.
+    pub filter: Option<OneEuroConfig>,
+    /// The pointer button clicked by each key.
+    pub buttons: HashMap<Key, PointerButton>,
+    /// The keyboard key pressed by each key.
+    pub keys: HashMap<Key, ::egui::Key>,
+}
+
+impl Default for RemoteConfig {
+    /// A and B click the primary and secondary buttons, the D-pad
+    /// presses the arrow keys, Plus presses Tab and Minus presses
+    /// Escape.
+    fn default() -> Self {
+        let buttons = [
+            (Key::A, PointerButton::Primary),
+            (Key::B, PointerButton::Secondary),
+        ];
+        let keys = [
+            (Key::Left, ::egui::Key::ArrowLeft),
+            (Key::Right, ::egui::Key::ArrowRight),
+            (Key::Up, ::egui::Key::ArrowUp),
+            (Key::Down, ::egui::Key::ArrowDown),
+            (Key::Plus, ::egui::Key::Tab),
+            (Key::Minus, ::egui::Key::Escape),
+        ];
+        Self {
+            sensor_bar: SensorBarConfig::default(),
+            reach: 0.4,
+            filter: Some(OneEuroConfig::default()),
+            buttons: buttons.into_iter().collect(),
+            keys: keys.into_iter().collect(),
+        }
+    }
+}
+
+/// The pointer and key state of a remote, as egui input.
+#[derive(Clone, Debug)]
+pub struct RemoteControl {
+    config: RemoteConfig,
+    pointer: Pointer,
+    filter: Option<PointerFilter>,
+    screen: Rect,
+    // The last pointer position, and whether the sensor bar is visible.
+    position: Pos2,
+    visible: bool,
+    pressed: HashSet<Key>,
+    // The keys whose click was sent, and not yet released.
+    clicked: HashSet<Key>,
+    // The input not yet added to a frame.
+    events: Vec<::egui::Event>,
+}
+
+impl RemoteControl {
+    /// Creates the state of a remote with the given configuration.
+    pub fn new(config: RemoteConfig) -> Self {
+        Self {
+            pointer: Pointer::new(config.sensor_bar),
+            filter: config.filter.map(PointerFilter::new),
+            config,
+            screen: Rect::ZERO,
+            position: Pos2::ZERO,
+            visible: false,
+            pressed: HashSet::new(),
+            clicked: HashSet::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Sets the screen area the pointer moves in, in points. It is also
+    /// updated from the raw input by [`RemoteControl::apply`].
+    pub fn set_screen_rect(&mut self, screen: Rect) {
+        self.screen = screen;
+    }
+
+    /// Returns the position of the pointer, if the sensor bar is visible.
+    pub fn position(&self) -> Option<Pos2> {
+        self.visible.then_some(self.position)
+    }
+
+    /// Checks whether the given key of the remote is held down.
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    /// Updates the state with the given event.
+    pub fn update(&mut self, event: &Event) {
+        match event.kind {
+            EventKind::Ir(_) => self.update_pointer(event),
+            EventKind::Key(key, state) => self.update_key(key, state),
+            _ => {}
+        }
+    }
+
+    fn update_pointer(&mut self, event: &Event) {
+        let sample = match self.pointer.update(event) {
+            Some(sample) => sample,
+            None => {
+                if self.visible {
+                    self.visible = false;
+                    if let Some(filter) = &mut self.filter {
+                        filter.reset();
+                    }
+                    self.events.push(::egui::Event::PointerGone);
+                }
+                return;
+            }
+        };
+        let scale = self.screen.width() / self.config.reach;
+        let center = self.screen.center();
+        let mut position = (
+            center.x - sample.pose.yaw * scale,
+            center.y - sample.pose.pitch * scale,
+        );
+        if let Some(filter) = &mut self.filter {
+            position = filter.filter(position, event.time);
+        }
+        self.position = self.screen.clamp(position.into());
+        self.visible = true;
+        self.events.push(::egui::Event::PointerMoved(self.position));
+    }
+
+    fn update_key(&mut self, key: Key, state: KeyState) {
+        let pressed = state != KeyState::Up;
+        if pressed {
+            self.pressed.insert(key);
+        } else {
+            self.pressed.remove(&key);
+        }
+        if let Some(&button) = self.config.buttons.get(&key) {
+            // Clicks land where the pointer was last seen, and are
+            // ignored while the sensor bar isn't visible.
+            let send = if pressed {
+                self.visible && self.clicked.insert(key)
+            } else {
+                self.clicked.remove(&key)
+            };
+            if send {
+                self.events.push(::egui::Event::PointerButton {
+                    pos: self.position,
+                    button,
+                    pressed,
+                    modifiers: Modifiers::NONE,
+                });
+            }
+        } else if let Some(&key) = self.config.keys.get(&key) {
+            self.events.push(::egui::Event::Key {
+                key,
+                physical_key: None,
+                pressed,
+                repeat: state == KeyState::AutoRepeat,
+                modifiers: Modifiers::NONE,
+            });
+        }
+    }
+
+    /// Adds the input received since the last call to the raw input
+    /// of a frame, and updates the screen area from it.
+    pub fn apply(&mut self, input: &mut RawInput) {
+        if let Some(screen) = input.screen_rect {
+            self.screen = screen;
+        }
+        input.events.append(&mut self.events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RemoteConfig, RemoteControl};
+    use crate::event::{Event, EventKind, IrSource, Key, KeyState};
+    use ::egui::{PointerButton, Pos2, RawInput, Rect};
+    use std::time::SystemTime;
+
+    fn event(kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH, kind)
+    }
+
+    fn ir(sources: [Option<IrSource>; 4]) -> Event {
+        event(EventKind::Ir(sources))
+    }
+
+    #[test]
+    fn feeds_pointer_and_keys() {
+        let config = RemoteConfig {
+            filter: None,
+            ..Default::default()
+        };
+        let mut remote = RemoteControl::new(config);
+        remote.set_screen_rect(Rect::from_min_max(Pos2::ZERO, Pos2::new(800.0, 600.0)));
+
+        // Clicks are ignored until the pointer is on the screen.
+        remote.update(&event(EventKind::Key(Key::A, KeyState::Down)));
+        assert!(remote.is_pressed(Key::A));
+        remote.update(&event(EventKind::Key(Key::A, KeyState::Up)));
+
+        // The sensor bar at the center of the camera.
+        let centered = [
+            Some(IrSource { x: 412, y: 384 }),
+            Some(IrSource { x: 612, y: 384 }),
+            None,
+            None,
+        ];
+        remote.update(&ir(centered));
+        assert_eq!(remote.position(), Some(Pos2::new(400.0, 300.0)));
+        remote.update(&event(EventKind::Key(Key::A, KeyState::Down)));
+        remote.update(&event(EventKind::Key(Key::Up, KeyState::Down)));
+        remote.update(&ir([None; 4]));
+        assert_eq!(remote.position(), None);
+
+        let mut input = RawInput::default();
+        remote.apply(&mut input);
+        let events = &input.events;
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], egui::Event::PointerMoved(_)));
+        assert!(matches!(
+            events[1],
+            egui::Event::PointerButton {
+                button: PointerButton::Primary,
+                pressed: true,
+                ..
+            }
+        ));
+        assert!(matches!(
+            events[2],
+            egui::Event::Key {
+                key: egui::Key::ArrowUp,
+                pressed: true,
+                ..
+            }
+        ));
+        assert!(matches!(events[3], egui::Event::PointerGone));
+    }
+}
@@ -0,0 +1,737 @@
+//! Keyboard and mouse emulation.
+//!
+//! An [`InputBridge`] creates a virtual input device with `uinput`, and
+//! replays the events of a Wii Remote on it, so that the remote drives
+//! any application, e.g. as a presentation remote or a media controller.
+//! A [`KeyMapping`] gives the keyboard key or mouse button emitted for
+//! each remote key, and how the IR pointer moves the mouse. Ready-made
+//! mappings are selected by [`Preset`], and [`pointer_mode`] turns the
+//! remote into a mouse. The [`Accessibility`] options of a mapping make
+//! the remote usable with limited dexterity or tremors.
+//!
+//! Requires the `uinput` feature, and write access to `/dev/uinput`.
+use crate::event::{Event, EventKind, IrSource, Key, KeyState};
+use crate::filter::{OneEuroConfig, PointerFilter};
+use crate::ir::{Pointer, SensorBarConfig, CAMERA_HEIGHT, CAMERA_WIDTH};
+use crate::Result;
+use ::evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use ::evdev::{AttributeSet, EventType, InputEvent, Key as OutputKey, RelativeAxisType};
+use futures::{future, Stream, TryStreamExt};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+/// The name of the virtual input device.
+pub const DEVICE_NAME: &str = "Nintendo Wii Remote Emulated Input";
+
+/// How the IR pointer of a remote moves the mouse.
+#[derive(Copy, Clone, Debug)]
+pub struct PointerMapping {
+    /// The layout of the sensor bar.
+    pub sensor_bar: SensorBarConfig,
+    /// The mouse movement per radian of rotation of the remote, in
+    /// pixels.
+    pub speed: f32,
+    /// The filter of the pointer position, or `None` to move the mouse
+    /// by the raw samples.
+    pub filter: Option<OneEuroConfig>,
+    /// The distance from the edges of the IR camera grid, in pixels,
+    /// within which the sensor bar is ignored.
+    ///
+    /// Near the edges one of its ends may drop in and out of view, and
+    /// the pointer bounces as the bar is lost and found again.
+    pub edge_margin: i32,
+    /// How long the mouse stays still after a mouse button is pressed
+    /// or released, so that the jolt of the remote doesn't drag.
+    pub click_hold: Duration,
+    /// How the Motion Plus moves the mouse while the sensor bar isn't
+    /// visible, or `None` to keep it still.
+    pub gyro: Option<GyroPointing>,
+}
+
+impl Default for PointerMapping {
+    fn default() -> Self {
+        Self {
+            sensor_bar: SensorBarConfig::default(),
+            speed: 2000.0,
+            filter: None,
+            edge_margin: 0,
+            click_hold: Duration::ZERO,
+            gyro: None,
+        }
+    }
+}
+
+/// Pointing with the Motion Plus, as a fallback for the IR pointer.
+///
+/// While the sensor bar isn't visible, the mouse moves at a speed given
+/// by the angular velocity of the remote. The pointer picks up from the
+/// resulting position once the bar is visible again. Requires
+/// [`Channels::MOTION_PLUS`](crate::Channels::MOTION_PLUS) to be open.
+#[derive(Copy, Clone, Debug)]
+pub struct GyroPointing {
+    /// The mouse speed per unit of angular velocity, in pixels per
+    /// second. Negative values invert the axes.
+    pub gain: f32,
+    /// The angular velocities ignored on each axis, so that the mouse
+    /// doesn't drift while the remote is held still.
+    pub dead_zone: i32,
+}
+
+impl Default for GyroPointing {
+    fn default() -> Self {
+        Self {
+            gain: 0.5,
+            dead_zone: 100,
+        }
+    }
+}
+
+/// Accessibility options of a [`KeyMapping`], for users with limited
+/// dexterity or tremors.
+///
+/// The defaults change nothing.
+#[derive(Clone, Debug)]
+pub struct Accessibility {
+    /// The keys that latch: a press holds their output down until the
+    /// next press, so that e.g. a single button drags with the mouse.
+    pub sticky_keys: HashSet<Key>,
+    /// The time after the release of a key during which it can't be
+    /// pressed again, so that unintended repeated presses are ignored.
+    pub debounce: Duration,
+    /// The distance the IR pointer must move from where it last moved
+    /// the mouse, in pixels, before it moves it again, so that tremors
+    /// don't move the mouse.
+    pub tremor_threshold: f32,
+    /// The factor applied to the mouse movement of the pointer, below 1
+    /// to slow it down.
+    pub pointer_gain: f32,
+}
+
+impl Default for Accessibility {
+    fn default() -> Self {
+        Self {
+            sticky_keys: HashSet::new(),
+            debounce: Duration::ZERO,
+            tremor_threshold: 0.0,
+            pointer_gain: 1.0,
+        }
+    }
+}
+
+/// A ready-made [`KeyMapping`].
+///
+/// Presets are identified by a stable [name](Self::name), e.g. to store
+/// the preferred mapping of a remote in its [`Profile`].
+///
+/// [`Profile`]: crate::profile::Profile
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Preset {
+    /// See [`KeyMapping::presentation`].
+    Presentation,
+    /// See [`KeyMapping::media_remote`].
+    MediaRemote,
+    /// See [`KeyMapping::pointer_mode`].
+    Pointer,
+}
+
+impl Preset {
+    /// Every preset.
+    pub const ALL: [Self; 3] = [Self::Presentation, Self::MediaRemote, Self::Pointer];
+
+    /// Returns the name of the preset, e.g. `media-remote`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Presentation => "presentation",
+            Self::MediaRemote => "media-remote",
+            Self::Pointer => "pointer",
+        }
+    }
+
+    /// Returns the preset with the given name, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    /// Returns the mapping of the preset.
+    pub fn mapping(self) -> KeyMapping {
+        match self {
+            Self::Presentation => KeyMapping::presentation(),
+            Self::MediaRemote => KeyMapping::media_remote(),
+            Self::Pointer => KeyMapping::pointer_mode(),
+        }
+    }
+}
+
+/// The output of each input of a remote.
+#[derive(Clone, Debug)]
+pub struct KeyMapping {
+    /// The keyboard key or mouse button (e.g. [`OutputKey::BTN_LEFT`])
+    /// emitted by each key. Keys without a mapping are ignored.
+    pub keys: HashMap<Key, OutputKey>,
+    /// How the IR pointer moves the mouse, or `None` to ignore it.
+    pub pointer: Option<PointerMapping>,
+    /// The accessibility options.
+    pub accessibility: Accessibility,
+}
+
+impl KeyMapping {
+    /// A mapping that ignores every input.
+    pub fn empty() -> Self {
+        Self {
+            keys: HashMap::new(),
+            pointer: None,
+            accessibility: Accessibility::default(),
+        }
+    }
+
+    /// A presentation remote: A and B go to the next and previous
+    /// slides, Plus starts the presentation, Minus ends it, and Home
+    /// blanks the screen. The D-pad emits the arrow keys.
+    pub fn presentation() -> Self {
+        let keys = [
+            (Key::Left, OutputKey::KEY_LEFT),
+            (Key::Right, OutputKey::KEY_RIGHT),
+            (Key::Up, OutputKey::KEY_UP),
+            (Key::Down, OutputKey::KEY_DOWN),
+            (Key::A, OutputKey::KEY_PAGEDOWN),
+            (Key::B, OutputKey::KEY_PAGEUP),
+            (Key::Plus, OutputKey::KEY_F5),
+            (Key::Minus, OutputKey::KEY_ESC),
+            (Key::Home, OutputKey::KEY_B),
+        ];
+        Self {
+            keys: keys.into_iter().collect(),
+            pointer: None,
+            accessibility: Accessibility::default(),
+        }
+    }
+
+    /// A media remote, e.g. for Kodi: A toggles playback, Left and
+    /// Right seek, Up and Down change the volume, and Home opens the
+    /// menu. B goes back, Plus and Minus skip to the next and previous
+    /// track, 1 stops playback and 2 mutes.
+    pub fn media_remote() -> Self {
+        let keys = [
+            (Key::A, OutputKey::KEY_PLAYPAUSE),
+            (Key::Left, OutputKey::KEY_REWIND),
+            (Key::Right, OutputKey::KEY_FASTFORWARD),
+            (Key::Up, OutputKey::KEY_VOLUMEUP),
+            (Key::Down, OutputKey::KEY_VOLUMEDOWN),
+            (Key::Home, OutputKey::KEY_MENU),
+            (Key::B, OutputKey::KEY_BACK),
+            (Key::Plus, OutputKey::KEY_NEXTSONG),
+            (Key::Minus, OutputKey::KEY_PREVIOUSSONG),
+            (Key::One, OutputKey::KEY_STOPCD),
+            (Key::Two, OutputKey::KEY_MUTE),
+        ];
+        Self {
+            keys: keys.into_iter().collect(),
+            pointer: None,
+            accessibility: Accessibility::default(),
+        }
+    }
+
+    /// A mouse: the IR pointer moves it, with filtering, and A and B
+    /// are the left and right buttons. The D-pad emits the arrow keys.
+    pub fn pointer_mode() -> Self {
+        let keys = [
+            (Key::A, OutputKey::BTN_LEFT),
+            (Key::B, OutputKey::BTN_RIGHT),
+            (Key::Left, OutputKey::KEY_LEFT),
+            (Key::Right, OutputKey::KEY_RIGHT),
+            (Key::Up, OutputKey::KEY_UP),
+            (Key::Down, OutputKey::KEY_DOWN),
+        ];
+        Self {
+            keys: keys.into_iter().collect(),
+            pointer: Some(PointerMapping {
+                filter: Some(OneEuroConfig::default()),
+                edge_margin: 32,
+                click_hold: Duration::from_millis(150),
+                ..Default::default()
+            }),
+            accessibility: Accessibility::default(),
+        }
+    }
+
+    /// Returns the keys and buttons that the mapping may emit.
+    fn output_keys(&self) -> AttributeSet<OutputKey> {
+        let mut keys = AttributeSet::new();
+        for key in self.keys.values() {
+            keys.insert(*key);
+        }
+        keys
+    }
+}
+
+impl Default for KeyMapping {
+    /// The [presentation](Self::presentation) mapping.
+    fn default() -> Self {
+        Self::presentation()
+    }
+}
+
+/// Translates the events of a remote into input events, according to
+/// a [`KeyMapping`].
+///
+/// This is the part of an [`InputBridge`] that doesn't need `uinput`.
+#[derive(Clone, Debug)]
+pub struct Translator {
+    mapping: KeyMapping,
+    pointer: Option<Pointer>,
+    filter: Option<PointerFilter>,
+    // The mouse position of the previous pointer sample.
+    last: Option<(f32, f32)>,
+    // The mouse movement not emitted yet, being less than a pixel.
+    remainder: (f32, f32),
+    // The end of the pause of the mouse after a click.
+    hold_until: Option<SystemTime>,
+    // The time of the previous Motion Plus event.
+    gyro_time: Option<SystemTime>,
+    // The sticky keys whose output is held down.
+    latched: HashSet<Key>,
+    // The time each key was last released, and the keys whose current
+    // press is ignored by the debounce.
+    released: HashMap<Key, SystemTime>,
+    bouncing: HashSet<Key>,
+}
+
+impl Translator {
+    /// Creates a translator with the given mapping.
+    pub fn new(mapping: KeyMapping) -> Self {
+        Self {
+            pointer: mapping
+                .pointer
+                .map(|pointer| Pointer::new(pointer.sensor_bar)),
+            filter: mapping
+                .pointer
+                .and_then(|pointer| pointer.filter)
+                .map(PointerFilter::new),
+            mapping,
+            last: None,
+            remainder: (0.0, 0.0),
+            hold_until: None,
+            gyro_time: None,
+            latched: HashSet::new(),
+            released: HashMap::new(),
+            bouncing: HashSet::new(),
+        }
+    }
+
+    /// Returns the mapping.
+    pub fn mapping(&self) -> &KeyMapping {
+        &self.mapping
+    }
+
+    /// Returns the input events for the given event, without the final
+    /// synchronization event. Empty if the event is not mapped.
+    pub fn update(&mut self, event: &Event) -> Vec<InputEvent> {
+        match event.kind {
+            EventKind::Key(key, state) => match self.mapping.keys.get(&key).copied() {
+                Some(output) => {
+                    let state = match self.filter_key(key, state, event.time) {
+                        Some(state) => state,
+                        None => return Vec::new(),
+                    };
+                    if is_mouse_button(output) && state != KeyState::AutoRepeat {
+                        let hold = self
+                            .mapping
+                            .pointer
+                            .map_or(Duration::ZERO, |p| p.click_hold);
+                        self.hold_until = Some(event.time + hold);
+                    }
+                    let value = match state {
+                        KeyState::Up => 0,
+                        KeyState::Down => 1,
+                        KeyState::AutoRepeat => 2,
+                    };
+                    vec![InputEvent::new(EventType::KEY, output.code(), value)]
+                }
+                None => Vec::new(),
+            },
+            EventKind::Ir(_) => self.update_pointer(event),
+            EventKind::MotionPlus { x, z, .. } => self.update_gyro(x, z, event.time),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Applies the debounce and sticky keys to a key transition, and
+    /// returns the state to emit, if any.
+    fn filter_key(&mut self, key: Key, state: KeyState, time: SystemTime) -> Option<KeyState> {
+        let options = &self.mapping.accessibility;
+        if state == KeyState::Up {
+            self.released.insert(key, time);
+        }
+        if state == KeyState::Down {
+            let bounced = self.released.get(&key).is_some_and(|&released| {
+                time.duration_since(released)
+                    .is_ok_and(|since| since < options.debounce)
+            });
+            if bounced {
+                self.bouncing.insert(key);
+            }
+        }
+        if self.bouncing.contains(&key) {
+            if state == KeyState::Up {
+                self.bouncing.remove(&key);
+            }
+            return None;
+        }
+        if !options.sticky_keys.contains(&key) {
+            return Some(state);
+        }
+        match state {
+            KeyState::Down if self.latched.remove(&key) => Some(KeyState::Up),
+            KeyState::Down => {
+                self.latched.insert(key);
+                Some(KeyState::Down)
+            }
+            _ => None,
+        }
+    }
+
+    fn update_pointer(&mut self, event: &Event) -> Vec<InputEvent> {
+        let (pointer, mapping) = match (&mut self.pointer, &self.mapping.pointer) {
+            (Some(pointer), Some(mapping)) => (pointer, mapping),
+            _ => return Vec::new(),
+        };
+        let sample = pointer.update(event).filter(|sample| {
+            let margin = mapping.edge_margin;
+            !near_edge(sample.bar.left, margin) && !near_edge(sample.bar.right, margin)
+        });
+        let pose = match sample {
+            Some(sample) => sample.pose,
+            None => {
+                // Don't jump once the sensor bar is visible again.
+                self.last = None;
+                if let Some(filter) = &mut self.filter {
+                    filter.reset();
+                }
+                return Vec::new();
+            }
+        };
+        // Turning the remote to the right moves the sensor bar to the left
+        // of the camera axis, and up moves it below.
+        let mut position = (-pose.yaw * mapping.speed, -pose.pitch * mapping.speed);
+        if let Some(filter) = &mut self.filter {
+            position = filter.filter(position, pose.time);
+        }
+        let last = match self.last.replace(position) {
+            Some(last) => last,
+            None => return Vec::new(),
+        };
+        let (dx, dy) = (position.0 - last.0, position.1 - last.1);
+        let options = &self.mapping.accessibility;
+        if dx.hypot(dy) * options.pointer_gain < options.tremor_threshold {
+            // Keep measuring from where the mouse last moved.
+            self.last = Some(last);
+            return Vec::new();
+        }
+        self.motion(dx, dy, event.time)
+    }
+
+    fn update_gyro(&mut self, x: i32, z: i32, time: SystemTime) -> Vec<InputEvent> {
+        let elapsed = self
+            .gyro_time
+            .replace(time)
+            .and_then(|last| time.duration_since(last).ok());
+        let gyro = match self.mapping.pointer.and_then(|pointer| pointer.gyro) {
+            Some(gyro) => gyro,
+            None => return Vec::new(),
+        };
+        let elapsed = match elapsed {
+            // The IR pointer moves the mouse while the bar is visible.
+            Some(elapsed) if self.last.is_none() => elapsed.as_secs_f32(),
+            _ => return Vec::new(),
+        };
+        let rate = |value: i32| match value.abs() > gyro.dead_zone {
+            true => value as f32,
+            false => 0.0,
+        };
+        // Turning the remote to the right is a negative rotation around
+        // the z-axis, and up a positive rotation around the x-axis.
+        let scale = gyro.gain * elapsed;
+        self.motion(-rate(z) * scale, -rate(x) * scale, time)
+    }
+
+    /// Returns the events that move the mouse by the given amount, in
+    /// pixels and scaled by the pointer gain, unless it is held after a
+    /// click.
+    fn motion(&mut self, dx: f32, dy: f32, time: SystemTime) -> Vec<InputEvent> {
+        if self.hold_until.is_some_and(|until| time < until) {
+            self.remainder = (0.0, 0.0);
+            return Vec::new();
+        }
+        let gain = self.mapping.accessibility.pointer_gain;
+        let dx = self.remainder.0 + dx * gain;
+        let dy = self.remainder.1 + dy * gain;
+        let (x, y) = (dx.trunc(), dy.trunc());
+        self.remainder = (dx - x, dy - y);
+
+        let mut events = Vec::new();
+        if x != 0.0 {
+            let code = RelativeAxisType::REL_X.0;
+            events.push(InputEvent::new(EventType::RELATIVE, code, x as i32));
+        }
+        if y != 0.0 {
+            let code = RelativeAxisType::REL_Y.0;
+            events.push(InputEvent::new(EventType::RELATIVE, code, y as i32));
+        }
+        events
+    }
+}
+
+/// Checks whether the key is a mouse button.
+fn is_mouse_button(key: OutputKey) -> bool {
+    (OutputKey::BTN_LEFT.code()..=OutputKey::BTN_TASK.code()).contains(&key.code())
+}
+
+/// Checks whether the source is within `margin` pixels of the edges of
+/// the IR camera grid.
+fn near_edge(source: IrSource, margin: i32) -> bool {
+    source.x < margin
+        || source.y < margin
+        || source.x >= CAMERA_WIDTH - margin
+        || source.y >= CAMERA_HEIGHT - margin
+}
+
+/// A virtual keyboard and mouse driven by the events of a remote.
+pub struct InputBridge {
+    device: VirtualDevice,
+    translator: Translator,
+}
+
+impl InputBridge {
+    /// Creates a virtual input device that emits the keys and buttons of
+    /// the given mapping, and relative motion if it maps the pointer.
+    pub fn new(mapping: KeyMapping) -> Result<Self> {
+        let mut builder = VirtualDeviceBuilder::new()?
+            .name(DEVICE_NAME)
+            .with_keys(&mapping.output_keys())?;
+        if mapping.pointer.is_some() {
+            let mut axes = AttributeSet::new();
+            axes.insert(RelativeAxisType::REL_X);
+            axes.insert(RelativeAxisType::REL_Y);
+            builder = builder.with_relative_axes(&axes)?;
+        }
+        Ok(Self {
+            device: builder.build()?,
+            translator: Translator::new(mapping),
+        })
+    }
+
+    /// Returns the mapping.
+    pub fn mapping(&self) -> &KeyMapping {
+        self.translator.mapping()
+    }
+
+    /// Emits the input events for the given event, if it is mapped.
+    pub fn handle(&mut self, event: &Event) -> Result<()> {
+        let events = self.translator.update(event);
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.device.emit(&events)
+    }
+}
+
+/// Creates a virtual mouse driven by the IR pointer, with the
+/// [`KeyMapping::pointer_mode`] mapping.
+pub fn pointer_mode() -> Result<InputBridge> {
+    InputBridge::new(KeyMapping::pointer_mode())
+}
+
+/// Adapts a stream of events into a stream that replays them on the
+/// given bridge, and yields them unchanged.
+pub fn bridged<S>(events: S, mut bridge: InputBridge) -> impl Stream<Item = Result<Event>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.and_then(move |event| future::ready(bridge.handle(&event).map(|()| event)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Accessibility, GyroPointing, KeyMapping, PointerMapping, Preset, Translator};
+    use crate::event::{Event, EventKind, IrSource, Key, KeyState};
+    use ::evdev::{EventType, InputEvent, Key as OutputKey, RelativeAxisType};
+    use std::time::{Duration, SystemTime};
+
+    fn event(kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH, kind)
+    }
+
+    fn ir(x: i32) -> Event {
+        let source = |x| Some(IrSource { x, y: 384 });
+        event(EventKind::Ir([
+            source(x - 100),
+            source(x + 100),
+            None,
+            None,
+        ]))
+    }
+
+    fn at(millis: u64, event: Event) -> Event {
+        Event {
+            time: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+            ..event
+        }
+    }
+
+    fn parts(events: Vec<InputEvent>) -> Vec<(EventType, u16, i32)> {
+        events
+            .iter()
+            .map(|event| (event.event_type(), event.code(), event.value()))
+            .collect()
+    }
+
+    #[test]
+    fn translates_keys() {
+        let mut translator = Translator::new(KeyMapping::presentation());
+        let down = translator.update(&event(EventKind::Key(Key::A, KeyState::Down)));
+        let code = OutputKey::KEY_PAGEDOWN.code();
+        assert_eq!(parts(down), [(EventType::KEY, code, 1)]);
+        let up = translator.update(&event(EventKind::Key(Key::A, KeyState::Up)));
+        assert_eq!(parts(up), [(EventType::KEY, code, 0)]);
+        assert!(translator
+            .update(&event(EventKind::Key(Key::One, KeyState::Down)))
+            .is_empty());
+    }
+
+    #[test]
+    fn selects_presets_by_name() {
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(Preset::from_name("kodi"), None);
+
+        let mapping = Preset::MediaRemote.mapping();
+        assert_eq!(mapping.keys[&Key::A], OutputKey::KEY_PLAYPAUSE);
+        assert_eq!(mapping.keys[&Key::Home], OutputKey::KEY_MENU);
+    }
+
+    #[test]
+    fn translates_pointer() {
+        let mut translator = Translator::new(KeyMapping {
+            pointer: Some(PointerMapping::default()),
+            ..KeyMapping::empty()
+        });
+        assert!(translator.update(&ir(512)).is_empty());
+        // The sensor bar moves to the left as the remote turns right.
+        let events = parts(translator.update(&ir(412)));
+        assert_eq!(events.len(), 1);
+        let (kind, code, value) = events[0];
+        assert_eq!(
+            (kind, code),
+            (EventType::RELATIVE, RelativeAxisType::REL_X.0)
+        );
+        assert!(value > 0);
+    }
+
+    #[test]
+    fn holds_pointer_after_clicks() {
+        let mut mapping = KeyMapping::pointer_mode();
+        mapping.pointer.as_mut().unwrap().filter = None;
+        let mut translator = Translator::new(mapping);
+        translator.update(&at(0, ir(512)));
+        let click = translator.update(&at(10, event(EventKind::Key(Key::A, KeyState::Down))));
+        let code = OutputKey::BTN_LEFT.code();
+        assert_eq!(parts(click), [(EventType::KEY, code, 1)]);
+        assert!(translator.update(&at(20, ir(500))).is_empty());
+        assert!(!translator.update(&at(200, ir(490))).is_empty());
+    }
+
+    #[test]
+    fn ignores_sensor_bar_near_edges() {
+        let mut translator = Translator::new(KeyMapping::pointer_mode());
+        translator.update(&at(0, ir(512)));
+        assert!(translator.update(&at(10, ir(110))).is_empty());
+        // The bar is found again, but the pointer doesn't jump.
+        assert!(translator.update(&at(20, ir(500))).is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_gyro_pointing() {
+        let mut mapping = KeyMapping::pointer_mode();
+        let pointer = mapping.pointer.as_mut().unwrap();
+        pointer.filter = None;
+        pointer.gyro = Some(GyroPointing::default());
+        let mut translator = Translator::new(mapping);
+        let gyro = |z| EventKind::MotionPlus { x: 0, y: 0, z };
+
+        translator.update(&at(0, ir(512)));
+        translator.update(&at(0, event(gyro(0))));
+        // The IR pointer takes precedence while the bar is visible.
+        assert!(translator.update(&at(10, event(gyro(-2000)))).is_empty());
+
+        translator.update(&at(20, event(EventKind::Ir([None; 4]))));
+        let events = parts(translator.update(&at(30, event(gyro(-2000)))));
+        let code = RelativeAxisType::REL_X.0;
+        assert_eq!(events, [(EventType::RELATIVE, code, 20)]);
+        assert!(translator.update(&at(40, event(gyro(50)))).is_empty());
+
+        // The pointer picks up from there once the bar is visible again.
+        assert!(translator.update(&at(50, ir(300))).is_empty());
+    }
+
+    #[test]
+    fn latches_sticky_keys_and_debounces() {
+        let mut mapping = KeyMapping::pointer_mode();
+        mapping.accessibility = Accessibility {
+            sticky_keys: [Key::A].into_iter().collect(),
+            debounce: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let mut translator = Translator::new(mapping);
+        let mut key = |millis, key, state| {
+            let event = at(millis, event(EventKind::Key(key, state)));
+            parts(translator.update(&event))
+        };
+        let left = OutputKey::BTN_LEFT.code();
+        assert_eq!(key(0, Key::A, KeyState::Down), [(EventType::KEY, left, 1)]);
+        assert!(key(50, Key::A, KeyState::Up).is_empty());
+        // Pressed again too soon.
+        assert!(key(100, Key::A, KeyState::Down).is_empty());
+        assert!(key(120, Key::A, KeyState::Up).is_empty());
+        assert_eq!(
+            key(300, Key::A, KeyState::Down),
+            [(EventType::KEY, left, 0)]
+        );
+
+        let right = OutputKey::KEY_RIGHT.code();
+        assert_eq!(
+            key(300, Key::Right, KeyState::Down),
+            [(EventType::KEY, right, 1)]
+        );
+        assert_eq!(
+            key(350, Key::Right, KeyState::Up),
+            [(EventType::KEY, right, 0)]
+        );
+    }
+
+    #[test]
+    fn slows_pointer_and_ignores_tremors() {
+        let mut translator = Translator::new(KeyMapping {
+            pointer: Some(PointerMapping::default()),
+            accessibility: Accessibility {
+                tremor_threshold: 5.0,
+                pointer_gain: 0.5,
+                ..Default::default()
+            },
+            ..KeyMapping::empty()
+        });
+        translator.update(&ir(512));
+        assert!(translator.update(&ir(510)).is_empty());
+        assert!(translator.update(&ir(514)).is_empty());
+        let slow = parts(translator.update(&ir(412)));
+
+        let mut translator = Translator::new(KeyMapping {
+            pointer: Some(PointerMapping::default()),
+            ..KeyMapping::empty()
+        });
+        translator.update(&ir(512));
+        let fast = parts(translator.update(&ir(412)));
+        assert_eq!(slow.len(), 1);
+        assert!((slow[0].2 - fast[0].2 / 2).abs() <= 1);
+    }
+}
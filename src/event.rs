@@ -1,175 +1,29 @@
-#[cfg(doc)]
-use crate::Channels;
+use crate::extension;
+use crate::sys;
+use crate::timer::Timer;
+use crate::types::{DRUMS_PADS, MAX_IR_SOURCES};
 use crate::IoBlocker;
-use crate::{Device, Result};
+use crate::{Channels, Device, Result};
+use futures::task::AtomicWaker;
 use futures::Stream;
-use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+use std::collections::VecDeque;
 use std::pin::Pin;
-use std::task::{Context, Poll};
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
 use std::{io, mem};
 
-// Keys
-
-// We provide a key enumeration for each controller and extension type.
-// To avoid repetition, we use a macro to define the common key variants.
-// A matrix of the buttons reported by each device is given in `BUTTONS.md`.
-// This macro definition uses the TT munching technique.
-macro_rules! key_enum {
-    ($doc:expr, $name:ident {$($body:tt)*} ($variant:expr) $($tail:tt)*) => {
-        inner_key_enum! {
-            $doc:expr,
-            $name {
-                $($body)*  // Previously-built variants.
-                $variant,
-            }
-        }
-        $($tail)* // Unprocessed variants.
-    };
-    // There are no more variants, emit the enum definition.
-    ($doc:expr, $name:ident {$($body:tt)*}) => {
-        #[derive(Copy, Clone, Debug, FromPrimitive)]
-        #[doc = $doc]
-        pub enum $name {
-            /// Plus (+) button.
-            Plus = 6,
-            /// Minus (-) button.
-            Minus = 7,
-            $($body)*
-        }
-    };
-}
+pub use crate::types::{
+    fmt_table, AxisId, ClassicControllerKey, DrumsKey, DrumsPad, Event, EventKind, GuitarKey,
+    IrSource, Key, KeyCode, KeyState, NunchukKey, ProControllerKey, WatchEvent, WatchKind,
+};
 
-macro_rules! regular_controller_key_enum {
-    ($doc:expr, $name:ident {$($body:tt)*}) => {
-        key_enum!{
-            $doc,
-            $name {
-                /// Left directional pad button.
-                Left = 0,
-                /// Right directional pad button.
-                Right = 1,
-                /// Up directional pad button.
-                Up = 2,
-                /// Down directional pad button.
-                Down = 3,
-                /// A button.
-                A = 4,
-                /// B button.
-                B = 5,
-                /// Home button.
-                Home = 8,
-                $($body)*
-            }
-        }
-    };
-}
+// Event parsing
 
-macro_rules! gamepad_key_enum {
-    ($doc:expr, $name:ident {$($body:tt)*}) => {
-        regular_controller_key_enum!{
-            $doc,
-            $name {
-                /// Joystick X-axis.
-                X = 11,
-                /// Joystick Y-axis.
-                Y = 12,
-                /// TL button.
-                TL = 13,
-                /// TR button.
-                TR = 14,
-                /// ZL button.
-                ZL = 15,
-                /// ZR button.
-                ZR = 16,
-                $($body)*
-            }
-        }
-    };
-}
-
-regular_controller_key_enum!(
-    "The keys of a Wii Remote",
-    Key {
-        /// 1 button.
-        One = 9,
-        /// 2 button.
-        Two = 10
-    }
-);
-
-gamepad_key_enum!(
-    "The keys of a Wii U Pro controller",
-    ProControllerKey {
-        /// Left thumb button.
-        ///
-        /// Reported if the left analog stick is pressed.
-        LeftThumb = 17,
-        /// Right thumb button.
-        ///
-        /// Reported if the right analog stick is pressed.
-        RightThumb = 18,
-    }
-);
-
-gamepad_key_enum!("The keys of a Classic controller", ClassicControllerKey {});
-
-/// The keys of a Nunchuk.
-// This is the only extension that doesn't have the + and - buttons.
-#[derive(Copy, Clone, Debug, FromPrimitive)]
-pub enum NunchukKey {
-    /// C button.
-    C = 19,
-    /// Z button.
-    Z = 20,
-}
-
-key_enum!("The keys of a drums controller.", DrumsKey {});
-
-key_enum!("The keys of a guitar controller.",
-    GuitarKey {
-        /// The StarPower/Home button.
-        StarPower = 8, // same as Key::Home
-        /// The guitar strum bar.
-        StrumBar = 21, // also 22
-        /// The guitar upper-most fret button.
-        HighestFretBar = 23,
-        /// The guitar second-upper fret button.
-        HighFretBar = 24,
-        /// The guitar mid fret button.
-        MidFretBar = 25,
-        /// The guitar second-lowest fret button.
-        LowFretBar = 26,
-        /// The guitar lowest fret button.
-        LowestFretBar = 27,
-    }
-);
-
-/// The state of a key.
-#[derive(Copy, Clone, Debug, FromPrimitive)]
-pub enum KeyState {
-    /// The key is released.
-    Up = 0,
-    /// The key is held down.
-    Down,
-    /// The key is [held down](`Self::Down`), and was reported as so in
-    /// the previous event for the same key.
-    AutoRepeat,
-}
-
-// Event kinds
-
-const MAX_IR_SOURCES: usize = 4;
-
-/// An IR source detected by the IR camera, as reported in [`EventKind::Ir`].
-#[derive(Copy, Clone, Debug)]
-pub struct IrSource {
-    /// The x-axis position.
-    pub x: i32,
-    /// The y-axis position.
-    pub y: i32,
-}
+// The pads follow the analog stick in the payload of drums events.
+const _: () = assert!(xwiimote_sys::DRUMS_ABS_NUM as usize == DRUMS_PADS + 1);
 
 impl IrSource {
     /// Parses the IR source data from the given event.
@@ -189,170 +43,19 @@ impl IrSource {
     }
 }
 
-/// The type of an [`Event`], including its associated data.
-#[non_exhaustive]
-#[derive(Copy, Clone, Debug)]
-pub enum EventKind {
-    /// The state of a Wii Remote controller key changed.
-    ///
-    /// Received only if [`Channels::CORE`] is open.
-    Key(Key, KeyState),
-    /// Provides the accelerometer data.
-    ///
-    /// Received only if [`Channels::ACCELEROMETER`] is open.
-    Accelerometer {
-        /// The x-axis acceleration.
-        x: i32,
-        /// The y-axis acceleration.
-        y: i32,
-        /// The z-axis acceleration.
-        z: i32,
-    },
-    /// Provides the IR camera data.
-    ///
-    /// The camera can track up to four IR sources. The index
-    /// of each source within the array is maintained across
-    /// events.
-    ///
-    /// Received only if [`Channels::IR`] is open.
-    Ir([Option<IrSource>; MAX_IR_SOURCES]),
-    /// Provides Balance Board weight data. Four sensors report
-    /// data for each of the edges of the board.
-    ///
-    /// Received only if [`Channels::BALANCE_BOARD`] is open.
-    BalanceBoard([i32; 4]),
-    /// Provides the Motion Plus extension gyroscope data.
-    ///
-    /// Received only if [`Channels::MOTION_PLUS`] is open.
-    MotionPlus {
-        /// The x-axis rotational speed.
-        x: i32,
-        /// The y-axis rotational speed.
-        y: i32,
-        /// The z-axis rotational speed.
-        z: i32,
-    },
-    /// The state of a Wii U Pro controller key changed.
-    ///
-    /// Received only if [`Channels::PRO_CONTROLLER`] is open.
-    ProControllerKey(ProControllerKey, KeyState),
-    /// Reports the movement of an analog stick from
-    /// a Wii U Pro controller.
-    ///
-    /// Received only if [`Channels::PRO_CONTROLLER`] is open.
-    ProControllerMove {
-        /// The left analog stick absolute x-axis position.
-        left_x: i32,
-        /// The left analog stick absolute y-axis position.
-        left_y: i32,
-        /// The right analog stick absolute x-axis position.
-        right_x: i32,
-        /// The right analog stick absolute y-axis position.
-        right_y: i32,
-    },
-    /// An extension was plugged or unplugged, or some other static
-    /// data that cannot be monitored separately changed.
-    ///
-    /// No payload is provided, hence the application should check
-    /// what changed by examining the [`Device`] manually.
-    Other,
-    /// The state of a Classic controller key changed.
-    ///
-    /// Received only if [`Channels::CLASSIC_CONTROLLER`] is open.
-    ClassicControllerKey(ClassicControllerKey, KeyState),
-    /// Reports the movement of an analog stick from
-    /// a Classic controller.
-    ///
-    /// Received only if [`Channels::CLASSIC_CONTROLLER`] is open.
-    ClassicControllerMove {
-        /// The left analog stick x-axis absolute position.
-        left_x: i32,
-        /// The left analog stick y-axis absolute position.
-        left_y: i32,
-        /// The right analog stick x-axis absolute position.
-        right_x: i32,
-        /// The right analog stick y-axis absolute position.
-        right_y: i32,
-        /// The TL trigger absolute position, ranging from 0 to 63.
-        ///
-        /// Many controller do not have analog controllers, in
-        /// which case this value is either 0 or 63.
-        left_trigger: u8,
-        /// The TR trigger absolute position, ranging from 0 to 63.
-        ///
-        /// Many controller do not have analog controllers, in
-        /// which case this value is either 0 or 63.
-        right_trigger: u8,
-    },
-    /// The state of a Nunchuk key changed.
-    ///
-    /// Received only if [`Channels::NUNCHUK`] is open.
-    NunchukKey(NunchukKey, KeyState),
-    /// Reports the movement of an analog stick from a Nunchuk.
-    ///
-    /// Received only if [`Channels::NUNCHUK`] is open.
-    NunchukMove {
-        /// The x-axis absolute position.
-        x: i32,
-        /// The y-axis absolute position.
-        y: i32,
-        /// The x-axis acceleration.
-        x_acceleration: i32,
-        /// The y-axis acceleration.
-        y_acceleration: i32,
-    },
-    /// The state of a drums controller key changed.
-    ///
-    /// Received only if [`Channels::DRUMS`] is open.
-    DrumsKey(DrumsKey, KeyState),
-    /// Reports the movement of an analog stick from a
-    /// drums controller.
-    ///
-    /// Received only if [`Channels::DRUMS`] is open.
-    // todo: figure out how many drums, and how to report pressure.
-    DrumsMove {},
-    /// The state of a guitar controller key changed.
-    ///
-    /// Received only if [`Channels::GUITAR`] is open.
-    GuitarKey(GuitarKey, KeyState),
-    /// Reports the movement of an analog stick, the whammy bar,
-    /// or the fret bar from a guitar controller.
-    ///
-    /// Received only if [`Channels::GUITAR`] is open.
-    GuitarMove {
-        /// The x-axis analog stick position.
-        x: i32,
-        /// The y-axis analog stick position.
-        y: i32,
-        /// The whammy bar position.
-        whammy_bar: i32,
-        /// The fret bar absolute position.
-        fret_bar: i32,
-    },
-}
-
-/// An event received from an open channel to a [`Device`].
-#[derive(Copy, Clone, Debug)]
-pub struct Event {
-    /// The time at which the kernel generated the event.
-    pub time: SystemTime,
-    /// The event type.
-    pub kind: EventKind,
-}
-
 impl Event {
     /// Parses the event.
     ///
     /// # Safety
     /// Assumes that `raw` is an object returned by [`xwiimote_sys::event_dispatch`].
-    unsafe fn parse(raw: &xwiimote_sys::event) -> Self {
+    unsafe fn parse(raw: &xwiimote_sys::event) -> Result<Self> {
         // Rust does not provide a way to create a `SystemTime` directly.
         let since_epoch = Duration::new(raw.time.tv_sec as u64, raw.time.tv_usec as u32 * 1000);
         let time = SystemTime::UNIX_EPOCH + since_epoch;
 
         let kind = match raw.type_ {
             xwiimote_sys::EVENT_KEY => {
-                let (key, state) = Self::parse_key(raw);
+                let (key, state) = Self::parse_key(raw)?;
                 EventKind::Key(key, state)
             }
             xwiimote_sys::EVENT_ACCEL => {
@@ -377,7 +80,7 @@ impl Event {
                 }
             }
             xwiimote_sys::EVENT_PRO_CONTROLLER_KEY => {
-                let (key, state) = Self::parse_key(raw);
+                let (key, state) = Self::parse_key(raw)?;
                 EventKind::ProControllerKey(key, state)
             }
             xwiimote_sys::EVENT_PRO_CONTROLLER_MOVE => {
@@ -389,60 +92,143 @@ impl Event {
                     right_y: pos[1].y,
                 }
             }
-            xwiimote_sys::EVENT_WATCH => EventKind::Other,
+            // The available channels are filled in by the `EventStream`.
+            xwiimote_sys::EVENT_WATCH => EventKind::Other(WatchEvent {
+                available_before: Channels::empty(),
+                available_after: Channels::empty(),
+            }),
             xwiimote_sys::EVENT_CLASSIC_CONTROLLER_KEY => {
-                let (key, state) = Self::parse_key(raw);
+                let (key, state) = Self::parse_key(raw)?;
                 EventKind::ClassicControllerKey(key, state)
             }
-            xwiimote_sys::EVENT_CLASSIC_CONTROLLER_MOVE => {
-                let pos = raw.v.abs;
-                EventKind::ClassicControllerMove {
-                    left_x: pos[0].x,
-                    left_y: pos[0].y,
-                    right_x: pos[1].x,
-                    right_y: pos[1].y,
-                    left_trigger: pos[2].x as u8,
-                    right_trigger: pos[2].y as u8,
-                }
-            }
             xwiimote_sys::EVENT_NUNCHUK_KEY => {
-                let (key, state) = Self::parse_key(raw);
+                let (key, state) = Self::parse_key(raw)?;
                 EventKind::NunchukKey(key, state)
             }
-            xwiimote_sys::EVENT_NUNCHUK_MOVE => {
-                let values = raw.v.abs;
-                EventKind::NunchukMove {
-                    x: values[0].x,
-                    y: values[0].y,
-                    x_acceleration: values[1].x,
-                    y_acceleration: values[1].y,
-                }
-            }
             xwiimote_sys::EVENT_DRUMS_KEY => {
-                let (key, state) = Self::parse_key(raw);
+                let (key, state) = Self::parse_key(raw)?;
                 EventKind::DrumsKey(key, state)
             }
-            xwiimote_sys::EVENT_DRUMS_MOVE => todo!(),
+            xwiimote_sys::EVENT_DRUMS_MOVE => {
+                let values = raw.v.abs;
+                let stick = values[xwiimote_sys::DRUMS_ABS_PAD as usize];
+                // The pads are in the order of `DrumsPad`.
+                let pads = &values[xwiimote_sys::DRUMS_ABS_CYMBAL_LEFT as usize..];
+                let mut velocities = [0; DRUMS_PADS];
+                for (velocity, pad) in velocities.iter_mut().zip(pads) {
+                    *velocity = pad.x.clamp(0, u8::MAX.into()) as u8;
+                }
+                EventKind::DrumsMove {
+                    x: stick.x,
+                    y: stick.y,
+                    velocities,
+                }
+            }
             xwiimote_sys::EVENT_GUITAR_KEY => {
-                let (key, state) = Self::parse_key(raw);
+                let (key, state) = Self::parse_key(raw)?;
                 EventKind::GuitarKey(key, state)
             }
-            xwiimote_sys::EVENT_GONE => panic!("unexpected removal event"), // handled by `EventStream`
-            type_id => panic!("unexpected event type {}", type_id),
+            xwiimote_sys::EVENT_GONE => EventKind::Disconnected,
+            // The movements of the extensions are decoded by their drivers.
+            type_id => {
+                let axes = raw.v.abs.map(|abs| (abs.x, abs.y));
+                extension::parse_builtin(type_id, &axes)
+                    .ok_or_else(|| invalid_event(format!("unexpected event type {}", type_id)))?
+            }
         };
-        Event { time, kind }
+        let key_code = match kind {
+            EventKind::Key(..)
+            | EventKind::ProControllerKey(..)
+            | EventKind::ClassicControllerKey(..)
+            | EventKind::NunchukKey(..)
+            | EventKind::DrumsKey(..)
+            | EventKind::GuitarKey(..) => Some(raw.v.key.code),
+            _ => None,
+        };
+        Ok(Event {
+            time,
+            kind,
+            key_code,
+            sequence: None,
+        })
+    }
+
+    /// Reads the next event of the device into `raw` and parses it,
+    /// without blocking. Returns `None` if no event is available.
+    pub(crate) fn dispatch(device: &Device, raw: &mut xwiimote_sys::event) -> Result<Option<Self>> {
+        // The number of invalid events skipped per call in clone-friendly
+        // mode, after which the last error is returned.
+        const MAX_SKIPPED: usize = 64;
+        const PENDING: libc::c_int = -libc::EAGAIN;
+        let mut skipped = 0;
+        loop {
+            let res_code = unsafe {
+                sys::iface_dispatch(device.handle, raw, mem::size_of::<xwiimote_sys::event>())
+            };
+            return match res_code {
+                0 => match unsafe { Self::parse(raw) } {
+                    Ok(event) => Ok(Some(event)),
+                    // Clones may report keys unknown to the kernel driver.
+                    Err(err) if device.clone_friendly && skipped < MAX_SKIPPED => {
+                        log::debug!("skipping invalid event: {}", err);
+                        skipped += 1;
+                        continue;
+                    }
+                    Err(err) => Err(err),
+                },
+                PENDING => Ok(None),
+                _ => Err(io::Error::last_os_error()),
+            };
+        }
+    }
+
+    /// Fills in the channels of a watch event, given those available as
+    /// of the previous one, which are then updated.
+    pub(crate) fn fill_watch(&mut self, available: &mut Channels, device: &Device) {
+        if let EventKind::Other(watch) = &mut self.kind {
+            watch.available_before = *available;
+            watch.available_after = device.available();
+            *available = watch.available_after;
+        }
     }
 
-    unsafe fn parse_key<T: FromPrimitive>(raw: &xwiimote_sys::event) -> (T, KeyState) {
+    /// Returns a [`EventKind::ChannelClosed`] event if any of the channels
+    /// open as of the previous call were closed since, updating `opened`.
+    pub(crate) fn closed(opened: &mut Channels, device: &Device, time: SystemTime) -> Option<Self> {
+        let now = device.all_open();
+        let closed = *opened - now;
+        *opened = now;
+        (!closed.is_empty()).then_some(Event {
+            time,
+            kind: EventKind::ChannelClosed(closed),
+            key_code: None,
+            sequence: None,
+        })
+    }
+
+    /// Parses the raw event, for use by benchmarks.
+    ///
+    /// # Safety
+    /// Same as [`Event::parse`].
+    #[doc(hidden)]
+    pub unsafe fn from_raw(raw: &xwiimote_sys::event) -> Self {
+        Self::parse(raw).expect("invalid event")
+    }
+
+    unsafe fn parse_key<T: KeyCode>(raw: &xwiimote_sys::event) -> Result<(T, KeyState)> {
         let data = raw.v.key;
-        let key =
-            T::from_u32(data.code).unwrap_or_else(|| panic!("unknown key code {}", data.code));
+        let key = T::from_code(data.code)
+            .ok_or_else(|| invalid_event(format!("unknown key code {}", data.code)))?;
         let state = KeyState::from_u32(data.state)
-            .unwrap_or_else(|| panic!("unknown key state {}", data.state));
-        (key, state)
+            .ok_or_else(|| invalid_event(format!("unknown key state {}", data.state)))?;
+        Ok((key, state))
     }
 }
 
+fn invalid_event(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
 /// Watches for events from a [`Device`].
 ///
 /// The kinds of streamed events depend on the open channels with
@@ -450,39 +236,356 @@ impl Event {
 /// for the channels needed to receive events of a certain kind.
 pub struct EventStream<'a> {
     device: &'a Device,
-    // Reuse event across
+    blocker: Arc<IoBlocker>,
+    // Reuse the same event buffer across `iface_dispatch` calls; parsing
+    // copies the payload out, so no allocation happens per event.
     last_event: xwiimote_sys::event,
     // Whether the epoll interest is currently registered. Used to
     // prevent a double-close when dropping the stream.
     have_interest: bool,
+    // The idle timeout, if set by `with_timeout`.
+    timeout: Option<Deadline>,
+    // The keep-alive interval, if set by `Device::set_keepalive`.
+    keepalive: Option<Deadline>,
+    // The channels available as of the last watch event, used to
+    // describe what changed in the next one.
+    available: Channels,
+    // The channels open as of the last watch event or error, used to
+    // detect those closed by the kernel.
+    opened: Channels,
+    // A `ChannelClosed` event to yield next.
+    closed: Option<Event>,
+    // The sequence number of the next event read from the device.
+    sequence: u64,
+    // The state shared with the handles returned by `cancel_handle`.
+    cancel: Arc<CancelState>,
+    // Whether the stream is paused, in which case the epoll interest is
+    // removed, and the task to wake once resumed.
+    paused: bool,
+    paused_waker: Option<Waker>,
+    // Events kept while discarding the queue on resume, to yield next.
+    retained: VecDeque<Event>,
+}
+
+/// What [`EventStream::resume`] does with the events queued while the
+/// stream was paused.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum QueuedEvents {
+    /// Drops the input events, e.g. the keys pressed while a menu was
+    /// open. Hot-plug, closed channel and disconnection events are
+    /// still yielded, so the stream keeps track of the device.
+    #[default]
+    Discard,
+    /// Yields every event, as if the stream was never paused. The
+    /// kernel queues a limited number of events, and reports
+    /// [`EventKind::Dropped`] past that.
+    Retain,
+}
+
+/// Ends an [`EventStream`] from another task.
+///
+/// See [`EventStream::cancel_handle`].
+#[derive(Clone, Debug)]
+pub struct CancelHandle {
+    state: Arc<CancelState>,
+}
+
+#[derive(Default, Debug)]
+struct CancelState {
+    cancelled: AtomicBool,
+    // Wakes the stream once cancelled.
+    waker: AtomicWaker,
+}
+
+impl CancelHandle {
+    /// Cancels the stream. The next time the stream is polled, it
+    /// removes its epoll interests and ends.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Release);
+        self.state.waker.wake();
+    }
+
+    /// Checks whether the stream was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// A deadline that restarts whenever an [`EventStream`] receives an event.
+struct Deadline {
+    duration: Duration,
+    at: Instant,
+    // Wakes the stream once the deadline passes.
+    timer: Timer,
+}
+
+impl Deadline {
+    fn new(blocker: Arc<IoBlocker>, duration: Duration) -> Self {
+        Self {
+            duration,
+            at: Instant::now() + duration,
+            timer: Timer::new(blocker),
+        }
+    }
+
+    fn restart(&mut self) {
+        self.at = Instant::now() + self.duration;
+    }
+
+    /// Checks whether the deadline passed, in which case it restarts.
+    /// Arranges for `wake` to be called once the (next) deadline passes.
+    fn poll_elapsed(&mut self, cx: &mut Context<'_>) -> Result<bool> {
+        let now = Instant::now();
+        let elapsed = now >= self.at;
+        if elapsed {
+            self.at = now + self.duration;
+        }
+        self.timer.wake_at(self.at, cx.waker())?;
+        Ok(elapsed)
+    }
 }
 
 impl<'a> EventStream<'a> {
-    const EPOLL_EVENTS: libc::c_int = libc::EPOLLIN | libc::EPOLLHUP | libc::EPOLLPRI;
+    const EPOLL_EVENTS: libc::c_int = IoBlocker::READ_EVENTS;
 
     /// Creates a new stream over the events from the device.
-    pub(crate) fn try_new(device: &'a Device) -> Result<Self> {
+    pub(crate) fn try_new(device: &'a Device, blocker: Arc<IoBlocker>) -> Result<Self> {
         // Watch the device fd for read availability to avoid busy-waiting.
-        let fd = unsafe { xwiimote_sys::iface_get_fd(device.handle) };
-        IoBlocker::get().add_interest(fd, Self::EPOLL_EVENTS)?;
+        let fd = unsafe { sys::iface_get_fd(device.handle) };
+        blocker.add_interest(fd, Self::EPOLL_EVENTS)?;
 
-        Ok(Self {
+        let mut stream = Self {
             device,
+            blocker,
             last_event: Default::default(),
             have_interest: true,
-        })
+            timeout: None,
+            keepalive: None,
+            available: device.available(),
+            opened: device.all_open(),
+            closed: None,
+            sequence: 0,
+            cancel: Default::default(),
+            paused: false,
+            paused_waker: None,
+            retained: VecDeque::new(),
+        };
+        if let Some(interval) = device.keepalive {
+            stream.keepalive = Some(Deadline::new(stream.blocker.clone(), interval));
+        }
+        Ok(stream)
+    }
+
+    /// Sets the maximum duration to wait for an event.
+    ///
+    /// If no event is received within `timeout`, the stream yields an
+    /// error of kind [`io::ErrorKind::TimedOut`] and keeps streaming
+    /// events. The timeout restarts after every yielded item, so an
+    /// error is yielded once per idle period of the given duration.
+    ///
+    /// This lets applications react to an idle controller, e.g. by
+    /// disabling the rumble motor, without racing a separate timer
+    /// against the stream.
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self> {
+        match &mut self.timeout {
+            Some(current) => {
+                current.duration = timeout;
+                current.restart();
+            }
+            None => self.timeout = Some(Deadline::new(self.blocker.clone(), timeout)),
+        }
+        Ok(self)
+    }
+
+    /// Returns a handle that ends the stream when cancelled.
+    ///
+    /// Unlike dropping the stream, cancellation can be requested from
+    /// any task, e.g. by a supervisor shutting down the task that reads
+    /// the events. The stream yields the error raised while removing
+    /// its interests, if any, and then ends.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle {
+            state: self.cancel.clone(),
+        }
+    }
+
+    /// Ends the stream, removing its epoll interests.
+    ///
+    /// The stream yields no more events afterwards. Unlike dropping the
+    /// stream, this reports whether the interests were removed. Closing
+    /// a stream that already ended has no effect.
+    pub fn close(&mut self) -> Result<()> {
+        self.retained.clear();
+        self.remove_interest()
+    }
+
+    /// Stops reading events from the device, without closing its
+    /// channels, e.g. to ignore the controller while a menu is open.
+    ///
+    /// The stream yields no events until [resumed](EventStream::resume),
+    /// and its timeout doesn't elapse meanwhile. The kernel keeps
+    /// queuing the events. Pausing a paused or closed stream has no
+    /// effect.
+    pub fn pause(&mut self) -> Result<()> {
+        if self.paused || !self.have_interest {
+            return Ok(());
+        }
+        let fd = unsafe { sys::iface_get_fd(self.device.handle) };
+        self.blocker.remove_interest(fd, Self::EPOLL_EVENTS)?;
+        self.paused = true;
+        Ok(())
+    }
+
+    /// Resumes reading events after [`EventStream::pause`], handling the
+    /// events queued meanwhile as given. Resuming a stream that is not
+    /// paused has no effect.
+    pub fn resume(&mut self, queued: QueuedEvents) -> Result<()> {
+        if !self.paused {
+            return Ok(());
+        }
+        let fd = unsafe { sys::iface_get_fd(self.device.handle) };
+        self.blocker.add_interest(fd, Self::EPOLL_EVENTS)?;
+        self.paused = false;
+        if let Some(timeout) = &mut self.timeout {
+            timeout.restart();
+        }
+        if let Some(waker) = self.paused_waker.take() {
+            waker.wake();
+        }
+        if queued == QueuedEvents::Discard {
+            while let Some(event) = Event::dispatch(self.device, &mut self.last_event)? {
+                let event = self.received(event)?;
+                if event.kind.channel().is_none() {
+                    self.retained.push_back(event);
+                }
+                self.retained.extend(self.closed.take());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the stream is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Checks whether the stream ended, because it was closed or
+    /// cancelled, or because the device was disconnected.
+    pub fn is_closed(&self) -> bool {
+        !self.have_interest
+    }
+
+    /// Converts the stream into an iterator that blocks the current
+    /// thread until each event is received.
+    ///
+    /// The events are still received by the event loop thread, so
+    /// classic loop code can consume them without an async executor.
+    pub fn into_blocking_iter(self) -> impl Iterator<Item = Result<Event>> + 'a {
+        futures::executor::block_on_stream(self)
+    }
+
+    /// Handles the timers of the stream while no event is available.
+    ///
+    /// Returns the error to yield, if any.
+    fn poll_deadlines(&mut self, cx: &mut Context<'_>) -> Option<io::Error> {
+        if let Some(err) = self.poll_keepalive(cx) {
+            return Some(err);
+        }
+        if let Some(timeout) = &mut self.timeout {
+            match timeout.poll_elapsed(cx) {
+                Ok(true) => return Some(io::ErrorKind::TimedOut.into()),
+                Ok(false) => {}
+                Err(err) => return Some(err),
+            }
+        }
+        None
+    }
+
+    /// Sends a keep-alive request once its interval elapses, even while
+    /// paused.
+    ///
+    /// Returns the error to yield, if any.
+    fn poll_keepalive(&mut self, cx: &mut Context<'_>) -> Option<io::Error> {
+        if let Some(keepalive) = &mut self.keepalive {
+            match keepalive.poll_elapsed(cx) {
+                // Requesting the battery level sends a status request to
+                // the device, which is enough to keep the connection alive.
+                Ok(true) => {
+                    if let Err(err) = self.device.battery() {
+                        return Some(err);
+                    }
+                }
+                Ok(false) => {}
+                Err(err) => return Some(err),
+            }
+        }
+        None
+    }
+
+    /// Updates the state of the stream with an event read from the
+    /// device.
+    fn received(&mut self, mut event: Event) -> Result<Event> {
+        for deadline in [&mut self.timeout, &mut self.keepalive]
+            .into_iter()
+            .flatten()
+        {
+            deadline.restart();
+        }
+        event.fill_watch(&mut self.available, self.device);
+        if let EventKind::Other(_) = event.kind {
+            self.closed = Event::closed(&mut self.opened, self.device, event.time);
+        }
+        if let EventKind::Disconnected = event.kind {
+            // We were watching for hot-plug events, and the device
+            // was closed. No more events are coming.
+            self.remove_interest()?;
+        }
+        Ok(event)
+    }
+
+    /// Reads a single incoming event, before the layers are applied.
+    fn poll_dispatch(&mut self, cx: &mut Context<'_>) -> Poll<Result<Event>> {
+        match Event::dispatch(self.device, &mut self.last_event) {
+            Ok(Some(event)) => Poll::Ready(self.received(event)),
+            Ok(None) => {
+                if let Some(err) = self.poll_deadlines(cx) {
+                    // A timeout elapsed, or handling a timer failed.
+                    return Poll::Ready(Err(err));
+                }
+                // No event is available, arrange for `wake` to be called once
+                // an event is available.
+                let fd = unsafe { sys::iface_get_fd(self.device.handle) };
+                self.blocker.set_callback(fd, cx.waker());
+                Poll::Pending
+            }
+            // Failure, perhaps the device was disconnected.
+            Err(err) => {
+                self.closed = Event::closed(&mut self.opened, self.device, SystemTime::now());
+                Poll::Ready(Err(err))
+            }
+        }
     }
 
-    /// Removes interest for the [`Device`] file events.
+    /// Removes interest for the [`Device`] file events, and for the
+    /// timers of the stream.
+    ///
+    /// Calling this again has no effect.
     fn remove_interest(&mut self) -> Result<()> {
+        // Dropping the deadlines cancels their timers.
+        self.timeout = None;
+        self.keepalive = None;
         if self.have_interest {
             self.have_interest = false;
+            if self.paused {
+                // Pausing removed the interest already.
+                self.paused = false;
+                return Ok(());
+            }
 
-            let fd = unsafe { xwiimote_sys::iface_get_fd(self.device.handle) };
-            IoBlocker::get().remove_interest(fd, Self::EPOLL_EVENTS)
-        } else {
-            Ok(())
+            let fd = unsafe { sys::iface_get_fd(self.device.handle) };
+            return self.blocker.remove_interest(fd, Self::EPOLL_EVENTS);
         }
+        Ok(())
     }
 }
 
@@ -490,49 +593,212 @@ impl Stream for EventStream<'_> {
     type Item = Result<Event>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if !self.have_interest {
-            // We stop reading events once a disconnect event is received.
-            return Poll::Ready(None);
+        self.cancel.waker.register(cx.waker());
+        if self.have_interest && self.cancel.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(self.close().err().map(Err));
         }
 
-        // Attempt to read a single incoming event.
-        let res_code = unsafe {
-            xwiimote_sys::iface_dispatch(
-                self.device.handle,
-                &mut self.last_event,
-                mem::size_of::<xwiimote_sys::event>(),
-            )
-        };
-
-        const PENDING: libc::c_int = -libc::EAGAIN;
-        let result = match res_code {
-            0 => {
-                if self.last_event.type_ == xwiimote_sys::EVENT_GONE {
-                    // We were watching for hot-plug events, and the device
-                    // was closed. No more events are coming.
-                    self.remove_interest().err().map(Err)
-                } else {
-                    let event = unsafe { Event::parse(&self.last_event) };
-                    Some(Ok(event))
-                }
+        loop {
+            if let Some(event) = self.device.layers.borrow_mut().next_pending() {
+                return Poll::Ready(Some(Ok(event)));
             }
-            PENDING => {
-                // No event is available, arrange for `wake` to be called once
-                // an event is available.
-                let fd = unsafe { xwiimote_sys::iface_get_fd(self.device.handle) };
-                IoBlocker::get().set_callback(fd, cx.waker().clone());
-                return Poll::Pending;
+            if !self.have_interest && self.retained.is_empty() {
+                // We stop reading events once a disconnect event is received.
+                return Poll::Ready(None);
             }
-            // Failure, perhaps the device was disconnected.
-            _ => Some(Err(io::Error::last_os_error())),
-        };
-        Poll::Ready(result)
+            if self.paused {
+                self.paused_waker = Some(cx.waker().clone());
+                return match self.poll_keepalive(cx) {
+                    Some(err) => Poll::Ready(Some(Err(err))),
+                    None => Poll::Pending,
+                };
+            }
+            let mut event = match self.closed.take().or_else(|| self.retained.pop_front()) {
+                Some(event) => event,
+                None => match self.poll_dispatch(cx) {
+                    Poll::Ready(Ok(event)) => event,
+                    Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    Poll::Pending => return Poll::Pending,
+                },
+            };
+            event.sequence = Some(self.sequence);
+            self.sequence += 1;
+            // The layers may drop the event, then read the next one.
+            if let Some(event) = self.device.layers.borrow_mut().process(event) {
+                return Poll::Ready(Some(Ok(event)));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl std::async_iter::AsyncIterator for EventStream<'_> {
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Stream::poll_next(self, cx)
     }
 }
 
 impl Drop for EventStream<'_> {
     fn drop(&mut self) {
-        self.remove_interest()
-            .expect("failed to remove interest for device fd");
+        // Panicking here could abort the process while unwinding. Use
+        // `EventStream::close` to handle the error.
+        if let Err(err) = self.remove_interest() {
+            log::warn!("failed to remove interest for device fd: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelHandle, CancelState};
+    use futures::task::{waker, ArcWake};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl ArcWake for CountingWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn cancel_wakes_stream() {
+        let state = Arc::new(CancelState::default());
+        let handle = CancelHandle {
+            state: state.clone(),
+        };
+        let counter = Arc::new(CountingWaker::default());
+        state.waker.register(&waker(counter.clone()));
+
+        assert!(!handle.is_cancelled());
+        handle.clone().cancel();
+        assert!(handle.is_cancelled());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn parses_drums_velocities() -> crate::Result<()> {
+        use crate::event::{DrumsPad, EventKind};
+        use crate::harness::{connect, FakeEvent, FakeIface};
+        use crate::Channels;
+
+        let fake = FakeIface::new(Channels::CORE | Channels::DRUMS)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::DRUMS, false)?;
+        fake.push(FakeEvent::drums_move(-3, 4, [0, 0, 5, 0, 0, 7, 0]));
+
+        let event = device.try_next_event()?.unwrap();
+        match event.kind {
+            EventKind::DrumsMove { x, y, velocities } => {
+                assert_eq!((x, y), (-3, 4));
+                assert_eq!(velocities[DrumsPad::TomLeft as usize], 5);
+                assert_eq!(velocities[DrumsPad::Bass as usize], 7);
+            }
+            kind => panic!("unexpected event {:?}", kind),
+        }
+        assert_eq!(
+            event.kind.to_string(),
+            "DRUMS_MOVE x=-3 y=4 TomLeft=5 Bass=7"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn parses_guitar_movements() -> crate::Result<()> {
+        use crate::event::EventKind;
+        use crate::harness::{connect, FakeEvent, FakeIface};
+        use crate::Channels;
+
+        let fake = FakeIface::new(Channels::CORE | Channels::GUITAR)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::GUITAR, false)?;
+        fake.push(FakeEvent::abs(
+            xwiimote_sys::EVENT_GUITAR_MOVE,
+            &[(-7, 12, 0), (15, 0, 0), (9, 0, 0)],
+        ));
+
+        let event = device.try_next_event()?.unwrap();
+        assert!(matches!(
+            event.kind,
+            EventKind::GuitarMove {
+                x: -7,
+                y: 12,
+                whammy_bar: 15,
+                fret_bar: 9,
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn skips_a_bounded_number_of_invalid_events() -> crate::Result<()> {
+        use crate::event::{EventKind, Key, KeyState};
+        use crate::harness::{FakeEvent, FakeIface};
+        use crate::{Channels, ConnectOptions, Device};
+
+        let fake = FakeIface::new(Channels::CORE)?;
+        let options = ConnectOptions {
+            blocking: false,
+            clone_friendly: true,
+            ..Default::default()
+        };
+        let mut device = Device::connect_with(&fake.address(), &options)?;
+        device.open(Channels::CORE, false)?;
+        let invalid = xwiimote_sys::event {
+            type_: 999,
+            ..Default::default()
+        };
+        let events = std::iter::repeat_with(|| FakeEvent::from_raw(invalid)).take(70);
+        fake.push_batch(events.chain([FakeEvent::key(Key::A, KeyState::Down)]));
+
+        // Reports the 65th invalid event, then skips the rest.
+        let err = device.try_next_event().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let event = device.try_next_event()?.unwrap();
+        assert!(matches!(event.kind, EventKind::Key(Key::A, KeyState::Down)));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn discards_or_retains_events_while_paused() -> crate::Result<()> {
+        use crate::event::{EventKind, Key, KeyState, QueuedEvents};
+        use crate::harness::{connect, FakeEvent, FakeIface};
+        use crate::Channels;
+        use futures::{executor, FutureExt, StreamExt};
+
+        let fake = FakeIface::new(Channels::CORE | Channels::NUNCHUK)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::CORE | Channels::NUNCHUK, false)?;
+        let mut events = device.events()?;
+
+        events.pause()?;
+        fake.push_batch([
+            FakeEvent::key(Key::A, KeyState::Down),
+            FakeEvent::hotplug(Channels::CORE),
+        ]);
+        assert!(events.next().now_or_never().is_none());
+        events.resume(QueuedEvents::Discard)?;
+        let kinds = executor::block_on((&mut events).take(2).collect::<Vec<_>>());
+        assert!(matches!(kinds[0], Ok(ref event) if matches!(event.kind, EventKind::Other(_))));
+        assert!(matches!(
+            kinds[1],
+            Ok(ref event) if matches!(event.kind, EventKind::ChannelClosed(Channels::NUNCHUK))
+        ));
+
+        events.pause()?;
+        fake.push(FakeEvent::key(Key::B, KeyState::Down));
+        events.resume(QueuedEvents::Retain)?;
+        let event = executor::block_on(events.next()).unwrap()?;
+        assert!(matches!(event.kind, EventKind::Key(Key::B, KeyState::Down)));
+        Ok(())
     }
 }
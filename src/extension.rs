@@ -0,0 +1,634 @@
+//! Drivers for extension controllers.
+//!
+//! An [`ExtensionDriver`] handles one kind of extension: it opens the
+//! channels the extension needs, decodes its events into a typed state,
+//! and closes the channels once the extension is unplugged. The drivers
+//! of the Nunchuk, Classic Controller and guitar are built in, and other
+//! crates can add drivers for extensions the library doesn't know, e.g.
+//! by decoding the [`EventKind::InputAxis`] events of the `supplemental`
+//! module.
+//!
+//! An [`ExtensionRegistry`] picks the driver of the extension reported
+//! by [`Device::extension`]. Call [`ExtensionRegistry::attach`] again
+//! after every hot-plug event, and pass the events of the device to
+//! [`ExtensionRegistry::update`]:
+//!
+//! ```no_run
+//! # use xwiimote::extension::{ExtensionRegistry, NunchukState};
+//! # fn run(device: &mut xwiimote::Device) -> std::io::Result<()> {
+//! let mut registry = ExtensionRegistry::new();
+//! registry.attach(device)?;
+//! while let Some(event) = device.try_next_event()? {
+//!     if registry.update(&event) {
+//!         if let Some(nunchuk) = registry.state::<NunchukState>() {
+//!             println!("stick at {}, {}", nunchuk.x, nunchuk.y);
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::event::{ClassicControllerKey, Event, EventKind, GuitarKey, KeyState, NunchukKey};
+use crate::{Channels, Device, Result};
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+
+/// Handles a kind of extension controller.
+pub trait ExtensionDriver {
+    /// Returns the extension type identifiers handled by the driver, as
+    /// returned by [`Device::extension`], e.g. `nunchuk`.
+    fn extension_types(&self) -> &[&str];
+
+    /// Returns the channels through which the extension reports its
+    /// events, if any.
+    fn channels(&self) -> Channels;
+
+    /// Prepares the device once the extension is plugged in. By default,
+    /// opens the [channels](ExtensionDriver::channels) in read-only mode.
+    fn open(&mut self, device: &mut Device) -> Result<()> {
+        device.open(self.channels(), false)
+    }
+
+    /// Releases the device once the extension is unplugged. By default,
+    /// closes the [channels](ExtensionDriver::channels).
+    fn close(&mut self, device: &mut Device) -> Result<()> {
+        device.close(self.channels())
+    }
+
+    /// Parses an event of the given type reported by the kernel driver
+    /// of the extension, given the `(x, y)` positions of its absolute
+    /// axes. Returns `None` if the driver doesn't know the type, which
+    /// is the default.
+    fn parse(&self, type_id: u32, axes: &[(i32, i32)]) -> Option<EventKind> {
+        let _ = (type_id, axes);
+        None
+    }
+
+    /// Updates the state with the given event. Returns `true` if the
+    /// event was reported by the extension.
+    fn decode(&mut self, event: &Event) -> bool;
+
+    /// Returns the state decoded from the events so far, to be
+    /// downcast to the type documented by the driver.
+    fn state(&self) -> &dyn Any;
+}
+
+/// Updates the pressed keys of an extension.
+fn press<K: Eq + Hash>(pressed: &mut HashSet<K>, key: K, state: KeyState) {
+    match state {
+        KeyState::Up => pressed.remove(&key),
+        KeyState::Down | KeyState::AutoRepeat => pressed.insert(key),
+    };
+}
+
+/// The state of a Nunchuk, decoded by [`NunchukDriver`].
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct NunchukState {
+    /// The analog stick x-axis position.
+    pub x: i32,
+    /// The analog stick y-axis position.
+    pub y: i32,
+    /// The x-axis acceleration.
+    pub x_acceleration: i32,
+    /// The y-axis acceleration.
+    pub y_acceleration: i32,
+    /// The keys held down.
+    pub pressed: HashSet<NunchukKey>,
+}
+
+/// The state of a Classic Controller, decoded by
+/// [`ClassicControllerDriver`].
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct ClassicControllerState {
+    /// The left analog stick x-axis position.
+    pub left_x: i32,
+    /// The left analog stick y-axis position.
+    pub left_y: i32,
+    /// The right analog stick x-axis position.
+    pub right_x: i32,
+    /// The right analog stick y-axis position.
+    pub right_y: i32,
+    /// The TL trigger position, from 0 to 63.
+    pub left_trigger: u8,
+    /// The TR trigger position, from 0 to 63.
+    pub right_trigger: u8,
+    /// The keys held down.
+    pub pressed: HashSet<ClassicControllerKey>,
+}
+
+/// The state of a guitar, decoded by [`GuitarDriver`].
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct GuitarState {
+    /// The analog stick x-axis position.
+    pub x: i32,
+    /// The analog stick y-axis position.
+    pub y: i32,
+    /// The whammy bar position.
+    pub whammy_bar: i32,
+    /// The fret bar position.
+    pub fret_bar: i32,
+    /// The keys held down.
+    pub pressed: HashSet<GuitarKey>,
+}
+
+/// Defines the driver of an extension decoded by the kernel.
+macro_rules! builtin_driver {
+    (
+        $doc:expr, $name:ident, $state:ident, $type_id:expr, $channel:expr,
+        $move_type:expr, |$axes:ident| $parse:expr,
+        |$this:ident, $event:ident| $decode:expr
+    ) => {
+        #[doc = $doc]
+        #[derive(Clone, Default, Debug)]
+        pub struct $name {
+            state: $state,
+        }
+
+        impl ExtensionDriver for $name {
+            fn extension_types(&self) -> &[&str] {
+                &[$type_id]
+            }
+
+            fn channels(&self) -> Channels {
+                $channel
+            }
+
+            fn open(&mut self, device: &mut Device) -> Result<()> {
+                self.state = Default::default();
+                device.open(self.channels(), false)
+            }
+
+            fn parse(&self, type_id: u32, $axes: &[(i32, i32)]) -> Option<EventKind> {
+                (type_id == $move_type).then(|| $parse)
+            }
+
+            fn decode(&mut self, event: &Event) -> bool {
+                let $this = &mut self.state;
+                let $event = event;
+                $decode
+            }
+
+            fn state(&self) -> &dyn Any {
+                &self.state
+            }
+        }
+    };
+}
+
+builtin_driver!(
+    "The driver of the Nunchuk. Its state is a [`NunchukState`].",
+    NunchukDriver,
+    NunchukState,
+    "nunchuk",
+    Channels::NUNCHUK,
+    xwiimote_sys::EVENT_NUNCHUK_MOVE,
+    |axes| EventKind::NunchukMove {
+        x: axes[0].0,
+        y: axes[0].1,
+        x_acceleration: axes[1].0,
+        y_acceleration: axes[1].1,
+    },
+    |state, event| match event.kind {
+        EventKind::NunchukKey(key, key_state) => {
+            press(&mut state.pressed, key, key_state);
+            true
+        }
+        EventKind::NunchukMove {
+            x,
+            y,
+            x_acceleration,
+            y_acceleration,
+        } => {
+            state.x = x;
+            state.y = y;
+            state.x_acceleration = x_acceleration;
+            state.y_acceleration = y_acceleration;
+            true
+        }
+        _ => false,
+    }
+);
+
+builtin_driver!(
+    "The driver of the Classic Controller. Its state is a [`ClassicControllerState`].",
+    ClassicControllerDriver,
+    ClassicControllerState,
+    "classic",
+    Channels::CLASSIC_CONTROLLER,
+    xwiimote_sys::EVENT_CLASSIC_CONTROLLER_MOVE,
+    |axes| EventKind::ClassicControllerMove {
+        left_x: axes[0].0,
+        left_y: axes[0].1,
+        right_x: axes[1].0,
+        right_y: axes[1].1,
+        left_trigger: axes[2].0 as u8,
+        right_trigger: axes[2].1 as u8,
+    },
+    |state, event| match event.kind {
+        EventKind::ClassicControllerKey(key, key_state) => {
+            press(&mut state.pressed, key, key_state);
+            true
+        }
+        EventKind::ClassicControllerMove {
+            left_x,
+            left_y,
+            right_x,
+            right_y,
+            left_trigger,
+            right_trigger,
+        } => {
+            state.left_x = left_x;
+            state.left_y = left_y;
+            state.right_x = right_x;
+            state.right_y = right_y;
+            state.left_trigger = left_trigger;
+            state.right_trigger = right_trigger;
+            true
+        }
+        _ => false,
+    }
+);
+
+builtin_driver!(
+    "The driver of the guitar. Its state is a [`GuitarState`].",
+    GuitarDriver,
+    GuitarState,
+    "guitar",
+    Channels::GUITAR,
+    xwiimote_sys::EVENT_GUITAR_MOVE,
+    |axes| EventKind::GuitarMove {
+        x: axes[0].0,
+        y: axes[0].1,
+        whammy_bar: axes[1].0,
+        fret_bar: axes[2].0,
+    },
+    |state, event| match event.kind {
+        EventKind::GuitarKey(key, key_state) => {
+            press(&mut state.pressed, key, key_state);
+            true
+        }
+        EventKind::GuitarMove {
+            x,
+            y,
+            whammy_bar,
+            fret_bar,
+        } => {
+            state.x = x;
+            state.y = y;
+            state.whammy_bar = whammy_bar;
+            state.fret_bar = fret_bar;
+            true
+        }
+        _ => false,
+    }
+);
+
+/// The drivers of the extensions an application supports, one of which
+/// handles the extension plugged into a device.
+pub struct ExtensionRegistry {
+    drivers: Vec<Box<dyn ExtensionDriver>>,
+    // The index of the driver of the plugged extension.
+    active: Option<usize>,
+}
+
+impl ExtensionRegistry {
+    /// Creates a registry with the built-in drivers.
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register(NunchukDriver::default());
+        registry.register(ClassicControllerDriver::default());
+        registry.register(GuitarDriver::default());
+        registry
+    }
+
+    /// Creates a registry without drivers.
+    pub fn empty() -> Self {
+        Self {
+            drivers: Vec::new(),
+            active: None,
+        }
+    }
+
+    /// Adds a driver. It takes precedence over the drivers registered
+    /// before for the same extension types.
+    pub fn register<D: ExtensionDriver + 'static>(&mut self, driver: D) {
+        self.drivers.push(Box::new(driver));
+    }
+
+    /// Returns the index of the driver of the given extension type.
+    fn find(&self, extension: &str) -> Option<usize> {
+        self.drivers
+            .iter()
+            .rposition(|driver| driver.extension_types().contains(&extension))
+    }
+
+    /// Returns the driver of the given extension type, if any.
+    pub fn driver_for(&self, extension: &str) -> Option<&dyn ExtensionDriver> {
+        self.find(extension).map(|index| &*self.drivers[index])
+    }
+
+    /// Returns the driver of the extension plugged into the device as of
+    /// the last call to [`ExtensionRegistry::attach`], if any.
+    pub fn active(&self) -> Option<&dyn ExtensionDriver> {
+        self.active.map(|index| &*self.drivers[index])
+    }
+
+    /// Returns the state of the active driver, if it is of type `T`.
+    pub fn state<T: Any>(&self) -> Option<&T> {
+        self.active()?.state().downcast_ref()
+    }
+
+    /// Activates the driver of the extension plugged into the device, if
+    /// any, closing the previously active driver if the extension
+    /// changed. Returns the active driver.
+    pub fn attach(&mut self, device: &mut Device) -> Result<Option<&dyn ExtensionDriver>> {
+        let index = self.find(&device.extension()?);
+        if index != self.active {
+            self.detach(device)?;
+            if let Some(index) = index {
+                self.drivers[index].open(device)?;
+                self.active = Some(index);
+            }
+        }
+        Ok(self.active())
+    }
+
+    /// Closes the active driver, if any.
+    pub fn detach(&mut self, device: &mut Device) -> Result<()> {
+        match self.active.take() {
+            Some(index) => self.drivers[index].close(device),
+            None => Ok(()),
+        }
+    }
+
+    /// Parses an event reported by the kernel driver of an extension
+    /// with the first driver that knows its type, latest registered
+    /// first. See [`ExtensionDriver::parse`].
+    pub fn parse(&self, type_id: u32, axes: &[(i32, i32)]) -> Option<EventKind> {
+        self.drivers
+            .iter()
+            .rev()
+            .find_map(|driver| driver.parse(type_id, axes))
+    }
+
+    /// Passes the event to the active driver. Returns `true` if the event
+    /// was reported by the extension.
+    pub fn update(&mut self, event: &Event) -> bool {
+        match self.active {
+            Some(index) => self.drivers[index].decode(event),
+            None => false,
+        }
+    }
+}
+
+/// Parses an event reported by the kernel driver of an extension with
+/// the built-in drivers.
+pub(crate) fn parse_builtin(type_id: u32, axes: &[(i32, i32)]) -> Option<EventKind> {
+    thread_local! {
+        static BUILTIN: ExtensionRegistry = ExtensionRegistry::new();
+    }
+    BUILTIN.with(|registry| registry.parse(type_id, axes))
+}
+
+impl Default for ExtensionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A model of extension controller, as identified by its
+/// [`ExtensionId`].
+#[non_exhaustive]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ExtensionModel {
+    /// A Nunchuk.
+    Nunchuk,
+    /// A Classic Controller.
+    ClassicController,
+    /// A Classic Controller Pro, which has no analog triggers.
+    ClassicControllerPro,
+    /// A Guitar Hero guitar.
+    Guitar,
+    /// A Guitar Hero World Tour drums controller.
+    Drums,
+    /// A DJ Hero turntable.
+    Turntable,
+    /// The uDraw GameTablet.
+    UDrawTablet,
+    /// The built-in extension of a Balance Board.
+    BalanceBoard,
+    /// The built-in extension of a Wii U Pro Controller.
+    ProController,
+    /// An active Motion Plus, with no extension plugged into it.
+    MotionPlus,
+    /// An active Motion Plus, passing through a Nunchuk.
+    MotionPlusNunchuk,
+    /// An active Motion Plus, passing through a Classic Controller.
+    MotionPlusClassic,
+}
+
+/// The 6-byte identifier of an extension, read from its registers
+/// with [`Device::extension_id`].
+///
+/// The last four bytes identify the kind of extension, and the first
+/// two tell apart its variants, e.g. the Classic Controller and the
+/// Classic Controller Pro. Third-party extensions often report
+/// variant bytes that Nintendo never used.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ExtensionId(pub [u8; 6]);
+
+impl ExtensionId {
+    /// The identifiers of the genuine extensions.
+    const KNOWN: [([u8; 6], ExtensionModel); 12] = [
+        (
+            [0x00, 0x00, 0xa4, 0x20, 0x00, 0x00],
+            ExtensionModel::Nunchuk,
+        ),
+        (
+            [0x00, 0x00, 0xa4, 0x20, 0x01, 0x01],
+            ExtensionModel::ClassicController,
+        ),
+        (
+            [0x01, 0x00, 0xa4, 0x20, 0x01, 0x01],
+            ExtensionModel::ClassicControllerPro,
+        ),
+        ([0x00, 0x00, 0xa4, 0x20, 0x01, 0x03], ExtensionModel::Guitar),
+        ([0x01, 0x00, 0xa4, 0x20, 0x01, 0x03], ExtensionModel::Drums),
+        (
+            [0x03, 0x00, 0xa4, 0x20, 0x01, 0x03],
+            ExtensionModel::Turntable,
+        ),
+        (
+            [0xff, 0x00, 0xa4, 0x20, 0x00, 0x13],
+            ExtensionModel::UDrawTablet,
+        ),
+        (
+            [0x00, 0x00, 0xa4, 0x20, 0x04, 0x02],
+            ExtensionModel::BalanceBoard,
+        ),
+        (
+            [0x00, 0x00, 0xa4, 0x20, 0x01, 0x20],
+            ExtensionModel::ProController,
+        ),
+        (
+            [0x00, 0x00, 0xa4, 0x20, 0x04, 0x05],
+            ExtensionModel::MotionPlus,
+        ),
+        (
+            [0x00, 0x00, 0xa4, 0x20, 0x05, 0x05],
+            ExtensionModel::MotionPlusNunchuk,
+        ),
+        (
+            [0x00, 0x00, 0xa4, 0x20, 0x07, 0x05],
+            ExtensionModel::MotionPlusClassic,
+        ),
+    ];
+
+    /// Identifies the model of the extension. Unknown variants of a
+    /// known kind are identified as its first variant, e.g. a Nunchuk
+    /// clone reporting `ff 00 a4 20 00 00` as a [`ExtensionModel::Nunchuk`].
+    pub fn model(&self) -> Option<ExtensionModel> {
+        let by_kind = || Self::KNOWN.iter().find(|(id, _)| id[2..] == self.0[2..]);
+        Self::KNOWN
+            .iter()
+            .find(|(id, _)| *id == self.0)
+            .or_else(by_kind)
+            .map(|&(_, model)| model)
+    }
+
+    /// Checks whether the identifier is that of a genuine extension.
+    pub fn is_genuine(&self) -> bool {
+        Self::KNOWN.iter().any(|(id, _)| *id == self.0)
+    }
+
+    /// Checks whether the extension is likely a third-party copy of a
+    /// known model: its kind is known, but not its variant.
+    pub fn is_likely_knockoff(&self) -> bool {
+        !self.is_genuine() && self.model().is_some()
+    }
+}
+
+impl fmt::Display for ExtensionId {
+    /// Formats the identifier as hexadecimal bytes, e.g. `0100a4200101`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ExtensionDriver, ExtensionId, ExtensionModel, ExtensionRegistry, GuitarDriver,
+        NunchukDriver, NunchukState,
+    };
+    use crate::event::{Event, EventKind, KeyState, NunchukKey};
+    use crate::Channels;
+    use std::any::Any;
+    use std::time::SystemTime;
+
+    fn event(kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH, kind)
+    }
+
+    #[test]
+    fn decodes_nunchuk() {
+        let mut driver = NunchukDriver::default();
+        assert!(driver.decode(&event(EventKind::NunchukKey(NunchukKey::Z, KeyState::Down))));
+        assert!(driver.decode(&event(EventKind::NunchukMove {
+            x: 10,
+            y: -20,
+            x_acceleration: 3,
+            y_acceleration: 4,
+        })));
+        assert!(!driver.decode(&event(EventKind::Accelerometer { x: 0, y: 0, z: 0 })));
+
+        let state = driver.state().downcast_ref::<NunchukState>().unwrap();
+        assert_eq!((state.x, state.y), (10, -20));
+        assert!(state.pressed.contains(&NunchukKey::Z));
+    }
+
+    #[derive(Default)]
+    struct Drums(u32);
+
+    impl ExtensionDriver for Drums {
+        fn extension_types(&self) -> &[&str] {
+            &["drums", "guitar"]
+        }
+
+        fn channels(&self) -> Channels {
+            Channels::DRUMS
+        }
+
+        fn decode(&mut self, _event: &Event) -> bool {
+            self.0 += 1;
+            true
+        }
+
+        fn state(&self) -> &dyn Any {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn later_drivers_take_precedence() {
+        let mut registry = ExtensionRegistry::new();
+        assert!(registry.driver_for("none").is_none());
+        assert_eq!(
+            registry.driver_for("guitar").unwrap().channels(),
+            GuitarDriver::default().channels()
+        );
+
+        registry.register(Drums::default());
+        assert_eq!(
+            registry.driver_for("guitar").unwrap().channels(),
+            Channels::DRUMS
+        );
+        // No extension was attached.
+        assert!(!registry.update(&event(EventKind::DrumsMove {
+            x: 0,
+            y: 0,
+            velocities: [0; 7],
+        })));
+        assert_eq!(registry.state::<u32>(), None);
+    }
+
+    #[test]
+    fn identifies_extensions() {
+        let pro = ExtensionId([0x01, 0x00, 0xa4, 0x20, 0x01, 0x01]);
+        assert_eq!(pro.model(), Some(ExtensionModel::ClassicControllerPro));
+        assert!(pro.is_genuine());
+        assert_eq!(pro.to_string(), "0100a4200101");
+
+        let knockoff = ExtensionId([0xff, 0x00, 0xa4, 0x20, 0x00, 0x00]);
+        assert_eq!(knockoff.model(), Some(ExtensionModel::Nunchuk));
+        assert!(knockoff.is_likely_knockoff());
+
+        let unknown = ExtensionId([0x00, 0x00, 0xa4, 0x20, 0x09, 0x09]);
+        assert_eq!(unknown.model(), None);
+        assert!(!unknown.is_likely_knockoff());
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn attaches_extension_drivers() -> crate::Result<()> {
+        use crate::harness::{connect, FakeIface};
+
+        let fake = FakeIface::new(Channels::CORE | Channels::NUNCHUK)?;
+        let mut device = connect(&fake)?;
+        let mut registry = ExtensionRegistry::new();
+        assert!(registry.attach(&mut device)?.is_none());
+
+        fake.set_extension("nunchuk");
+        let driver = registry.attach(&mut device)?.unwrap();
+        assert_eq!(driver.channels(), Channels::NUNCHUK);
+        assert_eq!(fake.opened(), Channels::NUNCHUK);
+        assert_eq!(registry.state::<NunchukState>(), Some(&Default::default()));
+
+        fake.set_extension("none");
+        assert!(registry.attach(&mut device)?.is_none());
+        assert_eq!(fake.opened(), Channels::empty());
+        Ok(())
+    }
+}
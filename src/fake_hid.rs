@@ -0,0 +1,356 @@
+//! Fake Wii Remotes created in the kernel, for integration tests.
+//!
+//! Unlike the [`harness`](crate::harness) fakes, which replace the
+//! `xwiimote` library, a [`FakeWiimote`] is a HID device created through
+//! `/dev/uhid`, the HID counterpart of `uinput`. The kernel binds its
+//! `hid-wiimote` driver to it, which registers the input devices found
+//! by [`Monitor`] and read by [`Device`](crate::Device), so tests
+//! exercise the whole stack without hardware. The fake answers the
+//! requests of the driver while probing, and reports the keys pressed
+//! by the test.
+//!
+//! Creating the device requires write access to `/dev/uhid`, usually
+//! root, and the `hid-wiimote` module. Tests should skip themselves if
+//! [`FakeWiimote::new`] fails, e.g. in unprivileged containers, unless
+//! the `XWIIMOTE_REQUIRE_UHID` environment variable is set, as in CI.
+//!
+//! ```no_run
+//! # use xwiimote::fake_hid::FakeWiimote;
+//! # use xwiimote::event::Key;
+//! # use xwiimote::{Channels, Device};
+//! # use std::time::Duration;
+//! # fn run() -> std::io::Result<()> {
+//! let fake = FakeWiimote::new()?;
+//! let address = fake.wait_for_address(Duration::from_secs(5))?;
+//! let mut device = Device::connect(&address)?;
+//! device.open(Channels::CORE, false)?;
+//! fake.press(Key::A, true)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requires the `test-uinput` feature.
+use crate::event::Key;
+use crate::{discovery, Address, Monitor, Result};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// The `uhid_event` types, see `linux/uhid.h`.
+const UHID_DESTROY: u32 = 1;
+const UHID_OUTPUT: u32 = 6;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+
+/// The size of a `uhid_event`: its type, followed by the largest
+/// request, `uhid_create2_req`.
+const EVENT_SIZE: usize = 4 + 128 + 64 + 64 + 2 + 2 + 4 * 4 + 4096;
+/// The offset of the request in a `uhid_event`.
+const REQUEST: usize = 4;
+
+const BUS_BLUETOOTH: u16 = 0x05;
+const NINTENDO_VENDOR: u32 = 0x057e;
+const WIIMOTE_PRODUCT: u32 = 0x0306;
+
+/// The interval at which the answering thread checks for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A vendor-defined report descriptor with the output reports of a Wii
+/// Remote and the input reports answered by the fake. The driver parses
+/// the raw reports itself.
+fn report_descriptor() -> Vec<u8> {
+    let mut descriptor = vec![
+        0x06, 0x00, 0xff, // Usage Page (Vendor Defined 0xFF00)
+        0x09, 0x01, // Usage (0x01)
+        0xa1, 0x01, // Collection (Application)
+        0x15, 0x00, // Logical Minimum (0)
+        0x26, 0xff, 0x00, // Logical Maximum (255)
+        0x75, 0x08, // Report Size (8)
+    ];
+    let outputs = (0x10..=0x1a).map(|id| (id, 21, 0x91));
+    let inputs = [
+        (0x20, 6, 0x81),
+        (0x21, 21, 0x81),
+        (0x22, 4, 0x81),
+        (0x30, 2, 0x81),
+    ];
+    for (id, count, main) in outputs.chain(inputs) {
+        // Report ID, Report Count, Usage, and Output or Input (Data).
+        descriptor.extend([0x85, id, 0x95, count, 0x09, 0x01, main, 0x00]);
+    }
+    descriptor.push(0xc0); // End Collection
+    descriptor
+}
+
+/// Returns the bit of a key in the core key bytes of input reports.
+fn key_bit(key: Key) -> u16 {
+    match key {
+        Key::Left => 0x0100,
+        Key::Right => 0x0200,
+        Key::Down => 0x0400,
+        Key::Up => 0x0800,
+        Key::Plus => 0x1000,
+        Key::Two => 0x0001,
+        Key::One => 0x0002,
+        Key::B => 0x0004,
+        Key::A => 0x0008,
+        Key::Minus => 0x0010,
+        Key::Home => 0x0080,
+    }
+}
+
+/// The state shared with the thread answering the driver.
+struct Shared {
+    uhid: File,
+    // The keys held down, as sent in input reports.
+    keys: AtomicU16,
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    /// Sends an input report, starting with its identifier.
+    fn input(&self, report: &[u8]) -> Result<()> {
+        let mut event = vec![0; EVENT_SIZE];
+        event[..4].copy_from_slice(&UHID_INPUT2.to_ne_bytes());
+        event[REQUEST..REQUEST + 2].copy_from_slice(&(report.len() as u16).to_ne_bytes());
+        event[REQUEST + 2..REQUEST + 2 + report.len()].copy_from_slice(report);
+        (&self.uhid).write_all(&event)
+    }
+
+    /// Answers an output report of the driver, like a Wii Remote with
+    /// no extension and a full battery.
+    fn answer(&self, report: &[u8]) -> Result<()> {
+        let [high, low] = self.keys.load(Ordering::Relaxed).to_be_bytes();
+        match *report {
+            // Status request: no extension, full battery.
+            [0x15, ..] => self.input(&[0x20, high, low, 0x00, 0x00, 0x00, 0xc8]),
+            // Memory read: every address is missing, e.g. the
+            // identifiers of the extension and the Motion Plus.
+            [0x17, _, _, address_high, address_low, ..] => {
+                let mut answer = vec![0x21, high, low, 0xf8, address_high, address_low];
+                answer.resize(22, 0);
+                self.input(&answer)
+            }
+            // Memory write, and other reports that request an ack.
+            [id @ 0x16, ..] => self.input(&[0x22, high, low, id, 0x00]),
+            [id, flags, ..] if flags & 0x02 != 0 => self.input(&[0x22, high, low, id, 0x00]),
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers the output reports of the driver until stopped.
+    fn run(&self) -> Result<()> {
+        let mut event = vec![0; EVENT_SIZE];
+        while !self.stopped.load(Ordering::Relaxed) {
+            let mut pollfd = libc::pollfd {
+                fd: self.uhid.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let res_code = unsafe { libc::poll(&mut pollfd, 1, POLL_INTERVAL.as_millis() as _) };
+            crate::bail_if!(res_code == -1);
+            let len = match (&self.uhid).read(&mut event) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err),
+            };
+            if len < REQUEST || event[..4] != UHID_OUTPUT.to_ne_bytes() {
+                continue; // e.g. `UHID_START` and `UHID_OPEN`
+            }
+            // The `uhid_output_req` holds the data, then its size.
+            let size = u16::from_ne_bytes([event[REQUEST + 4096], event[REQUEST + 4097]]);
+            let report = &event[REQUEST..REQUEST + (size as usize).min(4096)];
+            self.answer(report)?;
+        }
+        Ok(())
+    }
+}
+
+/// A fake Wii Remote, bound by the kernel driver until dropped.
+pub struct FakeWiimote {
+    shared: Arc<Shared>,
+    uniq: String,
+    answering: Option<JoinHandle<Result<()>>>,
+}
+
+impl FakeWiimote {
+    /// Creates a fake original Wii Remote with no extension.
+    ///
+    /// Fails if `/dev/uhid` can't be opened, e.g. without root.
+    pub fn new() -> Result<Self> {
+        static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+        let uhid = File::options()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open("/dev/uhid")?;
+        // A unique, locally administered Bluetooth address.
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let [pid_high, pid_low] = (std::process::id() as u16).to_be_bytes();
+        let [id_high, id_low] = id.to_be_bytes();
+        let uniq = format!(
+            "02:00:{:02x}:{:02x}:{:02x}:{:02x}",
+            pid_high, pid_low, id_high, id_low
+        );
+
+        let mut event = vec![0; EVENT_SIZE];
+        event[..4].copy_from_slice(&UHID_CREATE2.to_ne_bytes());
+        let request = &mut event[REQUEST..];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            request[offset..offset + bytes.len()].copy_from_slice(bytes)
+        };
+        put(0, b"Nintendo RVL-CNT-01");
+        put(128, b"xwiimote-fake");
+        put(192, uniq.as_bytes());
+        let descriptor = report_descriptor();
+        put(256, &(descriptor.len() as u16).to_ne_bytes());
+        put(258, &BUS_BLUETOOTH.to_ne_bytes());
+        put(260, &NINTENDO_VENDOR.to_ne_bytes());
+        put(264, &WIIMOTE_PRODUCT.to_ne_bytes());
+        put(276, &descriptor);
+        (&uhid).write_all(&event)?;
+
+        let shared = Arc::new(Shared {
+            uhid,
+            keys: AtomicU16::new(0),
+            stopped: AtomicBool::new(false),
+        });
+        let answering = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("xwiimote-fake-hid".into())
+                .spawn(move || shared.run())?
+        };
+        Ok(Self {
+            shared,
+            uniq,
+            answering: Some(answering),
+        })
+    }
+
+    /// Returns the Bluetooth address of the fake, as reported by
+    /// [`Device::mac_address`](crate::Device::mac_address).
+    pub fn mac_address(&self) -> &str {
+        &self.uniq
+    }
+
+    /// Enumerates the devices with a [`Monitor`] until the fake is
+    /// found, and returns its address.
+    ///
+    /// Fails with [`io::ErrorKind::TimedOut`] if the driver doesn't
+    /// register the fake in time.
+    pub fn wait_for_address(&self, timeout: Duration) -> Result<Address> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut monitor = Monitor::new(false)?;
+            while let Some(address) = monitor.next_address()? {
+                let uniq = discovery::uevent_property(&address.0, "HID_UNIQ")?;
+                if uniq.as_deref() == Some(self.uniq.as_str()) {
+                    return Ok(address);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the fake device was not registered",
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Presses or releases a key, and reports the keys held down.
+    pub fn press(&self, key: Key, pressed: bool) -> Result<()> {
+        let bit = key_bit(key);
+        let keys = if pressed {
+            self.shared.keys.fetch_or(bit, Ordering::Relaxed) | bit
+        } else {
+            self.shared.keys.fetch_and(!bit, Ordering::Relaxed) & !bit
+        };
+        let [high, low] = keys.to_be_bytes();
+        self.shared.input(&[0x30, high, low])
+    }
+}
+
+impl Drop for FakeWiimote {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        if let Some(Ok(Err(err))) = self.answering.take().map(JoinHandle::join) {
+            log::warn!("the fake device stopped answering: {}", err);
+        }
+        let mut event = vec![0; EVENT_SIZE];
+        event[..4].copy_from_slice(&UHID_DESTROY.to_ne_bytes());
+        if let Err(err) = (&self.shared.uhid).write_all(&event) {
+            log::warn!("failed to destroy the fake device: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FakeWiimote;
+    use crate::event::{EventKind, Key, KeyState};
+    use crate::{Channels, ConnectOptions, Device, Result};
+    use std::env;
+    use std::io;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Reads events until one matches, or fails after a second.
+    fn wait_for(device: &mut Device, matches: impl Fn(&EventKind) -> bool) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            match device.try_next_event()? {
+                Some(event) if matches(&event.kind) => return Ok(()),
+                Some(_) => {}
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        Err(io::Error::from(io::ErrorKind::TimedOut))
+    }
+
+    /// Checks whether the tests must fail instead of skipping themselves
+    /// if the fake can't be created.
+    fn uhid_required() -> bool {
+        env::var_os("XWIIMOTE_REQUIRE_UHID").is_some()
+    }
+
+    #[test]
+    fn enumerates_connects_and_dispatches() -> Result<()> {
+        let fake = match FakeWiimote::new() {
+            Ok(fake) => fake,
+            Err(_) if !uhid_required() => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let address = match fake.wait_for_address(Duration::from_secs(5)) {
+            Err(err) if err.kind() == io::ErrorKind::Unsupported && !uhid_required() => {
+                return Ok(())
+            }
+            address => address?,
+        };
+        let options = ConnectOptions {
+            blocking: false,
+            ..Default::default()
+        };
+        let mut device = Device::connect_with(&address, &options)?;
+        assert_eq!(device.mac_address()?, fake.mac_address());
+        device.open(Channels::CORE, false)?;
+
+        fake.press(Key::A, true)?;
+        wait_for(&mut device, |kind| {
+            matches!(kind, EventKind::Key(Key::A, KeyState::Down))
+        })?;
+        fake.press(Key::A, false)?;
+        wait_for(&mut device, |kind| {
+            matches!(kind, EventKind::Key(Key::A, KeyState::Up))
+        })?;
+
+        drop(fake);
+        wait_for(&mut device, |kind| matches!(kind, EventKind::Disconnected))
+    }
+}
@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::io;
+use std::ptr::NonNull;
+
+/// Calls `f` with a cleared `errno`, and returns the error it reported.
+///
+/// Some library functions return null both on failure and if no data
+/// is available, setting `errno` only in the former case. Errors caused
+/// by the call being unable to complete without blocking are ignored.
+pub(crate) fn with_errno<T>(f: impl FnOnce() -> T) -> (T, Option<io::Error>) {
+    unsafe { *errno_location() = 0 };
+    let value = f();
+    let err = match unsafe { *errno_location() } {
+        0 | libc::EAGAIN | libc::EINTR => None,
+        code => Some(io::Error::from_raw_os_error(code)),
+    };
+    (value, err)
+}
+
+/// Returns the location of `errno` for the current thread.
+pub(crate) fn errno_location() -> *mut libc::c_int {
+    #[cfg(target_os = "linux")]
+    return unsafe { libc::__errno_location() };
+    #[cfg(not(target_os = "linux"))]
+    return unsafe { libc::__error() };
+}
+
+/// An owned, nul-terminated string allocated by the `xwiimote`
+/// library (or `libudev`) through the C allocator.
+///
+/// The string is released with [`libc::free`] when dropped. It must
+/// never be freed by Rust's allocator, which may use a different heap.
+pub(crate) struct XwiiString(NonNull<libc::c_char>);
+
+impl XwiiString {
+    /// Takes ownership of the given C string, or returns `None`
+    /// if `raw` is null.
+    ///
+    /// # Safety
+    /// If non-null, `raw` must point to a nul-terminated string
+    /// allocated with `malloc` that is not freed elsewhere.
+    pub unsafe fn from_raw(raw: *mut libc::c_char) -> Option<Self> {
+        NonNull::new(raw).map(Self)
+    }
+
+    /// Returns the string as a C string slice.
+    pub fn as_c_str(&self) -> &CStr {
+        // Safety: guaranteed to be nul-terminated by `from_raw`.
+        unsafe { CStr::from_ptr(self.0.as_ptr()) }
+    }
+
+    /// Converts the string to UTF-8, replacing invalid sequences
+    /// with the replacement character.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        self.as_c_str().to_string_lossy()
+    }
+}
+
+impl Drop for XwiiString {
+    fn drop(&mut self) {
+        unsafe { libc::free(self.0.as_ptr().cast()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::XwiiString;
+
+    /// Copies the string into a `malloc`-allocated buffer, as the
+    /// `xwiimote` library would.
+    fn c_alloc(str: &[u8]) -> *mut libc::c_char {
+        unsafe {
+            let raw = libc::malloc(str.len() + 1) as *mut u8;
+            assert!(!raw.is_null());
+            std::ptr::copy_nonoverlapping(str.as_ptr(), raw, str.len());
+            *raw.add(str.len()) = 0;
+            raw.cast()
+        }
+    }
+
+    #[test]
+    fn null_is_none() {
+        assert!(unsafe { XwiiString::from_raw(std::ptr::null_mut()) }.is_none());
+    }
+
+    #[test]
+    fn reads_and_frees() {
+        let str = unsafe { XwiiString::from_raw(c_alloc(b"nunchuk")) }.unwrap();
+        assert_eq!(str.as_c_str().to_bytes(), b"nunchuk");
+        assert_eq!(str.to_string_lossy(), "nunchuk");
+        // Dropping must release the buffer through `free`, matching
+        // the `malloc` above.
+    }
+
+    #[test]
+    fn lossy_conversion() {
+        let str = unsafe { XwiiString::from_raw(c_alloc(b"gen\xffic")) }.unwrap();
+        assert_eq!(str.to_string_lossy(), "gen\u{fffd}ic");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn reports_errno() {
+        use super::with_errno;
+
+        let (_, err) = with_errno(|| unsafe { libc::close(-1) });
+        assert_eq!(err.and_then(|err| err.raw_os_error()), Some(libc::EBADF));
+
+        // Reading an empty non-blocking file is not an error.
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        let mut value = 0u64;
+        let (res_code, err) =
+            with_errno(|| unsafe { libc::read(fd, &mut value as *mut u64 as *mut _, 8) });
+        assert_eq!(res_code, -1);
+        assert!(err.is_none());
+        unsafe { libc::close(fd) };
+    }
+}
@@ -0,0 +1,208 @@
+//! Smoothing of noisy pointer positions.
+//!
+//! The IR camera positions jitter by a few pixels even when the remote
+//! is held still. A fixed low-pass filter removes the jitter, but makes
+//! the cursor lag behind fast movements. The [One Euro filter] adapts
+//! its cutoff frequency to the speed instead: slow movements are heavily
+//! smoothed, while fast movements are followed closely.
+//!
+//! [One Euro filter]: https://gery.casiez.net/1euro/
+use crate::Result;
+use futures::{Stream, TryStreamExt};
+use std::f32::consts::PI;
+use std::time::{Duration, SystemTime};
+
+/// The parameters of a [`OneEuroFilter`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OneEuroConfig {
+    /// The cutoff frequency at rest, in Hz. Lower values remove more
+    /// jitter.
+    pub min_cutoff: f32,
+    /// The increase of the cutoff frequency per unit of speed. Higher
+    /// values reduce the lag of fast movements.
+    pub beta: f32,
+    /// The cutoff frequency used to smooth the speed, in Hz.
+    pub derivative_cutoff: f32,
+}
+
+impl Default for OneEuroConfig {
+    /// Parameters for pointer positions in IR camera pixels, or in
+    /// screen pixels of a similar resolution.
+    fn default() -> Self {
+        Self {
+            min_cutoff: 1.0,
+            beta: 0.05,
+            derivative_cutoff: 1.0,
+        }
+    }
+}
+
+/// The filter output for the previous sample.
+#[derive(Copy, Clone, Debug)]
+struct Sample {
+    value: f32,
+    derivative: f32,
+    time: SystemTime,
+}
+
+/// A One Euro filter of a single value.
+#[derive(Clone, Debug)]
+pub struct OneEuroFilter {
+    config: OneEuroConfig,
+    last: Option<Sample>,
+}
+
+impl OneEuroFilter {
+    /// Creates a filter with the given parameters.
+    pub fn new(config: OneEuroConfig) -> Self {
+        Self { config, last: None }
+    }
+
+    /// Returns the smoothing factor for the given cutoff frequency.
+    fn alpha(cutoff: f32, elapsed: f32) -> f32 {
+        let tau = 1.0 / (2.0 * PI * cutoff);
+        1.0 / (1.0 + tau / elapsed)
+    }
+
+    /// Filters the value sampled at the given time.
+    ///
+    /// Samples that are not newer than the previous one are ignored,
+    /// returning the previous output.
+    pub fn filter(&mut self, value: f32, time: SystemTime) -> f32 {
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                self.last = Some(Sample {
+                    value,
+                    derivative: 0.0,
+                    time,
+                });
+                return value;
+            }
+        };
+        let elapsed = match time.duration_since(last.time) {
+            Ok(elapsed) if !elapsed.is_zero() => elapsed.as_secs_f32(),
+            _ => return last.value,
+        };
+
+        let speed = (value - last.value) / elapsed;
+        let derivative_alpha = Self::alpha(self.config.derivative_cutoff, elapsed);
+        let derivative = last.derivative + derivative_alpha * (speed - last.derivative);
+
+        let cutoff = self.config.min_cutoff + self.config.beta * derivative.abs();
+        let value = last.value + Self::alpha(cutoff, elapsed) * (value - last.value);
+        self.last = Some(Sample {
+            value,
+            derivative,
+            time,
+        });
+        value
+    }
+
+    /// Extrapolates the last output by the given duration, using the
+    /// smoothed speed. Returns `None` if no value was filtered yet.
+    ///
+    /// Rendering the predicted value hides part of the input latency.
+    pub fn predict(&self, ahead: Duration) -> Option<f32> {
+        self.last
+            .map(|last| last.value + last.derivative * ahead.as_secs_f32())
+    }
+
+    /// Forgets the previous samples, e.g. after the pointer was lost.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// A One Euro filter of two-dimensional pointer positions.
+#[derive(Clone, Debug)]
+pub struct PointerFilter {
+    x: OneEuroFilter,
+    y: OneEuroFilter,
+}
+
+impl PointerFilter {
+    /// Creates a filter with the given parameters for both axes.
+    pub fn new(config: OneEuroConfig) -> Self {
+        Self {
+            x: OneEuroFilter::new(config),
+            y: OneEuroFilter::new(config),
+        }
+    }
+
+    /// Filters the position sampled at the given time.
+    pub fn filter(&mut self, (x, y): (f32, f32), time: SystemTime) -> (f32, f32) {
+        (self.x.filter(x, time), self.y.filter(y, time))
+    }
+
+    /// Extrapolates the last output by the given duration.
+    /// See [`OneEuroFilter::predict`].
+    pub fn predict(&self, ahead: Duration) -> Option<(f32, f32)> {
+        Some((self.x.predict(ahead)?, self.y.predict(ahead)?))
+    }
+
+    /// Forgets the previous samples.
+    pub fn reset(&mut self) {
+        self.x.reset();
+        self.y.reset();
+    }
+}
+
+impl Default for PointerFilter {
+    fn default() -> Self {
+        Self::new(OneEuroConfig::default())
+    }
+}
+
+/// Adapts a stream of timed pointer positions, e.g. obtained from
+/// [`ScreenCalibration::map_ir_to_screen`](crate::ir::ScreenCalibration::map_ir_to_screen),
+/// into a stream of smoothed positions.
+pub fn smooth_pointer<S>(
+    positions: S,
+    mut filter: PointerFilter,
+) -> impl Stream<Item = Result<(SystemTime, (f32, f32))>>
+where
+    S: Stream<Item = Result<(SystemTime, (f32, f32))>>,
+{
+    positions.map_ok(move |(time, position)| (time, filter.filter(position, time)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OneEuroConfig, OneEuroFilter};
+    use std::time::{Duration, SystemTime};
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn smooths_jitter() {
+        let mut filter = OneEuroFilter::new(OneEuroConfig::default());
+        let outputs: Vec<f32> = (0..100)
+            .map(|i| {
+                let jitter = if i % 2 == 0 { 2.0 } else { -2.0 };
+                filter.filter(500.0 + jitter, at(i * 10))
+            })
+            .collect();
+
+        // The jitter amplitude is reduced by an order of magnitude.
+        let last = &outputs[90..];
+        assert!(last.iter().all(|value| (value - 500.0).abs() < 0.5));
+        assert_eq!(filter.filter(0.0, at(990)), outputs[99]);
+    }
+
+    #[test]
+    fn follows_fast_movements() {
+        let mut filter = OneEuroFilter::new(OneEuroConfig::default());
+        let mut value = 0.0;
+        for i in 0..50 {
+            value = filter.filter(i as f32 * 20.0, at(i * 10));
+        }
+
+        // Moving at 2000 px/s, the output lags by a few samples at most.
+        assert!(value > 49.0 * 20.0 - 100.0);
+        let predicted = filter.predict(Duration::from_millis(10)).unwrap();
+        assert!(predicted > value);
+    }
+}
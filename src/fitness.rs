@@ -0,0 +1,343 @@
+//! Detection of steps, jumps and weight shifts on a Balance Board.
+//!
+//! A [`FitnessDetector`] tracks the distribution of weight across the
+//! four sensors of a Balance Board, and synthesizes the movements used
+//! by exergames from [`EventKind::BalanceBoard`] events. Use
+//! [`fitness_events`] to adapt an event stream.
+//!
+//! The [`Channels::BALANCE_BOARD`](crate::Channels::BALANCE_BOARD)
+//! channel must be open.
+use crate::event::{Event, EventKind};
+use crate::Result;
+use futures::{future, Stream, TryStreamExt};
+use std::time::{Duration, SystemTime};
+
+/// The thresholds used to classify movements.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FitnessConfig {
+    /// The minimum total weight for the board to be considered in use,
+    /// in kilograms.
+    pub min_weight_kg: f32,
+    /// The fraction of the standing weight below which the user is
+    /// considered to be in the air.
+    pub jump_fraction: f32,
+    /// The minimum time in the air to report a jump.
+    pub min_airtime: Duration,
+    /// The maximum time in the air to report a jump. Longer periods
+    /// mean the user stepped off the board.
+    pub max_airtime: Duration,
+    /// The fraction of the total weight below which a foot is
+    /// considered lifted.
+    pub lift_fraction: f32,
+    /// The offset of the center of pressure, from 0 (centered) to 1
+    /// (at the edge of the board), beyond which the weight is
+    /// considered shifted.
+    pub shift_threshold: f32,
+    /// The minimum time a foot must be lifted, or the weight shifted,
+    /// to report a step or a weight shift.
+    pub debounce: Duration,
+}
+
+impl Default for FitnessConfig {
+    fn default() -> Self {
+        Self {
+            min_weight_kg: 10.0,
+            jump_fraction: 0.1,
+            min_airtime: Duration::from_millis(60),
+            max_airtime: Duration::from_secs(1),
+            lift_fraction: 0.15,
+            shift_threshold: 0.35,
+            debounce: Duration::from_millis(150),
+        }
+    }
+}
+
+/// A foot of the user.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Foot {
+    /// The left foot, on the side of the power button.
+    Left,
+    /// The right foot.
+    Right,
+}
+
+/// A direction in which the weight is shifted.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Shift {
+    /// Towards the left side.
+    Left,
+    /// Towards the right side.
+    Right,
+    /// Towards the front, away from the power button edge.
+    Forward,
+    /// Towards the back.
+    Backward,
+}
+
+/// A movement on the board.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Movement {
+    /// The foot was lifted and put down again.
+    Step(Foot),
+    /// The user jumped, and was in the air for the given duration.
+    Jump {
+        /// The time between leaving and landing on the board.
+        airtime: Duration,
+    },
+    /// The weight was shifted in the given direction, with both feet
+    /// on the board. Reported once until the weight is centered again.
+    WeightShift(Shift),
+}
+
+/// A movement synthesized by a [`FitnessDetector`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FitnessEvent {
+    /// The movement.
+    pub movement: Movement,
+    /// The time of the event that completed the movement.
+    pub time: SystemTime,
+}
+
+/// Synthesizes [`FitnessEvent`]s from Balance Board [`Event`]s.
+#[derive(Clone, Default, Debug)]
+pub struct FitnessDetector {
+    config: FitnessConfig,
+    // The smoothed total weight while standing, in kernel units.
+    standing: Option<f32>,
+    // The time the user left the board, if in the air.
+    airborne: Option<SystemTime>,
+    // The lifted foot, and the time it was lifted.
+    lifted: Option<(Foot, SystemTime)>,
+    // The direction the weight is shifted to, and since when.
+    shift: Option<(Shift, SystemTime)>,
+    // Whether the current shift was reported.
+    shift_reported: bool,
+}
+
+impl FitnessDetector {
+    /// The kernel reports weights in units of 10 grams.
+    const UNITS_PER_KG: f32 = 100.0;
+    /// The weight of the standing weight average given to each sample.
+    const STANDING_SMOOTHING: f32 = 0.05;
+
+    /// Creates a detector with the given thresholds.
+    pub fn new(config: FitnessConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Updates the detector with the given event, and returns the
+    /// movement completed by it, if any.
+    pub fn update(&mut self, event: &Event) -> Option<FitnessEvent> {
+        let weights = match event.kind {
+            EventKind::BalanceBoard(weights) => weights.map(|weight| weight.max(0) as f32),
+            _ => return None,
+        };
+        let time = event.time;
+        let since = |start: SystemTime| time.duration_since(start).unwrap_or_default();
+        let config = self.config;
+
+        // The sensors are ordered top right, bottom right, top left and
+        // bottom left, with the power button at the bottom.
+        let total: f32 = weights.iter().sum();
+        let right = (weights[0] + weights[1]) / total.max(1.0);
+        let front = (weights[0] + weights[2]) / total.max(1.0);
+
+        if let Some(left_at) = self.airborne {
+            let standing = self.standing.unwrap_or_default();
+            if total < standing / 2.0 {
+                if since(left_at) > config.max_airtime {
+                    // The user stepped off the board.
+                    *self = Self::new(config);
+                }
+                return None;
+            }
+            self.airborne = None;
+            let airtime = since(left_at);
+            return (airtime >= config.min_airtime).then_some(FitnessEvent {
+                movement: Movement::Jump { airtime },
+                time,
+            });
+        }
+
+        let standing = match self.standing {
+            Some(standing) => standing,
+            None if total >= config.min_weight_kg * Self::UNITS_PER_KG => {
+                self.standing = Some(total);
+                return None;
+            }
+            None => return None,
+        };
+        if total < standing * config.jump_fraction {
+            self.airborne = Some(time);
+            self.lifted = None;
+            return None;
+        }
+
+        // A foot is lifted when its side carries almost no weight, and
+        // put down once it carries twice as much.
+        let foot_weight = |foot| match foot {
+            Foot::Left => 1.0 - right,
+            Foot::Right => right,
+        };
+        match self.lifted {
+            Some((foot, lifted_at)) => {
+                if foot_weight(foot) >= config.lift_fraction * 2.0 {
+                    self.lifted = None;
+                    if since(lifted_at) >= config.debounce {
+                        return Some(FitnessEvent {
+                            movement: Movement::Step(foot),
+                            time,
+                        });
+                    }
+                }
+                return None;
+            }
+            None => {
+                for foot in [Foot::Left, Foot::Right] {
+                    if foot_weight(foot) < config.lift_fraction {
+                        self.lifted = Some((foot, time));
+                        self.shift = None;
+                        return None;
+                    }
+                }
+            }
+        }
+
+        // Both feet are on the board.
+        self.standing = Some(standing + (total - standing) * Self::STANDING_SMOOTHING);
+        let (x, y) = (right * 2.0 - 1.0, front * 2.0 - 1.0);
+        let direction = if x.abs() >= y.abs() {
+            match x {
+                x if x > config.shift_threshold => Some(Shift::Right),
+                x if x < -config.shift_threshold => Some(Shift::Left),
+                _ => None,
+            }
+        } else {
+            match y {
+                y if y > config.shift_threshold => Some(Shift::Forward),
+                y if y < -config.shift_threshold => Some(Shift::Backward),
+                _ => None,
+            }
+        };
+        match (direction, self.shift) {
+            (Some(direction), Some((current, shifted_at))) if direction == current => {
+                if !self.shift_reported && since(shifted_at) >= config.debounce {
+                    self.shift_reported = true;
+                    return Some(FitnessEvent {
+                        movement: Movement::WeightShift(direction),
+                        time,
+                    });
+                }
+            }
+            (Some(direction), _) => {
+                self.shift = Some((direction, time));
+                self.shift_reported = false;
+            }
+            (None, _) => {
+                // Require the weight to be roughly centered again.
+                if x.abs().max(y.abs()) < config.shift_threshold / 2.0 {
+                    self.shift = None;
+                    self.shift_reported = false;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Adapts a stream of events into a stream of the movements
+/// synthesized by `detector`.
+pub fn fitness_events<S>(
+    events: S,
+    mut detector: FitnessDetector,
+) -> impl Stream<Item = Result<FitnessEvent>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(detector.update(&event))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FitnessConfig, FitnessDetector, Foot, Movement, Shift};
+    use crate::event::{Event, EventKind};
+    use std::time::{Duration, SystemTime};
+
+    /// Feeds the weights every 10 ms from the given time, and returns
+    /// the reported movements.
+    fn feed(detector: &mut FitnessDetector, start_ms: u64, weights: &[[i32; 4]]) -> Vec<Movement> {
+        weights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &weights)| {
+                detector.update(&Event::at(
+                    SystemTime::UNIX_EPOCH + Duration::from_millis(start_ms + i as u64 * 10),
+                    EventKind::BalanceBoard(weights),
+                ))
+            })
+            .map(|event| event.movement)
+            .collect()
+    }
+
+    const STANDING: [i32; 4] = [1750; 4];
+
+    fn standing_detector() -> FitnessDetector {
+        let mut detector = FitnessDetector::new(FitnessConfig::default());
+        assert!(feed(&mut detector, 0, &[STANDING; 10]).is_empty());
+        detector
+    }
+
+    #[test]
+    fn detects_jump() {
+        let mut detector = standing_detector();
+        let mut weights = vec![[20, 0, 10, 0]; 30];
+        weights.push(STANDING);
+        let movements = feed(&mut detector, 100, &weights);
+        assert_eq!(
+            movements,
+            [Movement::Jump {
+                airtime: Duration::from_millis(300)
+            }]
+        );
+
+        // Stepping off the board is not a jump.
+        let movements = feed(&mut detector, 1000, &[[0; 4]; 200]);
+        assert!(movements.is_empty());
+        assert!(feed(&mut detector, 3000, &[STANDING]).is_empty());
+    }
+
+    #[test]
+    fn detects_steps_with_debounce() {
+        let mut detector = standing_detector();
+        // The right foot carries all the weight.
+        let mut weights = vec![[3500, 3500, 0, 0]; 20];
+        weights.extend([STANDING; 5]);
+        assert_eq!(
+            feed(&mut detector, 100, &weights),
+            [Movement::Step(Foot::Left)]
+        );
+
+        // Too short to be a step.
+        let mut weights = vec![[0, 0, 3500, 3500]; 5];
+        weights.push(STANDING);
+        assert!(feed(&mut detector, 1000, &weights).is_empty());
+    }
+
+    #[test]
+    fn detects_weight_shift_once() {
+        let mut detector = standing_detector();
+        let leaning = [2000, 1000, 3500, 500];
+        let movements = feed(&mut detector, 100, &[leaning; 50]);
+        assert_eq!(movements, [Movement::WeightShift(Shift::Forward)]);
+
+        let mut weights = vec![STANDING];
+        weights.extend([leaning; 20]);
+        assert_eq!(
+            feed(&mut detector, 1000, &weights),
+            [Movement::WeightShift(Shift::Forward)]
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Rumble through the force-feedback interface of the kernel.
+//!
+//! The kernel driver exposes the rumble motor as an `FF_RUMBLE` effect
+//! on the core input device of a remote. [`ForceFeedback`] uploads an
+//! effect with a magnitude and duration, and the kernel times it, so the
+//! motor stops on time even if the application is busy. This is an
+//! alternative to [`Device::rumble`] and the pulses of
+//! [`Device::set_rumble_intensity`], which are timed by the library.
+//!
+//! The driver only switches the motor on or off: any nonzero magnitude
+//! plays at full speed. Use [`Device::set_rumble_intensity`] for partial
+//! intensities.
+//!
+//! Requires the `evdev` feature, and write access to the core node in
+//! `/dev/input`.
+use crate::{input, Channels, Device, Result};
+use ::evdev::raw_stream::RawDevice;
+use ::evdev::{FFEffect, FFEffectData, FFEffectKind, FFEffectType, FFReplay, FFTrigger};
+use std::io;
+use std::time::Duration;
+
+/// A rumble effect played by the kernel on the motor of a device.
+///
+/// The effect is stopped and removed when dropped.
+pub struct ForceFeedback {
+    node: RawDevice,
+    effect: Option<FFEffect>,
+}
+
+impl ForceFeedback {
+    /// Opens the core evdev node of the device.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if the core channel has no
+    /// input device, e.g. on devices without buttons, and with
+    /// [`io::ErrorKind::Unsupported`] if the kernel doesn't support
+    /// rumble effects on it.
+    pub fn open(device: &Device) -> Result<Self> {
+        let name = input::channel_name(Channels::CORE).expect("core channel has a name");
+        let devnode = input::named_input_node(&device.syspath(), name)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "the device has no core input node")
+        })?;
+        let node = RawDevice::open(devnode)?;
+        let supports_rumble = node
+            .supported_ff()
+            .is_some_and(|supported| supported.contains(FFEffectType::FF_RUMBLE));
+        if !supports_rumble {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the device has no force-feedback rumble",
+            ));
+        }
+        Ok(Self { node, effect: None })
+    }
+
+    /// Rumbles with the given magnitude, from 0 to 1, for the given
+    /// duration, replacing the effect being played.
+    ///
+    /// A zero duration rumbles until stopped. Durations are rounded down
+    /// to milliseconds, up to about 65 seconds.
+    pub fn play(&mut self, magnitude: f32, duration: Duration) -> Result<()> {
+        let data = rumble_effect(magnitude, duration);
+        let effect = match &mut self.effect {
+            Some(effect) => {
+                effect.update(data)?;
+                effect
+            }
+            None => self.effect.insert(self.node.upload_ff_effect(data)?),
+        };
+        effect.play(1)
+    }
+
+    /// Stops the effect being played, if any.
+    pub fn stop(&mut self) -> Result<()> {
+        match &mut self.effect {
+            Some(effect) => effect.stop(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the data of a rumble effect.
+fn rumble_effect(magnitude: f32, duration: Duration) -> FFEffectData {
+    let magnitude = (magnitude.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+    FFEffectData {
+        direction: 0,
+        trigger: FFTrigger::default(),
+        replay: FFReplay {
+            length: duration.as_millis().min(u16::MAX as u128) as u16,
+            delay: 0,
+        },
+        kind: FFEffectKind::Rumble {
+            strong_magnitude: magnitude,
+            weak_magnitude: magnitude,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rumble_effect;
+    use ::evdev::FFEffectKind;
+    use std::time::Duration;
+
+    #[test]
+    fn builds_rumble_effects() {
+        let effect = rumble_effect(0.5, Duration::from_millis(250));
+        assert_eq!(effect.replay.length, 250);
+        assert!(matches!(
+            effect.kind,
+            FFEffectKind::Rumble {
+                strong_magnitude: 32768,
+                weak_magnitude: 32768,
+            }
+        ));
+
+        let effect = rumble_effect(2.0, Duration::from_secs(100));
+        assert_eq!(effect.replay.length, u16::MAX);
+        assert!(matches!(
+            effect.kind,
+            FFEffectKind::Rumble {
+                strong_magnitude: u16::MAX,
+                ..
+            }
+        ));
+    }
+}
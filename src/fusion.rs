@@ -0,0 +1,273 @@
+//! Orientation from the Motion Plus gyroscope.
+//!
+//! [`IntegratedAngles`] integrates the angular velocities reported in
+//! [`EventKind::MotionPlus`] into yaw, pitch and roll angles. Gyroscope
+//! integration drifts over time; the accelerometer senses gravity, so
+//! it can optionally correct the pitch and roll. Nothing corrects the
+//! yaw, which should be reset from time to time, e.g. when the user
+//! recenters the remote.
+//!
+//! The angles are always in a canonical range: the yaw and roll wrap
+//! around at ±π, and the pitch stays within ±π/2, as if the remote
+//! was turned around rather than pointed over the top.
+use crate::calibration::AccelCalibration;
+use crate::event::{Event, EventKind};
+use crate::gyro::GyroScale;
+use crate::motion::Vector3;
+use crate::Result;
+use futures::{future, Stream, TryStreamExt};
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::time::SystemTime;
+
+/// The orientation of a remote, in radians.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Angles {
+    /// The rotation around the vertical axis, positive if turned
+    /// counterclockwise seen from above. Within ±π.
+    pub yaw: f32,
+    /// The rotation around the horizontal axis, positive if pointing
+    /// up. Within ±π/2.
+    pub pitch: f32,
+    /// The rotation around the pointing axis, positive if the right
+    /// side is down. Within ±π.
+    pub roll: f32,
+    /// The time of the last event that updated the angles.
+    pub time: SystemTime,
+}
+
+/// The correction of the pitch and roll by the accelerometer.
+#[derive(Copy, Clone, Debug)]
+pub struct AccelCorrection {
+    /// The accelerometer calibration of the device.
+    pub calibration: AccelCalibration,
+    /// The fraction of the difference between the integrated and the
+    /// sensed angles that is corrected per second.
+    pub rate: f32,
+    /// The deviation of the sensed acceleration from 1 g, in g, above
+    /// which the remote is considered to be moving. No correction is
+    /// made while moving, since gravity can't be told apart.
+    pub max_deviation: f32,
+}
+
+impl Default for AccelCorrection {
+    fn default() -> Self {
+        Self {
+            calibration: AccelCalibration::NOMINAL,
+            rate: 0.5,
+            max_deviation: 0.1,
+        }
+    }
+}
+
+/// The parameters of [`IntegratedAngles`].
+#[derive(Copy, Clone, Debug)]
+pub struct FusionConfig {
+    /// The factor applied to the angular velocities converted by a
+    /// [`GyroScale`], to correct the scale of a specific device.
+    pub gyro_gain: f32,
+    /// The accelerometer correction, or `None` to integrate the
+    /// gyroscope alone.
+    pub accel_correction: Option<AccelCorrection>,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        Self {
+            gyro_gain: 1.0,
+            accel_correction: None,
+        }
+    }
+}
+
+/// Integrates the Motion Plus angular velocities into angles.
+///
+/// The angles start at zero, i.e. the remote is assumed to be level
+/// and pointing forward when the first event is received.
+#[derive(Clone, Debug)]
+pub struct IntegratedAngles {
+    config: FusionConfig,
+    scale: GyroScale,
+    angles: Angles,
+    last_gyro: Option<SystemTime>,
+    last_accel: Option<SystemTime>,
+}
+
+impl IntegratedAngles {
+    /// Creates an integrator with the given parameters.
+    pub fn new(config: FusionConfig) -> Self {
+        Self {
+            config,
+            scale: GyroScale::new(),
+            angles: Angles {
+                yaw: 0.0,
+                pitch: 0.0,
+                roll: 0.0,
+                time: SystemTime::UNIX_EPOCH,
+            },
+            last_gyro: None,
+            last_accel: None,
+        }
+    }
+
+    /// Returns the current angles.
+    pub fn angles(&self) -> Angles {
+        self.angles
+    }
+
+    /// Resets the angles to zero, e.g. to recenter the yaw.
+    pub fn reset(&mut self) {
+        self.angles.yaw = 0.0;
+        self.angles.pitch = 0.0;
+        self.angles.roll = 0.0;
+    }
+
+    /// Updates the angles with the given event. Returns the new angles
+    /// if the event changed them.
+    pub fn update(&mut self, event: &Event) -> Option<Angles> {
+        match event.kind {
+            EventKind::MotionPlus { .. } => {
+                let velocity = self.scale.update(event)?;
+                let elapsed = elapsed(self.last_gyro.replace(event.time), event.time)?;
+                let scale = self.config.gyro_gain * elapsed;
+                let angles = &mut self.angles;
+                angles.yaw += velocity.z.to_radians() * scale;
+                angles.pitch += velocity.x.to_radians() * scale;
+                angles.roll += velocity.y.to_radians() * scale;
+            }
+            EventKind::Accelerometer { x, y, z } => {
+                let correction = self.config.accel_correction?;
+                let elapsed = elapsed(self.last_accel.replace(event.time), event.time)?;
+                let accel = correction.calibration.apply(Vector3 { x, y, z });
+                let magnitude = (accel.x.powi(2) + accel.y.powi(2) + accel.z.powi(2)).sqrt();
+                if (magnitude - 1.0).abs() > correction.max_deviation {
+                    return None;
+                }
+                let pitch = accel.y.atan2(accel.x.hypot(accel.z));
+                let roll = (-accel.x).atan2(accel.z);
+                let weight = (correction.rate * elapsed).min(1.0);
+                let angles = &mut self.angles;
+                angles.pitch += weight * (pitch - angles.pitch);
+                angles.roll += weight * wrap(roll - angles.roll);
+            }
+            _ => return None,
+        }
+        self.normalize();
+        self.angles.time = event.time;
+        Some(self.angles)
+    }
+
+    /// Brings the angles back to their canonical ranges.
+    fn normalize(&mut self) {
+        let angles = &mut self.angles;
+        angles.pitch = wrap(angles.pitch);
+        if angles.pitch.abs() > FRAC_PI_2 {
+            // Pointing over the top is the same as turning around,
+            // and rolling upside down.
+            angles.pitch = PI.copysign(angles.pitch) - angles.pitch;
+            angles.yaw += PI;
+            angles.roll += PI;
+        }
+        angles.yaw = wrap(angles.yaw);
+        angles.roll = wrap(angles.roll);
+    }
+}
+
+/// Returns the seconds elapsed since `last`, if known.
+fn elapsed(last: Option<SystemTime>, now: SystemTime) -> Option<f32> {
+    Some(now.duration_since(last?).ok()?.as_secs_f32())
+}
+
+/// Wraps an angle into the range from -π to π.
+fn wrap(angle: f32) -> f32 {
+    let angle = (angle + PI).rem_euclid(TAU) - PI;
+    // `rem_euclid` may round up to exactly `TAU`.
+    if angle >= PI {
+        angle - TAU
+    } else {
+        angle
+    }
+}
+
+/// Adapts a stream of events into a stream of the angles integrated by
+/// `angles`.
+pub fn integrated_angles<S>(
+    events: S,
+    mut angles: IntegratedAngles,
+) -> impl Stream<Item = Result<Angles>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(angles.update(&event))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccelCorrection, FusionConfig, IntegratedAngles};
+    use crate::event::{Event, EventKind};
+    use crate::gyro::GyroMode;
+    use std::f32::consts::{FRAC_PI_2, PI};
+    use std::time::{Duration, SystemTime};
+
+    fn at(millis: u64, kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH + Duration::from_millis(millis), kind)
+    }
+
+    fn gyro(x: f32, y: f32, z: f32) -> EventKind {
+        // The angular velocities, in radians per second, in slow mode.
+        let units = |rate: f32| {
+            let units = rate.to_degrees() * GyroMode::Slow.units_per_degree() / 9.0;
+            units.round() as i32 * 9
+        };
+        EventKind::MotionPlus {
+            x: units(x),
+            y: units(y),
+            z: units(z),
+        }
+    }
+
+    #[test]
+    fn integrates_and_wraps_angles() {
+        let config = FusionConfig::default();
+        let mut angles = IntegratedAngles::new(config);
+        assert_eq!(angles.update(&at(0, gyro(0.0, 0.0, 1.0))), None);
+        let turned = angles.update(&at(1000, gyro(0.0, 0.0, 1.0)));
+        assert!((turned.unwrap().yaw - 1.0).abs() < 0.01);
+
+        // Turning a further 3 rad wraps around.
+        let turned = angles.update(&at(4000, gyro(0.0, 0.0, 1.0)));
+        assert!((turned.unwrap().yaw - (4.0 - 2.0 * PI)).abs() < 0.01);
+
+        // Pointing over the top turns around.
+        angles.reset();
+        let over = angles.update(&at(6000, gyro(1.0, 0.0, 0.0))).unwrap();
+        assert!((over.pitch - (PI - 2.0)).abs() < 0.01);
+        assert!(over.pitch < FRAC_PI_2);
+        assert!((over.yaw.abs() - PI).abs() < 0.01);
+        assert!((over.roll.abs() - PI).abs() < 0.01);
+    }
+
+    #[test]
+    fn corrects_tilt_with_accelerometer() {
+        let config = FusionConfig {
+            accel_correction: Some(AccelCorrection {
+                rate: 10.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut angles = IntegratedAngles::new(config);
+        angles.update(&at(0, gyro(0.0, 0.0, 0.0)));
+        angles.update(&at(500, gyro(0.5, 0.0, 0.0)));
+        assert!((angles.angles().pitch - 0.25).abs() < 0.01);
+
+        // Lying flat, with 1 g along the z-axis.
+        let flat = EventKind::Accelerometer { x: 0, y: 0, z: 100 };
+        angles.update(&at(500, flat));
+        angles.update(&at(1000, flat));
+        assert!(angles.angles().pitch.abs() < 0.01);
+
+        // Shaking is not mistaken for gravity.
+        let shaken = EventKind::Accelerometer { x: 0, y: 0, z: 300 };
+        assert_eq!(angles.update(&at(1100, shaken)), None);
+    }
+}
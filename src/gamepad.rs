@@ -0,0 +1,290 @@
+//! Frame-synchronized input for game loops.
+//!
+//! Game loops read their input once per rendered frame, and react to
+//! what changed since the previous frame rather than to single events.
+//! A [`Gamepad`] owns a [`Device`]; [`Gamepad::poll_frame`] reads the
+//! pending events without blocking, and returns the [`Frame`] of changes:
+//!
+//! ```no_run
+//! # use xwiimote::event::{AxisId, Key};
+//! # use xwiimote::gamepad::{Button, Gamepad};
+//! # use xwiimote::Device;
+//! # fn run(device: Device) -> std::io::Result<()> {
+//! let mut gamepad = Gamepad::new(device);
+//! loop {
+//!     let frame = gamepad.poll_frame()?;
+//!     if frame.was_pressed(Button::Remote(Key::A)) {
+//!         // Jump.
+//!     }
+//!     let steering = gamepad.axis(AxisId::LeftStickX);
+//!     // Render the frame.
+//! }
+//! # }
+//! ```
+//!
+//! The buttons of every controller are reported, and the sticks,
+//! triggers and bars are normalized by an [`AxisNormalizer`].
+use crate::axis::AxisNormalizer;
+use crate::event::{
+    AxisId, ClassicControllerKey, DrumsKey, Event, EventKind, GuitarKey, Key, KeyState, NunchukKey,
+    ProControllerKey,
+};
+use crate::{Channels, Device, Result};
+use std::mem;
+
+/// A button of any controller.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Button {
+    /// A key of the Wii Remote.
+    Remote(Key),
+    /// A key of a Wii U Pro Controller.
+    ProController(ProControllerKey),
+    /// A key of a Classic Controller.
+    ClassicController(ClassicControllerKey),
+    /// A key of a Nunchuk.
+    Nunchuk(NunchukKey),
+    /// A key of the drums.
+    Drums(DrumsKey),
+    /// A key of a guitar.
+    Guitar(GuitarKey),
+}
+
+impl Button {
+    /// Returns the button and its state reported by a key event.
+    fn of(kind: &EventKind) -> Option<(Self, KeyState)> {
+        Some(match *kind {
+            EventKind::Key(key, state) => (Self::Remote(key), state),
+            EventKind::ProControllerKey(key, state) => (Self::ProController(key), state),
+            EventKind::ClassicControllerKey(key, state) => (Self::ClassicController(key), state),
+            EventKind::NunchukKey(key, state) => (Self::Nunchuk(key), state),
+            EventKind::DrumsKey(key, state) => (Self::Drums(key), state),
+            EventKind::GuitarKey(key, state) => (Self::Guitar(key), state),
+            _ => return None,
+        })
+    }
+
+    /// Returns the channel that reports the button.
+    pub fn channel(&self) -> Channels {
+        match self {
+            Self::Remote(_) => Channels::CORE,
+            Self::ProController(_) => Channels::PRO_CONTROLLER,
+            Self::ClassicController(_) => Channels::CLASSIC_CONTROLLER,
+            Self::Nunchuk(_) => Channels::NUNCHUK,
+            Self::Drums(_) => Channels::DRUMS,
+            Self::Guitar(_) => Channels::GUITAR,
+        }
+    }
+}
+
+/// The changes of the input of a [`Gamepad`] since the previous frame.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Frame {
+    /// The buttons pressed during the frame, in order. A button pressed
+    /// and released during the frame is in both lists.
+    pub pressed: Vec<Button>,
+    /// The buttons released during the frame, in order, including those
+    /// of unplugged controllers.
+    pub released: Vec<Button>,
+    /// The change of each axis that moved during the frame.
+    pub axis_deltas: Vec<(AxisId, f32)>,
+    /// The number of events read.
+    pub events: usize,
+}
+
+impl Frame {
+    /// Checks whether the button was pressed during the frame.
+    pub fn was_pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    /// Checks whether the button was released during the frame.
+    pub fn was_released(&self, button: Button) -> bool {
+        self.released.contains(&button)
+    }
+
+    /// Returns the change of the axis during the frame.
+    pub fn axis_delta(&self, axis: AxisId) -> f32 {
+        self.axis_deltas
+            .iter()
+            .find(|(moved, _)| *moved == axis)
+            .map_or(0.0, |(_, delta)| *delta)
+    }
+}
+
+/// A device whose input is read once per frame.
+pub struct Gamepad {
+    device: Device,
+    normalizer: AxisNormalizer,
+    held: Vec<Button>,
+    // The value of each axis, now and as of the previous frame.
+    axes: Vec<(AxisId, f32)>,
+    previous_axes: Vec<(AxisId, f32)>,
+}
+
+impl Gamepad {
+    /// Creates a gamepad reading the events of the given device, whose
+    /// axes are normalized with the ranges advertised by the kernel.
+    pub fn new(device: Device) -> Self {
+        Self::with_normalizer(device, AxisNormalizer::new())
+    }
+
+    /// Creates a gamepad whose axes are normalized by `normalizer`.
+    pub fn with_normalizer(device: Device, normalizer: AxisNormalizer) -> Self {
+        Self {
+            device,
+            normalizer,
+            held: Vec::new(),
+            axes: Vec::new(),
+            previous_axes: Vec::new(),
+        }
+    }
+
+    /// Returns the device.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Returns the device, e.g. to open channels.
+    pub fn device_mut(&mut self) -> &mut Device {
+        &mut self.device
+    }
+
+    /// Returns the device, dropping the state of the gamepad.
+    pub fn into_inner(self) -> Device {
+        self.device
+    }
+
+    /// Reads the pending events without blocking, and returns the
+    /// changes since the previous call. Call it once per frame.
+    pub fn poll_frame(&mut self) -> Result<Frame> {
+        let mut frame = Frame::default();
+        while let Some(event) = self.device.try_next_event()? {
+            frame.events += 1;
+            self.update(&event, &mut frame);
+        }
+        for &(axis, value) in &self.axes {
+            let previous = find(&self.previous_axes, axis).unwrap_or(0.0);
+            if value != previous {
+                frame.axis_deltas.push((axis, value - previous));
+            }
+        }
+        self.previous_axes.clone_from(&self.axes);
+        Ok(frame)
+    }
+
+    fn update(&mut self, event: &Event, frame: &mut Frame) {
+        if let Some((button, state)) = Button::of(&event.kind) {
+            let held = self.held.contains(&button);
+            match state {
+                KeyState::Down if !held => {
+                    self.held.push(button);
+                    frame.pressed.push(button);
+                }
+                KeyState::Up if held => {
+                    self.held.retain(|&other| other != button);
+                    frame.released.push(button);
+                }
+                _ => {}
+            }
+            return;
+        }
+        let removed = match event.kind {
+            EventKind::Other(watch) => watch.removed(),
+            EventKind::Disconnected => Channels::all(),
+            _ => Channels::empty(),
+        };
+        if !removed.is_empty() {
+            // Unplugged controllers don't report the release of their keys.
+            let (gone, held): (Vec<_>, _) = mem::take(&mut self.held)
+                .into_iter()
+                .partition(|button| removed.contains(button.channel()));
+            frame.released.extend(gone);
+            self.held = held;
+        }
+        for moved in self.normalizer.normalize(event).into_iter().flatten() {
+            if let EventKind::Axis { axis, value } = moved.kind {
+                match self.axes.iter_mut().find(|(known, _)| *known == axis) {
+                    Some((_, known)) => *known = value,
+                    None => self.axes.push((axis, value)),
+                }
+            }
+        }
+    }
+
+    /// Checks whether the button is held down, as of the last frame.
+    pub fn is_held(&self, button: Button) -> bool {
+        self.held.contains(&button)
+    }
+
+    /// Returns the buttons held down as of the last frame, in the order
+    /// they were pressed.
+    pub fn held(&self) -> &[Button] {
+        &self.held
+    }
+
+    /// Returns the value of the axis as of the last frame, or 0 if it
+    /// never moved.
+    pub fn axis(&self, axis: AxisId) -> f32 {
+        find(&self.axes, axis).unwrap_or(0.0)
+    }
+}
+
+/// Returns the value of the axis in the given list.
+fn find(axes: &[(AxisId, f32)], axis: AxisId) -> Option<f32> {
+    axes.iter()
+        .find(|(known, _)| *known == axis)
+        .map(|(_, value)| *value)
+}
+
+#[cfg(all(test, feature = "test-harness"))]
+mod tests {
+    use super::{Button, Gamepad};
+    use crate::event::{AxisId, Key, KeyState, NunchukKey};
+    use crate::harness::{connect, FakeEvent, FakeIface};
+    use crate::{Channels, Result};
+
+    #[test]
+    fn reports_changes_per_frame() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE | Channels::NUNCHUK)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::CORE | Channels::NUNCHUK, false)?;
+        let mut gamepad = Gamepad::new(device);
+        let nunchuk_move = |x| FakeEvent::abs(xwiimote_sys::EVENT_NUNCHUK_MOVE, &[(x, 0, 0)]);
+
+        fake.push_batch([
+            FakeEvent::key(Key::A, KeyState::Down),
+            FakeEvent::key(Key::B, KeyState::Down),
+            FakeEvent::key(Key::B, KeyState::Up),
+            nunchuk_move(60),
+        ]);
+        let frame = gamepad.poll_frame()?;
+        assert_eq!(frame.events, 4);
+        assert_eq!(
+            frame.pressed,
+            [Button::Remote(Key::A), Button::Remote(Key::B)]
+        );
+        assert_eq!(frame.released, [Button::Remote(Key::B)]);
+        assert_eq!(frame.axis_delta(AxisId::LeftStickX), 0.5);
+        assert!(gamepad.is_held(Button::Remote(Key::A)));
+
+        fake.push_batch([
+            FakeEvent::key_of(
+                xwiimote_sys::EVENT_NUNCHUK_KEY,
+                NunchukKey::Z,
+                KeyState::Down,
+            ),
+            nunchuk_move(-60),
+        ]);
+        let frame = gamepad.poll_frame()?;
+        assert_eq!(frame.pressed, [Button::Nunchuk(NunchukKey::Z)]);
+        assert_eq!(frame.axis_delta(AxisId::LeftStickX), -1.0);
+        assert_eq!(gamepad.axis(AxisId::LeftStickX), -0.5);
+
+        fake.push(FakeEvent::hotplug(Channels::CORE));
+        let frame = gamepad.poll_frame()?;
+        assert_eq!(frame.released, [Button::Nunchuk(NunchukKey::Z)]);
+        assert!(frame.axis_deltas.is_empty());
+        assert_eq!(gamepad.held(), [Button::Remote(Key::A)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! Units of the Motion Plus gyroscope.
+//!
+//! Each axis of the Motion Plus switches between a slow mode, for
+//! precise readings of slow rotations, and a fast mode with a wider
+//! range. The kernel decodes the mode bits to scale the readings of each
+//! axis, but doesn't report the mode. Since its scale factors don't match
+//! the hardware, the units of [`EventKind::MotionPlus`] change with the
+//! mode, and a fast rotation seems about half as fast as it is.
+//!
+//! A [`GyroScale`] infers the mode of each axis from the readings, and
+//! converts them to degrees per second:
+//!
+//! - Readings beyond the range of the slow mode are in fast mode.
+//! - The kernel multiplies slow readings by 9 and fast readings by 18,
+//!   so a reading that is an odd multiple of 9 is in slow mode, and a
+//!   run of even multiples suggests fast mode. This only works while the
+//!   [`MotionPlusNormalization`] is zero, since the library subtracts the
+//!   normalization offsets from the kernel readings.
+//!
+//! [`with_gyro_modes`] reports the inferred modes as events instead, so
+//! that integrators can tell a mode change from a sudden rotation.
+//!
+//! The nominal scales are accurate to a few percent, depending on the
+//! device. In slow mode, the resolution is about 0.05°/s and the range
+//! about ±410°/s; in fast mode, about 0.23°/s and ±1860°/s.
+//!
+//! [`MotionPlusNormalization`]: crate::MotionPlusNormalization
+use crate::event::{Event, EventKind};
+use crate::Result;
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
+use std::time::SystemTime;
+
+/// The mode of an axis of the Motion Plus gyroscope.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum GyroMode {
+    /// The precise mode, used for slow rotations.
+    Slow,
+    /// The wide-range mode, used for fast rotations.
+    Fast,
+}
+
+impl GyroMode {
+    /// The largest reading in slow mode, as scaled by the kernel.
+    pub const SLOW_LIMIT: i32 = 8192 * 9;
+
+    /// Returns the factor by which the kernel scales the hardware
+    /// readings in this mode.
+    fn kernel_factor(self) -> i32 {
+        match self {
+            Self::Slow => 9,
+            Self::Fast => 18,
+        }
+    }
+
+    /// Returns the number of [`EventKind::MotionPlus`] units per degree
+    /// per second in this mode.
+    pub fn units_per_degree(self) -> f32 {
+        // The hardware reports about 20 units per degree per second in
+        // slow mode, and 2000/440 times fewer in fast mode.
+        match self {
+            Self::Slow => 20.0 * self.kernel_factor() as f32,
+            Self::Fast => 20.0 * 440.0 / 2000.0 * self.kernel_factor() as f32,
+        }
+    }
+
+    /// Converts a reading in this mode to degrees per second.
+    pub fn to_degrees_per_second(self, value: i32) -> f32 {
+        value as f32 / self.units_per_degree()
+    }
+}
+
+/// A Motion Plus reading, in degrees per second.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AngularVelocity {
+    /// The rotational speed around the x-axis, i.e. pitch.
+    pub x: f32,
+    /// The rotational speed around the y-axis, i.e. roll.
+    pub y: f32,
+    /// The rotational speed around the z-axis, i.e. yaw.
+    pub z: f32,
+    /// The modes of the x, y and z axes.
+    pub modes: [GyroMode; 3],
+    /// The time of the event.
+    pub time: SystemTime,
+}
+
+/// Infers the mode of each axis of a Motion Plus, and converts its
+/// readings to degrees per second.
+///
+/// See the [module](self) docs for how the mode is inferred.
+#[derive(Clone, Debug)]
+pub struct GyroScale {
+    modes: [GyroMode; 3],
+    // The number of consecutive readings of each axis that suggested
+    // fast mode.
+    fast_readings: [u32; 3],
+}
+
+impl GyroScale {
+    /// The number of consecutive even multiples of 9 after which an
+    /// axis is assumed to be in fast mode. A slow axis produces such a
+    /// run with a probability of 1 in 2⁸.
+    const FAST_READINGS: u32 = 8;
+
+    /// Creates a converter that assumes all axes start in slow mode.
+    pub fn new() -> Self {
+        Self {
+            modes: [GyroMode::Slow; 3],
+            fast_readings: [0; 3],
+        }
+    }
+
+    /// Returns the inferred modes of the x, y and z axes.
+    pub fn modes(&self) -> [GyroMode; 3] {
+        self.modes
+    }
+
+    /// Converts the given event, if it is an [`EventKind::MotionPlus`]
+    /// event, updating the inferred modes.
+    pub fn update(&mut self, event: &Event) -> Option<AngularVelocity> {
+        let (x, y, z) = match event.kind {
+            EventKind::MotionPlus { x, y, z } => (x, y, z),
+            _ => return None,
+        };
+        Some(AngularVelocity {
+            x: self.update_axis(0, x),
+            y: self.update_axis(1, y),
+            z: self.update_axis(2, z),
+            modes: self.modes,
+            time: event.time,
+        })
+    }
+
+    fn update_axis(&mut self, axis: usize, value: i32) -> f32 {
+        let slow = GyroMode::Slow.kernel_factor();
+        let fast = GyroMode::Fast.kernel_factor();
+        if value.abs() > GyroMode::SLOW_LIMIT {
+            self.modes[axis] = GyroMode::Fast;
+        } else if value % slow != 0 {
+            // The reading is normalized, which hides the scale.
+        } else if value % fast != 0 {
+            self.modes[axis] = GyroMode::Slow;
+            self.fast_readings[axis] = 0;
+        } else {
+            self.fast_readings[axis] += 1;
+            if self.fast_readings[axis] >= Self::FAST_READINGS {
+                self.modes[axis] = GyroMode::Fast;
+            }
+        }
+        self.modes[axis].to_degrees_per_second(value)
+    }
+}
+
+impl Default for GyroScale {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Adapts a stream of events into a stream of angular velocities.
+///
+/// Events other than [`EventKind::MotionPlus`] are skipped.
+pub fn angular_velocities<S>(events: S) -> impl Stream<Item = Result<AngularVelocity>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    let mut scale = GyroScale::new();
+    events.try_filter_map(move |event| future::ready(Ok(scale.update(&event))))
+}
+
+/// Adapts a stream of events into a stream that also yields an
+/// [`EventKind::MotionPlusMode`] event whenever the inferred mode of
+/// the Motion Plus axes changes, before the event that changed it.
+///
+/// The first event is yielded with the first [`EventKind::MotionPlus`]
+/// event. The other events are yielded unchanged.
+pub fn with_gyro_modes<S>(events: S) -> impl Stream<Item = Result<Event>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    let mut scale = GyroScale::new();
+    let mut last = None;
+    events.flat_map(move |event| {
+        let mode = match &event {
+            Ok(event) => scale
+                .update(event)
+                .filter(|velocity| last.replace(velocity.modes) != Some(velocity.modes))
+                .map(|velocity| {
+                    let [x_fast, y_fast, z_fast] =
+                        velocity.modes.map(|mode| mode == GyroMode::Fast);
+                    Ok(Event {
+                        kind: EventKind::MotionPlusMode {
+                            x_fast,
+                            y_fast,
+                            z_fast,
+                        },
+                        key_code: None,
+                        ..*event
+                    })
+                }),
+            Err(_) => None,
+        };
+        stream::iter(mode.into_iter().chain([event]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{with_gyro_modes, GyroMode, GyroScale};
+    use crate::event::{Event, EventKind};
+    use futures::{executor, stream, TryStreamExt};
+    use std::time::SystemTime;
+
+    fn gyro(x: i32) -> Event {
+        Event::at(
+            SystemTime::UNIX_EPOCH,
+            EventKind::MotionPlus { x, y: 0, z: 9 },
+        )
+    }
+
+    #[test]
+    fn infers_modes() {
+        let mut scale = GyroScale::new();
+        let slow = scale.update(&gyro(180 * 10)).unwrap();
+        assert_eq!(slow.modes, [GyroMode::Slow; 3]);
+        assert!((slow.x - 10.0).abs() < 0.01);
+
+        // Beyond the slow range.
+        let fast = scale.update(&gyro(GyroMode::SLOW_LIMIT + 18)).unwrap();
+        assert_eq!(fast.modes[0], GyroMode::Fast);
+        assert!(fast.x > 900.0);
+
+        // An odd multiple of 9 is a slow reading.
+        scale.update(&gyro(9 * 7));
+        assert_eq!(scale.modes()[0], GyroMode::Slow);
+        for _ in 0..8 {
+            scale.update(&gyro(18 * 100));
+        }
+        assert_eq!(scale.modes()[0], GyroMode::Fast);
+        assert_eq!(scale.modes()[2], GyroMode::Slow);
+    }
+
+    #[test]
+    fn reports_mode_changes() {
+        let events = [9, 9, GyroMode::SLOW_LIMIT + 18, 9].map(|x| Ok(gyro(x)));
+        let kinds: Vec<_> = executor::block_on(
+            with_gyro_modes(stream::iter(events))
+                .map_ok(|event| event.kind)
+                .try_collect(),
+        )
+        .unwrap();
+        let modes: Vec<_> = kinds
+            .iter()
+            .filter_map(|kind| match *kind {
+                EventKind::MotionPlusMode { x_fast, .. } => Some(x_fast),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kinds.len(), 7);
+        assert!(matches!(kinds[0], EventKind::MotionPlusMode { .. }));
+        assert_eq!(modes, [false, true, false]);
+    }
+}
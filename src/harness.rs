@@ -0,0 +1,495 @@
+//! Fake devices for integration tests.
+//!
+//! A [`FakeIface`] stands in for the `xwiimote` interface of a remote.
+//! Connecting to its [address](FakeIface::address) yields a regular
+//! [`Device`](crate::Device), whose events are the canned
+//! [`FakeEvent`]s pushed to the fake, and are parsed and streamed by
+//! the same code as those of a real device. This exercises the paths
+//! that are hard to reproduce with hardware: batches of events read at
+//! once, `EAGAIN` between them, extensions being plugged, and the
+//! device going away.
+//!
+//! Requires the `test-harness` feature. Fakes are registered for the
+//! lifetime of the process, so devices may outlive them.
+use crate::event::{Key, KeyCode, KeyState, ProControllerKey};
+use crate::ffi::errno_location;
+use crate::{bail_if, Address, Channels, Result};
+use libc::{c_char, c_int, c_uint};
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use xwiimote_sys::{event, iface};
+
+/// The registered fakes.
+static FAKES: Lazy<Mutex<Vec<Arc<Shared>>>> = Lazy::new(Default::default);
+
+/// Returns the fake with the given interface pointer, if any.
+pub(crate) fn find(dev: *mut iface) -> Option<Arc<Shared>> {
+    let fakes = FAKES.lock().unwrap();
+    fakes
+        .iter()
+        .find(|fake| Arc::as_ptr(fake) as *mut iface == dev)
+        .cloned()
+}
+
+/// Returns the interface pointer of the fake with the given sysfs
+/// path, if any.
+pub(crate) fn find_at(syspath: &CStr) -> Option<*mut iface> {
+    let fakes = FAKES.lock().unwrap();
+    fakes
+        .iter()
+        .find(|fake| fake.syspath.as_c_str() == syspath)
+        .map(|fake| Arc::as_ptr(fake) as *mut iface)
+}
+
+/// Sets `errno` to `code`, and returns the negative error code like
+/// the library functions.
+fn fail(code: c_int) -> c_int {
+    unsafe { *errno_location() = code };
+    -code
+}
+
+/// An event to be read from a [`FakeIface`].
+#[derive(Copy, Clone)]
+pub struct FakeEvent {
+    raw: event,
+    // The available channels after the event is read.
+    available: Option<Channels>,
+}
+
+impl FakeEvent {
+    /// Creates an event from its raw representation, which may be
+    /// invalid, e.g. to test the handling of unknown key codes.
+    pub fn from_raw(raw: event) -> Self {
+        Self {
+            raw,
+            available: None,
+        }
+    }
+
+    fn new(type_: c_uint) -> Self {
+        let mut raw = event {
+            type_,
+            ..Default::default()
+        };
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        raw.time.tv_sec = since_epoch.as_secs() as _;
+        raw.time.tv_usec = since_epoch.subsec_micros() as _;
+        Self::from_raw(raw)
+    }
+
+    pub(crate) fn key_of<T: KeyCode + PartialEq>(type_: c_uint, key: T, state: KeyState) -> Self {
+        let mut fake = Self::new(type_);
+        let code = T::CODES
+            .iter()
+            .find(|(_, known)| *known == key)
+            .map(|&(code, _)| code)
+            .unwrap();
+        fake.raw.v.key = xwiimote_sys::event_key {
+            code,
+            state: state as c_uint,
+        };
+        fake
+    }
+
+    pub(crate) fn abs(type_: c_uint, values: &[(i32, i32, i32)]) -> Self {
+        let mut fake = Self::new(type_);
+        let mut abs = [xwiimote_sys::event_abs::default(); xwiimote_sys::ABS_NUM as usize];
+        for (slot, &(x, y, z)) in abs.iter_mut().zip(values) {
+            *slot = xwiimote_sys::event_abs { x, y, z };
+        }
+        fake.raw.v.abs = abs;
+        fake
+    }
+
+    /// A Wii Remote key event.
+    pub fn key(key: Key, state: KeyState) -> Self {
+        Self::key_of(xwiimote_sys::EVENT_KEY, key, state)
+    }
+
+    /// A Pro Controller key event.
+    pub fn pro_controller_key(key: ProControllerKey, state: KeyState) -> Self {
+        Self::key_of(xwiimote_sys::EVENT_PRO_CONTROLLER_KEY, key, state)
+    }
+
+    /// An accelerometer event.
+    pub fn accelerometer(x: i32, y: i32, z: i32) -> Self {
+        Self::abs(xwiimote_sys::EVENT_ACCEL, &[(x, y, z)])
+    }
+
+    /// A Motion Plus event.
+    pub fn motion_plus(x: i32, y: i32, z: i32) -> Self {
+        Self::abs(xwiimote_sys::EVENT_MOTION_PLUS, &[(x, y, z)])
+    }
+
+    /// A drums event, with the analog stick position and the velocity
+    /// of each pad, in the order of
+    /// [`DrumsPad::ALL`](crate::event::DrumsPad::ALL).
+    pub fn drums_move(x: i32, y: i32, velocities: [u8; 7]) -> Self {
+        let mut values = vec![(x, y, 0)];
+        values.extend(velocities.map(|velocity| (velocity.into(), 0, 0)));
+        Self::abs(xwiimote_sys::EVENT_DRUMS_MOVE, &values)
+    }
+
+    /// A hot-plug event, after which the given channels are available.
+    /// Open channels that become unavailable are closed.
+    pub fn hotplug(available: Channels) -> Self {
+        let mut fake = Self::new(xwiimote_sys::EVENT_WATCH);
+        fake.available = Some(available);
+        fake
+    }
+
+    /// The event reported once the device goes away. Every channel is
+    /// closed.
+    pub fn gone() -> Self {
+        let mut fake = Self::new(xwiimote_sys::EVENT_GONE);
+        fake.available = Some(Channels::empty());
+        fake
+    }
+
+    /// Sets the time of the event.
+    pub fn at(mut self, time: SystemTime) -> Self {
+        let since_epoch = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.raw.time.tv_sec = since_epoch.as_secs() as _;
+        self.raw.time.tv_usec = since_epoch.subsec_micros() as _;
+        self
+    }
+}
+
+struct State {
+    // Each batch is read at once, and followed by `EAGAIN`.
+    batches: VecDeque<VecDeque<FakeEvent>>,
+    available: c_uint,
+    opened: c_uint,
+    rumble: bool,
+    leds: [bool; 4],
+    battery: u8,
+    devtype: CString,
+    extension: CString,
+    mp_normalization: [i32; 4],
+}
+
+/// The state of a fake, shared with the devices connected to it.
+pub(crate) struct Shared {
+    syspath: CString,
+    // An `eventfd` that is readable while events are queued.
+    fd: RawFd,
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn signal(&self) {
+        let value = 1u64;
+        unsafe { libc::write(self.fd, &value as *const u64 as *const _, 8) };
+    }
+
+    fn clear(&self) {
+        let mut value = 0u64;
+        unsafe { libc::read(self.fd, &mut value as *mut u64 as *mut _, 8) };
+    }
+
+    pub unsafe fn iface_get_syspath(&self) -> *const c_char {
+        self.syspath.as_ptr()
+    }
+
+    pub unsafe fn iface_get_fd(&self) -> c_int {
+        self.fd
+    }
+
+    pub unsafe fn iface_watch(&self, _watch: bool) -> c_int {
+        0
+    }
+
+    pub unsafe fn iface_open(&self, ifaces: c_uint) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        let requested = ifaces & !xwiimote_sys::IFACE_WRITABLE;
+        state.opened |= requested & state.available;
+        if requested & !state.available != 0 {
+            return fail(libc::ENODEV);
+        }
+        0
+    }
+
+    pub unsafe fn iface_close(&self, ifaces: c_uint) {
+        self.state.lock().unwrap().opened &= !ifaces;
+    }
+
+    pub unsafe fn iface_opened(&self) -> c_uint {
+        self.state.lock().unwrap().opened
+    }
+
+    pub unsafe fn iface_available(&self) -> c_uint {
+        self.state.lock().unwrap().available
+    }
+
+    pub unsafe fn iface_dispatch(&self, ev: *mut event, _size: usize) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        let batch = match state.batches.front_mut() {
+            Some(batch) => batch,
+            None => return fail(libc::EAGAIN),
+        };
+        match batch.pop_front() {
+            Some(fake) => {
+                if let Some(available) = fake.available {
+                    state.available = available.bits();
+                    state.opened &= state.available;
+                }
+                *ev = fake.raw;
+                0
+            }
+            None => {
+                // The batch ended; the next one is announced separately.
+                state.batches.pop_front();
+                self.clear();
+                if !state.batches.is_empty() {
+                    self.signal();
+                }
+                fail(libc::EAGAIN)
+            }
+        }
+    }
+
+    pub unsafe fn iface_rumble(&self, on: bool) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if state.opened & xwiimote_sys::IFACE_CORE == 0 {
+            return fail(libc::ENODEV);
+        }
+        state.rumble = on;
+        0
+    }
+
+    pub unsafe fn iface_get_led(&self, led: c_uint, out: *mut bool) -> c_int {
+        match self.state.lock().unwrap().leds.get(led as usize - 1) {
+            Some(&on) => {
+                *out = on;
+                0
+            }
+            None => fail(libc::EINVAL),
+        }
+    }
+
+    pub unsafe fn iface_set_led(&self, led: c_uint, on: bool) -> c_int {
+        match self.state.lock().unwrap().leds.get_mut(led as usize - 1) {
+            Some(state) => {
+                *state = on;
+                0
+            }
+            None => fail(libc::EINVAL),
+        }
+    }
+
+    pub unsafe fn iface_get_battery(&self, capacity: *mut u8) -> c_int {
+        *capacity = self.state.lock().unwrap().battery;
+        0
+    }
+
+    pub unsafe fn iface_get_devtype(&self, out: *mut *mut c_char) -> c_int {
+        // Released by the caller with `free`.
+        *out = libc::strdup(self.state.lock().unwrap().devtype.as_ptr());
+        0
+    }
+
+    pub unsafe fn iface_get_extension(&self, out: *mut *mut c_char) -> c_int {
+        *out = libc::strdup(self.state.lock().unwrap().extension.as_ptr());
+        0
+    }
+
+    pub unsafe fn iface_set_mp_normalization(&self, x: i32, y: i32, z: i32, factor: i32) {
+        self.state.lock().unwrap().mp_normalization = [x, y, z, factor];
+    }
+
+    pub unsafe fn iface_get_mp_normalization(
+        &self,
+        x: *mut i32,
+        y: *mut i32,
+        z: *mut i32,
+        factor: *mut i32,
+    ) {
+        let [x_value, y_value, z_value, factor_value] = self.state.lock().unwrap().mp_normalization;
+        (*x, *y, *z, *factor) = (x_value, y_value, z_value, factor_value);
+    }
+}
+
+/// A fake `xwiimote` interface, whose events are pushed by the test.
+pub struct FakeIface {
+    shared: Arc<Shared>,
+}
+
+impl FakeIface {
+    /// Creates a fake Wii Remote with the given available channels.
+    pub fn new(available: Channels) -> Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        bail_if!(fd == -1);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let syspath = format!("/nonexistent/xwiimote-fake/{}/{}", std::process::id(), id);
+        let shared = Arc::new(Shared {
+            syspath: CString::new(syspath).unwrap(),
+            fd,
+            state: Mutex::new(State {
+                batches: VecDeque::new(),
+                available: available.bits(),
+                opened: 0,
+                rumble: false,
+                leds: [false; 4],
+                battery: 100,
+                devtype: CString::new("gen20").unwrap(),
+                extension: CString::new("none").unwrap(),
+                mp_normalization: [0; 4],
+            }),
+        });
+        FAKES.lock().unwrap().push(shared.clone());
+        Ok(Self { shared })
+    }
+
+    /// Returns the address to connect to the fake.
+    pub fn address(&self) -> Address {
+        let syspath = self.shared.syspath.to_str().unwrap();
+        Address::from(PathBuf::from(syspath))
+    }
+
+    /// Queues an event, to be read on its own.
+    pub fn push(&self, event: FakeEvent) {
+        self.push_batch([event]);
+    }
+
+    /// Queues a batch of events, which are read at once. Reading past
+    /// the end of a batch fails with `EAGAIN`, even if more batches
+    /// are queued.
+    pub fn push_batch(&self, events: impl IntoIterator<Item = FakeEvent>) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.batches.push_back(events.into_iter().collect());
+        if state.batches.len() == 1 {
+            self.shared.signal();
+        }
+    }
+
+    /// Returns the number of queued events.
+    pub fn pending(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.batches.iter().map(VecDeque::len).sum()
+    }
+
+    /// Returns the open channels.
+    pub fn opened(&self) -> Channels {
+        Channels::from_bits_truncate(self.shared.state.lock().unwrap().opened)
+    }
+
+    /// Checks whether the rumble motor is on.
+    pub fn rumble(&self) -> bool {
+        self.shared.state.lock().unwrap().rumble
+    }
+
+    /// Sets the battery level reported by the device.
+    pub fn set_battery(&self, level: u8) {
+        self.shared.state.lock().unwrap().battery = level;
+    }
+
+    /// Sets the extension name reported by the device, e.g. `nunchuk`.
+    pub fn set_extension(&self, extension: &str) {
+        self.shared.state.lock().unwrap().extension = CString::new(extension).unwrap();
+    }
+}
+
+/// Connects to the fake without blocking.
+#[cfg(test)]
+pub(crate) fn connect(fake: &FakeIface) -> Result<crate::Device> {
+    connect_to(&fake.address())
+}
+
+/// Connects to the device at the given address without blocking.
+#[cfg(test)]
+pub(crate) fn connect_to(address: &Address) -> Result<crate::Device> {
+    let options = crate::ConnectOptions {
+        blocking: false,
+        ..Default::default()
+    };
+    crate::Device::connect_with(address, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connect, FakeEvent, FakeIface};
+    use crate::event::{EventKind, Key, KeyState};
+    use crate::report::Report;
+    use crate::{Channels, Result};
+    use futures::{executor, StreamExt};
+    use std::io;
+
+    #[test]
+    fn streams_batches_until_gone() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::CORE | Channels::ACCELEROMETER, true)?;
+        assert_eq!(fake.opened(), Channels::CORE | Channels::ACCELEROMETER);
+
+        fake.push_batch([
+            FakeEvent::key(Key::A, KeyState::Down),
+            FakeEvent::accelerometer(1, 2, 3),
+        ]);
+        fake.push(FakeEvent::hotplug(Channels::CORE | Channels::NUNCHUK));
+        fake.push(FakeEvent::gone());
+
+        let kinds: Vec<_> = executor::block_on(
+            device
+                .events()?
+                .map(|event| event.map(|event| event.kind))
+                .collect::<Vec<_>>(),
+        )
+        .into_iter()
+        .collect::<Result<_>>()?;
+        assert!(matches!(kinds[0], EventKind::Key(Key::A, KeyState::Down)));
+        assert!(matches!(
+            kinds[1],
+            EventKind::Accelerometer { x: 1, y: 2, z: 3 }
+        ));
+        match kinds[2] {
+            EventKind::Other(watch) => {
+                assert_eq!(watch.available_after, Channels::CORE | Channels::NUNCHUK)
+            }
+            kind => panic!("unexpected event {:?}", kind),
+        }
+        assert!(matches!(
+            kinds[3],
+            EventKind::ChannelClosed(Channels::ACCELEROMETER)
+        ));
+        assert!(matches!(kinds[4], EventKind::Disconnected));
+        assert_eq!(fake.pending(), 0);
+        assert_eq!(device.all_open(), Channels::empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reads_until_eagain() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE)?;
+        let mut device = connect(&fake)?;
+        fake.push_batch([
+            FakeEvent::key(Key::One, KeyState::Down),
+            FakeEvent::key(Key::One, KeyState::Up),
+        ]);
+        fake.push(FakeEvent::key(Key::Two, KeyState::Down));
+
+        assert_eq!(device.try_next_event()?.unwrap().sequence, Some(0));
+        assert!(device.try_next_event()?.is_some());
+        assert!(device.try_next_event()?.is_none());
+        assert_eq!(device.try_next_event()?.unwrap().sequence, Some(2));
+
+        fake.set_battery(42);
+        assert_eq!(device.battery()?, 42);
+        // The fake has no sysfs entries.
+        assert_eq!(device.hidraw_node(), None);
+        let err = device.send_report(&Report::status_request()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+}
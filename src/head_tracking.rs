@@ -0,0 +1,144 @@
+//! Head tracking with IR glasses, as popularized by Johnny Lee.
+//!
+//! The Wii Remote is placed along the top or bottom edge of the screen,
+//! facing the user, who wears glasses with an IR LED on each side. The
+//! camera tracks the LEDs like the ends of a sensor bar, so the
+//! [estimated pose](crate::ir::estimate_pose) of the remote yields the
+//! position of the head relative to the screen. Rendering the scene from
+//! that position produces a "fish tank" virtual reality effect.
+use crate::event::{Event, EventKind, IrSource};
+use crate::ir::{self, SensorBarConfig};
+use crate::Result;
+use futures::{future, Stream, TryStreamExt};
+use std::time::SystemTime;
+
+/// The placement of the remote and the glasses.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HeadTrackingConfig {
+    /// The distance between the IR LEDs of the glasses, in millimeters.
+    pub led_spacing_mm: f32,
+    /// The height of the screen, in millimeters.
+    pub screen_height_mm: f32,
+    /// Whether the remote is placed above the screen, rather than
+    /// below it. The camera axis must be perpendicular to the screen.
+    pub camera_above_screen: bool,
+}
+
+impl Default for HeadTrackingConfig {
+    fn default() -> Self {
+        Self {
+            led_spacing_mm: 150.0,
+            screen_height_mm: 300.0,
+            camera_above_screen: false,
+        }
+    }
+}
+
+/// The position of the head relative to the center of the screen,
+/// in millimeters.
+///
+/// The x-axis points right and the y-axis points up, as seen by the
+/// user. The z-axis points from the screen towards the user.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HeadPosition {
+    /// The horizontal offset of the head.
+    pub x: f32,
+    /// The vertical offset of the head.
+    pub y: f32,
+    /// The distance of the head to the plane of the screen.
+    pub z: f32,
+    /// The time of the IR event.
+    pub time: SystemTime,
+}
+
+/// Estimates the position of the head from the IR sources of an
+/// [`EventKind::Ir`] event.
+///
+/// Returns `None` unless both LEDs of the glasses are visible.
+pub fn head_position(
+    sources: &[Option<IrSource>],
+    config: &HeadTrackingConfig,
+    time: SystemTime,
+) -> Option<HeadPosition> {
+    let glasses = SensorBarConfig {
+        spacing_mm: config.led_spacing_mm,
+        ..Default::default()
+    };
+    let pose = ir::estimate_pose(sources, &glasses, time)?;
+
+    // The camera faces the user, so its x-axis points to the left of
+    // the user, and its y-axis points down.
+    let (dx, dy) = (pose.yaw.tan(), pose.pitch.tan());
+    let z = pose.distance_mm / (dx * dx + dy * dy + 1.0).sqrt();
+    let (x, y) = (-dx * z, -dy * z);
+    let camera_y = if config.camera_above_screen {
+        config.screen_height_mm / 2.0
+    } else {
+        -config.screen_height_mm / 2.0
+    };
+    Some(HeadPosition {
+        x,
+        y: y + camera_y,
+        z,
+        time,
+    })
+}
+
+/// Adapts a stream of events into a stream of head positions.
+///
+/// IR events where the glasses are not visible are skipped.
+pub fn head_positions<S>(
+    events: S,
+    config: HeadTrackingConfig,
+) -> impl Stream<Item = Result<HeadPosition>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| {
+        let position = match &event.kind {
+            EventKind::Ir(sources) => head_position(sources, &config, event.time),
+            _ => None,
+        };
+        future::ready(Ok(position))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{head_position, HeadTrackingConfig};
+    use crate::event::IrSource;
+    use std::time::SystemTime;
+
+    #[test]
+    fn head_in_front_of_camera() {
+        let config = HeadTrackingConfig {
+            camera_above_screen: true,
+            ..Default::default()
+        };
+        let sources = [
+            Some(IrSource { x: 462, y: 384 }),
+            Some(IrSource { x: 562, y: 384 }),
+        ];
+        let head = head_position(&sources, &config, SystemTime::UNIX_EPOCH).unwrap();
+
+        // The head is level with the top edge of the screen.
+        assert!(head.x.abs() < 1e-3);
+        assert!((head.y - config.screen_height_mm / 2.0).abs() < 1e-3);
+        assert!(head.z > 0.0);
+    }
+
+    #[test]
+    fn head_moves_opposite_to_dots() {
+        let config = HeadTrackingConfig::default();
+        // The dots appear to the right of and above the camera axis.
+        let sources = [
+            Some(IrSource { x: 712, y: 184 }),
+            Some(IrSource { x: 812, y: 184 }),
+        ];
+        let head = head_position(&sources, &config, SystemTime::UNIX_EPOCH).unwrap();
+
+        assert!(head.x < 0.0);
+        assert!(head.y > -config.screen_height_mm / 2.0);
+        assert_eq!(head_position(&sources[..1], &config, head.time), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Processes competing for a device.
+//!
+//! A channel grabbed by another process with [`OpenMode::Exclusive`]
+//! (or by any other program using `EVIOCGRAB`, e.g. a game controller
+//! mapper) delivers no events to anyone else, and grabbing it fails
+//! with `EBUSY`. [`Device::open_with_mode`] reports such failures with
+//! a [`DeviceBusy`] error, which names the [`Holder`]s of the device.
+//!
+//! [`OpenMode::Exclusive`]: crate::OpenMode::Exclusive
+//! [`Device::open_with_mode`]: crate::Device::open_with_mode
+use crate::input;
+use crate::Result;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A process with a device node open.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Holder {
+    /// The process id.
+    pub pid: u32,
+    /// The command name of the process, e.g. `steam`.
+    pub command: String,
+}
+
+impl Holder {
+    /// Lists the other processes that have any of the given device
+    /// nodes open.
+    ///
+    /// Processes whose file descriptors can't be inspected, usually
+    /// those of other users, are skipped.
+    pub fn find(nodes: &[PathBuf]) -> Result<Vec<Self>> {
+        Self::find_in(Path::new("/proc"), nodes, std::process::id())
+    }
+
+    fn find_in(proc: &Path, nodes: &[PathBuf], own_pid: u32) -> Result<Vec<Self>> {
+        let mut holders = Vec::new();
+        for entry in fs::read_dir(proc)? {
+            let entry = entry?;
+            let pid = match entry.file_name().to_string_lossy().parse() {
+                Ok(pid) if pid != own_pid => pid,
+                _ => continue,
+            };
+            let holds = nodes
+                .iter()
+                .any(|node| input::fds_of(&entry.path(), node).is_ok_and(|fds| !fds.is_empty()));
+            if holds {
+                let command = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+                holders.push(Self {
+                    pid,
+                    command: command.trim_end().to_string(),
+                });
+            }
+        }
+        holders.sort_by_key(|holder| holder.pid);
+        Ok(holders)
+    }
+}
+
+/// The error of opening a device held by another process.
+///
+/// Wrapped in an [`io::Error`] of kind [`io::ErrorKind::ResourceBusy`];
+/// use [`DeviceBusy::from_io`] to inspect it.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DeviceBusy {
+    /// The busy device nodes.
+    pub nodes: Vec<PathBuf>,
+    /// The other processes with the nodes open. Empty if the holder
+    /// couldn't be identified, e.g. because it runs as another user,
+    /// or because it is another handle within this process.
+    pub holders: Vec<Holder>,
+}
+
+impl DeviceBusy {
+    /// Returns the details of a busy device error, if `err` is one.
+    pub fn from_io(err: &io::Error) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    pub(crate) fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::ResourceBusy, self)
+    }
+}
+
+impl fmt::Display for DeviceBusy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "device is busy")?;
+        let mut holders = self.holders.iter();
+        if let Some(holder) = holders.next() {
+            write!(f, ", held by {} ({})", holder.command, holder.pid)?;
+            for holder in holders {
+                write!(f, ", {} ({})", holder.command, holder.pid)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for DeviceBusy {}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceBusy, Holder};
+    use crate::Result;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    #[test]
+    fn finds_holders() -> Result<()> {
+        let proc = std::env::temp_dir().join(format!("xwiimote-proc-{}", std::process::id()));
+        for (pid, command, target) in [
+            ("41", "self", "/dev/input/event8"),
+            ("1200", "steam", "/dev/input/event8"),
+            ("1300", "bash", "/dev/null"),
+        ] {
+            let dir = proc.join(pid);
+            fs::create_dir_all(dir.join("fd"))?;
+            fs::write(dir.join("comm"), format!("{}\n", command))?;
+            symlink(target, dir.join("fd").join("3"))?;
+        }
+        fs::create_dir_all(proc.join("self"))?;
+
+        let nodes = [PathBuf::from("/dev/input/event8")];
+        let holders = Holder::find_in(&proc, &nodes, 41)?;
+        assert_eq!(
+            holders,
+            [Holder {
+                pid: 1200,
+                command: "steam".into()
+            }]
+        );
+
+        let err = DeviceBusy {
+            nodes: nodes.to_vec(),
+            holders,
+        }
+        .into_io();
+        assert_eq!(err.to_string(), "device is busy, held by steam (1200)");
+        assert_eq!(DeviceBusy::from_io(&err).unwrap().holders[0].pid, 1200);
+        fs::remove_dir_all(proc)
+    }
+}
@@ -0,0 +1,258 @@
+//! Closing high-rate channels while the user is idle.
+//!
+//! The accelerometer, Motion Plus and IR camera report data at about
+//! 100 Hz, which drains the batteries of the remote fast. An
+//! [`IdlePolicy`] decides when to close them: after some time without
+//! key presses, or while the application is paused. It reopens them
+//! once a key is pressed again, or when the application asks for them.
+//!
+//! The policy only returns [`IdleAction`]s, since the [`Device`] cannot
+//! be modified while streaming its events; apply them with
+//! [`IdlePolicy::apply`] between reads, e.g. with
+//! [`Device::try_next_event`].
+use crate::event::{Event, EventKind};
+use crate::{Channels, Device, Result};
+use futures::{future, Stream, TryStreamExt};
+use std::time::{Duration, SystemTime};
+
+/// The parameters of an [`IdlePolicy`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct IdleConfig {
+    /// The channels to close while idle.
+    pub channels: Channels,
+    /// The time without key events after which the user is idle.
+    pub timeout: Duration,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            channels: Channels::ACCELEROMETER | Channels::MOTION_PLUS | Channels::IR,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A change of the open channels decided by an [`IdlePolicy`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum IdleAction {
+    /// Close the open channels of [`IdleConfig::channels`].
+    Close,
+    /// Reopen the channels closed by the last [`IdleAction::Close`].
+    Reopen,
+}
+
+/// Decides when to close and reopen high-rate channels.
+#[derive(Clone, Debug)]
+pub struct IdlePolicy {
+    config: IdleConfig,
+    last_activity: Option<SystemTime>,
+    idle: bool,
+    paused: bool,
+    // The channels closed by `apply`, and those of them that were
+    // open in writable mode.
+    closed: Channels,
+    closed_writable: Channels,
+}
+
+impl IdlePolicy {
+    /// Creates a policy with the given parameters.
+    pub fn new(config: IdleConfig) -> Self {
+        Self {
+            config,
+            last_activity: None,
+            idle: false,
+            paused: false,
+            closed: Channels::empty(),
+            closed_writable: Channels::empty(),
+        }
+    }
+
+    /// Checks whether the channels should be closed.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Updates the policy with the given event, and returns the action
+    /// to take, if any.
+    ///
+    /// Key events count as activity. Since the closed channels report
+    /// events continuously while open, their events are enough to
+    /// notice the timeout; otherwise, call [`IdlePolicy::poll`].
+    pub fn update(&mut self, event: &Event) -> Option<IdleAction> {
+        if is_activity(&event.kind) {
+            if self.paused {
+                return None;
+            }
+            return self.wake(event.time);
+        }
+        self.last_activity.get_or_insert(event.time);
+        self.poll(event.time)
+    }
+
+    /// Returns [`IdleAction::Close`] if the timeout elapsed at `now`
+    /// since the last activity.
+    pub fn poll(&mut self, now: SystemTime) -> Option<IdleAction> {
+        let last_activity = self.last_activity?;
+        let elapsed = now.duration_since(last_activity).unwrap_or_default();
+        if self.idle || elapsed < self.config.timeout {
+            return None;
+        }
+        self.idle = true;
+        Some(IdleAction::Close)
+    }
+
+    /// Registers activity at `now`, e.g. because the application needs
+    /// the channels, and returns [`IdleAction::Reopen`] if they were
+    /// closed. Resumes the policy if paused.
+    pub fn wake(&mut self, now: SystemTime) -> Option<IdleAction> {
+        self.last_activity = Some(now);
+        self.paused = false;
+        if !self.idle {
+            return None;
+        }
+        self.idle = false;
+        Some(IdleAction::Reopen)
+    }
+
+    /// Pauses the policy until [`IdlePolicy::wake`] is called, e.g.
+    /// while the application is in the background. Returns
+    /// [`IdleAction::Close`] unless the channels were already closed.
+    ///
+    /// Key events don't reopen the channels while paused.
+    pub fn pause(&mut self) -> Option<IdleAction> {
+        self.paused = true;
+        if self.idle {
+            return None;
+        }
+        self.idle = true;
+        Some(IdleAction::Close)
+    }
+
+    /// Checks whether the policy is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Applies the given action to the device.
+    ///
+    /// Only the channels that were open are closed, and only those are
+    /// reopened afterwards, in the mode they were open in.
+    pub fn apply(&mut self, device: &mut Device, action: IdleAction) -> Result<()> {
+        match action {
+            IdleAction::Close => {
+                let open = device.all_open() & self.config.channels;
+                let writable = device.writable() & open;
+                device.close(open)?;
+                self.closed |= open;
+                self.closed_writable |= writable;
+            }
+            IdleAction::Reopen => {
+                let read_only = self.closed - self.closed_writable;
+                if !read_only.is_empty() {
+                    device.open(read_only, false)?;
+                }
+                if !self.closed_writable.is_empty() {
+                    device.open(self.closed_writable, true)?;
+                }
+                self.closed = Channels::empty();
+                self.closed_writable = Channels::empty();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        Self::new(IdleConfig::default())
+    }
+}
+
+fn is_activity(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Key(..)
+            | EventKind::ProControllerKey(..)
+            | EventKind::ClassicControllerKey(..)
+            | EventKind::NunchukKey(..)
+            | EventKind::DrumsKey(..)
+            | EventKind::GuitarKey(..)
+    )
+}
+
+/// Adapts a stream of events into a stream of the actions decided by
+/// `policy`.
+pub fn idle_actions<S>(events: S, mut policy: IdlePolicy) -> impl Stream<Item = Result<IdleAction>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(policy.update(&event))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdleAction, IdleConfig, IdlePolicy};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use std::time::{Duration, SystemTime};
+
+    fn at(secs: u64, kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH + Duration::from_secs(secs), kind)
+    }
+
+    fn accel(secs: u64) -> Event {
+        at(secs, EventKind::Accelerometer { x: 0, y: 0, z: 0 })
+    }
+
+    fn press(secs: u64) -> Event {
+        at(secs, EventKind::Key(Key::A, KeyState::Down))
+    }
+
+    #[test]
+    fn closes_after_timeout() {
+        let mut policy = IdlePolicy::new(IdleConfig {
+            timeout: Duration::from_secs(10),
+            ..Default::default()
+        });
+        assert_eq!(policy.update(&accel(0)), None);
+        assert_eq!(policy.update(&press(5)), None);
+        assert_eq!(policy.update(&accel(14)), None);
+        assert_eq!(policy.update(&accel(15)), Some(IdleAction::Close));
+        assert_eq!(policy.update(&accel(16)), None);
+        assert_eq!(policy.update(&press(20)), Some(IdleAction::Reopen));
+        assert!(!policy.is_idle());
+    }
+
+    #[test]
+    fn stays_closed_while_paused() {
+        let mut policy = IdlePolicy::default();
+        assert_eq!(policy.pause(), Some(IdleAction::Close));
+        assert_eq!(policy.pause(), None);
+        assert_eq!(policy.update(&press(1)), None);
+        assert_eq!(policy.wake(SystemTime::now()), Some(IdleAction::Reopen));
+        assert!(!policy.is_paused());
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn reopens_writable_channels() -> crate::Result<()> {
+        use crate::harness::{connect, FakeIface};
+        use crate::Channels;
+
+        let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER | Channels::IR)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::CORE | Channels::IR, true)?;
+        device.open(Channels::ACCELEROMETER, false)?;
+
+        let mut policy = IdlePolicy::default();
+        policy.apply(&mut device, IdleAction::Close)?;
+        assert_eq!(device.all_open(), Channels::CORE);
+        policy.apply(&mut device, IdleAction::Reopen)?;
+        assert_eq!(
+            device.all_open(),
+            Channels::CORE | Channels::ACCELEROMETER | Channels::IR
+        );
+        assert_eq!(device.writable(), Channels::CORE | Channels::IR);
+        Ok(())
+    }
+}
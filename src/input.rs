@@ -0,0 +1,228 @@
+//! Access to the kernel input devices backing a [`Device`](crate::Device).
+//!
+//! The `hid-wiimote` driver registers an evdev input device for each
+//! channel, e.g. one for the core keys and one for the accelerometer.
+//! The `xwiimote` library reads events from them, but doesn't expose
+//! settings like the key auto-repeat timing.
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::{bail_if, Channels, Result};
+
+/// Lists the device nodes of the input devices of the HID device at
+/// the given sysfs path, e.g. `/dev/input/event7`, in sorted order.
+pub(crate) fn input_nodes(syspath: &Path) -> Result<Vec<PathBuf>> {
+    // Each input device is a `input/inputN` directory, whose `eventM`
+    // subdirectory names the device node in `/dev/input`.
+    let mut nodes = Vec::new();
+    for entry in fs::read_dir(syspath.join("input"))? {
+        for child in fs::read_dir(entry?.path())? {
+            let child = child?.file_name();
+            if child.to_string_lossy().starts_with("event") {
+                nodes.push(Path::new("/dev/input").join(child));
+                break;
+            }
+        }
+    }
+    nodes.sort();
+    Ok(nodes)
+}
+
+/// Checks whether the kernel registered the input devices of the HID
+/// device at the given sysfs path, and their device nodes were created.
+///
+/// The nodes are probed concurrently, since opening some of them can
+/// take a while as the device is being set up.
+pub(crate) fn nodes_ready(syspath: &Path) -> bool {
+    let nodes = match input_nodes(syspath) {
+        Ok(nodes) if !nodes.is_empty() => nodes,
+        _ => return false,
+    };
+    thread::scope(|scope| {
+        let probes: Vec<_> = nodes
+            .iter()
+            .map(|node| scope.spawn(move || probe(node)))
+            .collect();
+        probes
+            .into_iter()
+            .all(|probe| probe.join().unwrap_or(false))
+    })
+}
+
+/// Checks whether the given device node exists and opens. Nodes that
+/// the process may not open are ready, and fail to open later with a
+/// more helpful error.
+fn probe(node: &Path) -> bool {
+    let opened = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(node);
+    match opened {
+        Ok(_) => true,
+        Err(err) => err.kind() == io::ErrorKind::PermissionDenied,
+    }
+}
+
+/// Returns the name of the input device of the given channel, as
+/// registered by the kernel driver.
+pub(crate) fn channel_name(channel: Channels) -> Option<&'static str> {
+    let name = match channel {
+        Channels::CORE => "Nintendo Wii Remote",
+        Channels::ACCELEROMETER => "Nintendo Wii Remote Accelerometer",
+        Channels::IR => "Nintendo Wii Remote IR",
+        Channels::MOTION_PLUS => "Nintendo Wii Remote Motion Plus",
+        Channels::NUNCHUK => "Nintendo Wii Remote Nunchuk",
+        Channels::CLASSIC_CONTROLLER => "Nintendo Wii Remote Classic Controller",
+        Channels::BALANCE_BOARD => "Nintendo Wii Remote Balance Board",
+        Channels::PRO_CONTROLLER => "Nintendo Wii Remote Pro Controller",
+        Channels::DRUMS => "Nintendo Wii Remote Drums",
+        Channels::GUITAR => "Nintendo Wii Remote Guitar",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Returns the device node of the input device with the given name of
+/// the HID device at the given sysfs path, if any.
+pub(crate) fn named_input_node(syspath: &Path, name: &str) -> Result<Option<PathBuf>> {
+    for entry in fs::read_dir(syspath.join("input"))? {
+        let dir = entry?.path();
+        let input_name = fs::read_to_string(dir.join("name")).unwrap_or_default();
+        if input_name.trim_end() != name {
+            continue;
+        }
+        for child in fs::read_dir(&dir)? {
+            let child = child?.file_name();
+            if child.to_string_lossy().starts_with("event") {
+                return Ok(Some(Path::new("/dev/input").join(child)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Lists the file descriptors of the process whose `/proc` directory
+/// is at `process_dir` that refer to the given device node.
+pub(crate) fn fds_of(process_dir: &Path, node: &Path) -> Result<Vec<RawFd>> {
+    let mut fds = Vec::new();
+    for entry in fs::read_dir(process_dir.join("fd"))? {
+        let entry = entry?;
+        if fs::read_link(entry.path()).is_ok_and(|target| target == node) {
+            if let Ok(fd) = entry.file_name().to_string_lossy().parse() {
+                fds.push(fd);
+            }
+        }
+    }
+    Ok(fds)
+}
+
+/// Grabs the input device open as `fd` for exclusive access, or
+/// releases it. While grabbed, other file descriptors of the input
+/// device receive no events.
+///
+/// Fails with `EBUSY` if another file descriptor grabbed the device.
+pub(crate) fn grab(fd: RawFd, grab: bool) -> Result<()> {
+    // _IOW('E', 0x90, int)
+    const EVIOCGRAB: u32 = 0x4004_4590;
+    let res_code = unsafe { libc::ioctl(fd, EVIOCGRAB as _, grab as libc::c_int) };
+    bail_if!(res_code == -1);
+    Ok(())
+}
+
+/// Returns the device node of the hidraw device of the HID device at the
+/// given sysfs path, e.g. `/dev/hidraw3`.
+///
+/// The hidraw node receives a copy of every input report, including the
+/// bytes the kernel driver doesn't decode.
+pub(crate) fn hidraw_node(syspath: &Path) -> Result<PathBuf> {
+    for entry in fs::read_dir(syspath.join("hidraw"))? {
+        let name = entry?.file_name();
+        if name.to_string_lossy().starts_with("hidraw") {
+            return Ok(Path::new("/dev").join(name));
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no hidraw device"))
+}
+
+/// Sets the key auto-repeat timing of the input device.
+///
+/// Fails with `ENOSYS` if the device doesn't report repeated keys.
+pub(crate) fn set_repeat(devnode: &Path, delay: Duration, period: Duration) -> Result<()> {
+    // _IOW('E', 0x03, unsigned int[2])
+    const EVIOCSREP: u32 = 0x4008_4503;
+    let millis = |duration: Duration| duration.as_millis().min(libc::c_uint::MAX as u128);
+    let rep = [
+        millis(delay) as libc::c_uint,
+        millis(period) as libc::c_uint,
+    ];
+
+    let file = File::open(devnode)?;
+    let res_code = unsafe { libc::ioctl(file.as_raw_fd(), EVIOCSREP as _, rep.as_ptr()) };
+    bail_if!(res_code == -1);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fds_of, hidraw_node, input_nodes, named_input_node, nodes_ready};
+    use crate::Result;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn lists_input_nodes() -> Result<()> {
+        let syspath = std::env::temp_dir().join(format!("xwiimote-input-{}", std::process::id()));
+        for (input, event, name) in [
+            ("input12", "event9", "Nintendo Wii Remote"),
+            ("input11", "event8", "Nintendo Wii Remote Accelerometer"),
+        ] {
+            let dir = syspath.join("input").join(input);
+            fs::create_dir_all(dir.join(event))?;
+            fs::write(dir.join("name"), format!("{}\n", name))?;
+        }
+
+        assert_eq!(
+            input_nodes(&syspath)?,
+            [
+                Path::new("/dev/input/event8"),
+                Path::new("/dev/input/event9")
+            ]
+        );
+
+        assert_eq!(
+            named_input_node(&syspath, "Nintendo Wii Remote")?,
+            Some(Path::new("/dev/input/event9").to_path_buf())
+        );
+        assert_eq!(named_input_node(&syspath, "Nintendo Wii Remote IR")?, None);
+        // Devices without input devices yet are still settling.
+        assert!(!nodes_ready(&syspath.join("settling")));
+
+        fs::remove_dir_all(syspath)
+    }
+
+    #[test]
+    fn finds_process_fds() -> Result<()> {
+        let process_dir = std::env::temp_dir().join(format!("xwiimote-fds-{}", std::process::id()));
+        fs::create_dir_all(process_dir.join("fd"))?;
+        std::os::unix::fs::symlink("/dev/input/event8", process_dir.join("fd").join("5"))?;
+        std::os::unix::fs::symlink("/dev/null", process_dir.join("fd").join("6"))?;
+
+        assert_eq!(fds_of(&process_dir, Path::new("/dev/input/event8"))?, [5]);
+        fs::remove_dir_all(process_dir)
+    }
+
+    #[test]
+    fn finds_hidraw_node() -> Result<()> {
+        let syspath = std::env::temp_dir().join(format!("xwiimote-hidraw-{}", std::process::id()));
+        assert!(hidraw_node(&syspath).is_err());
+        fs::create_dir_all(syspath.join("hidraw").join("hidraw3"))?;
+
+        assert_eq!(hidraw_node(&syspath)?, Path::new("/dev/hidraw3"));
+        fs::remove_dir_all(syspath)
+    }
+}
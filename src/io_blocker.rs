@@ -1,57 +1,157 @@
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::os::unix::io::RawFd;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::Waker;
 use std::thread;
+use std::time::Instant;
 
+use crate::runtime::Trigger;
 use crate::{bail_if, Result};
 
-/// Listens for events from all monitors and devices associated
-/// with the application.
+/// Listens for events from the monitors and devices associated
+/// with a [`Runtime`](crate::runtime::Runtime), or with the
+/// application if using the global instance.
 pub(crate) struct IoBlocker {
     ep_fd: RawFd,
-    wakers: Mutex<HashMap<RawFd, Waker>>,
+    // An `eventfd` used to interrupt `epoll_wait` on shutdown, or when
+    // a timer expires before the current wait would end.
+    notify_fd: RawFd,
+    shutdown: Arc<AtomicBool>,
+    trigger: Trigger,
+    // The registrations, sharded by file descriptor so that the wake-ups
+    // of different devices don't contend for a single lock.
+    interests: [Mutex<HashMap<RawFd, Registration>>; SHARDS],
+    timers: Mutex<Timers>,
+}
+
+/// The number of shards of the registrations. Since descriptors are
+/// allocated sequentially, the files of a few devices rarely share one.
+const SHARDS: usize = 16;
+
+/// The pending timers, by deadline.
+#[derive(Default)]
+struct Timers {
+    next_id: u64,
+    wakers: BTreeMap<TimerKey, Waker>,
+}
+
+/// Identifies a timer added by [`IoBlocker::add_timer`].
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub(crate) struct TimerKey {
+    at: Instant,
+    // Distinguishes timers with the same deadline.
+    id: u64,
+}
+
+impl TimerKey {
+    /// Returns the deadline of the timer.
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+}
+
+/// A file with a registered interest.
+struct Registration {
+    // The requested epoll events, without the trigger flags.
+    events: libc::c_int,
+    state: Interest,
+}
+
+/// The wake-up state of a file with a registered interest.
+enum Interest {
+    /// No future waits for events, and no event arrived since the
+    /// last wake up.
+    Idle,
+    /// A future waits for the next event on the file.
+    Waiting(Waker),
+    /// An event arrived while no future was waiting.
+    Ready,
 }
 
 impl IoBlocker {
-    pub fn get() -> &'static Self {
-        static BLOCKER: Lazy<IoBlocker> = Lazy::new(|| {
-            thread::spawn(move || {
-                let blocker = IoBlocker::get();
-                blocker.run().expect("event loop failed");
-            });
-
-            // Create epoll instance
-            let ep_fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
-            assert_ne!(ep_fd, -1, "failed to create epoll instance");
-
-            IoBlocker {
-                ep_fd,
-                wakers: Mutex::new(HashMap::new()),
-            }
+    /// The events signalling that data can be read from a file, or
+    /// that the file was closed.
+    pub const READ_EVENTS: libc::c_int = libc::EPOLLIN | libc::EPOLLHUP | libc::EPOLLPRI;
+
+    /// Returns the global instance, whose event loop runs on a
+    /// dedicated thread until the process receives `SIGTERM`.
+    pub fn get() -> &'static Arc<Self> {
+        static BLOCKER: Lazy<Arc<IoBlocker>> = Lazy::new(|| {
+            let blocker = IoBlocker::new(Trigger::Edge).expect("failed to create epoll instance");
+            signal_hook::flag::register(signal_hook::consts::SIGTERM, blocker.shutdown.clone())
+                .expect("failed to register SIGTERM handler");
+
+            let loop_blocker = Arc::clone(&blocker);
+            thread::spawn(move || loop_blocker.run().expect("event loop failed"));
+            blocker
         });
         &BLOCKER
     }
 
-    /// Executes the event loop.
-    fn run(&self) -> Result<()> {
-        let term = Arc::new(AtomicBool::new(false));
-        signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&term))?;
+    /// Creates an instance whose event loop is not running yet.
+    pub fn new(trigger: Trigger) -> Result<Arc<Self>> {
+        // Create epoll instance
+        let ep_fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        bail_if!(ep_fd == -1);
+
+        let notify_fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if notify_fd == -1 {
+            let err = std::io::Error::last_os_error();
+            unsafe { libc::close(ep_fd) };
+            return Err(err);
+        }
 
+        let blocker = IoBlocker {
+            ep_fd,
+            notify_fd,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            trigger,
+            interests: std::array::from_fn(|_| Mutex::new(HashMap::new())),
+            timers: Mutex::new(Timers::default()),
+        };
+        blocker.add_interest(notify_fd, libc::EPOLLIN)?;
+        Ok(Arc::new(blocker))
+    }
+
+    /// Returns the shard of the registration of the file.
+    fn shard(&self, fd: RawFd) -> &Mutex<HashMap<RawFd, Registration>> {
+        &self.interests[fd as usize % SHARDS]
+    }
+
+    /// Executes the event loop until [`IoBlocker::shutdown`] is called.
+    pub fn run(&self) -> Result<()> {
         // Reuse the readiness events vector across `wake_ready` calls.
         let mut events = Vec::with_capacity(16);
-        while !term.load(Ordering::Relaxed) {
+        while !self.shutdown.load(Ordering::Relaxed) {
             self.wake_ready(&mut events)?;
         }
+        Ok(())
+    }
+
+    /// Stops the event loop. Pending futures are not woken up.
+    pub fn shutdown(&self) -> Result<()> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.notify()
+    }
 
-        unsafe { libc::close(self.ep_fd) };
+    /// Interrupts the current `epoll_wait` call, if any.
+    fn notify(&self) -> Result<()> {
+        let value = 1u64;
+        let res_code = unsafe {
+            libc::write(
+                self.notify_fd,
+                &value as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        bail_if!(res_code == -1);
         Ok(())
     }
 
-    /// Blocks until one or more events occurs, and wakes the futures that
-    /// expressed interest in them.
+    /// Blocks until one or more events occurs or the next timer expires,
+    /// and wakes the futures that expressed interest in them.
     fn wake_ready(&self, events: &mut Vec<libc::epoll_event>) -> Result<()> {
         events.clear();
         let n_ready = unsafe {
@@ -59,29 +159,161 @@ impl IoBlocker {
                 self.ep_fd,
                 events.as_mut_ptr(),
                 events.capacity() as libc::c_int,
-                -1, // todo: set reasonable timeout
+                self.wait_timeout(),
             )
         };
-        bail_if!(n_ready == -1);
+        if n_ready == -1 {
+            let err = std::io::Error::last_os_error();
+            // A signal handler ran, e.g. the `SIGTERM` one of the global
+            // instance. Wait again, unless asked to shut down.
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(err);
+        }
 
         // Safety: `epoll_wait` ensures `n_ready` events are assigned.
         unsafe { events.set_len(n_ready as usize) };
 
-        let mut wakers = self.wakers.lock().unwrap();
         for event in events.iter() {
             let fd = event.u64 as RawFd;
-            if let Some(waker) = wakers.remove(&fd) {
-                waker.wake();
+            if fd == self.notify_fd {
+                self.clear_notification()?;
+                continue;
+            }
+            let waker = {
+                let mut interests = self.shard(fd).lock().unwrap();
+                let registration = match interests.get_mut(&fd) {
+                    Some(registration) => registration,
+                    // The interest was removed while waiting.
+                    None => continue,
+                };
+                // The future may be between reading the last available data
+                // and calling `set_callback`. Remember the event, since an
+                // edge-triggered or one-shot epoll won't report it again.
+                match std::mem::replace(&mut registration.state, Interest::Ready) {
+                    Interest::Waiting(waker) => {
+                        registration.state = Interest::Idle;
+                        waker
+                    }
+                    _ => continue,
+                }
+            };
+            // Wake outside the lock, so that the woken future doesn't
+            // block on it when polling again.
+            waker.wake();
+        }
+        self.wake_expired();
+        Ok(())
+    }
+
+    /// Returns the time until the next timer expires, in milliseconds,
+    /// rounded up so that timers never fire early, or -1 if none is set.
+    fn wait_timeout(&self) -> libc::c_int {
+        let timers = self.timers.lock().unwrap();
+        match timers.wakers.keys().next() {
+            Some(key) => {
+                let remaining = key.at.saturating_duration_since(Instant::now());
+                let millis = remaining.as_nanos().div_ceil(1_000_000);
+                millis.min(libc::c_int::MAX as u128) as libc::c_int
+            }
+            None => -1,
+        }
+    }
+
+    /// Wakes the futures whose timers expired, and forgets the timers.
+    fn wake_expired(&self) {
+        let expired = {
+            let mut timers = self.timers.lock().unwrap();
+            let now = Instant::now();
+            let pending = match timers.wakers.keys().find(|key| key.at > now) {
+                Some(&key) => timers.wakers.split_off(&key),
+                None => BTreeMap::new(),
+            };
+            std::mem::replace(&mut timers.wakers, pending)
+        };
+        // Wake outside the lock, since a woken future may add a timer.
+        for waker in expired.into_values() {
+            waker.wake();
+        }
+    }
+
+    /// Consumes the notifications of [`IoBlocker::notify`].
+    fn clear_notification(&self) -> Result<()> {
+        let mut value = 0u64;
+        let res_code = unsafe {
+            libc::read(
+                self.notify_fd,
+                &mut value as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        if res_code == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::WouldBlock {
+                return Err(err);
             }
         }
+        if self.trigger == Trigger::Level {
+            // Re-arm the one-shot interest, as no future does it.
+            self.ctl_interest(libc::EPOLL_CTL_MOD, self.notify_fd, libc::EPOLLIN)?;
+        }
         Ok(())
     }
 
+    /// Arranges for the waker to be called once the given deadline
+    /// passes. Unlike file interests, timers fire once and need no
+    /// cleanup after firing.
+    pub fn add_timer(&self, at: Instant, waker: &Waker) -> Result<TimerKey> {
+        let mut timers = self.timers.lock().unwrap();
+        let key = TimerKey {
+            at,
+            id: timers.next_id,
+        };
+        timers.next_id += 1;
+        let earliest = timers.wakers.keys().next().is_none_or(|first| key < *first);
+        timers.wakers.insert(key, waker.clone());
+        drop(timers);
+        if earliest {
+            // The event loop may be waiting for a later deadline.
+            self.notify()?;
+        }
+        Ok(key)
+    }
+
+    /// Replaces the waker of a pending timer. Returns whether the timer
+    /// is still pending, otherwise it fired or was removed.
+    pub fn update_timer(&self, key: TimerKey, waker: &Waker) -> bool {
+        match self.timers.lock().unwrap().wakers.get_mut(&key) {
+            Some(stored) => {
+                if !stored.will_wake(waker) {
+                    stored.clone_from(waker);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes a timer, if it didn't fire yet.
+    pub fn remove_timer(&self, key: TimerKey) {
+        let waker = self.timers.lock().unwrap().wakers.remove(&key);
+        // Drop the waker outside the lock, in case it owns the last
+        // reference to something that removes a timer.
+        drop(waker);
+    }
+
     fn ctl_interest(&self, op: libc::c_int, fd: RawFd, events: libc::c_int) -> Result<()> {
+        let flags = match self.trigger {
+            // The caller is expected to read all available data from `fd`.
+            Trigger::Edge => libc::EPOLLET,
+            // Report a single event until `set_callback` re-arms the
+            // interest, so the event loop doesn't spin while a readable
+            // file has no waiting future.
+            Trigger::Level => libc::EPOLLONESHOT,
+        };
         let mut event = libc::epoll_event {
-            // Enable edge-triggered mechanism, caller is expected to
-            // read all available data from `fd`.
-            events: (events | libc::EPOLLET) as libc::c_uint,
+            events: (events | flags) as libc::c_uint,
             u64: fd.try_into().unwrap(),
         };
         let res_code = unsafe { libc::epoll_ctl(self.ep_fd, op, fd, &mut event) };
@@ -91,72 +323,377 @@ impl IoBlocker {
 
     /// Expresses an interest in a particular event on the file.
     pub fn add_interest(&self, fd: RawFd, events: libc::c_int) -> Result<()> {
-        self.ctl_interest(libc::EPOLL_CTL_ADD, fd, events)
+        self.ctl_interest(libc::EPOLL_CTL_ADD, fd, events)?;
+        // Forget the events of a closed file with the same descriptor.
+        self.shard(fd).lock().unwrap().insert(
+            fd,
+            Registration {
+                events,
+                state: Interest::Idle,
+            },
+        );
+        Ok(())
+    }
+
+    /// Replaces the events of interest on the file.
+    ///
+    /// The pending future, if set, is kept.
+    pub fn modify_interest(&self, fd: RawFd, events: libc::c_int) -> Result<()> {
+        let mut interests = self.shard(fd).lock().unwrap();
+        self.ctl_interest(libc::EPOLL_CTL_MOD, fd, events)?;
+        if let Some(registration) = interests.get_mut(&fd) {
+            registration.events = events;
+        }
+        Ok(())
     }
 
     /// Removes the interest in a particular event on the file.
     ///
-    /// This also wakes the pending future, if set.
+    /// This also wakes the pending future, if set. Removing the interest
+    /// of a closed file, e.g. of a device that was unplugged, or an
+    /// interest that was already removed succeeds.
     pub fn remove_interest(&self, fd: RawFd, events: libc::c_int) -> Result<()> {
-        self.ctl_interest(libc::EPOLL_CTL_DEL, fd, events)?;
-        if let Some(waker) = self.wakers.lock().unwrap().remove(&fd) {
+        let result = self.ctl_interest(libc::EPOLL_CTL_DEL, fd, events);
+        // Forget the registration even if `epoll_ctl` fails, so that no
+        // waker outlives the interest.
+        let registration = self.shard(fd).lock().unwrap().remove(&fd);
+        if let Some(Registration {
+            state: Interest::Waiting(waker),
+            ..
+        }) = registration
+        {
             waker.wake();
         }
-        Ok(())
+        match result {
+            // Closing a file removes it from the epoll instance.
+            Err(err) if matches!(err.raw_os_error(), Some(libc::EBADF | libc::ENOENT)) => Ok(()),
+            result => result,
+        }
     }
 
     /// Stores the waker to be called once an IO event on the file
     /// arrives.
     ///
-    /// The future is expected to read all available data from `fd`
-    /// once waken up. Otherwise the event loop can block indefinitely.
-    pub fn set_callback(&self, fd: RawFd, waker: Waker) {
-        self.wakers.lock().unwrap().insert(fd, waker);
+    /// With [`Trigger::Edge`], the future is expected to read all
+    /// available data from `fd` once waken up. Otherwise the event loop
+    /// can block indefinitely. With [`Trigger::Level`], the waker is
+    /// called immediately if data is still available.
+    ///
+    /// If an event arrived since the last wake up, the waker is called
+    /// immediately. Hence no event is lost if it arrives after the future
+    /// read the last available data, but before it calls this method.
+    ///
+    /// The waker is only cloned if it differs from the stored one, so
+    /// repeatedly polling a pending stream does not allocate. If there
+    /// is no interest in the file, the waker is called immediately.
+    pub fn set_callback(&self, fd: RawFd, waker: &Waker) {
+        let mut interests = self.shard(fd).lock().unwrap();
+        let registration = match interests.get_mut(&fd) {
+            Some(registration) => registration,
+            None => {
+                // The interest was removed, hence no event will arrive.
+                // Let the future observe it.
+                waker.wake_by_ref();
+                return;
+            }
+        };
+        match &mut registration.state {
+            Interest::Waiting(stored) if stored.will_wake(waker) => {}
+            Interest::Waiting(stored) => stored.clone_from(waker),
+            Interest::Ready => {
+                registration.state = Interest::Idle;
+                waker.wake_by_ref();
+            }
+            Interest::Idle => {
+                registration.state = Interest::Waiting(waker.clone());
+                if self.trigger == Trigger::Level {
+                    // Re-arm the one-shot interest. The event loop won't
+                    // report the file until then, so unlocking is safe.
+                    let events = registration.events;
+                    drop(interests);
+                    if self.modify_interest(fd, events).is_err() {
+                        // The interest was removed, let the future observe it.
+                        waker.wake_by_ref();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Registers interest in the read events of the file with an epoll
+/// instance owned by the application, which receives `token` as the
+/// event data. The interest is level-triggered.
+pub(crate) fn register_external(epfd: RawFd, fd: RawFd, token: u64) -> Result<()> {
+    let mut event = libc::epoll_event {
+        events: IoBlocker::READ_EVENTS as libc::c_uint,
+        u64: token,
+    };
+    let res_code = unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event) };
+    bail_if!(res_code == -1);
+    Ok(())
+}
+
+/// Removes the interest registered by [`register_external`].
+pub(crate) fn deregister_external(epfd: RawFd, fd: RawFd) -> Result<()> {
+    // Kernels before 2.6.9 require a non-null event, even if ignored.
+    let mut event = libc::epoll_event { events: 0, u64: 0 };
+    let res_code = unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_DEL, fd, &mut event) };
+    bail_if!(res_code == -1);
+    Ok(())
+}
+
+impl Drop for IoBlocker {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.notify_fd);
+            libc::close(self.ep_fd);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::runtime::{Runtime, Trigger};
     use crate::{IoBlocker, Result};
     use futures::executor;
     use std::future::Future;
-
+    use std::os::unix::io::RawFd;
     use std::pin::Pin;
-
+    use std::sync::Arc;
     use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+
+    fn event_fd() -> RawFd {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        assert_ne!(fd, -1, "failed to create eventfd");
+        fd
+    }
+
+    fn signal(fd: RawFd, value: u64) {
+        let res_code = unsafe { libc::write(fd, &value as *const u64 as *const _, 8) };
+        assert_eq!(res_code, 8);
+    }
+
+    /// Reads the counter of the eventfd, or 0 if no event is available.
+    fn drain(fd: RawFd) -> u64 {
+        let mut value = 0u64;
+        let res_code = unsafe { libc::read(fd, &mut value as *mut u64 as *mut _, 8) };
+        if res_code == -1 {
+            0
+        } else {
+            value
+        }
+    }
+
+    /// Completes once the eventfd counter adds up to the given total.
+    struct CountFuture {
+        blocker: Arc<IoBlocker>,
+        fd: RawFd,
+        remaining: u64,
+    }
+
+    impl Future for CountFuture {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            loop {
+                let value = drain(self.fd);
+                if value == 0 {
+                    break;
+                }
+                self.remaining -= value;
+            }
+            if self.remaining == 0 {
+                return Poll::Ready(());
+            }
+            // An event may arrive right here, before registering the waker.
+            self.blocker.set_callback(self.fd, cx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// Completes once woken up, without reading any data.
+    struct WakeFuture {
+        blocker: Arc<IoBlocker>,
+        fd: RawFd,
+        waiting: bool,
+    }
+
+    impl WakeFuture {
+        fn new(blocker: &Arc<IoBlocker>, fd: RawFd) -> Self {
+            Self {
+                blocker: Arc::clone(blocker),
+                fd,
+                waiting: false,
+            }
+        }
+    }
+
+    impl Future for WakeFuture {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.waiting {
+                return Poll::Ready(());
+            }
+            self.waiting = true;
+            self.blocker.set_callback(self.fd, cx.waker());
+            Poll::Pending
+        }
+    }
 
     #[test]
-    fn double_interest_fails() {
-        let blocker = IoBlocker::get();
-        blocker.add_interest(0, libc::EPOLLIN).unwrap();
-        assert!(blocker.add_interest(0, libc::EPOLLIN).is_err());
+    fn double_interest_fails() -> Result<()> {
+        let blocker = IoBlocker::new(Trigger::Edge)?;
+        let fd = event_fd();
+        blocker.add_interest(fd, libc::EPOLLIN)?;
+        assert!(blocker.add_interest(fd, libc::EPOLLIN).is_err());
+
+        unsafe { libc::close(fd) };
+        Ok(())
     }
 
-    // todo: the following assumes that stdout is open.
+    #[test]
+    fn removing_closed_interest_succeeds() -> Result<()> {
+        let blocker = IoBlocker::new(Trigger::Edge)?;
+        let fd = event_fd();
+        blocker.add_interest(fd, libc::EPOLLIN)?;
+
+        // The device was unplugged, and its file closed, before the
+        // interest is removed.
+        unsafe { libc::close(fd) };
+        blocker.remove_interest(fd, libc::EPOLLIN)?;
+        // Removal is idempotent.
+        blocker.remove_interest(fd, libc::EPOLLIN)
+    }
+
+    #[test]
+    fn missing_interest_wakes_future() -> Result<()> {
+        let blocker = Arc::new(IoBlocker::new(Trigger::Edge)?);
+        let fd = event_fd();
+        blocker.add_interest(fd, libc::EPOLLIN)?;
+        blocker.remove_interest(fd, libc::EPOLLIN)?;
+
+        executor::block_on(WakeFuture::new(&blocker, fd));
+        assert!(!blocker.shard(fd).lock().unwrap().contains_key(&fd));
+
+        unsafe { libc::close(fd) };
+        Ok(())
+    }
+
+    #[test]
+    fn external_epoll_receives_token() -> Result<()> {
+        let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        assert_ne!(epfd, -1);
+        let fd = event_fd();
+        super::register_external(epfd, fd, 42)?;
+
+        signal(fd, 1);
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let n_ready = unsafe { libc::epoll_wait(epfd, &mut event, 1, 1000) };
+        assert_eq!(n_ready, 1);
+        assert_eq!({ event.u64 }, 42);
+
+        super::deregister_external(epfd, fd)?;
+        assert!(super::deregister_external(epfd, fd).is_err());
+        unsafe {
+            libc::close(fd);
+            libc::close(epfd);
+        }
+        Ok(())
+    }
 
     #[test]
     fn event_wakes_future() -> Result<()> {
-        const FD: libc::c_int = libc::STDOUT_FILENO;
-        IoBlocker::get().add_interest(FD, libc::EPOLLOUT)?;
-
-        struct StdoutFuture(bool);
-        impl Future for StdoutFuture {
-            type Output = ();
-
-            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-                if self.0 {
-                    // First try, let `IoBlocker` wake us up for the second try.
-                    IoBlocker::get().set_callback(FD, cx.waker().clone());
-                    self.0 = false;
-                    Poll::Pending
-                } else {
-                    // Second try, we're done.
-                    Poll::Ready(())
+        let runtime = Runtime::new()?;
+        let fd = event_fd();
+        runtime.blocker().add_interest(fd, libc::EPOLLIN)?;
+
+        thread::spawn(move || signal(fd, 1));
+        executor::block_on(CountFuture {
+            blocker: runtime.blocker().clone(),
+            fd,
+            remaining: 1,
+        });
+
+        unsafe { libc::close(fd) };
+        Ok(())
+    }
+
+    #[test]
+    fn event_before_callback_is_not_lost() -> Result<()> {
+        let runtime = Runtime::new()?;
+        let fd = event_fd();
+        runtime.blocker().add_interest(fd, libc::EPOLLIN)?;
+
+        // Let the event loop observe the event before any waker is set.
+        signal(fd, 1);
+        thread::sleep(Duration::from_millis(50));
+
+        executor::block_on(WakeFuture::new(runtime.blocker(), fd));
+        unsafe { libc::close(fd) };
+        Ok(())
+    }
+
+    #[test]
+    fn no_lost_wakeups_under_stress() -> Result<()> {
+        const EVENTS: u64 = 20_000;
+        let runtime = Runtime::new()?;
+        let fd = event_fd();
+        runtime.blocker().add_interest(fd, libc::EPOLLIN)?;
+
+        let writer = thread::spawn(move || {
+            for ix in 0..EVENTS {
+                signal(fd, 1);
+                if ix % 64 == 0 {
+                    thread::yield_now();
                 }
             }
-        }
+        });
+        executor::block_on(CountFuture {
+            blocker: runtime.blocker().clone(),
+            fd,
+            remaining: EVENTS,
+        });
+        writer.join().unwrap();
+
+        unsafe { libc::close(fd) };
+        Ok(())
+    }
+
+    #[test]
+    fn level_trigger_wakes_until_drained() -> Result<()> {
+        let runtime = Runtime::with_trigger(Trigger::Level)?;
+        let fd = event_fd();
+        runtime.blocker().add_interest(fd, libc::EPOLLIN)?;
+
+        signal(fd, 1);
+        executor::block_on(WakeFuture::new(runtime.blocker(), fd));
+        // The data was not read, so the next wait completes immediately.
+        executor::block_on(WakeFuture::new(runtime.blocker(), fd));
+
+        unsafe { libc::close(fd) };
+        Ok(())
+    }
+
+    #[test]
+    fn modified_interest_wakes_future() -> Result<()> {
+        let runtime = Runtime::new()?;
+        let fd = event_fd();
+        // An eventfd never has priority data.
+        runtime.blocker().add_interest(fd, libc::EPOLLPRI)?;
+        signal(fd, 1);
+
+        let blocker = Arc::clone(runtime.blocker());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            blocker.modify_interest(fd, libc::EPOLLIN).unwrap();
+        });
+        executor::block_on(WakeFuture::new(runtime.blocker(), fd));
 
-        executor::block_on(StdoutFuture(true));
+        unsafe { libc::close(fd) };
         Ok(())
     }
 }
@@ -0,0 +1,541 @@
+//! Geometry of the IR camera and the sensor bar.
+//!
+//! The Wii Remote IR camera tracks up to four IR sources, reported in
+//! [`EventKind::Ir`] as pixel positions on a 1024x768 grid. The sensor
+//! bar has an IR source at each end, so the two dots it produces reveal
+//! how far away the remote is, and where it is pointing to. DIY sensor
+//! bars and wireless candles work too, once their layout is described
+//! by a [`SensorBarConfig`].
+//!
+//! Conversely, the remote can stay in place while an IR pen moves in its
+//! field of view, e.g. over a projected screen. A [`ScreenCalibration`]
+//! maps the camera positions of the pen to screen coordinates.
+//!
+//! Sunlight, lamps and candles can produce more than two dots. A
+//! [`Pointer`] tracks the sensor bar among them for a single remote,
+//! and reports how confident it is in its choice.
+use crate::event::{Event, EventKind, IrSource};
+use crate::Result;
+use futures::{future, Stream, TryStreamExt};
+use std::io;
+use std::time::SystemTime;
+
+/// The width of the IR camera grid, in pixels.
+pub const CAMERA_WIDTH: i32 = 1024;
+/// The height of the IR camera grid, in pixels.
+pub const CAMERA_HEIGHT: i32 = 768;
+/// The horizontal field of view of the IR camera, in radians.
+///
+/// This is an approximation: the exact angle varies slightly between
+/// devices, and is not reported by the hardware.
+pub const CAMERA_FOV: f32 = 33.0 * std::f32::consts::PI / 180.0;
+/// The distance between the IR sources of a Nintendo sensor bar,
+/// in millimeters.
+pub const SENSOR_BAR_WIDTH_MM: f32 = 200.0;
+
+/// The layout of the IR sources used as a sensor bar.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SensorBarConfig {
+    /// The distance between the two IR sources, in millimeters.
+    pub spacing_mm: f32,
+    /// Whether the sources are placed above the screen, rather than
+    /// below it.
+    pub above_screen: bool,
+    /// The angle of the line from the left to the right source, in
+    /// radians. Positive if the right source is higher.
+    pub tilt: f32,
+}
+
+impl SensorBarConfig {
+    /// Describes two independent IR sources, e.g. wireless candles,
+    /// from their `[x, y]` positions in millimeters on the plane of the
+    /// screen, with the y-axis pointing up.
+    pub fn from_sources(left: [f32; 2], right: [f32; 2], above_screen: bool) -> Self {
+        let (dx, dy) = (right[0] - left[0], right[1] - left[1]);
+        Self {
+            spacing_mm: dx.hypot(dy),
+            above_screen,
+            tilt: dy.atan2(dx),
+        }
+    }
+}
+
+impl Default for SensorBarConfig {
+    /// A Nintendo sensor bar placed below the screen.
+    fn default() -> Self {
+        Self {
+            spacing_mm: SENSOR_BAR_WIDTH_MM,
+            above_screen: false,
+            tilt: 0.0,
+        }
+    }
+}
+
+/// The position of the remote relative to the sensor bar.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Pose {
+    /// The distance from the camera to the center of the sensor bar,
+    /// in millimeters.
+    pub distance_mm: f32,
+    /// The horizontal angle between the camera axis and the center of
+    /// the sensor bar, in radians. Positive if the bar appears to the
+    /// right of the camera axis.
+    pub yaw: f32,
+    /// The vertical angle between the camera axis and the center of the
+    /// sensor bar, in radians. Positive if the bar appears below the
+    /// camera axis.
+    pub pitch: f32,
+    /// The rotation of the remote around the camera axis, in radians.
+    /// Zero if the remote is level with the sensor bar.
+    pub roll: f32,
+    /// The time of the IR event.
+    pub time: SystemTime,
+}
+
+impl Pose {
+    /// Returns the vertical angle between the camera axis and the center
+    /// of a screen with the given height in millimeters, which has the
+    /// sensor bar along its top or bottom edge. Positive if the center
+    /// of the screen is below the camera axis.
+    pub fn screen_pitch(&self, config: &SensorBarConfig, screen_height_mm: f32) -> f32 {
+        let offset = (screen_height_mm / 2.0 / self.distance_mm).atan();
+        if config.above_screen {
+            self.pitch + offset
+        } else {
+            self.pitch - offset
+        }
+    }
+}
+
+/// The focal length of the camera, in pixels.
+fn focal_length() -> f32 {
+    (CAMERA_WIDTH as f32 / 2.0) / (CAMERA_FOV / 2.0).tan()
+}
+
+/// Returns the unit direction vector from the camera to the source.
+fn direction(source: IrSource) -> [f32; 3] {
+    let x = source.x as f32 - CAMERA_WIDTH as f32 / 2.0;
+    let y = source.y as f32 - CAMERA_HEIGHT as f32 / 2.0;
+    let z = focal_length();
+    let norm = (x * x + y * y + z * z).sqrt();
+    [x / norm, y / norm, z / norm]
+}
+
+/// Estimates the pose of the remote from the IR sources of an
+/// [`EventKind::Ir`] event, given the layout of the sensor bar.
+///
+/// The first two visible sources are assumed to be the ends of the bar,
+/// facing the camera. Returns `None` if less than two sources are
+/// visible, or if they coincide.
+pub fn estimate_pose(
+    sources: &[Option<IrSource>],
+    config: &SensorBarConfig,
+    time: SystemTime,
+) -> Option<Pose> {
+    let mut visible = sources.iter().flatten();
+    let (left, right) = ordered(*visible.next()?, *visible.next()?);
+    pose_from_pair(left, right, config, time)
+}
+
+/// Orders two dots from left to right, so the roll is within ±90°.
+fn ordered(first: IrSource, second: IrSource) -> (IrSource, IrSource) {
+    if first.x <= second.x {
+        (first, second)
+    } else {
+        (second, first)
+    }
+}
+
+/// Estimates the pose from the left and right ends of the sensor bar.
+fn pose_from_pair(
+    left: IrSource,
+    right: IrSource,
+    config: &SensorBarConfig,
+    time: SystemTime,
+) -> Option<Pose> {
+    let (a, b) = (direction(left), direction(right));
+    let cos = (a[0] * b[0] + a[1] * b[1] + a[2] * b[2]).clamp(-1.0, 1.0);
+    let angle = cos.acos();
+    if angle <= 0.0 {
+        return None;
+    }
+
+    let center_x = (left.x + right.x) as f32 / 2.0 - CAMERA_WIDTH as f32 / 2.0;
+    let center_y = (left.y + right.y) as f32 / 2.0 - CAMERA_HEIGHT as f32 / 2.0;
+    Some(Pose {
+        distance_mm: (config.spacing_mm / 2.0) / (angle / 2.0).tan(),
+        yaw: (center_x / focal_length()).atan(),
+        pitch: (center_y / focal_length()).atan(),
+        // The camera y-axis points down, unlike the one of the tilt.
+        roll: ((right.y - left.y) as f32).atan2((right.x - left.x) as f32) + config.tilt,
+        time,
+    })
+}
+
+/// The pair of IR sources identified as the sensor bar.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BarMatch {
+    /// The left end of the sensor bar.
+    pub left: IrSource,
+    /// The right end of the sensor bar.
+    pub right: IrSource,
+    /// How likely it is that the pair is the sensor bar, from 0 to 1.
+    /// Exactly 1 if only two sources are visible.
+    pub confidence: f32,
+    /// The number of visible IR sources.
+    pub visible: usize,
+}
+
+impl BarMatch {
+    /// Returns `true` if more sources than the ends of the sensor bar
+    /// are visible, e.g. due to sunlight or candles.
+    pub fn has_interference(&self) -> bool {
+        self.visible > 2
+    }
+}
+
+/// Identifies the sensor bar among the visible IR sources.
+///
+/// If the bar was identified in the previous event, the pair of
+/// sources closest to it is chosen. Otherwise the pair that appears
+/// the most level with the layout of the bar is chosen, since remotes
+/// are usually held upright.
+pub fn find_sensor_bar(
+    sources: &[Option<IrSource>],
+    config: &SensorBarConfig,
+    previous: Option<&BarMatch>,
+) -> Option<BarMatch> {
+    let visible: Vec<IrSource> = sources.iter().flatten().copied().collect();
+    let cost = |left: IrSource, right: IrSource| match previous {
+        Some(previous) => {
+            let offset = |a: IrSource, b: IrSource| ((a.x - b.x) as f32).hypot((a.y - b.y) as f32);
+            offset(left, previous.left) + offset(right, previous.right)
+        }
+        // A level remote sees the dots of the bar rotated by its tilt.
+        None => {
+            let roll = ((right.y - left.y) as f32).atan2((right.x - left.x) as f32);
+            (roll + config.tilt).abs()
+        }
+    };
+
+    let (mut best, mut runner_up) = (None, f32::INFINITY);
+    for (i, &first) in visible.iter().enumerate() {
+        for &second in &visible[i + 1..] {
+            let (left, right) = ordered(first, second);
+            let cost = cost(left, right);
+            match best {
+                Some((_, _, best_cost)) if cost >= best_cost => runner_up = runner_up.min(cost),
+                _ => {
+                    runner_up = best.map_or(runner_up, |(_, _, best_cost)| best_cost);
+                    best = Some((left, right, cost));
+                }
+            }
+        }
+    }
+
+    let (left, right, cost) = best?;
+    // Confidence is the relative margin to the next best candidate.
+    let confidence = if runner_up.is_infinite() {
+        1.0
+    } else if runner_up > 0.0 {
+        (runner_up - cost) / runner_up
+    } else {
+        0.0
+    };
+    Some(BarMatch {
+        left,
+        right,
+        confidence,
+        visible: visible.len(),
+    })
+}
+
+/// The estimated pose of a remote, and the sources it was estimated from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PointerSample {
+    /// The estimated pose.
+    pub pose: Pose,
+    /// The sources identified as the sensor bar.
+    pub bar: BarMatch,
+}
+
+/// Tracks the sensor bar in the IR events of a single remote.
+///
+/// Each remote needs its own pointer, so that multiple remotes pointing
+/// at the same sensor bar are tracked independently.
+#[derive(Clone, Debug)]
+pub struct Pointer {
+    config: SensorBarConfig,
+    last: Option<BarMatch>,
+}
+
+impl Pointer {
+    /// Creates a pointer for the given sensor bar.
+    pub fn new(config: SensorBarConfig) -> Self {
+        Self { config, last: None }
+    }
+
+    /// Updates the pointer with the given event. Returns the new sample
+    /// if the event is an IR event where the sensor bar is visible.
+    pub fn update(&mut self, event: &Event) -> Option<PointerSample> {
+        let sources = match &event.kind {
+            EventKind::Ir(sources) => sources,
+            _ => return None,
+        };
+        self.last = find_sensor_bar(sources, &self.config, self.last.as_ref());
+        let bar = self.last?;
+        let pose = pose_from_pair(bar.left, bar.right, &self.config, event.time)?;
+        Some(PointerSample { pose, bar })
+    }
+}
+
+/// Adapts a stream of events into a stream of the samples produced by
+/// `pointer`.
+pub fn pointer_samples<S>(
+    events: S,
+    mut pointer: Pointer,
+) -> impl Stream<Item = Result<PointerSample>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(pointer.update(&event))))
+}
+
+/// Adapts a stream of events into a stream of estimated poses, for a
+/// sensor bar with the given layout.
+///
+/// IR events where the sensor bar is not visible are skipped.
+pub fn poses<S>(events: S, config: SensorBarConfig) -> impl Stream<Item = Result<Pose>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| {
+        let pose = match &event.kind {
+            EventKind::Ir(sources) => estimate_pose(sources, &config, event.time),
+            _ => None,
+        };
+        future::ready(Ok(pose))
+    })
+}
+
+/// A perspective mapping from the camera grid to screen coordinates.
+///
+/// The camera doesn't need to face the screen: any camera placement
+/// is corrected, as long as the screen is flat.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ScreenCalibration {
+    // The first 8 entries of the row-major homography matrix, whose
+    // last entry is 1.
+    matrix: [f64; 8],
+}
+
+impl ScreenCalibration {
+    /// Computes the mapping from four pairs of camera and screen
+    /// positions, e.g. by asking the user to touch the corners of the
+    /// screen with an IR pen.
+    ///
+    /// Fails if three of the positions lie on the same line.
+    pub fn calibrate(points: [(IrSource, (f32, f32)); 4]) -> Result<Self> {
+        // Each pair yields two linear equations on the matrix entries.
+        let mut system = [[0.0; 9]; 8];
+        for (i, (source, (u, v))) in points.into_iter().enumerate() {
+            let (x, y, u, v) = (source.x as f64, source.y as f64, u as f64, v as f64);
+            system[2 * i] = [x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u, u];
+            system[2 * i + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v, v];
+        }
+
+        // Gaussian elimination with partial pivoting.
+        for col in 0..8 {
+            let pivot = (col..8)
+                .max_by(|&a, &b| system[a][col].abs().total_cmp(&system[b][col].abs()))
+                .unwrap();
+            if system[pivot][col].abs() < 1e-9 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "degenerate calibration points",
+                ));
+            }
+            system.swap(col, pivot);
+            let pivot = system[col];
+            for (row, equation) in system.iter_mut().enumerate() {
+                if row != col {
+                    let factor = equation[col] / pivot[col];
+                    for (value, pivot_value) in equation.iter_mut().zip(pivot).skip(col) {
+                        *value -= factor * pivot_value;
+                    }
+                }
+            }
+        }
+
+        let mut matrix = [0.0; 8];
+        for (i, entry) in matrix.iter_mut().enumerate() {
+            *entry = system[i][8] / system[i][i];
+        }
+        Ok(Self { matrix })
+    }
+
+    /// Maps a camera position to screen coordinates.
+    pub fn map_ir_to_screen(&self, source: IrSource) -> (f32, f32) {
+        let [a, b, c, d, e, f, g, h] = self.matrix;
+        let (x, y) = (source.x as f64, source.y as f64);
+        let w = g * x + h * y + 1.0;
+        (
+            ((a * x + b * y + c) / w) as f32,
+            ((d * x + e * y + f) / w) as f32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        estimate_pose, find_sensor_bar, focal_length, Pose, ScreenCalibration, SensorBarConfig,
+        CAMERA_FOV, CAMERA_HEIGHT, SENSOR_BAR_WIDTH_MM,
+    };
+    use crate::event::IrSource;
+    use std::f32::consts::FRAC_PI_4;
+    use std::time::SystemTime;
+
+    fn pose(sources: &[Option<IrSource>]) -> Option<Pose> {
+        let config = SensorBarConfig::default();
+        estimate_pose(sources, &config, SystemTime::UNIX_EPOCH)
+    }
+
+    #[test]
+    fn centered_bar() {
+        // The bar spans the whole field of view at this distance.
+        let distance = (SENSOR_BAR_WIDTH_MM / 2.0) / (CAMERA_FOV / 2.0).tan();
+        let pose = pose(&[
+            Some(IrSource { x: 0, y: 384 }),
+            None,
+            Some(IrSource { x: 1024, y: 384 }),
+            None,
+        ])
+        .unwrap();
+
+        assert!((pose.distance_mm - distance).abs() < 0.01);
+        assert_eq!((pose.yaw, pose.pitch, pose.roll), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn offset_and_rotated_bar() {
+        let pose = pose(&[
+            Some(IrSource { x: 812, y: 584 }),
+            Some(IrSource { x: 612, y: 384 }),
+        ])
+        .unwrap();
+
+        let offset = (200.0 / focal_length()).atan();
+        assert!((pose.yaw - offset).abs() < 1e-6);
+        assert!((pose.pitch - (100.0 / focal_length()).atan()).abs() < 1e-6);
+        assert!((pose.roll - FRAC_PI_4).abs() < 1e-6);
+        assert!(pose.distance_mm > 0.0);
+    }
+
+    #[test]
+    fn needs_two_sources() {
+        let source = Some(IrSource {
+            x: 10,
+            y: CAMERA_HEIGHT / 2,
+        });
+        assert_eq!(pose(&[source, None, None, None]), None);
+        assert_eq!(pose(&[source, source]), None);
+    }
+
+    #[test]
+    fn candles() {
+        // The right candle is 400 mm to the right and 400 mm higher.
+        let config = SensorBarConfig::from_sources([-200.0, 0.0], [200.0, 400.0], false);
+        assert!((config.spacing_mm - 400.0 * 2f32.sqrt()).abs() < 1e-3);
+
+        // The dots of a level remote appear rotated the other way.
+        let sources = [
+            Some(IrSource { x: 412, y: 484 }),
+            Some(IrSource { x: 612, y: 284 }),
+        ];
+        let pose = estimate_pose(&sources, &config, SystemTime::UNIX_EPOCH).unwrap();
+        assert!(pose.roll.abs() < 1e-6);
+
+        // The candles are further away than a bar producing the same dots.
+        let bar = self::pose(&sources).unwrap();
+        let ratio = config.spacing_mm / SENSOR_BAR_WIDTH_MM;
+        assert!((pose.distance_mm - bar.distance_mm * ratio).abs() < 1e-2);
+    }
+
+    #[test]
+    fn screen_below_bar() {
+        let pose = pose(&[
+            Some(IrSource { x: 412, y: 384 }),
+            Some(IrSource { x: 612, y: 384 }),
+        ])
+        .unwrap();
+        let config = SensorBarConfig {
+            above_screen: true,
+            ..Default::default()
+        };
+
+        // The center of a screen as tall as the distance is 26.6° below.
+        let angle = pose.screen_pitch(&config, pose.distance_mm);
+        assert!((angle - 0.5f32.atan()).abs() < 1e-6);
+        assert!((pose.screen_pitch(&Default::default(), pose.distance_mm) + angle).abs() < 1e-6);
+    }
+
+    #[test]
+    fn maps_calibrated_corners() {
+        // The camera sees the screen as a trapezoid.
+        let corners = [
+            (IrSource { x: 100, y: 100 }, (0.0, 0.0)),
+            (IrSource { x: 900, y: 100 }, (1920.0, 0.0)),
+            (IrSource { x: 1000, y: 700 }, (1920.0, 1080.0)),
+            (IrSource { x: 0, y: 700 }, (0.0, 1080.0)),
+        ];
+        let calibration = ScreenCalibration::calibrate(corners).unwrap();
+
+        for (source, (u, v)) in corners {
+            let (x, y) = calibration.map_ir_to_screen(source);
+            assert!((x - u).abs() < 1e-2 && (y - v).abs() < 1e-2);
+        }
+        // The perspective moves the center of the trapezoid downwards.
+        let (x, y) = calibration.map_ir_to_screen(IrSource { x: 500, y: 400 });
+        assert!((x - 960.0).abs() < 1e-2);
+        assert!(y > 540.0);
+    }
+
+    #[test]
+    fn rejects_collinear_points() {
+        let points = [0, 1, 2, 3].map(|i| {
+            (
+                IrSource {
+                    x: i * 100,
+                    y: i * 100,
+                },
+                (i as f32, 0.0),
+            )
+        });
+        assert!(ScreenCalibration::calibrate(points).is_err());
+    }
+
+    #[test]
+    fn finds_bar_among_interference() {
+        let config = SensorBarConfig::default();
+        let (left, right) = (IrSource { x: 400, y: 400 }, IrSource { x: 600, y: 410 });
+        // A candle appears above the bar.
+        let candle = IrSource { x: 500, y: 100 };
+        let sources = [Some(left), Some(candle), Some(right), None];
+
+        let bar = find_sensor_bar(&sources, &config, None).unwrap();
+        assert_eq!((bar.left, bar.right), (left, right));
+        assert!(bar.has_interference());
+        assert!(bar.confidence > 0.5 && bar.confidence < 1.0);
+
+        // The bar is followed as the remote rolls towards the candle.
+        let (left, right) = (IrSource { x: 420, y: 450 }, IrSource { x: 580, y: 330 });
+        let sources = [Some(left), Some(candle), Some(right), None];
+        let next = find_sensor_bar(&sources, &config, Some(&bar)).unwrap();
+        assert_eq!((next.left, next.right), (left, right));
+
+        let sources = [Some(left), None, Some(right), None];
+        let clean = find_sensor_bar(&sources, &config, None).unwrap();
+        assert_eq!((clean.confidence, clean.has_interference()), (1.0, false));
+    }
+}
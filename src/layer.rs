@@ -0,0 +1,206 @@
+//! Middleware between the event dispatch and the application.
+//!
+//! An [`EventLayer`] receives each event read from a device, and passes
+//! on any number of events in its place: it can remap, log, filter or
+//! record them. The layers added with [`Device::add_layer`] are applied
+//! in order to the events of every [`EventStream`] of the device and of
+//! [`Device::try_next_event`], so the rest of the application sees the
+//! transformed events only.
+//!
+//! [`filter_map`] and [`inspect`] build layers from closures.
+//!
+//! [`Device::add_layer`]: crate::Device::add_layer
+//! [`Device::try_next_event`]: crate::Device::try_next_event
+//! [`EventStream`]: crate::event::EventStream
+use crate::event::Event;
+use std::collections::VecDeque;
+
+/// Transforms the events of a device.
+pub trait EventLayer {
+    /// Handles an event, passing the resulting events, if any, to the
+    /// next layer through `emit`.
+    fn handle(&mut self, event: Event, emit: &mut dyn FnMut(Event));
+}
+
+/// A layer that replaces each event by the result of a closure, or
+/// drops it. See [`filter_map`].
+pub struct FilterMap<F> {
+    f: F,
+}
+
+impl<F> EventLayer for FilterMap<F>
+where
+    F: FnMut(Event) -> Option<Event>,
+{
+    fn handle(&mut self, event: Event, emit: &mut dyn FnMut(Event)) {
+        if let Some(event) = (self.f)(event) {
+            emit(event);
+        }
+    }
+}
+
+/// Returns a layer that replaces each event by the result of `f`, or
+/// drops it if `f` returns `None`.
+pub fn filter_map<F>(f: F) -> FilterMap<F>
+where
+    F: FnMut(Event) -> Option<Event>,
+{
+    FilterMap { f }
+}
+
+/// A layer that calls a closure with each event, and passes it on
+/// unchanged. See [`inspect`].
+pub struct Inspect<F> {
+    f: F,
+}
+
+impl<F> EventLayer for Inspect<F>
+where
+    F: FnMut(&Event),
+{
+    fn handle(&mut self, event: Event, emit: &mut dyn FnMut(Event)) {
+        (self.f)(&event);
+        emit(event);
+    }
+}
+
+/// Returns a layer that calls `f` with each event, e.g. to log or
+/// record it, and passes it on unchanged.
+pub fn inspect<F>(f: F) -> Inspect<F>
+where
+    F: FnMut(&Event),
+{
+    Inspect { f }
+}
+
+/// The layers of a device, and the events they emitted that were not
+/// read yet.
+#[derive(Default)]
+pub(crate) struct LayerStack {
+    layers: Vec<Box<dyn EventLayer>>,
+    pending: VecDeque<Event>,
+}
+
+impl LayerStack {
+    /// Adds a layer, which handles the events emitted by the previous
+    /// ones.
+    pub fn push(&mut self, layer: Box<dyn EventLayer>) {
+        self.layers.push(layer);
+    }
+
+    /// Removes every layer. Pending events are kept.
+    pub fn clear(&mut self) {
+        self.layers.clear();
+    }
+
+    /// Passes the event through the layers. Returns the next event to
+    /// read, if any.
+    pub fn process(&mut self, event: Event) -> Option<Event> {
+        if self.layers.is_empty() && self.pending.is_empty() {
+            return Some(event);
+        }
+        let pending = &mut self.pending;
+        run(&mut self.layers, event, &mut |event| {
+            pending.push_back(event)
+        });
+        self.pending.pop_front()
+    }
+
+    /// Returns the next event emitted by the layers and not read yet.
+    pub fn next_pending(&mut self) -> Option<Event> {
+        self.pending.pop_front()
+    }
+}
+
+/// Passes the event through the given layers, and the resulting events
+/// to `out`.
+fn run(layers: &mut [Box<dyn EventLayer>], event: Event, out: &mut dyn FnMut(Event)) {
+    match layers.split_first_mut() {
+        Some((layer, rest)) => layer.handle(event, &mut |event| run(rest, event, out)),
+        None => out(event),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filter_map, inspect, EventLayer, LayerStack};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::SystemTime;
+
+    fn key(key: Key) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH, EventKind::Key(key, KeyState::Down))
+    }
+
+    /// Emits every event twice.
+    struct Twice;
+
+    impl EventLayer for Twice {
+        fn handle(&mut self, event: Event, emit: &mut dyn FnMut(Event)) {
+            emit(event);
+            emit(event);
+        }
+    }
+
+    #[test]
+    fn applies_layers_in_order() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut stack = LayerStack::default();
+        stack.push(Box::new(filter_map(|event: Event| match event.kind {
+            EventKind::Key(Key::One, state) => Some(Event {
+                kind: EventKind::Key(Key::Two, state),
+                ..event
+            }),
+            EventKind::Key(Key::Home, _) => None,
+            _ => Some(event),
+        })));
+        stack.push(Box::new(Twice));
+        let recorded = seen.clone();
+        stack.push(Box::new(inspect(move |event: &Event| {
+            recorded.borrow_mut().push(event.kind)
+        })));
+
+        assert!(stack.process(key(Key::Home)).is_none());
+        let remapped = stack.process(key(Key::One)).unwrap();
+        assert!(matches!(remapped.kind, EventKind::Key(Key::Two, _)));
+        assert!(stack.next_pending().is_some());
+        assert!(stack.next_pending().is_none());
+        assert_eq!(seen.borrow().len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "test-harness")]
+    fn applies_layers_to_streams() -> crate::Result<()> {
+        use crate::harness::{connect, FakeEvent, FakeIface};
+        use crate::layer;
+        use crate::{Channels, Result};
+        use futures::{executor, StreamExt};
+
+        let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER)?;
+        let mut device = connect(&fake)?;
+        device.add_layer(layer::filter_map(|event| match event.kind {
+            EventKind::Accelerometer { .. } => None,
+            _ => Some(event),
+        }));
+        fake.push_batch([
+            FakeEvent::accelerometer(1, 2, 3),
+            FakeEvent::key(Key::A, KeyState::Down),
+        ]);
+        fake.push(FakeEvent::accelerometer(4, 5, 6));
+        fake.push(FakeEvent::gone());
+
+        let kinds: Vec<_> = executor::block_on(
+            device
+                .events()?
+                .map(|event| event.map(|event| event.kind))
+                .collect::<Vec<_>>(),
+        )
+        .into_iter()
+        .collect::<Result<_>>()?;
+        assert_eq!(kinds.len(), 2);
+        assert!(matches!(kinds[0], EventKind::Key(Key::A, KeyState::Down)));
+        assert!(matches!(kinds[1], EventKind::Disconnected));
+        Ok(())
+    }
+}
@@ -9,27 +9,122 @@
 //!    - Open, close and detect available [channels](Channels).
 //!    - Efficient [event dispatching](Device::events) through `epoll`.
 //!
+//! Import the [`prelude`] for the types and traits needed by most
+//! applications.
+//!
 //! [xwiimote]: https://github.com/dvdhrm/xwiimote
 //! [tokio]: https://crates.io/crates/tokio
 // todo: add examples and fix links
-use crate::event::EventStream;
+#![cfg_attr(feature = "nightly", feature(async_iterator))]
+
+#[cfg(not(any(target_os = "linux", feature = "stub")))]
+compile_error!("xwiimote only works on Linux, enable the `stub` feature to build elsewhere");
+use crate::battery::BatteryLevels;
+use crate::connect::{ConnectProgress, ConnectState, ProgressReporter, Sleep};
+use crate::control::ControlSink;
+use crate::discovery::DiscoveredDevice;
+use crate::event::{Event, EventKind, EventStream, Key, KeyState};
+use crate::extension::ExtensionId;
+use crate::ffi::XwiiString;
+use crate::holders::{DeviceBusy, Holder};
 use crate::io_blocker::IoBlocker;
+use crate::layer::{EventLayer, LayerStack};
+use crate::link::{LinkQualities, LinkQuality};
+use crate::profile::{Profile, ProfileStore};
+use crate::pwm::RumblePwm;
+use crate::quirks::{Capabilities, Quirks};
+use crate::report::{AddressSpace, Report};
+use crate::runtime::Runtime;
+pub use crate::types::Channels;
 use bitflags::bitflags;
-use futures::Stream;
+use futures::future::Either;
+use futures::{executor, future, Stream, TryStreamExt};
 use num_derive::FromPrimitive;
 
-use std::ffi::{CStr, CString};
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::File;
+use std::future::Future;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::RawFd;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 
+use std::sync::Arc;
 use std::task::Poll;
-use std::time::Duration;
-use std::{alloc, io, ptr, thread};
+use std::time::{Duration, Instant, SystemTime};
+use std::{io, ptr, thread};
 
+pub mod actor;
+pub mod axis;
+pub mod balance_board;
+pub mod battery;
+pub mod broadcast;
+pub mod calibration;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod capture;
+pub mod clock;
+pub mod combo;
+pub mod connect;
+pub mod control;
+pub mod dedup;
+pub mod diagnostics;
+pub mod discovery;
+#[cfg(feature = "egui")]
+pub mod egui_input;
+#[cfg(feature = "uinput")]
+pub mod emulation;
 pub mod event;
+pub mod extension;
+#[cfg(feature = "test-uinput")]
+pub mod fake_hid;
+mod ffi;
+pub mod filter;
+pub mod fitness;
+#[cfg(feature = "evdev")]
+pub mod force_feedback;
+pub mod fusion;
+pub mod gamepad;
+pub mod gyro;
+#[cfg(feature = "test-harness")]
+pub mod harness;
+pub mod head_tracking;
+pub mod holders;
+pub mod idle;
+mod input;
+#[cfg_attr(not(target_os = "linux"), path = "stub/io_blocker.rs")]
 mod io_blocker;
+pub mod ir;
+pub mod layer;
+pub mod link;
+pub mod logger;
+#[cfg(feature = "mio")]
+mod mio_source;
+pub mod motion;
+pub mod pool;
+pub mod prelude;
+pub mod press;
+pub mod pro_controller;
+pub mod profile;
+mod pwm;
+#[cfg(feature = "python")]
+mod python;
+pub mod quirks;
+pub mod rate;
+pub mod report;
+pub mod runtime;
+pub mod setup;
+#[cfg(feature = "evdev")]
+pub mod supplemental;
+pub mod sway;
+mod sys;
+pub mod text_entry;
+mod timer;
+pub mod types;
+#[cfg(feature = "wire")]
+pub mod wire;
 
 // FFI and libc utilities
 
@@ -44,15 +139,6 @@ macro_rules! bail_if {
 // Expose macro to all modules within crate.
 pub(crate) use bail_if;
 
-/// Converts a C string into a Rust [`String`](std::String).
-fn into_owned_str(raw: *const libc::c_char) -> String {
-    unsafe { CStr::from_ptr(raw).to_string_lossy().into_owned() }
-}
-
-fn dealloc_str(str: *const libc::c_char) {
-    unsafe { alloc::dealloc(str as *mut u8, alloc::Layout::new::<libc::c_char>()) };
-}
-
 pub(crate) type Result<T> = io::Result<T>;
 
 /// A Wii Remote device address.
@@ -61,9 +147,10 @@ pub struct Address(PathBuf);
 
 impl Address {
     /// Converts the path given as a C string to an address.
-    fn from_raw(path_str: *const libc::c_char) -> Self {
-        let path = PathBuf::from(into_owned_str(path_str));
-        path.into()
+    fn from_raw(path_str: &CStr) -> Self {
+        // Copy the bytes directly; paths need not be valid UTF-8, and
+        // going through `String` would cost an extra validation pass.
+        PathBuf::from(OsStr::from_bytes(path_str.to_bytes())).into()
     }
 }
 
@@ -84,12 +171,15 @@ impl From<PathBuf> for Address {
 /// may be returned multiple times.
 ///
 /// The stream returns `None` only if discover is disabled and all
-/// connected devices have been returned.
+/// connected devices have been returned. Failures to receive hot-plug
+/// events from udev are returned as errors, after which the stream
+/// can be polled again.
 ///
 /// A monitor should be dropped when no longer needed to avoid
 /// needlessly polling the system for new devices.
 pub struct Monitor {
     handle: *mut xwiimote_sys::monitor,
+    blocker: Arc<IoBlocker>,
     // The file descriptor used by the handle monitor, only present
     // in discovery mode to monitor for hot-plug events.
     fd: Option<RawFd>,
@@ -98,22 +188,110 @@ pub struct Monitor {
 }
 
 impl Monitor {
-    const HOTPLUG_EVENTS: libc::c_int = libc::EPOLLIN | libc::EPOLLHUP | libc::EPOLLPRI;
+    const HOTPLUG_EVENTS: libc::c_int = IoBlocker::READ_EVENTS;
 
     /// Creates a monitor that first streams the connected devices' addresses
     /// and, if `discover` is `true`, then listens for hot-plug events,
     /// streaming the new addresses.
     pub fn new(discover: bool) -> Result<Self> {
+        Self::with_blocker(discover, IoBlocker::get().clone())
+    }
+
+    /// Creates a monitor like [`Monitor::new`], whose hot-plug events
+    /// are received by the given runtime instead of the global one.
+    pub fn with_runtime(discover: bool, runtime: &Runtime) -> Result<Self> {
+        Self::with_blocker(discover, runtime.blocker().clone())
+    }
+
+    fn with_blocker(discover: bool, blocker: Arc<IoBlocker>) -> Result<Self> {
         // Create monitor based on udevd events.
         let handle = unsafe { xwiimote_sys::monitor_new(discover, false) };
         bail_if!(handle.is_null());
 
         Ok(Monitor {
             handle,
+            blocker,
             fd: discover.then(|| unsafe { xwiimote_sys::monitor_get_fd(handle, false) }),
             enumerated: false,
         })
     }
+
+    /// Registers interest in the hot-plug events of the monitor with an
+    /// epoll instance owned by the application, which receives `token`
+    /// as the event data. The interest is level-triggered.
+    ///
+    /// This lets applications running their own event loop wait for
+    /// new devices without the async runtime. Once `epfd` reports the
+    /// monitor as readable, read the new addresses with
+    /// [`Monitor::next_address`]. Fails with
+    /// [`io::ErrorKind::InvalidInput`] if the monitor doesn't discover
+    /// new devices.
+    pub fn register_with_epoll(&self, epfd: RawFd, token: u64) -> Result<()> {
+        io_blocker::register_external(epfd, self.discovery_fd()?, token)
+    }
+
+    /// Removes the interest registered by [`Monitor::register_with_epoll`].
+    pub fn deregister_from_epoll(&self, epfd: RawFd) -> Result<()> {
+        io_blocker::deregister_external(epfd, self.discovery_fd()?)
+    }
+
+    fn discovery_fd(&self) -> Result<RawFd> {
+        self.fd.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the monitor does not discover devices",
+            )
+        })
+    }
+
+    /// Returns the address of the next connected or discovered device,
+    /// without blocking. Returns `None` once the connected devices are
+    /// enumerated, until a new device is discovered.
+    ///
+    /// Use this instead of polling the monitor as a [`Stream`], e.g.
+    /// with [`Monitor::register_with_epoll`].
+    pub fn next_address(&mut self) -> Result<Option<Address>> {
+        match self.poll_path()? {
+            Some(path) => Ok(Some(Address::from_raw(path.as_c_str()))),
+            None if !self.enumerated => {
+                self.enumerated = true;
+                // Read the first discovered device, if any.
+                match self.fd {
+                    Some(_) => self.next_address(),
+                    None => Ok(None),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the next connected or discovered device like
+    /// [`Monitor::next_address`], described by its properties.
+    pub fn next_device(&mut self) -> Result<Option<DiscoveredDevice>> {
+        Ok(self.next_address()?.map(DiscoveredDevice::new))
+    }
+
+    /// Converts the monitor into a stream of the connected and discovered
+    /// devices, described by their properties, instead of their bare
+    /// addresses. See the [`discovery`] module.
+    pub fn devices(self) -> impl Stream<Item = Result<DiscoveredDevice>> {
+        self.map_ok(DiscoveredDevice::new)
+    }
+
+    /// Reads the next device path from the monitor, if any.
+    ///
+    /// A null path marks both the end of the enumeration and the lack of
+    /// new hot-plug events, but only udev failures set `errno` while
+    /// discovering devices.
+    fn poll_path(&self) -> Result<Option<XwiiString>> {
+        let (raw, err) = ffi::with_errno(|| unsafe { xwiimote_sys::monitor_poll(self.handle) });
+        match unsafe { XwiiString::from_raw(raw) } {
+            Some(path) => Ok(Some(path)),
+            // Skipped non-Wii Remote devices may leave `errno` set.
+            None if !self.enumerated => Ok(None),
+            None => err.map_or(Ok(None), Err),
+        }
+    }
 }
 
 impl Stream for Monitor {
@@ -123,7 +301,7 @@ impl Stream for Monitor {
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        let raw_path = if self.enumerated {
+        let path = if self.enumerated {
             // Discover devices only if `self.fd` is present. Otherwise,
             // we completed the enumeration process.
             let fd = match self.fd {
@@ -131,82 +309,95 @@ impl Stream for Monitor {
                 None => return Poll::Ready(None),
             };
 
-            let raw_path = unsafe { xwiimote_sys::monitor_poll(self.handle) };
-            if raw_path.is_null() {
-                // No new device is available, arrange for `wake` to be called
-                // once a new device is found.
-                IoBlocker::get().set_callback(fd, cx.waker().clone());
-                return Poll::Pending;
+            match self.poll_path()? {
+                Some(path) => path,
+                None => {
+                    // No new device is available, arrange for `wake` to be called
+                    // once a new device is found.
+                    self.blocker.set_callback(fd, cx.waker());
+                    return Poll::Pending;
+                }
             }
-            raw_path
         } else {
             // Device enumeration requires no blocking, read directly.
-            let raw_path = unsafe { xwiimote_sys::monitor_poll(self.handle) };
-            if raw_path.is_null() {
-                // Read the first `null` address; completed device enumeration.
-                self.enumerated = true;
+            match self.poll_path()? {
+                Some(path) => path,
+                None => {
+                    // Read the first `null` address; completed device enumeration.
+                    self.enumerated = true;
 
-                return if let Some(fd) = self.fd {
-                    // Listen for hot-plug events on the monitor descriptor.
-                    IoBlocker::get().add_interest(fd, Self::HOTPLUG_EVENTS)?;
-                    // Poll again to return the first discovered device.
-                    self.poll_next(cx)
-                } else {
-                    Poll::Ready(None)
-                };
+                    return if let Some(fd) = self.fd {
+                        // Listen for hot-plug events on the monitor descriptor.
+                        self.blocker.add_interest(fd, Self::HOTPLUG_EVENTS)?;
+                        // Poll again to return the first discovered device.
+                        self.poll_next(cx)
+                    } else {
+                        Poll::Ready(None)
+                    };
+                }
             }
-            raw_path
         };
 
-        let address = Address::from_raw(raw_path);
-        dealloc_str(raw_path);
-        Poll::Ready(Some(Ok(address)))
+        Poll::Ready(Some(Ok(Address::from_raw(path.as_c_str()))))
     }
 }
 
 impl Drop for Monitor {
     fn drop(&mut self) {
         if let Some(fd) = self.fd {
-            IoBlocker::get()
-                .remove_interest(fd, Self::HOTPLUG_EVENTS)
-                .expect("failed to remove interest for monitor fd");
+            if let Err(err) = self.blocker.remove_interest(fd, Self::HOTPLUG_EVENTS) {
+                log::warn!("failed to remove interest for monitor fd: {}", err);
+            }
         }
         // Decrements ref-count to zero. This closes `self.fd`, if set.
         unsafe { xwiimote_sys::monitor_unref(self.handle) };
     }
 }
 
-// Device and interfaces
+/// Connects to the first Wii Remote found, waiting up to `timeout` for
+/// one to be discovered if none is connected.
+///
+/// Fails with [`io::ErrorKind::TimedOut`] if no device is found in time.
+/// See [`wait_for_device_matching`] to choose among several devices.
+pub fn first_device(timeout: Duration) -> Result<Device> {
+    wait_for_device_matching(|_| true, timeout)
+}
 
-bitflags! {
-    /// Represents the channels that can be opened on a [`Device`].
-    ///
-    /// The `xwiimote` library calls these interfaces.
-    pub struct Channels: libc::c_uint {
-        // todo: improve docs
-        /// Primary channel.
-        const CORE = 0x1;
-        /// Accelerometer channel.
-        const ACCELEROMETER = 0x2;
-        /// IR camera channel.
-        const IR = 0x4;
-        /// MotionPlus extension channel.
-        const MOTION_PLUS = 0x100;
-        /// Nunchuk extension channel.
-        const NUNCHUK = 0x200;
-        /// Classic controller channel.
-        const CLASSIC_CONTROLLER = 0x400;
-        /// Balance board channel.
-        const BALANCE_BOARD = 0x800;
-        /// ProController channel.
-        const PRO_CONTROLLER = 0x1000;
-        /// Drums channel.
-        const DRUMS = 0x2000;
-        /// Guitar channel.
-        const GUITAR = 0x4000;
+/// Connects to the connected and discovered Wii Remotes in turn, until
+/// `filter` accepts one, waiting up to `timeout` in total.
+///
+/// The devices that fail to connect are skipped. Fails with
+/// [`io::ErrorKind::TimedOut`] if no device is accepted in time.
+pub fn wait_for_device_matching<F>(mut filter: F, timeout: Duration) -> Result<Device>
+where
+    F: FnMut(&Device) -> bool,
+{
+    let mut monitor = Monitor::new(true)?;
+    let find = Box::pin(async move {
+        while let Some(address) = monitor.try_next().await? {
+            match Device::connect(&address) {
+                Ok(device) if filter(&device) => return Ok(device),
+                Ok(_) => {}
+                Err(err) => log::debug!("skipping device {:?}: {}", address, err),
+            }
+        }
+        // The monitor only ends if it doesn't discover devices.
+        Err(io::Error::from(io::ErrorKind::NotFound))
+    });
+    let sleep = Sleep::new(timeout);
+    match executor::block_on(future::select(find, sleep)) {
+        Either::Left((found, _)) => found,
+        Either::Right((slept, _)) => slept.and_then(|()| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no matching device was found",
+            ))
+        }),
     }
 }
 
+// Device and interfaces
+
 /// Motion Plus sensor normalization and calibration values.
 ///
 /// The absolute offsets are subtracted from any Motion Plus
@@ -224,8 +415,29 @@ pub struct MotionPlusNormalization {
     pub factor: i32,
 }
 
+/// The configuration of a device, as captured by [`Device::snapshot`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DeviceState {
+    /// The open channels.
+    pub channels: Channels,
+    /// Whether the core channel is open in writable mode.
+    pub writable: bool,
+    /// The channels closed by [`Device::set_streaming`].
+    pub suspended: Channels,
+    /// The state of each LED light, from [`Led::One`] to [`Led::Four`].
+    pub leds: [bool; 4],
+    /// The Motion Plus sensor normalization values.
+    pub mp_normalization: MotionPlusNormalization,
+    /// Whether the rumble motor is on.
+    pub rumble: bool,
+    /// The extension type, as returned by [`Device::extension`].
+    ///
+    /// Not restored, since it depends on the plugged extension.
+    pub extension: String,
+}
+
 /// The Wii Remote LED lights.
-#[derive(Copy, Clone, Debug, FromPrimitive)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, FromPrimitive)]
 pub enum Led {
     /// The left-most light.
     One = 1,
@@ -237,41 +449,396 @@ pub enum Led {
     Four,
 }
 
+bitflags! {
+    /// A set of Wii Remote LED lights.
+    pub struct Leds: u8 {
+        /// The left-most light.
+        const ONE = 0x1;
+        /// The mid-left light.
+        const TWO = 0x2;
+        /// The mid-right light.
+        const THREE = 0x4;
+        /// The right-most light.
+        const FOUR = 0x8;
+    }
+}
+
+impl From<Led> for Leds {
+    fn from(light: Led) -> Self {
+        Self::from_bits_truncate(1 << (light as u8 - 1))
+    }
+}
+
+/// Whether other handles keep receiving the events of open channels.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum OpenMode {
+    /// Other handles to the device, in this or other processes, also
+    /// receive the events.
+    #[default]
+    Shared,
+    /// Only this handle receives the events, until the channels are
+    /// closed. Fails if another handle already has a channel open in
+    /// exclusive mode.
+    Exclusive,
+}
+
+/// The options used to connect to a [`Device`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ConnectOptions {
+    /// Whether to watch the device for hot-plug events, see
+    /// [`Device::set_watch`].
+    ///
+    /// If disabled, the device behaves as a raw `xwiimote` interface:
+    /// its [`EventStream`]s are not notified of the device being
+    /// disconnected, and keep waiting for events until dropped.
+    pub watch: bool,
+    /// Whether to wait for a newly discovered device to settle before
+    /// connecting to it.
+    ///
+    /// Opening the device immediately after it is discovered by a
+    /// [`Monitor`] may fail with a "Transport is not connected" error.
+    /// If enabled, the input nodes of the device are probed until they
+    /// open, for up to 100 ms. Disable this to connect to already
+    /// connected devices without blocking the current thread.
+    pub blocking: bool,
+    /// Whether to accommodate third-party remotes, which often misreport
+    /// their extensions or need several attempts to initialize them.
+    ///
+    /// If enabled, [`Device::open`] retries failing channels, missing
+    /// calibration data is replaced by nominal values, and events with
+    /// unknown keys are skipped instead of failing the stream, up to 64
+    /// in a row per read. See [`CloneHeuristics`](quirks::CloneHeuristics)
+    /// to decide whether to enable it.
+    pub clone_friendly: bool,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            watch: true,
+            blocking: true,
+            clone_friendly: false,
+        }
+    }
+}
+
 /// A connected Wii Remote.
 pub struct Device {
     pub(crate) handle: *mut xwiimote_sys::iface,
-    // Have we opened the core channel in writable mode? We keep track
-    // of this because some operations like `rumble` need this channel
-    // open to function.
-    core_open: bool,
+    // The channels opened in writable mode, and not closed since by
+    // `close`. We keep track of this because some operations like
+    // `rumble` need the core channel open for writing to function.
+    writable: Channels,
+    // The interval between keep-alive requests sent while streaming.
+    pub(crate) keepalive: Option<Duration>,
+    // The channels available as of the last watch event read by
+    // `try_next_event`.
+    available: Channels,
+    quirks: Quirks,
+    pub(crate) clone_friendly: bool,
+    // The channels closed by `set_streaming`, and those of them that
+    // were writable before closing them.
+    suspended: Channels,
+    suspended_writable: Channels,
+    // The channels opened by the application and not closed since,
+    // even if the kernel closed them.
+    requested: Channels,
+    // A `ChannelClosed` event to return from `try_next_event`.
+    closed: Option<Event>,
+    // The sequence number of the next event read by `try_next_event`.
+    sequence: u64,
+    // The last state of the rumble motor set by `set_rumble`.
+    rumbling: Cell<bool>,
+    // The emulation of a partial rumble intensity, if running.
+    rumble_pwm: Option<RumblePwm>,
+    rumble_period: Duration,
+    // The layers applied to the events read from the device, shared by
+    // its event streams.
+    pub(crate) layers: RefCell<LayerStack>,
 }
 
 impl Device {
-    /// Connects to the Wii Remote at the given address.
+    /// The number of times a failed [`Device::open`] is retried on
+    /// slow devices.
+    const OPEN_RETRIES: u32 = 3;
+    /// The delay between attempts to open channels on slow devices.
+    const OPEN_RETRY_DELAY: Duration = Duration::from_millis(100);
+    /// The longest time to wait for a newly discovered device to settle.
+    ///
+    /// Opening the device file immediately after being discovered
+    /// results in a "Transport is not connected" error. The device is
+    /// probed until its input nodes open, which usually takes a few
+    /// milliseconds, and connected anyway after this delay.
+    const SETTLE_DELAY: Duration = Duration::from_millis(100);
+    /// The interval between probes of a settling device.
+    const SETTLE_PROBE_INTERVAL: Duration = Duration::from_millis(5);
+    /// The longest time to wait for the answer to a memory read.
+    const MEMORY_READ_TIMEOUT: Duration = Duration::from_secs(1);
+    /// The register address of the identifier of the extension.
+    const EXTENSION_ID_ADDRESS: u32 = 0xa4_00fa;
+    /// The default period of the pulses that emulate a partial rumble
+    /// intensity. See [`Device::set_rumble_period`].
+    pub const DEFAULT_RUMBLE_PERIOD: Duration = Duration::from_millis(40);
+
+    /// Connects to the Wii Remote at the given address, with the
+    /// default [`ConnectOptions`].
     pub fn connect(address: &Address) -> Result<Self> {
+        Self::connect_with(address, &ConnectOptions::default())
+    }
+
+    /// Connects to the Wii Remote at the given address.
+    pub fn connect_with(address: &Address, options: &ConnectOptions) -> Result<Self> {
+        if options.blocking {
+            let start = Instant::now();
+            while !input::nodes_ready(&address.0) && start.elapsed() < Self::SETTLE_DELAY {
+                thread::sleep(Self::SETTLE_PROBE_INTERVAL);
+            }
+        }
+        Self::connect_settled(address, options, |_| {})
+    }
+
+    /// Connects to the Wii Remote at the given address like
+    /// [`Device::connect_with`], and opens the given channels like
+    /// [`Device::open`].
+    ///
+    /// The channels are opened in a single pass, so this is the fastest
+    /// way to start reading events from a newly discovered device.
+    pub fn connect_and_open(
+        address: &Address,
+        options: &ConnectOptions,
+        channels: Channels,
+        writable: bool,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let mut device = Self::connect_with(address, options)?;
+        let connected = start.elapsed();
+        device.open(channels, writable)?;
+        log::debug!(
+            "connected to {:?} in {:?}, opened {:?} in {:?}",
+            address,
+            connected,
+            channels,
+            start.elapsed() - connected
+        );
+        Ok(device)
+    }
+
+    /// Returns a future that connects to the Wii Remote at the given
+    /// address, and a stream of its progress.
+    ///
+    /// Unlike [`Device::connect_with`], waiting for the device to settle
+    /// (see [`ConnectOptions::blocking`]) doesn't block the current
+    /// thread. The remaining steps are brief, but still block.
+    pub fn connect_async(
+        address: &Address,
+        options: &ConnectOptions,
+    ) -> (impl Future<Output = Result<Self>>, ConnectProgress) {
+        let (reporter, progress) = ProgressReporter::new();
+        let (address, options) = (address.clone(), *options);
+        let connect = async move {
+            if options.blocking {
+                let start = Instant::now();
+                while !input::nodes_ready(&address.0) && start.elapsed() < Self::SETTLE_DELAY {
+                    Sleep::new(Self::SETTLE_PROBE_INTERVAL).await?;
+                }
+            }
+            Self::connect_settled(&address, &options, |state| reporter.report(state))
+        };
+        (connect, progress)
+    }
+
+    /// Connects to a device that had time to settle, reporting the
+    /// progress to `report`.
+    fn connect_settled<F>(address: &Address, options: &ConnectOptions, report: F) -> Result<Self>
+    where
+        F: Fn(ConnectState),
+    {
         let mut handle = ptr::null_mut();
         let path = CString::new(address.0.as_os_str().as_bytes()).unwrap();
 
-        // Opening the device file immediately after being discovered
-        // results in a "Transport is not connected" error. This delays
-        // the operation, but isn't ideal (the delay is arbitrary).
-        thread::sleep(Duration::from_millis(100));
-
-        let res_code = unsafe { xwiimote_sys::iface_new(&mut handle, path.as_ptr()) };
+        report(ConnectState::OpeningInterface);
+        let res_code = unsafe { sys::iface_new(&mut handle, path.as_ptr()) };
         bail_if!(res_code != 0);
 
-        // Watch the device for hot-plug events. Otherwise, the
-        // `xwiimote_sys:iface_dispatch` function does not report
-        // events of type `xwii_sys::EVENT_GONE`, which we need to
-        // remove interest for the device file in the `IoBlocker`
-        // (see `EventStream::remove_interest`).
-        let res_code = unsafe { xwiimote_sys::iface_watch(handle, true) };
-        bail_if!(res_code != 0);
+        if options.watch {
+            report(ConnectState::Watching);
+            // Watch the device for hot-plug events. Otherwise, the
+            // `xwiimote_sys:iface_dispatch` function does not report
+            // events of type `xwii_sys::EVENT_GONE`, which we need to
+            // remove interest for the device file in the `IoBlocker`
+            // (see `EventStream::remove_interest`).
+            let res_code = unsafe { sys::iface_watch(handle, true) };
+            bail_if!(res_code != 0);
+        }
 
-        Ok(Self {
+        let mut device = Self {
             handle,
-            core_open: false,
-        })
+            writable: Channels::empty(),
+            keepalive: None,
+            available: Channels::from_bits_truncate(unsafe { sys::iface_available(handle) }),
+            quirks: Quirks::empty(),
+            clone_friendly: options.clone_friendly,
+            suspended: Channels::empty(),
+            suspended_writable: Channels::empty(),
+            requested: Channels::empty(),
+            closed: None,
+            sequence: 0,
+            rumbling: Cell::new(false),
+            rumble_pwm: None,
+            rumble_period: Self::DEFAULT_RUMBLE_PERIOD,
+            layers: Default::default(),
+        };
+        device.quirks = Quirks::detect(&device).unwrap_or_else(|err| {
+            log::debug!("failed to detect device quirks: {}", err);
+            Quirks::empty()
+        });
+        report(ConnectState::Ready);
+        Ok(device)
+    }
+
+    /// Returns the quirks of the device, which are detected when
+    /// connecting to it. See the [`quirks`] module.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Checks whether the device was connected with
+    /// [`ConnectOptions::clone_friendly`].
+    pub fn is_clone_friendly(&self) -> bool {
+        self.clone_friendly
+    }
+
+    /// Returns the hardware features of the device, as derived from its
+    /// [quirks](Device::quirks).
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::from(self.quirks)
+    }
+
+    /// Replaces the quirks of the device, e.g. to correct a wrong
+    /// detection. See [`QuirkOverride`](quirks::QuirkOverride).
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Returns the sysfs path of the device.
+    pub fn syspath(&self) -> PathBuf {
+        let raw_path = unsafe { sys::iface_get_syspath(self.handle) };
+        // The path is owned by the interface, copy it.
+        let path = unsafe { CStr::from_ptr(raw_path) };
+        PathBuf::from(OsStr::from_bytes(path.to_bytes()))
+    }
+
+    /// Returns the paths of the evdev nodes of the kernel input devices
+    /// of the device, e.g. `/dev/input/event7`, in sorted order.
+    ///
+    /// The kernel driver registers an input device for the Wii Remote
+    /// and for each extension, some of which report data that is not
+    /// decoded by the library. See the `supplemental` module, which
+    /// requires the `evdev` feature.
+    pub fn evdev_nodes(&self) -> Result<Vec<PathBuf>> {
+        input::input_nodes(&self.syspath())
+    }
+
+    /// Returns the path of the hidraw node of the device, e.g.
+    /// `/dev/hidraw3`, or `None` if the kernel registered none.
+    ///
+    /// The hidraw node receives a copy of every input report, and
+    /// accepts output reports, e.g. to access the speaker, the EEPROM
+    /// or the registers of the IR camera, which the kernel driver
+    /// doesn't expose. Reports sent this way bypass the driver, which
+    /// doesn't track their effects. The processes holding the node are
+    /// included in [`Device::holders`].
+    pub fn hidraw_node(&self) -> Option<PathBuf> {
+        input::hidraw_node(&self.syspath()).ok()
+    }
+
+    /// Sends a raw output report through the hidraw node of the device.
+    ///
+    /// Every output report carries the state of the rumble motor. The
+    /// rumble bit of the report is set to the state last set through
+    /// [`Device::rumble`], so sending it doesn't toggle the motor, unless
+    /// it is a [`Report::rumble`](report::Report::rumble), which updates
+    /// that state. The kernel driver is not told about the other effects
+    /// of the report, e.g. its LED state is not updated.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if the device has no
+    /// hidraw node. Requires write access to the node.
+    pub fn send_report(&self, report: &Report) -> Result<()> {
+        let mut node = File::options().write(true).open(self.hidraw()?)?;
+        self.write_report(&mut node, report)
+    }
+
+    /// Reads `size` bytes from the given address of the EEPROM or the
+    /// registers of the device, through its hidraw node.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if the device has no
+    /// hidraw node or the address can't be read, and with
+    /// [`io::ErrorKind::TimedOut`] if the device doesn't answer. Requires
+    /// read and write access to the node.
+    pub fn read_memory(&self, space: AddressSpace, address: u32, size: u16) -> Result<Vec<u8>> {
+        let report = Report::read_memory(space, address, size)?;
+        let mut node = File::options()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(self.hidraw()?)?;
+        self.write_report(&mut node, &report)?;
+        report::read_answer(&mut node, address, size, Self::MEMORY_READ_TIMEOUT)
+    }
+
+    /// Reads the identifier of the plugged extension from its registers,
+    /// e.g. to tell apart variants that share an [extension type
+    /// identifier](Device::extension), or third-party copies.
+    ///
+    /// While the Motion Plus is active, its identifier is returned, see
+    /// [`ExtensionModel`](extension::ExtensionModel). Fails like
+    /// [`Device::read_memory`], with [`io::ErrorKind::NotFound`] if no
+    /// extension is plugged.
+    pub fn extension_id(&self) -> Result<ExtensionId> {
+        let id = self.read_memory(AddressSpace::Registers, Self::EXTENSION_ID_ADDRESS, 6)?;
+        Ok(ExtensionId(id.try_into().expect("short memory read")))
+    }
+
+    /// Returns the path of the hidraw node, or fails if there is none.
+    fn hidraw(&self) -> Result<PathBuf> {
+        self.hidraw_node()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the device has no hidraw node"))
+    }
+
+    /// Writes a report to the hidraw node, keeping the rumble state.
+    fn write_report(&self, node: &mut File, report: &Report) -> Result<()> {
+        let rumbling = if report.id() == report::RUMBLE_ID {
+            report.rumble_bit()
+        } else {
+            self.rumbling.get()
+        };
+        report.clone().with_rumble_bit(rumbling).write_to(node)?;
+        self.rumbling.set(rumbling);
+        Ok(())
+    }
+
+    /// Returns the Bluetooth address of the device, e.g. `00:1f:32:aa:bb:cc`.
+    pub fn mac_address(&self) -> Result<String> {
+        discovery::uevent_property(&self.syspath(), "HID_UNIQ")?
+            .filter(|mac| !mac.is_empty())
+            .map(|mac| mac.to_ascii_lowercase())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown device address"))
+    }
+
+    /// Enables or disables watching the device for hot-plug events.
+    ///
+    /// Watching is enabled by [`Device::connect`], unless disabled
+    /// through [`ConnectOptions::watch`]. While disabled, the
+    /// [`EventKind::Other`] and [`EventKind::Disconnected`] events are
+    /// not reported; in particular, an [`EventStream`] created from an
+    /// unwatched device does not end when the device is disconnected.
+    ///
+    /// [`EventKind::Other`]: crate::event::EventKind::Other
+    /// [`EventKind::Disconnected`]: crate::event::EventKind::Disconnected
+    pub fn set_watch(&mut self, watch: bool) -> Result<()> {
+        let res_code = unsafe { sys::iface_watch(self.handle, watch) };
+        bail_if!(res_code != 0);
+        Ok(())
     }
 
     // Channels
@@ -284,19 +851,134 @@ impl Device {
     ///
     /// A channel may be closed automatically e.g. if an extension is
     /// unplugged or on error conditions.
+    ///
+    /// If `writable` is set, the channels are opened for writing too,
+    /// which is required to e.g. control the rumble motor. Opening
+    /// an open channel doesn't change whether it is writable; close
+    /// it first. [`Device::writable`] lists the writable channels.
+    ///
+    /// The IR and Motion Plus channels are not opened if the device
+    /// [quirks](Device::quirks) rule them out, in which case the error
+    /// is of kind [`io::ErrorKind::Unsupported`].
     pub fn open(&mut self, channels: Channels, writable: bool) -> Result<()> {
-        let ifaces = channels.bits | (writable as libc::c_uint) << 16;
-        let res_code = unsafe { xwiimote_sys::iface_open(self.handle, ifaces) };
+        self.open_with_mode(channels, writable, OpenMode::Shared)
+    }
+
+    /// Opens the given channels like [`Device::open`], in the given mode.
+    ///
+    /// If another process holds a channel in exclusive mode, the error
+    /// is of kind [`io::ErrorKind::ResourceBusy`], and describes the
+    /// competing processes; see [`DeviceBusy::from_io`](holders::DeviceBusy::from_io).
+    /// Channels that were already open are not grabbed.
+    pub fn open_with_mode(
+        &mut self,
+        channels: Channels,
+        writable: bool,
+        mode: OpenMode,
+    ) -> Result<()> {
+        let mut unsupported = Channels::empty();
+        if self.quirks.contains(Quirks::NO_IR) {
+            unsupported |= Channels::IR;
+        }
+        if self.quirks.contains(Quirks::NO_MOTION_PLUS_DRIVER) {
+            unsupported |= Channels::MOTION_PLUS;
+        }
+        let (channels, unsupported) = (channels - unsupported, channels & unsupported);
+
+        let mut ifaces = channels.bits();
+        if writable {
+            ifaces |= xwiimote_sys::IFACE_WRITABLE;
+        }
+        let was_open = self.all_open();
+        let mut res_code = unsafe { sys::iface_open(self.handle, ifaces) };
+        if self.clone_friendly || self.quirks.contains(Quirks::SLOW_EXTENSION_INIT) {
+            // Extensions may not be ready yet; channels that opened
+            // successfully are ignored by the following attempts.
+            for _ in 0..Self::OPEN_RETRIES {
+                if res_code == 0 {
+                    break;
+                }
+                thread::sleep(Self::OPEN_RETRY_DELAY);
+                res_code = unsafe { sys::iface_open(self.handle, ifaces) };
+            }
+        }
+        if res_code == -libc::EBUSY {
+            return Err(self.busy_error(self.evdev_nodes().unwrap_or_default()));
+        }
         bail_if!(res_code != 0);
+        if mode == OpenMode::Exclusive {
+            self.grab((channels & self.all_open()) - was_open)?;
+        }
 
-        if channels.contains(Channels::CORE) && writable {
-            self.core_open = true;
+        // The library ignores the flag for channels that are already open.
+        if writable {
+            self.writable |= (channels & self.all_open()) - was_open;
+        }
+        self.suspended -= channels;
+        self.requested |= channels & self.all_open();
+        if !unsupported.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("the device quirks prevent opening {:?}", unsupported),
+            ));
         }
         Ok(())
     }
 
+    /// Grabs the input devices of the given open channels, so that only
+    /// this device receives their events.
+    fn grab(&self, channels: Channels) -> Result<()> {
+        let syspath = self.syspath();
+        let own_fds = Path::new("/proc/self");
+        for bit in 0..u32::BITS {
+            let channel = Channels::from_bits_truncate(1 << bit);
+            if channel.is_empty() || !channels.contains(channel) {
+                continue;
+            }
+            let node = match input::channel_name(channel) {
+                Some(name) => input::named_input_node(&syspath, name)?,
+                None => None,
+            };
+            let node = match node {
+                Some(node) => node,
+                None => continue,
+            };
+            for fd in input::fds_of(own_fds, &node)? {
+                match input::grab(fd, true) {
+                    Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {
+                        return Err(self.busy_error(vec![node]));
+                    }
+                    result => result?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn busy_error(&self, nodes: Vec<PathBuf>) -> io::Error {
+        let holders = Holder::find(&nodes).unwrap_or_else(|err| {
+            log::debug!("failed to find device holders: {}", err);
+            Vec::new()
+        });
+        DeviceBusy { nodes, holders }.into_io()
+    }
+
+    /// Lists the other processes that have the input devices or the
+    /// hidraw node of this device open, e.g. to find out why opening it
+    /// fails with `EBUSY`.
+    pub fn holders(&self) -> Result<Vec<Holder>> {
+        let mut nodes = self.evdev_nodes()?;
+        nodes.extend(self.hidraw_node());
+        Holder::find(&nodes)
+    }
+
+    /// Ensures the core channel is open for writing, reopening it if it
+    /// was opened as read-only.
     fn ensure_core_open(&mut self) -> Result<()> {
-        if !self.core_open {
+        if !self.writable().contains(Channels::CORE) {
+            if self.all_open().contains(Channels::CORE) {
+                self.close(Channels::CORE)?;
+            }
             self.open(Channels::CORE, true)?
         }
         Ok(())
@@ -307,15 +989,89 @@ impl Device {
     /// If a channel is already closed, it is ignored.
     pub fn close(&mut self, channels: Channels) -> Result<()> {
         if channels.contains(Channels::CORE) {
-            self.core_open = false;
+            self.rumble_pwm = None;
+        }
+        self.writable -= channels;
+        self.suspended -= channels;
+        self.requested -= channels;
+        unsafe { sys::iface_close(self.handle, channels.bits()) };
+        Ok(())
+    }
+
+    /// Stops or resumes the streaming of events from the given channels,
+    /// without changing the interest of the application in them.
+    ///
+    /// Stopping closes the open channels, which saves battery, and
+    /// resuming reopens them with the same mode. Channels that were not
+    /// open are ignored. See [`Device::interest`].
+    pub fn set_streaming(&mut self, channels: Channels, enabled: bool) -> Result<()> {
+        if enabled {
+            let resumed = channels & self.suspended;
+            self.open_split(resumed, resumed & self.suspended_writable)?;
+            self.suspended_writable -= resumed;
+        } else {
+            let stopped = channels & self.all_open();
+            let writable = stopped & self.writable;
+            self.close(stopped)?;
+            self.suspended |= stopped;
+            self.suspended_writable |= writable;
+        }
+        Ok(())
+    }
+
+    /// Reopens the channels that were closed by the kernel, as reported
+    /// by [`EventKind::ChannelClosed`], and are available again. Returns
+    /// the reopened channels.
+    ///
+    /// Channels are reopened in writable mode if they were writable.
+    /// Channels closed by [`Device::close`] or [`Device::set_streaming`]
+    /// are not reopened.
+    pub fn reopen_closed(&mut self) -> Result<Channels> {
+        let reopened = (self.requested - self.all_open()) & self.available();
+        let writable = reopened & self.writable;
+        self.writable -= reopened;
+        self.open_split(reopened, writable)?;
+        Ok(reopened)
+    }
+
+    /// Opens the given channels, those in `writable` in writable mode.
+    fn open_split(&mut self, channels: Channels, writable: Channels) -> Result<()> {
+        if !writable.is_empty() {
+            self.open(writable, true)?;
+        }
+        if !(channels - writable).is_empty() {
+            self.open(channels - writable, false)?;
         }
-        unsafe { xwiimote_sys::iface_close(self.handle, channels.bits) };
         Ok(())
     }
 
+    /// Lists the channels the application is interested in: those that
+    /// are open, and those whose streaming was stopped by
+    /// [`Device::set_streaming`].
+    pub fn interest(&self) -> Channels {
+        self.all_open() | self.suspended
+    }
+
+    /// Lists the channels whose streaming was stopped by
+    /// [`Device::set_streaming`].
+    pub fn suspended(&self) -> Channels {
+        self.suspended
+    }
+
     /// Lists the currently open channels.
+    ///
+    /// Channels unknown to this library are ignored.
     pub fn all_open(&self) -> Channels {
-        Channels::from_bits(unsafe { xwiimote_sys::iface_opened(self.handle) }).unwrap()
+        Channels::from_bits_truncate(unsafe { sys::iface_opened(self.handle) })
+    }
+
+    /// Lists the open channels that were opened in writable mode.
+    ///
+    /// Controlling the rumble motor requires the core channel to be
+    /// writable; [`Device::rumble`] reopens it in writable mode if it
+    /// isn't, which briefly interrupts its events.
+    pub fn writable(&self) -> Channels {
+        self.writable & self.all_open()
     }
 
     /// Lists the channels that can be opened, including those
@@ -325,8 +1081,24 @@ impl Device {
     /// plugged to the device. Correspondingly, it becomes unavailable
     /// when the extension is disconnected.
     ///
+    /// Channels unknown to this library, e.g. those of extensions added
+    /// to newer versions of the `xwiimote` library, are ignored. See
+    /// [`Device::unknown_bits`].
     pub fn available(&self) -> Channels {
-        Channels::from_bits(unsafe { xwiimote_sys::iface_available(self.handle) }).unwrap()
+        Channels::from_bits_truncate(self.raw_available())
+    }
+
+    /// Returns the bits of the available interfaces reported by the
+    /// `xwiimote` library that don't correspond to any [`Channels`] flag.
+    ///
+    /// These are usually zero. Otherwise, the device has an extension
+    /// that is not supported by this version of the library.
+    pub fn unknown_bits(&self) -> u32 {
+        Channels::unknown_bits(self.raw_available())
+    }
+
+    fn raw_available(&self) -> u32 {
+        unsafe { sys::iface_available(self.handle) }
     }
 
     // Events
@@ -334,9 +1106,172 @@ impl Device {
     /// Returns an stream that yields events received from the device.
     ///
     /// Most event types are received only if the appropriate channels
-    /// are open. See [`EventKind`](crate::event::EventKind) for more.
-    pub fn events(&self) -> Result<impl Stream<Item = Result<event::Event>> + '_> {
-        EventStream::try_new(self)
+    /// are open. See [`EventKind`] for more.
+    pub fn events(&self) -> Result<EventStream<'_>> {
+        EventStream::try_new(self, IoBlocker::get().clone())
+    }
+
+    /// Returns an stream like [`Device::events`], whose events are
+    /// received by the given runtime instead of the global one.
+    pub fn events_with_runtime(&self, runtime: &Runtime) -> Result<EventStream<'_>> {
+        EventStream::try_new(self, runtime.blocker().clone())
+    }
+
+    /// Registers interest in the events of the device with an epoll
+    /// instance owned by the application, which receives `token` as the
+    /// event data. The interest is level-triggered.
+    ///
+    /// This lets applications running their own event loop, e.g. with
+    /// `epoll` or `mio`, receive events without the async runtime. Once
+    /// `epfd` reports the device as readable, read the events with
+    /// [`Device::try_next_event`] until it returns `None`.
+    pub fn register_with_epoll(&self, epfd: RawFd, token: u64) -> Result<()> {
+        io_blocker::register_external(epfd, self.fd(), token)
+    }
+
+    /// Removes the interest registered by [`Device::register_with_epoll`].
+    pub fn deregister_from_epoll(&self, epfd: RawFd) -> Result<()> {
+        io_blocker::deregister_external(epfd, self.fd())
+    }
+
+    fn fd(&self) -> RawFd {
+        unsafe { sys::iface_get_fd(self.handle) }
+    }
+
+    /// Reads the next event received from the device, without blocking.
+    /// Returns `None` if no event is available.
+    ///
+    /// Use this instead of an [`EventStream`], e.g. with
+    /// [`Device::register_with_epoll`], or in a `mio` poll with the
+    /// `mio` feature, which implements `mio::event::Source` for devices
+    /// and monitors. Timeouts and keep-alive requests are not handled.
+    pub fn try_next_event(&mut self) -> Result<Option<Event>> {
+        loop {
+            if let Some(event) = self.layers.get_mut().next_pending() {
+                return Ok(Some(event));
+            }
+            let mut event = match self.closed.take() {
+                Some(closed) => closed,
+                None => match self.dispatch_next()? {
+                    Some(event) => event,
+                    None => return Ok(None),
+                },
+            };
+            event.sequence = Some(self.sequence);
+            self.sequence += 1;
+            // The layers may drop the event, then read the next one.
+            if let Some(event) = self.layers.get_mut().process(event) {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Reads the next event for [`Device::try_next_event`], before the
+    /// layers are applied.
+    fn dispatch_next(&mut self) -> Result<Option<Event>> {
+        let mut raw = Default::default();
+        let mut opened = self.all_open();
+        let mut event = match Event::dispatch(self, &mut raw) {
+            Ok(Some(event)) => event,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                self.closed = Event::closed(&mut opened, self, SystemTime::now());
+                return Err(err);
+            }
+        };
+        let mut available = self.available;
+        event.fill_watch(&mut available, self);
+        self.available = available;
+        if let EventKind::Other(_) = event.kind {
+            self.closed = Event::closed(&mut opened, self, event.time);
+        }
+        Ok(Some(event))
+    }
+
+    /// Adds a layer that transforms the events read from the device. It
+    /// handles the events emitted by the layers added before.
+    ///
+    /// The layers apply to every [`EventStream`] of the device, and to
+    /// [`Device::try_next_event`]. See the [`layer`] module.
+    pub fn add_layer<L: EventLayer + 'static>(&mut self, layer: L) {
+        self.layers.get_mut().push(Box::new(layer));
+    }
+
+    /// Removes every layer added by [`Device::add_layer`].
+    pub fn clear_layers(&mut self) {
+        self.layers.get_mut().clear();
+    }
+
+    /// Returns a stream that yields the state changes of the given
+    /// Wii Remote key.
+    ///
+    /// Key events are received only if [`Channels::CORE`] is open.
+    pub fn key_events(&self, key: Key) -> Result<impl Stream<Item = Result<KeyState>> + '_> {
+        let events = self.events()?;
+        Ok(events.try_filter_map(move |event| {
+            future::ready(Ok(match event.kind {
+                EventKind::Key(changed, state) if changed == key => Some(state),
+                _ => None,
+            }))
+        }))
+    }
+
+    /// Waits until the given Wii Remote key reaches the given state,
+    /// e.g. until the A button is pressed.
+    ///
+    /// Key events are received only if [`Channels::CORE`] is open.
+    /// Returns an error of kind [`io::ErrorKind::NotConnected`] if the
+    /// device is disconnected while waiting.
+    pub async fn wait_for_key(&self, key: Key, state: KeyState) -> Result<()> {
+        let states = self.key_events(key)?;
+        futures::pin_mut!(states);
+        while let Some(current) = states.try_next().await? {
+            if current == state {
+                return Ok(());
+            }
+        }
+        Err(io::ErrorKind::NotConnected.into())
+    }
+
+    /// Sets the interval at which keep-alive requests are sent to the
+    /// device while no events are received, or `None` to disable them.
+    ///
+    /// The Bluetooth stack disconnects idle Wii Remotes, which then power
+    /// off. If set, an [`EventStream`] created afterwards periodically
+    /// requests a status report from the device, which resets the idle
+    /// timer. The stream yields an [`EventKind::Disconnected`] event once
+    /// the device powers down regardless of this setting.
+    ///
+    /// [`EventKind::Disconnected`]: crate::event::EventKind::Disconnected
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive = interval;
+    }
+
+    /// Sets the timing of the [`KeyState::AutoRepeat`] events: the time
+    /// a key must be held down before the first repeat, and the time
+    /// between subsequent repeats.
+    ///
+    /// The timing is configured on the kernel input devices of the
+    /// Wii Remote and its extensions, so it applies to every application
+    /// reading events from the device until it is disconnected. This
+    /// requires write access to their nodes in `/dev/input`.
+    pub fn set_key_repeat(&self, delay: Duration, period: Duration) -> Result<()> {
+        let mut applied = false;
+        for devnode in self.evdev_nodes()? {
+            match input::set_repeat(&devnode, delay, period) {
+                Ok(()) => applied = true,
+                // The input device doesn't report keys, e.g. the IR camera.
+                Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if !applied {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no input device reports repeated keys",
+            ));
+        }
+        Ok(())
     }
 
     // Out-of-band actions (these don't require any channel open to work)
@@ -344,17 +1279,15 @@ impl Device {
     /// Reads the current state of the LED light.
     pub fn led(&self, light: Led) -> Result<bool> {
         let mut enabled = false;
-        let res_code = unsafe {
-            xwiimote_sys::iface_get_led(self.handle, light as libc::c_uint, &mut enabled)
-        };
+        let res_code =
+            unsafe { sys::iface_get_led(self.handle, light as libc::c_uint, &mut enabled) };
         bail_if!(res_code != 0);
         Ok(enabled)
     }
 
     /// Changes the state of the LED light.
     pub fn set_led(&self, light: Led, enabled: bool) -> Result<()> {
-        let res_code =
-            unsafe { xwiimote_sys::iface_set_led(self.handle, light as libc::c_uint, enabled) };
+        let res_code = unsafe { sys::iface_set_led(self.handle, light as libc::c_uint, enabled) };
         bail_if!(res_code != 0);
         Ok(())
     }
@@ -366,40 +1299,243 @@ impl Device {
     /// means the battery is fully-charged.
     pub fn battery(&self) -> Result<u8> {
         let mut level = 0;
-        let res_code = unsafe { xwiimote_sys::iface_get_battery(self.handle, &mut level) };
+        let res_code = unsafe { sys::iface_get_battery(self.handle, &mut level) };
         bail_if!(res_code != 0);
         Ok(level)
     }
 
+    /// Returns a stream that reads the battery level every `interval`,
+    /// starting immediately. See the [`battery`] module to smooth the
+    /// readings.
+    pub fn battery_levels(&self, interval: Duration) -> Result<BatteryLevels<'_>> {
+        Ok(BatteryLevels::new(self, IoBlocker::get().clone(), interval))
+    }
+
+    /// Returns a stream like [`Device::battery_levels`], whose timer is
+    /// watched by the given runtime instead of the global one.
+    pub fn battery_levels_with_runtime(
+        &self,
+        interval: Duration,
+        runtime: &Runtime,
+    ) -> Result<BatteryLevels<'_>> {
+        Ok(BatteryLevels::new(
+            self,
+            runtime.blocker().clone(),
+            interval,
+        ))
+    }
+
+    /// Reads the signal strength and quality of the Bluetooth link to
+    /// the device, from the adapter it is connected to. See the [`link`]
+    /// module.
+    pub fn link_quality(&self) -> Result<LinkQuality> {
+        link::read(&self.mac_address()?)
+    }
+
+    /// Returns a stream that reads the link quality every `interval`,
+    /// starting immediately.
+    pub fn link_qualities(&self, interval: Duration) -> Result<LinkQualities<'_>> {
+        Ok(LinkQualities::new(self, IoBlocker::get().clone(), interval))
+    }
+
+    /// Returns a stream like [`Device::link_qualities`], whose timer is
+    /// watched by the given runtime instead of the global one.
+    pub fn link_qualities_with_runtime(
+        &self,
+        interval: Duration,
+        runtime: &Runtime,
+    ) -> Result<LinkQualities<'_>> {
+        Ok(LinkQualities::new(
+            self,
+            runtime.blocker().clone(),
+            interval,
+        ))
+    }
+
     /// Returns the device type identifier.
     pub fn kind(&self) -> Result<String> {
         let mut raw_kind = ptr::null_mut();
-        let res_code = unsafe { xwiimote_sys::iface_get_devtype(self.handle, &mut raw_kind) };
+        let res_code = unsafe { sys::iface_get_devtype(self.handle, &mut raw_kind) };
         bail_if!(res_code != 0);
 
-        let kind = into_owned_str(raw_kind);
-        dealloc_str(raw_kind);
-        Ok(kind)
+        let kind = unsafe { XwiiString::from_raw(raw_kind) }.expect("missing device type");
+        Ok(kind.to_string_lossy().into_owned())
     }
 
     /// Returns the current extension type identifier.
     pub fn extension(&self) -> Result<String> {
         let mut raw_ext_kind = ptr::null_mut();
-        let res_code = unsafe { xwiimote_sys::iface_get_extension(self.handle, &mut raw_ext_kind) };
+        let res_code = unsafe { sys::iface_get_extension(self.handle, &mut raw_ext_kind) };
         bail_if!(res_code != 0);
 
-        let ext_kind = into_owned_str(raw_ext_kind);
-        dealloc_str(raw_ext_kind);
-        Ok(ext_kind)
+        let ext_kind =
+            unsafe { XwiiString::from_raw(raw_ext_kind) }.expect("missing extension type");
+        Ok(ext_kind.to_string_lossy().into_owned())
     }
 
     /// Toggles the rumble motor.
     ///
     /// If the core channel is closed, it is opened in writable mode.
+    /// Fails with [`io::ErrorKind::Unsupported`] if the device has the
+    /// [`Quirks::NO_RUMBLE`] quirk.
     pub fn rumble(&mut self, enabled: bool) -> Result<()> {
+        if self.quirks.contains(Quirks::NO_RUMBLE) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the device has no rumble motor",
+            ));
+        }
         self.ensure_core_open()?;
-        let res_code = unsafe { xwiimote_sys::iface_rumble(self.handle, enabled) };
+        self.rumble_pwm = None;
+        self.set_rumble(enabled)
+    }
+
+    /// Sets the rumble intensity, from 0 (off) to 1 (full speed).
+    ///
+    /// The motor has no speed control, so partial intensities are
+    /// emulated by switching it on for that fraction of each period, from
+    /// the event loop thread. The pulses continue until the intensity is
+    /// changed, [`Device::rumble`] is called, the core channel is closed,
+    /// or the device is dropped. Commands of a [`ControlSink`] interfere
+    /// with the pulses.
+    ///
+    /// Opens the core channel like [`Device::rumble`]. Fails with
+    /// [`io::ErrorKind::InvalidInput`] if `intensity` is not finite.
+    pub fn set_rumble_intensity(&mut self, intensity: f32) -> Result<()> {
+        if !intensity.is_finite() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the rumble intensity must be finite",
+            ));
+        }
+        let intensity = intensity.clamp(0.0, 1.0);
+        if intensity == 0.0 || intensity == 1.0 {
+            return self.rumble(intensity == 1.0);
+        }
+        match &self.rumble_pwm {
+            Some(pwm) => pwm.set_intensity(intensity),
+            None => {
+                self.rumble(false)?;
+                self.rumble_pwm = Some(RumblePwm::start(
+                    self.handle,
+                    self.rumble_period,
+                    intensity,
+                )?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the period of the pulses that emulate a partial rumble
+    /// intensity, from the next call to [`Device::set_rumble_intensity`].
+    ///
+    /// Shorter periods feel smoother, down to the period of the reports
+    /// sent to the device, but spin the motor up less.
+    pub fn set_rumble_period(&mut self, period: Duration) {
+        self.rumble_period = period;
+    }
+
+    /// Toggles the rumble motor, failing if the core channel is closed.
+    fn set_rumble(&self, enabled: bool) -> Result<()> {
+        let res_code = unsafe { sys::iface_rumble(self.handle, enabled) };
         bail_if!(res_code != 0); // the channel might have been closed by the kernel
+        self.rumbling.set(enabled);
+        Ok(())
+    }
+
+    /// Returns a sink that applies LED and rumble [commands](control::Command)
+    /// to the device.
+    ///
+    /// Unlike [`Device::rumble`], the sink does not open the core
+    /// channel; open it in writable mode beforehand to send rumble
+    /// commands.
+    pub fn control_sink(&self) -> ControlSink<'_> {
+        ControlSink::new(self, IoBlocker::get().clone())
+    }
+
+    /// Returns a sink like [`Device::control_sink`], whose rumble pulses
+    /// are timed by the given runtime instead of the global one.
+    pub fn control_sink_with_runtime(&self, runtime: &Runtime) -> ControlSink<'_> {
+        ControlSink::new(self, runtime.blocker().clone())
+    }
+
+    // Profiles
+
+    /// Loads the profile of the device from the [user store](ProfileStore::user)
+    /// and applies it. Returns the profile, or `None` if no profile was saved.
+    pub fn load_profile(&mut self) -> Result<Option<Profile>> {
+        let profile = ProfileStore::user()?.load(&self.mac_address()?)?;
+        if let Some(profile) = &profile {
+            profile.apply(self)?;
+        }
+        Ok(profile)
+    }
+
+    /// Saves the current settings of the device to its profile in the
+    /// [user store](ProfileStore::user).
+    ///
+    /// The additional [values](Profile::values) of an existing profile
+    /// are preserved.
+    pub fn save_profile(&self) -> Result<()> {
+        let store = ProfileStore::user()?;
+        let mac = self.mac_address()?;
+
+        let mut profile = Profile::capture(self)?;
+        if let Some(saved) = store.load(&mac)? {
+            profile.values = saved.values;
+        }
+        store.save(&mac, &profile)
+    }
+
+    // Snapshots
+
+    /// Captures the configuration of the device: its open channels,
+    /// LED lights, Motion Plus normalization and rumble motor.
+    ///
+    /// The rumble state is the last one set through this device; other
+    /// processes may have changed it since.
+    pub fn snapshot(&self) -> Result<DeviceState> {
+        let mut leds = [false; 4];
+        for (ix, light) in [Led::One, Led::Two, Led::Three, Led::Four]
+            .into_iter()
+            .enumerate()
+        {
+            leds[ix] = self.led(light)?;
+        }
+        Ok(DeviceState {
+            channels: self.all_open(),
+            writable: self.writable().contains(Channels::CORE),
+            suspended: self.suspended,
+            leds,
+            mp_normalization: self.mp_normalization(),
+            rumble: self.rumbling.get(),
+            extension: self.extension()?,
+        })
+    }
+
+    /// Restores a configuration captured by [`Device::snapshot`], e.g.
+    /// after reconnecting to the device.
+    ///
+    /// Channels open but not in the snapshot are closed. Fails if an
+    /// extension channel in the snapshot is no longer available.
+    pub fn restore(&mut self, state: &DeviceState) -> Result<()> {
+        let channels = state.channels | state.suspended;
+        self.close(self.all_open() - channels)?;
+        if state.writable && channels.contains(Channels::CORE) {
+            self.ensure_core_open()?;
+        }
+        self.open(channels - self.all_open(), state.writable)?;
+        self.set_streaming(state.suspended, false)?;
+        for (light, &enabled) in [Led::One, Led::Two, Led::Three, Led::Four]
+            .into_iter()
+            .zip(&state.leds)
+        {
+            self.set_led(light, enabled)?;
+        }
+        self.set_mp_normalization(&state.mp_normalization);
+        if state.rumble || self.rumbling.get() {
+            self.rumble(state.rumble)?;
+        }
         Ok(())
     }
 
@@ -409,7 +1545,7 @@ impl Device {
     pub fn mp_normalization(&self) -> MotionPlusNormalization {
         let mut values = MotionPlusNormalization::default();
         unsafe {
-            xwiimote_sys::iface_get_mp_normalization(
+            sys::iface_get_mp_normalization(
                 self.handle,
                 &mut values.x,
                 &mut values.y,
@@ -423,7 +1559,7 @@ impl Device {
     /// Updates the Motion Plus sensor normalization values.
     pub fn set_mp_normalization(&mut self, values: &MotionPlusNormalization) {
         unsafe {
-            xwiimote_sys::iface_set_mp_normalization(
+            sys::iface_set_mp_normalization(
                 self.handle,
                 values.x,
                 values.y,
@@ -433,3 +1569,121 @@ impl Device {
         };
     }
 }
+
+#[cfg(all(test, feature = "stub"))]
+mod tests {
+    use super::{first_device, Address, ConnectOptions, ConnectState, Device, Monitor};
+    use futures::{executor, StreamExt};
+    use std::io;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn stub_is_unsupported() {
+        let err = first_device(Duration::ZERO).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let err = Monitor::new(true).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let address = Address::from(PathBuf::from("/sys/devices/unknown"));
+        let options = ConnectOptions {
+            blocking: false,
+            ..Default::default()
+        };
+        let err = Device::connect_with(&address, &options).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn async_connect_reports_progress() {
+        let address = Address::from(PathBuf::from("/sys/devices/unknown"));
+        let (connect, progress) = Device::connect_async(&address, &Default::default());
+        let err = executor::block_on(connect).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let states: Vec<_> = executor::block_on(progress.collect());
+        assert_eq!(states, [ConnectState::OpeningInterface]);
+    }
+}
+
+#[cfg(all(test, feature = "test-harness"))]
+mod harness_tests {
+    use crate::event::EventKind;
+    use crate::harness::{connect, FakeEvent, FakeIface};
+    use crate::{Channels, Led, Result};
+    use futures::{executor, StreamExt};
+
+    #[test]
+    fn restores_snapshots() -> Result<()> {
+        let available = Channels::CORE | Channels::ACCELEROMETER | Channels::IR;
+        let fake = FakeIface::new(available)?;
+        let mut device = connect(&fake)?;
+        device.open(
+            Channels::CORE | Channels::ACCELEROMETER | Channels::IR,
+            true,
+        )?;
+        device.set_streaming(Channels::IR, false)?;
+        device.set_led(Led::Two, true)?;
+        device.rumble(true)?;
+        let state = device.snapshot()?;
+        assert_eq!(state.channels, Channels::CORE | Channels::ACCELEROMETER);
+        assert_eq!(state.leds, [false, true, false, false]);
+
+        let other = FakeIface::new(available)?;
+        let mut restored = connect(&other)?;
+        restored.open(Channels::IR, false)?;
+        restored.restore(&state)?;
+        assert_eq!(other.opened(), Channels::CORE | Channels::ACCELEROMETER);
+        assert_eq!(restored.suspended(), Channels::IR);
+        assert!(other.rumble());
+        assert_eq!(restored.snapshot()?, state);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_and_reopens_closed_channels() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE | Channels::NUNCHUK)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::CORE | Channels::NUNCHUK, false)?;
+        fake.push(FakeEvent::hotplug(Channels::CORE));
+
+        let mut events = device.events()?;
+        let kinds = executor::block_on((&mut events).take(2).collect::<Vec<_>>());
+        assert!(matches!(kinds[0], Ok(ref event) if matches!(event.kind, EventKind::Other(_))));
+        assert!(matches!(
+            kinds[1],
+            Ok(ref event) if matches!(event.kind, EventKind::ChannelClosed(Channels::NUNCHUK))
+        ));
+        drop(events);
+
+        assert_eq!(device.reopen_closed()?, Channels::empty());
+        fake.push(FakeEvent::hotplug(Channels::CORE | Channels::NUNCHUK));
+        // The end of the first batch, which the stream didn't read.
+        assert!(device.try_next_event()?.is_none());
+        assert!(device.try_next_event()?.is_some());
+        assert_eq!(device.reopen_closed()?, Channels::NUNCHUK);
+        assert_eq!(fake.opened(), Channels::CORE | Channels::NUNCHUK);
+        Ok(())
+    }
+
+    #[test]
+    fn tracks_writable_channels() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE | Channels::ACCELEROMETER)?;
+        let mut device = connect(&fake)?;
+        device.open(Channels::CORE, true)?;
+        device.open(Channels::CORE | Channels::ACCELEROMETER, false)?;
+        assert_eq!(device.writable(), Channels::CORE);
+
+        device.set_streaming(Channels::all(), false)?;
+        assert_eq!(device.writable(), Channels::empty());
+        device.set_streaming(Channels::all(), true)?;
+        assert_eq!(device.writable(), Channels::CORE);
+
+        device.close(Channels::CORE)?;
+        device.open(Channels::CORE, false)?;
+        assert_eq!(device.writable(), Channels::empty());
+        device.rumble(true)?;
+        assert_eq!(device.writable(), Channels::CORE);
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+//! Quality of the Bluetooth link to a remote.
+//!
+//! Choppy motion data is almost always caused by the radio: a remote
+//! that is far away, behind an obstacle or next to a Wi-Fi access point
+//! drops and delays reports. [`Device::link_quality`] asks the adapter
+//! the remote is connected to for its received signal strength (RSSI)
+//! and link quality, through the same HCI commands as `hcitool rssi`.
+//! The kernel lets unprivileged processes send these commands.
+//!
+//! [`Device::link_qualities`] reads them periodically, e.g. to show a
+//! signal strength indicator.
+use crate::timer::Timer;
+use crate::{Device, IoBlocker, Result};
+use futures::Stream;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use std::{fmt, mem};
+
+// The constants and structures of the kernel's Bluetooth socket API,
+// from `include/net/bluetooth/hci.h` and `hci_sock.h`.
+const AF_BLUETOOTH: libc::c_int = 31;
+#[cfg(target_os = "linux")]
+const BTPROTO_HCI: libc::c_int = 1;
+const HCI_CHANNEL_RAW: u16 = 0;
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+const HCI_MAX_DEV: u16 = 16;
+// `_IOR('H', 212, int)`
+const HCIGETCONNLIST: libc::c_ulong = 0x8004_48d4;
+const ACL_LINK: u8 = 1;
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+const EVT_CMD_COMPLETE: u8 = 0x0e;
+const EVT_CMD_STATUS: u8 = 0x0f;
+// The Read Link Quality and Read RSSI commands of the status parameters
+// group (OGF 0x05).
+const OP_READ_LINK_QUALITY: u16 = 0x05 << 10 | 0x0003;
+const OP_READ_RSSI: u16 = 0x05 << 10 | 0x0005;
+
+// The maximum number of connections listed per adapter.
+const MAX_CONNECTIONS: usize = 16;
+
+/// The time to wait for the adapter to answer a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct ConnInfo {
+    handle: u16,
+    bdaddr: [u8; 6],
+    kind: u8,
+    out: u8,
+    state: u16,
+    link_mode: u32,
+}
+
+#[repr(C)]
+struct ConnListRequest {
+    dev_id: u16,
+    conn_num: u16,
+    conn_info: [ConnInfo; MAX_CONNECTIONS],
+}
+
+#[repr(C)]
+struct SockAddrHci {
+    family: libc::sa_family_t,
+    dev: u16,
+    channel: u16,
+}
+
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+/// The signal strength and quality of the link to a remote, as measured
+/// by the Bluetooth adapter.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct LinkQuality {
+    /// The received signal strength, in dB relative to the range the
+    /// adapter considers ideal: 0 within the range, negative below it,
+    /// positive above it. Values below -10 usually mean lost reports.
+    pub rssi: i8,
+    /// The quality of the link as estimated by the adapter, from 0
+    /// (worst) to 255 (best). Most adapters derive it from the bit
+    /// error rate; its scale is vendor-specific.
+    pub quality: u8,
+}
+
+impl fmt::Display for LinkQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RSSI {} dB, quality {}/255", self.rssi, self.quality)
+    }
+}
+
+/// Parses a Bluetooth address, e.g. `00:1f:32:aa:bb:cc`, into the
+/// little-endian byte order of the kernel.
+fn parse_bdaddr(mac: &str) -> Option<[u8; 6]> {
+    let mut bdaddr = [0; 6];
+    let mut parts = mac.split(':');
+    for byte in bdaddr.iter_mut().rev() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(bdaddr)
+}
+
+/// Returns the return parameters of the command with the given opcode,
+/// if `packet` is the event completing it.
+fn command_complete(packet: &[u8], opcode: u16) -> Option<Result<&[u8]>> {
+    let [HCI_EVENT_PKT, event, _len, params @ ..] = packet else {
+        return None;
+    };
+    let (status, params) = match (*event, params) {
+        (EVT_CMD_COMPLETE, [_ncmd, lo, hi, status, params @ ..]) => {
+            if u16::from_le_bytes([*lo, *hi]) != opcode {
+                return None;
+            }
+            (*status, params)
+        }
+        // The command was rejected without running.
+        (EVT_CMD_STATUS, [status, _ncmd, lo, hi]) => {
+            if u16::from_le_bytes([*lo, *hi]) != opcode || *status == 0 {
+                return None;
+            }
+            (*status, &[][..])
+        }
+        _ => return None,
+    };
+    if status != 0 {
+        return Some(Err(io::Error::other(format!(
+            "the adapter failed the command with status {:#04x}",
+            status
+        ))));
+    }
+    Some(Ok(params))
+}
+
+/// Opens a raw HCI socket.
+#[cfg(target_os = "linux")]
+fn hci_socket() -> Result<File> {
+    use std::os::unix::io::FromRawFd;
+
+    let fd = unsafe {
+        libc::socket(
+            AF_BLUETOOTH,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            BTPROTO_HCI,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Opens a raw HCI socket, which only Linux provides.
+#[cfg(not(target_os = "linux"))]
+fn hci_socket() -> Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "HCI sockets are only available on Linux",
+    ))
+}
+
+/// Finds the adapter and handle of the ACL connection to the given
+/// address.
+fn find_connection(socket: RawFd, bdaddr: [u8; 6]) -> Result<(u16, u16)> {
+    for dev_id in 0..HCI_MAX_DEV {
+        let mut request = ConnListRequest {
+            dev_id,
+            conn_num: MAX_CONNECTIONS as u16,
+            conn_info: Default::default(),
+        };
+        if unsafe { libc::ioctl(socket, HCIGETCONNLIST as _, &mut request) } < 0 {
+            // The adapter doesn't exist or is down.
+            continue;
+        }
+        let count = usize::from(request.conn_num).min(MAX_CONNECTIONS);
+        if let Some(conn) = request.conn_info[..count]
+            .iter()
+            .find(|conn| conn.bdaddr == bdaddr && conn.kind == ACL_LINK)
+        {
+            return Ok((dev_id, conn.handle));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no adapter is connected to the device",
+    ))
+}
+
+/// Sends a command with the given connection handle as its parameter
+/// and returns the return parameters, after the status.
+fn send_command(socket: &mut File, opcode: u16, handle: u16) -> Result<Vec<u8>> {
+    let filter = HciFilter {
+        type_mask: 1 << HCI_EVENT_PKT,
+        event_mask: [1 << EVT_CMD_COMPLETE | 1 << EVT_CMD_STATUS, 0],
+        opcode: opcode.to_le(),
+    };
+    let res_code = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            SOL_HCI,
+            HCI_FILTER,
+            &filter as *const HciFilter as *const libc::c_void,
+            mem::size_of::<HciFilter>() as libc::socklen_t,
+        )
+    };
+    if res_code < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [op_lo, op_hi] = opcode.to_le_bytes();
+    let [handle_lo, handle_hi] = handle.to_le_bytes();
+    socket.write_all(&[HCI_COMMAND_PKT, op_lo, op_hi, 2, handle_lo, handle_hi])?;
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let mut packet = [0; 260];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut poll_fd = libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, remaining.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if ready == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the adapter did not answer",
+            ));
+        }
+        let len = socket.read(&mut packet)?;
+        if let Some(params) = command_complete(&packet[..len], opcode) {
+            return params.map(<[u8]>::to_vec);
+        }
+    }
+}
+
+/// Reads the link quality of the connection to the given address.
+pub(crate) fn read(mac: &str) -> Result<LinkQuality> {
+    let bdaddr = parse_bdaddr(mac)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid Bluetooth address"))?;
+    let mut socket = hci_socket()?;
+    let (dev_id, handle) = find_connection(socket.as_raw_fd(), bdaddr)?;
+    let addr = SockAddrHci {
+        family: AF_BLUETOOTH as libc::sa_family_t,
+        dev: dev_id,
+        channel: HCI_CHANNEL_RAW,
+    };
+    let res_code = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const SockAddrHci as *const libc::sockaddr,
+            mem::size_of::<SockAddrHci>() as libc::socklen_t,
+        )
+    };
+    if res_code < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Both answers are the handle followed by the value.
+    let value = |params: Vec<u8>| {
+        params
+            .get(2)
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated command answer"))
+    };
+    let rssi = value(send_command(&mut socket, OP_READ_RSSI, handle)?)? as i8;
+    let quality = value(send_command(&mut socket, OP_READ_LINK_QUALITY, handle)?)?;
+    Ok(LinkQuality { rssi, quality })
+}
+
+/// A stream that reads the link quality of a device periodically.
+/// The first reading is taken immediately.
+///
+/// See [`Device::link_qualities`].
+pub struct LinkQualities<'a> {
+    device: &'a Device,
+    interval: Duration,
+    next: Instant,
+    // Wakes the stream once the next reading is due.
+    timer: Timer,
+}
+
+impl<'a> LinkQualities<'a> {
+    pub(crate) fn new(device: &'a Device, blocker: Arc<IoBlocker>, interval: Duration) -> Self {
+        Self {
+            device,
+            interval,
+            next: Instant::now(),
+            timer: Timer::new(blocker),
+        }
+    }
+}
+
+impl Stream for LinkQualities<'_> {
+    type Item = Result<LinkQuality>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let now = Instant::now();
+        if now < this.next {
+            if let Err(err) = this.timer.wake_at(this.next, cx.waker()) {
+                return Poll::Ready(Some(Err(err)));
+            }
+            return Poll::Pending;
+        }
+        this.next = now + this.interval;
+        Poll::Ready(Some(this.device.link_quality()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_complete, parse_bdaddr, OP_READ_LINK_QUALITY, OP_READ_RSSI};
+    use std::io;
+
+    #[test]
+    fn parses_addresses_in_kernel_order() {
+        assert_eq!(
+            parse_bdaddr("00:1f:32:aa:bb:CC"),
+            Some([0xcc, 0xbb, 0xaa, 0x32, 0x1f, 0x00])
+        );
+        assert_eq!(parse_bdaddr("00:1f:32:aa:bb"), None);
+        assert_eq!(parse_bdaddr("00:1f:32:aa:bb:cc:dd"), None);
+        assert_eq!(parse_bdaddr("00:1f:32:aa:bb:zz"), None);
+    }
+
+    #[test]
+    fn matches_command_answers() {
+        // Command Complete for Read RSSI: handle 0x000b, RSSI -7.
+        let rssi = [0x04, 0x0e, 0x07, 0x01, 0x05, 0x14, 0x00, 0x0b, 0x00, 0xf9];
+        let params = command_complete(&rssi, OP_READ_RSSI).unwrap().unwrap();
+        assert_eq!(params, [0x0b, 0x00, 0xf9]);
+        assert!(command_complete(&rssi, OP_READ_LINK_QUALITY).is_none());
+
+        // Unknown connection identifier.
+        let failed = [0x04, 0x0e, 0x07, 0x01, 0x05, 0x14, 0x02, 0x0b, 0x00, 0x00];
+        let err = command_complete(&failed, OP_READ_RSSI)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        // A pending Command Status is not an answer.
+        let pending = [0x04, 0x0f, 0x04, 0x00, 0x01, 0x05, 0x14];
+        assert!(command_complete(&pending, OP_READ_RSSI).is_none());
+    }
+}
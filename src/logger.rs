@@ -0,0 +1,597 @@
+//! Logging of raw sensor samples for offline analysis.
+//!
+//! A [`DataLogger`] records the Balance Board and motion sensor data of
+//! an event stream as [`Sample`]s, timestamped with both a monotonic
+//! offset from the start of the session and the kernel event time. The
+//! samples are written in CSV by a [`CsvWriter`], or in the Parquet
+//! format by a `ParquetWriter` if the `parquet` feature is enabled.
+//! Each file begins with the [`SessionMetadata`] of the recording.
+//!
+//! Writers created with labels record the samples of several devices in
+//! one file, with a `device` column naming the device of each sample;
+//! see the [`capture`](crate::capture) module.
+use crate::event::{Event, EventKind};
+use crate::{Device, Result};
+use futures::{Stream, TryStreamExt};
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The sensor that produced a [`Sample`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SampleSource {
+    /// The accelerometer of a Wii Remote, with the x, y and z-axis
+    /// accelerations as values.
+    Accelerometer,
+    /// The gyroscope of a Motion Plus, with the x, y and z-axis
+    /// rotational speeds as values.
+    MotionPlus,
+    /// The accelerometer of a Nunchuk, with the x and y-axis
+    /// accelerations as values.
+    Nunchuk,
+    /// The weight sensors of a Balance Board, ordered as in
+    /// [`EventKind::BalanceBoard`].
+    BalanceBoard,
+}
+
+impl SampleSource {
+    /// Returns the name of the source in log files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Accelerometer => "accelerometer",
+            Self::MotionPlus => "motion_plus",
+            Self::Nunchuk => "nunchuk",
+            Self::BalanceBoard => "balance_board",
+        }
+    }
+
+    /// Returns the number of values reported by the source.
+    pub fn value_count(&self) -> usize {
+        match self {
+            Self::Accelerometer | Self::MotionPlus => 3,
+            Self::Nunchuk => 2,
+            Self::BalanceBoard => 4,
+        }
+    }
+}
+
+/// A single reading of a sensor.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Sample {
+    /// The monotonic time since the start of the session.
+    pub elapsed: Duration,
+    /// The time of the event.
+    pub time: SystemTime,
+    /// The index of the device that produced the sample, among those
+    /// recorded together, or 0.
+    pub device: usize,
+    /// The sensor that produced the sample.
+    pub source: SampleSource,
+    /// The values, of which only the first [`SampleSource::value_count`] are
+    /// meaningful. The rest are zero.
+    pub values: [i32; 4],
+}
+
+impl Sample {
+    /// Creates a sample from the given event, received `elapsed` after
+    /// the start of the session. Returns `None` if the event carries no
+    /// sensor data.
+    pub fn from_event(event: &Event, elapsed: Duration) -> Option<Self> {
+        let (source, values) = match event.kind {
+            EventKind::Accelerometer { x, y, z } => (SampleSource::Accelerometer, [x, y, z, 0]),
+            EventKind::MotionPlus { x, y, z } => (SampleSource::MotionPlus, [x, y, z, 0]),
+            EventKind::NunchukMove {
+                x_acceleration,
+                y_acceleration,
+                ..
+            } => (
+                SampleSource::Nunchuk,
+                [x_acceleration, y_acceleration, 0, 0],
+            ),
+            EventKind::BalanceBoard(weights) => (SampleSource::BalanceBoard, weights),
+            _ => return None,
+        };
+        Some(Self {
+            elapsed,
+            time: event.time,
+            device: 0,
+            source,
+            values,
+        })
+    }
+
+    /// Returns the meaningful values of the sample.
+    pub fn values(&self) -> &[i32] {
+        &self.values[..self.source.value_count()]
+    }
+}
+
+/// Describes a recording session.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SessionMetadata {
+    /// The time the session started.
+    pub started: SystemTime,
+    /// Additional key-value pairs, e.g. a subject identifier or the
+    /// experimental condition.
+    pub fields: Vec<(String, String)>,
+}
+
+impl SessionMetadata {
+    /// Creates the metadata of a session starting now.
+    pub fn new() -> Self {
+        Self {
+            started: SystemTime::now(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Creates the metadata of a session starting now, recorded from
+    /// the given device.
+    pub fn with_device(device: &Device) -> Result<Self> {
+        let mut metadata = Self::new();
+        metadata.insert("device_kind", device.kind()?);
+        metadata.insert("mac_address", device.mac_address()?);
+        Ok(metadata)
+    }
+
+    /// Adds a key-value pair.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.fields.push((key.into(), value.into()));
+    }
+
+    /// Returns the pairs stored in log files, including the start time
+    /// in nanoseconds since the Unix epoch.
+    fn entries(&self) -> Vec<(String, String)> {
+        let started = nanos_since_epoch(self.started).to_string();
+        let mut entries = vec![("started_unix_ns".to_owned(), started)];
+        entries.extend(self.fields.iter().cloned());
+        entries
+    }
+}
+
+impl Default for SessionMetadata {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn nanos_since_epoch(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i64,
+        Err(err) => -(err.duration().as_nanos() as i64),
+    }
+}
+
+/// A log file format.
+pub trait SampleWriter {
+    /// The value returned once the log is complete.
+    type Output;
+
+    /// Appends a sample to the log.
+    fn write_sample(&mut self, sample: &Sample) -> Result<()>;
+
+    /// Completes the log.
+    fn finish(self) -> Result<Self::Output>;
+}
+
+/// Writes samples as comma-separated values.
+///
+/// The metadata is written first, as `# key: value` comment lines,
+/// followed by a header row. The columns are `elapsed_ns`, `time_ns`,
+/// `source` and one per value; values not reported by the source are
+/// left empty.
+#[derive(Debug)]
+pub struct CsvWriter<W: Write> {
+    out: W,
+    labels: Option<Vec<String>>,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Creates a writer to the given output, and writes the metadata
+    /// and the header.
+    pub fn new(out: W, metadata: &SessionMetadata) -> Result<Self> {
+        Self::create(out, metadata, None)
+    }
+
+    /// Creates a writer like [`CsvWriter::new`], with a `device` column
+    /// after `time_ns` holding the label of the [device](Sample::device)
+    /// of each sample.
+    pub fn with_labels(out: W, metadata: &SessionMetadata, labels: &[String]) -> Result<Self> {
+        Self::create(out, metadata, Some(labels.to_vec()))
+    }
+
+    fn create(mut out: W, metadata: &SessionMetadata, labels: Option<Vec<String>>) -> Result<Self> {
+        for (key, value) in metadata.entries() {
+            // Keep each pair on a single comment line.
+            let value = value.replace(['\r', '\n'], " ");
+            writeln!(out, "# {}: {}", key, value)?;
+        }
+        let device = if labels.is_some() { "device," } else { "" };
+        writeln!(
+            out,
+            "elapsed_ns,time_ns,{}source,value0,value1,value2,value3",
+            device
+        )?;
+        Ok(Self { out, labels })
+    }
+}
+
+/// Returns the label of the device of a sample.
+fn label(labels: &[String], sample: &Sample) -> String {
+    match labels.get(sample.device) {
+        Some(label) => label.clone(),
+        None => sample.device.to_string(),
+    }
+}
+
+impl<W: Write> SampleWriter for CsvWriter<W> {
+    type Output = W;
+
+    fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+        write!(
+            self.out,
+            "{},{},",
+            sample.elapsed.as_nanos(),
+            nanos_since_epoch(sample.time)
+        )?;
+        if let Some(labels) = &self.labels {
+            // Labels are chosen by the application, so keep them on a
+            // single line and in a single column.
+            write!(
+                self.out,
+                "{},",
+                label(labels, sample).replace(['\r', '\n', ','], " ")
+            )?;
+        }
+        write!(self.out, "{}", sample.source.name())?;
+        for (i, value) in sample.values.iter().enumerate() {
+            if i < sample.source.value_count() {
+                write!(self.out, ",{}", value)?;
+            } else {
+                write!(self.out, ",")?;
+            }
+        }
+        writeln!(self.out)
+    }
+
+    fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub use self::parquet_writer::ParquetWriter;
+
+#[cfg(feature = "parquet")]
+mod parquet_writer {
+    use super::{label, nanos_since_epoch, Sample, SampleWriter, SessionMetadata};
+    use crate::Result;
+    use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::format::KeyValue;
+    use parquet::schema::parser::parse_message_type;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    const SCHEMA: &str = "
+        message sample {
+            required int64 elapsed_ns;
+            required int64 time_ns;
+            required binary source (UTF8);
+            required int32 value0;
+            required int32 value1;
+            optional int32 value2;
+            optional int32 value3;
+        }
+    ";
+
+    /// The schema of writers with labels.
+    const LABELED_SCHEMA: &str = "
+        message sample {
+            required int64 elapsed_ns;
+            required int64 time_ns;
+            required binary device (UTF8);
+            required binary source (UTF8);
+            required int32 value0;
+            required int32 value1;
+            optional int32 value2;
+            optional int32 value3;
+        }
+    ";
+
+    /// Writes samples in the Parquet format.
+    ///
+    /// The columns are those of a [`CsvWriter`](super::CsvWriter), with
+    /// null values not reported by the source. The metadata is stored
+    /// as key-value metadata of the file.
+    pub struct ParquetWriter<W: Write + Send> {
+        writer: SerializedFileWriter<W>,
+        labels: Option<Vec<String>>,
+        // Samples not yet written in a row group.
+        rows: Vec<Sample>,
+        row_group_size: usize,
+    }
+
+    impl<W: Write + Send> ParquetWriter<W> {
+        /// The default number of samples in each row group.
+        pub const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+        /// Creates a writer to the given output.
+        pub fn new(out: W, metadata: &SessionMetadata) -> Result<Self> {
+            Self::with_row_group_size(out, metadata, Self::ROW_GROUP_SIZE)
+        }
+
+        /// Creates a writer to the given output, which buffers the given
+        /// number of samples before writing them.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `row_group_size` is 0.
+        pub fn with_row_group_size(
+            out: W,
+            metadata: &SessionMetadata,
+            row_group_size: usize,
+        ) -> Result<Self> {
+            Self::create(out, metadata, row_group_size, None)
+        }
+
+        /// Creates a writer like [`ParquetWriter::new`], with a `device`
+        /// column after `time_ns` holding the label of the
+        /// [device](Sample::device) of each sample.
+        pub fn with_labels(out: W, metadata: &SessionMetadata, labels: &[String]) -> Result<Self> {
+            Self::create(out, metadata, Self::ROW_GROUP_SIZE, Some(labels.to_vec()))
+        }
+
+        fn create(
+            out: W,
+            metadata: &SessionMetadata,
+            row_group_size: usize,
+            labels: Option<Vec<String>>,
+        ) -> Result<Self> {
+            assert!(row_group_size > 0, "row group size must be positive");
+            let schema = match labels {
+                Some(_) => LABELED_SCHEMA,
+                None => SCHEMA,
+            };
+            let schema = Arc::new(parse_message_type(schema)?);
+            let key_values = metadata
+                .entries()
+                .into_iter()
+                .map(|(key, value)| KeyValue::new(key, value))
+                .collect();
+            let properties = WriterProperties::builder()
+                .set_key_value_metadata(Some(key_values))
+                .build();
+            Ok(Self {
+                writer: SerializedFileWriter::new(out, schema, Arc::new(properties))?,
+                labels,
+                rows: Vec::with_capacity(row_group_size),
+                row_group_size,
+            })
+        }
+
+        /// Writes the buffered samples as a row group.
+        fn flush_rows(&mut self) -> Result<()> {
+            if self.rows.is_empty() {
+                return Ok(());
+            }
+            let rows = &self.rows;
+            let mut row_group = self.writer.next_row_group()?;
+
+            let elapsed: Vec<_> = rows.iter().map(|s| s.elapsed.as_nanos() as i64).collect();
+            let times: Vec<_> = rows.iter().map(|s| nanos_since_epoch(s.time)).collect();
+            let sources: Vec<_> = rows
+                .iter()
+                .map(|s| ByteArray::from(s.source.name()))
+                .collect();
+            for column in [elapsed, times] {
+                let mut writer = row_group.next_column()?.unwrap();
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&column, None, None)?;
+                writer.close()?;
+            }
+            let devices = self.labels.as_ref().map(|labels| {
+                rows.iter()
+                    .map(|s| ByteArray::from(label(labels, s).as_str()))
+                    .collect()
+            });
+            for column in devices.into_iter().chain([sources]) {
+                let mut writer = row_group.next_column()?.unwrap();
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&column, None, None)?;
+                writer.close()?;
+            }
+
+            for i in 0..4 {
+                let values: Vec<_> = rows
+                    .iter()
+                    .filter(|s| i < s.source.value_count())
+                    .map(|s| s.values[i])
+                    .collect();
+                let mut writer = row_group.next_column()?.unwrap();
+                if i < 2 {
+                    writer
+                        .typed::<Int32Type>()
+                        .write_batch(&values, None, None)?;
+                } else {
+                    let levels: Vec<_> = rows
+                        .iter()
+                        .map(|s| (i < s.source.value_count()) as i16)
+                        .collect();
+                    writer
+                        .typed::<Int32Type>()
+                        .write_batch(&values, Some(&levels), None)?;
+                }
+                writer.close()?;
+            }
+            row_group.close()?;
+            self.rows.clear();
+            Ok(())
+        }
+    }
+
+    impl<W: Write + Send> SampleWriter for ParquetWriter<W> {
+        type Output = W;
+
+        fn write_sample(&mut self, sample: &Sample) -> Result<()> {
+            self.rows.push(*sample);
+            if self.rows.len() >= self.row_group_size {
+                self.flush_rows()?;
+            }
+            Ok(())
+        }
+
+        fn finish(mut self) -> Result<W> {
+            self.flush_rows()?;
+            Ok(self.writer.into_inner()?)
+        }
+    }
+}
+
+/// Records the sensor data of events to a log.
+#[derive(Debug)]
+pub struct DataLogger<W> {
+    writer: W,
+    start: Instant,
+    samples: u64,
+}
+
+impl<W: SampleWriter> DataLogger<W> {
+    /// Creates a logger to the given log, whose session starts now.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            start: Instant::now(),
+            samples: 0,
+        }
+    }
+
+    /// Logs the sensor data of the given event, if any. Returns whether
+    /// a sample was written.
+    pub fn log(&mut self, event: &Event) -> Result<bool> {
+        self.log_from(0, event)
+    }
+
+    /// Logs the sensor data of the given event, if any, as produced by
+    /// the device with the given index. Returns whether a sample was
+    /// written.
+    pub fn log_from(&mut self, device: usize, event: &Event) -> Result<bool> {
+        match Sample::from_event(event, self.start.elapsed()) {
+            Some(sample) => {
+                let sample = Sample { device, ..sample };
+                self.writer.write_sample(&sample)?;
+                self.samples += 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Logs the events of the stream until it ends or yields an error.
+    pub async fn record<S>(&mut self, events: S) -> Result<()>
+    where
+        S: Stream<Item = Result<Event>>,
+    {
+        events
+            .try_for_each(|event| futures::future::ready(self.log(&event).map(drop)))
+            .await
+    }
+
+    /// Returns the number of samples written.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Completes the log.
+    pub fn finish(self) -> Result<W::Output> {
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvWriter, DataLogger, Sample, SampleSource, SessionMetadata};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use futures::{executor, stream};
+    use std::time::{Duration, SystemTime};
+
+    fn event(kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH + Duration::from_millis(5), kind)
+    }
+
+    #[test]
+    fn samples_sensor_events() {
+        let accel = event(EventKind::Accelerometer { x: 1, y: 2, z: 3 });
+        let sample = Sample::from_event(&accel, Duration::ZERO).unwrap();
+        assert_eq!(sample.source, SampleSource::Accelerometer);
+        assert_eq!(sample.values(), [1, 2, 3]);
+
+        let key = event(EventKind::Key(Key::A, KeyState::Down));
+        assert_eq!(Sample::from_event(&key, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn writes_csv() {
+        let mut metadata = SessionMetadata::new();
+        metadata.started = SystemTime::UNIX_EPOCH;
+        metadata.insert("subject", "s01\nnext");
+        let writer = CsvWriter::new(Vec::new(), &metadata).unwrap();
+        let mut logger = DataLogger::new(writer);
+
+        let events = [
+            event(EventKind::BalanceBoard([10, 20, 30, 40])),
+            event(EventKind::Key(Key::A, KeyState::Down)),
+            event(EventKind::MotionPlus { x: -1, y: 0, z: 1 }),
+        ];
+        executor::block_on(logger.record(stream::iter(events.map(Ok)))).unwrap();
+        assert_eq!(logger.samples(), 2);
+
+        let csv = String::from_utf8(logger.finish().unwrap()).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "# started_unix_ns: 0");
+        assert_eq!(lines[1], "# subject: s01 next");
+        assert_eq!(
+            lines[2],
+            "elapsed_ns,time_ns,source,value0,value1,value2,value3"
+        );
+        assert!(lines[3].ends_with(",5000000,balance_board,10,20,30,40"));
+        assert!(lines[4].ends_with(",5000000,motion_plus,-1,0,1,"));
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn writes_parquet() {
+        use super::ParquetWriter;
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let metadata = SessionMetadata::new();
+        let writer = ParquetWriter::with_row_group_size(Vec::new(), &metadata, 2).unwrap();
+        let mut logger = DataLogger::new(writer);
+        for kind in [
+            EventKind::Accelerometer { x: 1, y: 2, z: 3 },
+            EventKind::BalanceBoard([1, 2, 3, 4]),
+            EventKind::NunchukMove {
+                x: 0,
+                y: 0,
+                x_acceleration: 5,
+                y_acceleration: 6,
+            },
+        ] {
+            assert!(logger.log(&event(kind)).unwrap());
+        }
+
+        let path = std::env::temp_dir().join(format!("xwiimote-{}.parquet", std::process::id()));
+        std::fs::write(&path, logger.finish().unwrap()).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let file = reader.metadata().file_metadata();
+        assert_eq!(file.num_rows(), 3);
+        assert_eq!(reader.num_row_groups(), 2);
+        let key_values = file.key_value_metadata().unwrap();
+        assert_eq!(key_values[0].key, "started_unix_ns");
+    }
+}
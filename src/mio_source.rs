@@ -0,0 +1,61 @@
+//! Registration of devices and monitors in [`mio`] polls.
+//!
+//! Requires the `mio` feature. The registrations are edge-triggered, so
+//! once a [`Device`] is reported as readable, its events must be read
+//! with [`Device::try_next_event`] until it returns `None`. Similarly,
+//! read new addresses with [`Monitor::next_address`].
+use crate::{Device, Monitor};
+use mio::event::Source;
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+use std::io;
+
+impl Source for Device {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.fd()).deregister(registry)
+    }
+}
+
+/// Fails with [`io::ErrorKind::InvalidInput`] if the monitor doesn't
+/// discover new devices.
+impl Source for Monitor {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.discovery_fd()?).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.discovery_fd()?).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.discovery_fd()?).deregister(registry)
+    }
+}
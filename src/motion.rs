@@ -0,0 +1,305 @@
+//! Combined motion state of a Wii Remote and its extensions.
+//!
+//! The Motion Plus extension has a pass-through port for a Nunchuk or
+//! a Classic Controller. If both [`Channels::MOTION_PLUS`] and the
+//! channel of the extension are open, the device alternates between
+//! Motion Plus and extension reports, and the kernel decodes both into
+//! separate events. Hence each source is updated at about half the
+//! rate, and the Nunchuk accelerometer values lose their least
+//! significant bit. The Wii Remote Plus has a built-in Motion Plus (see
+//! [`Capabilities::builtin_motion_plus`]), which behaves the same with
+//! an extension plugged into the remote.
+//!
+//! A [`MotionState`] merges the latest data from every motion source,
+//! so applications don't have to track interleaved events themselves.
+//!
+//! The quality of the Motion Plus data changes as it is plugged in and
+//! out, calibrated, or shares its port with a Nunchuk. A
+//! [`MotionPlusMonitor`] reports the [`MotionPlusState`] when it changes,
+//! so sensor fusion can reset its filters rather than integrate data
+//! from a different source.
+use crate::event::{Event, EventKind};
+#[cfg(doc)]
+use crate::quirks::Capabilities;
+use crate::{Channels, Device, MotionPlusNormalization, Result};
+use futures::{future, Stream, TryStreamExt};
+use std::time::SystemTime;
+
+/// A three-dimensional sensor reading.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub struct Vector3 {
+    /// The x-axis value.
+    pub x: i32,
+    /// The y-axis value.
+    pub y: i32,
+    /// The z-axis value.
+    pub z: i32,
+}
+
+/// The motion data reported by a Nunchuk.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub struct NunchukMotion {
+    /// The analog stick x-axis absolute position.
+    pub x: i32,
+    /// The analog stick y-axis absolute position.
+    pub y: i32,
+    /// The x-axis acceleration.
+    pub x_acceleration: i32,
+    /// The y-axis acceleration.
+    pub y_acceleration: i32,
+}
+
+/// The latest motion data received from each source.
+///
+/// A source is `None` until its first event is received.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub struct MotionState {
+    /// The Wii Remote accelerometer data.
+    pub accelerometer: Option<Vector3>,
+    /// The Motion Plus gyroscope data.
+    pub motion_plus: Option<Vector3>,
+    /// The Nunchuk motion data.
+    pub nunchuk: Option<NunchukMotion>,
+    /// The time of the last motion event.
+    pub time: Option<SystemTime>,
+}
+
+impl MotionState {
+    /// Updates the state with the given event. Returns `true` if the
+    /// event carried motion data.
+    pub fn update(&mut self, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Accelerometer { x, y, z } => {
+                self.accelerometer = Some(Vector3 { x, y, z });
+            }
+            EventKind::MotionPlus { x, y, z } => {
+                self.motion_plus = Some(Vector3 { x, y, z });
+            }
+            EventKind::NunchukMove {
+                x,
+                y,
+                x_acceleration,
+                y_acceleration,
+            } => {
+                self.nunchuk = Some(NunchukMotion {
+                    x,
+                    y,
+                    x_acceleration,
+                    y_acceleration,
+                });
+            }
+            EventKind::Other(watch) => {
+                // Forget the data of unplugged extensions.
+                if watch.removed().contains(Channels::MOTION_PLUS) {
+                    self.motion_plus = None;
+                }
+                if watch.removed().contains(Channels::NUNCHUK) {
+                    self.nunchuk = None;
+                }
+                return false;
+            }
+            _ => return false,
+        }
+        self.time = Some(event.time);
+        true
+    }
+
+    /// Returns `true` if both Motion Plus and Nunchuk data are present,
+    /// meaning the Nunchuk is (most likely) plugged into the Motion Plus
+    /// pass-through port.
+    pub fn is_passthrough(&self) -> bool {
+        self.motion_plus.is_some() && self.nunchuk.is_some()
+    }
+}
+
+/// The availability and quality of the Motion Plus data.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum MotionPlusState {
+    /// No Motion Plus data is received.
+    Absent,
+    /// The data is received, but no normalization is set, so the
+    /// angular velocities have an offset.
+    Uncalibrated,
+    /// The data is received and normalized.
+    Calibrated,
+    /// An extension is plugged into the pass-through port, or into a
+    /// remote with a built-in Motion Plus, so the data is received at
+    /// about half the rate. See the [module](self) docs.
+    Passthrough,
+}
+
+/// The extensions whose reports are interleaved with the Motion Plus.
+const PASSTHROUGH_EXTENSIONS: Channels =
+    Channels::from_bits_truncate(Channels::NUNCHUK.bits() | Channels::CLASSIC_CONTROLLER.bits());
+
+/// Tracks the [`MotionPlusState`] of a device.
+#[derive(Clone, Debug)]
+pub struct MotionPlusMonitor {
+    motion: MotionState,
+    calibrated: bool,
+    // Whether a pass-through extension is plugged, as of the last
+    // watch event.
+    extension: bool,
+    state: MotionPlusState,
+}
+
+impl MotionPlusMonitor {
+    /// Creates a monitor for a device whose Motion Plus normalization
+    /// is set, or not.
+    pub fn new(calibrated: bool) -> Self {
+        Self {
+            motion: MotionState::default(),
+            calibrated,
+            extension: false,
+            state: MotionPlusState::Absent,
+        }
+    }
+
+    /// Creates a monitor for the given device, reading whether its
+    /// normalization is set and whether an extension is plugged.
+    pub fn for_device(device: &Device) -> Self {
+        let mut monitor =
+            Self::new(device.mp_normalization() != MotionPlusNormalization::default());
+        monitor.extension = device.available().intersects(PASSTHROUGH_EXTENSIONS);
+        monitor
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> MotionPlusState {
+        self.state
+    }
+
+    /// Records a change of the normalization of the device, e.g. after
+    /// calling [`Device::set_mp_normalization`]. Returns the new state,
+    /// if it changed.
+    pub fn set_calibrated(&mut self, calibrated: bool) -> Option<MotionPlusState> {
+        self.calibrated = calibrated;
+        self.refresh()
+    }
+
+    /// Updates the monitor with the given event. Returns the new state,
+    /// if it changed.
+    pub fn update(&mut self, event: &Event) -> Option<MotionPlusState> {
+        self.motion.update(event);
+        if let EventKind::Other(watch) = event.kind {
+            self.extension = watch.available_after.intersects(PASSTHROUGH_EXTENSIONS);
+        }
+        self.refresh()
+    }
+
+    fn refresh(&mut self) -> Option<MotionPlusState> {
+        let state = if self.motion.motion_plus.is_none() {
+            MotionPlusState::Absent
+        } else if self.extension || self.motion.is_passthrough() {
+            MotionPlusState::Passthrough
+        } else if self.calibrated {
+            MotionPlusState::Calibrated
+        } else {
+            MotionPlusState::Uncalibrated
+        };
+        (state != self.state).then(|| {
+            self.state = state;
+            state
+        })
+    }
+}
+
+/// Adapts a stream of events into a stream of the state changes
+/// reported by `monitor`.
+pub fn motion_plus_states<S>(
+    events: S,
+    mut monitor: MotionPlusMonitor,
+) -> impl Stream<Item = Result<MotionPlusState>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(monitor.update(&event))))
+}
+
+/// Adapts a stream of events into a stream of motion states, yielding
+/// the updated state after every motion event.
+pub fn motion_states<S>(events: S) -> impl Stream<Item = Result<MotionState>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    let mut state = MotionState::default();
+    events.try_filter_map(move |event| future::ready(Ok(state.update(&event).then_some(state))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MotionPlusMonitor, MotionPlusState, MotionState, Vector3};
+    use crate::event::{Event, EventKind, WatchEvent};
+    use crate::Channels;
+    use std::time::SystemTime;
+
+    fn event(kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH, kind)
+    }
+
+    #[test]
+    fn merges_interleaved_sources() {
+        let mut state = MotionState::default();
+        assert!(state.update(&event(EventKind::MotionPlus { x: 1, y: 2, z: 3 })));
+        assert!(!state.is_passthrough());
+        assert!(state.update(&event(EventKind::NunchukMove {
+            x: 10,
+            y: -10,
+            x_acceleration: 100,
+            y_acceleration: 200,
+        })));
+
+        assert!(state.is_passthrough());
+        assert_eq!(state.motion_plus, Some(Vector3 { x: 1, y: 2, z: 3 }));
+        assert_eq!(
+            state.nunchuk.map(|nunchuk| nunchuk.y_acceleration),
+            Some(200)
+        );
+    }
+
+    #[test]
+    fn forgets_unplugged() {
+        let mut state = MotionState::default();
+        state.update(&event(EventKind::MotionPlus { x: 1, y: 2, z: 3 }));
+        assert!(!state.update(&event(EventKind::Other(WatchEvent {
+            available_before: Channels::CORE | Channels::MOTION_PLUS,
+            available_after: Channels::CORE,
+        }))));
+        assert_eq!(state.motion_plus, None);
+    }
+
+    #[test]
+    fn reports_motion_plus_state_changes() {
+        let mut monitor = MotionPlusMonitor::new(false);
+        let gyro = event(EventKind::MotionPlus { x: 1, y: 2, z: 3 });
+        assert_eq!(monitor.update(&gyro), Some(MotionPlusState::Uncalibrated));
+        assert_eq!(monitor.update(&gyro), None);
+        assert_eq!(
+            monitor.set_calibrated(true),
+            Some(MotionPlusState::Calibrated)
+        );
+
+        let nunchuk = event(EventKind::NunchukMove {
+            x: 0,
+            y: 0,
+            x_acceleration: 0,
+            y_acceleration: 0,
+        });
+        assert_eq!(monitor.update(&nunchuk), Some(MotionPlusState::Passthrough));
+
+        let unplugged = event(EventKind::Other(WatchEvent {
+            available_before: Channels::CORE | Channels::MOTION_PLUS | Channels::NUNCHUK,
+            available_after: Channels::CORE,
+        }));
+        assert_eq!(monitor.update(&unplugged), Some(MotionPlusState::Absent));
+        assert_eq!(monitor.update(&gyro), Some(MotionPlusState::Calibrated));
+
+        // A Classic Controller, e.g. plugged into a Wii Remote Plus,
+        // reports no motion but is interleaved all the same.
+        let classic = event(EventKind::Other(WatchEvent {
+            available_before: Channels::CORE | Channels::MOTION_PLUS,
+            available_after: Channels::CORE | Channels::MOTION_PLUS | Channels::CLASSIC_CONTROLLER,
+        }));
+        assert_eq!(monitor.update(&classic), Some(MotionPlusState::Passthrough));
+    }
+}
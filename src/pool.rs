@@ -0,0 +1,93 @@
+//! Sharing devices between the subsystems of an application.
+//!
+//! Each [`Device`] owns an `xwiimote` interface, and two interfaces to
+//! the same remote fight over its channels: closing a channel on one
+//! stops its events on the other. A [`DevicePool`] connects to each
+//! remote once, and hands out [`SharedDevice`]s to it. The device is
+//! disconnected once every handle is dropped.
+use crate::{Address, ConnectOptions, Device, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+
+/// A reference-counted handle to a device of a [`DevicePool`].
+pub type SharedDevice = Rc<RefCell<Device>>;
+
+/// Connects to each device once, and shares it between callers.
+///
+/// The pool only keeps weak references, so it doesn't keep the devices
+/// connected by itself. Since devices are not thread-safe, neither is
+/// the pool; create one per thread.
+#[derive(Default)]
+pub struct DevicePool {
+    options: ConnectOptions,
+    devices: RefCell<HashMap<PathBuf, Weak<RefCell<Device>>>>,
+}
+
+impl DevicePool {
+    /// Creates a pool that connects to devices with the default
+    /// [`ConnectOptions`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a pool that connects to devices with the given options.
+    pub fn with_options(options: ConnectOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the device at the given address, connecting to it if
+    /// it isn't in use.
+    pub fn get_or_connect(&self, address: &Address) -> Result<SharedDevice> {
+        if let Some(device) = self.get(address) {
+            return Ok(device);
+        }
+        let device = Rc::new(RefCell::new(Device::connect_with(address, &self.options)?));
+        let mut devices = self.devices.borrow_mut();
+        devices.retain(|_, device| device.strong_count() > 0);
+        devices.insert(address.0.clone(), Rc::downgrade(&device));
+        Ok(device)
+    }
+
+    /// Returns the device at the given address, if it is in use.
+    pub fn get(&self, address: &Address) -> Option<SharedDevice> {
+        self.devices.borrow().get(&address.0)?.upgrade()
+    }
+
+    /// Returns the number of devices in use.
+    pub fn len(&self) -> usize {
+        self.devices
+            .borrow()
+            .values()
+            .filter(|device| device.strong_count() > 0)
+            .count()
+    }
+
+    /// Checks whether no device is in use.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(all(test, feature = "stub"))]
+mod tests {
+    use super::DevicePool;
+    use crate::{Address, ConnectOptions};
+    use std::path::PathBuf;
+
+    #[test]
+    fn failed_connections_are_not_pooled() {
+        let pool = DevicePool::with_options(ConnectOptions {
+            blocking: false,
+            ..Default::default()
+        });
+        let address = Address::from(PathBuf::from("/sys/devices/unknown"));
+        assert!(pool.get_or_connect(&address).is_err());
+        assert!(pool.get(&address).is_none());
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+//! The types and traits needed by most applications.
+//!
+//! Glob-import the prelude to connect to a device and read its events
+//! without listing each type:
+//!
+//! ```no_run
+//! use xwiimote::prelude::*;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let mut monitor = Monitor::new(false)?;
+//! while let Some(address) = monitor.try_next().await? {
+//!     let mut device = Device::connect(&address)?;
+//!     device.open(Channels::CORE, false)?;
+//!     let mut events = device.events()?;
+//!     while let Some(event) = events.try_next().await? {
+//!         if let EventKind::Key(Key::Home, KeyState::Down) = event.kind {
+//!             break;
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Devices and monitors are [`Stream`]s. The prelude brings the
+//! [`StreamExt`] and [`TryStreamExt`] methods of the `futures` crate
+//! into scope, e.g. `next` and `try_next`, without binding their names,
+//! so it doesn't conflict with other imports of the traits, e.g. from
+//! `tokio-stream`. Import the traits by name to refer to them.
+//!
+//! The errors of the crate are [`std::io::Error`]s. Errors with a
+//! payload, e.g. [`DeviceBusy`], are recovered with their `from_io`
+//! function.
+//!
+//! [`Stream`]: futures::Stream
+//! [`StreamExt`]: futures::StreamExt
+//! [`TryStreamExt`]: futures::TryStreamExt
+pub use crate::event::{Event, EventKind, EventStream, Key, KeyCode, KeyState};
+pub use crate::holders::DeviceBusy;
+pub use crate::layer::EventLayer;
+pub use crate::{Address, Channels, ConnectOptions, Device, Led, Leds, Monitor};
+pub use futures::{StreamExt as _, TryStreamExt as _};
@@ -0,0 +1,214 @@
+//! Synthesis of taps, double taps and long presses from key transitions.
+//!
+//! The kernel only reports whether a key is up, down or
+//! [auto-repeating](KeyState::AutoRepeat). A [`PressDetector`] turns
+//! these transitions into the higher-level interactions expected by
+//! user interfaces. Use [`presses`] to adapt an event stream.
+//!
+//! Only the Wii Remote [keys](Key) are considered, hence the
+//! [`Channels::CORE`](crate::Channels::CORE) channel must be open.
+use crate::event::{Event, EventKind, Key, KeyState};
+use crate::Result;
+use futures::{future, Stream, TryStreamExt};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// The timing used to classify key presses.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PressConfig {
+    /// The maximum time between the release of a tap and the next
+    /// press for both to be reported as a double tap.
+    pub double_tap_window: Duration,
+    /// The minimum time a key must be held down to be reported as
+    /// a hold instead of a tap.
+    pub hold_threshold: Duration,
+}
+
+impl Default for PressConfig {
+    fn default() -> Self {
+        Self {
+            double_tap_window: Duration::from_millis(250),
+            hold_threshold: Duration::from_millis(500),
+        }
+    }
+}
+
+/// A key interaction.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Press {
+    /// The key was pressed and released quickly.
+    Tap,
+    /// The key was tapped twice in quick succession.
+    ///
+    /// The first tap is also reported as a [`Press::Tap`].
+    DoubleTap,
+    /// The key was held down for the given duration.
+    ///
+    /// Reported once per press, as soon as an auto-repeat event shows
+    /// the key was held down for longer than the hold threshold, or
+    /// once the key is released if no such event was received.
+    Hold(Duration),
+}
+
+/// A key interaction synthesized by a [`PressDetector`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PressEvent {
+    /// The key.
+    pub key: Key,
+    /// The interaction.
+    pub press: Press,
+    /// The time of the event that completed the interaction.
+    pub time: SystemTime,
+}
+
+/// The state of a single key.
+#[derive(Copy, Clone, Default, Debug)]
+struct KeyProgress {
+    // The time the key was pressed, if held down.
+    down: Option<SystemTime>,
+    // Whether a hold was reported for the current press.
+    held: bool,
+    // Whether the current press may complete a double tap.
+    second_tap: bool,
+    // The time the last tap was released.
+    last_tap: Option<SystemTime>,
+}
+
+/// Synthesizes [`PressEvent`]s from key [`Event`]s.
+#[derive(Clone, Default, Debug)]
+pub struct PressDetector {
+    config: PressConfig,
+    keys: HashMap<Key, KeyProgress>,
+}
+
+impl PressDetector {
+    /// Creates a detector with the given timing.
+    pub fn new(config: PressConfig) -> Self {
+        Self {
+            config,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Updates the detector with the given event, and returns the
+    /// interaction completed by it, if any.
+    pub fn update(&mut self, event: &Event) -> Option<PressEvent> {
+        let (key, state) = match event.kind {
+            EventKind::Key(key, state) => (key, state),
+            _ => return None,
+        };
+        let config = self.config;
+        let progress = self.keys.entry(key).or_default();
+        let held_for = |down: SystemTime| event.time.duration_since(down).unwrap_or_default();
+
+        let press = match state {
+            KeyState::Down => {
+                progress.second_tap = progress
+                    .last_tap
+                    .and_then(|last| event.time.duration_since(last).ok())
+                    .is_some_and(|since| since <= config.double_tap_window);
+                progress.down = Some(event.time);
+                progress.held = false;
+                None
+            }
+            KeyState::AutoRepeat => match progress.down {
+                Some(down) if !progress.held && held_for(down) >= config.hold_threshold => {
+                    progress.held = true;
+                    Some(Press::Hold(held_for(down)))
+                }
+                _ => None,
+            },
+            KeyState::Up => {
+                let down = progress.down.take()?;
+                if progress.held {
+                    // Already reported while held down.
+                    progress.last_tap = None;
+                    None
+                } else if held_for(down) >= config.hold_threshold {
+                    progress.last_tap = None;
+                    Some(Press::Hold(held_for(down)))
+                } else if progress.second_tap {
+                    // A third tap starts over instead of being a double tap.
+                    progress.last_tap = None;
+                    Some(Press::DoubleTap)
+                } else {
+                    progress.last_tap = Some(event.time);
+                    Some(Press::Tap)
+                }
+            }
+        };
+        press.map(|press| PressEvent {
+            key,
+            press,
+            time: event.time,
+        })
+    }
+}
+
+/// Adapts a stream of events into a stream of the key interactions
+/// synthesized by `detector`.
+pub fn presses<S>(events: S, mut detector: PressDetector) -> impl Stream<Item = Result<PressEvent>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(detector.update(&event))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Press, PressConfig, PressDetector};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use std::time::{Duration, SystemTime};
+
+    fn press(detector: &mut PressDetector, ms: u64, state: KeyState) -> Option<Press> {
+        let event = Event::at(
+            SystemTime::UNIX_EPOCH + Duration::from_millis(ms),
+            EventKind::Key(Key::A, state),
+        );
+        detector.update(&event).map(|event| event.press)
+    }
+
+    #[test]
+    fn tap_and_double_tap() {
+        let mut detector = PressDetector::new(PressConfig::default());
+
+        assert_eq!(press(&mut detector, 0, KeyState::Down), None);
+        assert_eq!(press(&mut detector, 100, KeyState::Up), Some(Press::Tap));
+        assert_eq!(press(&mut detector, 200, KeyState::Down), None);
+        assert_eq!(
+            press(&mut detector, 300, KeyState::Up),
+            Some(Press::DoubleTap)
+        );
+        // Too late for another double tap.
+        press(&mut detector, 1000, KeyState::Down);
+        assert_eq!(press(&mut detector, 1100, KeyState::Up), Some(Press::Tap));
+    }
+
+    #[test]
+    fn hold_while_repeating() {
+        let mut detector = PressDetector::new(PressConfig::default());
+
+        press(&mut detector, 0, KeyState::Down);
+        assert_eq!(press(&mut detector, 250, KeyState::AutoRepeat), None);
+        assert_eq!(
+            press(&mut detector, 500, KeyState::AutoRepeat),
+            Some(Press::Hold(Duration::from_millis(500)))
+        );
+        assert_eq!(press(&mut detector, 750, KeyState::AutoRepeat), None);
+        assert_eq!(press(&mut detector, 800, KeyState::Up), None);
+    }
+
+    #[test]
+    fn hold_on_release() {
+        let mut detector = PressDetector::new(PressConfig::default());
+
+        press(&mut detector, 0, KeyState::Down);
+        assert_eq!(
+            press(&mut detector, 700, KeyState::Up),
+            Some(Press::Hold(Duration::from_millis(700)))
+        );
+        // A hold doesn't count as the first tap of a double tap.
+        press(&mut detector, 800, KeyState::Down);
+        assert_eq!(press(&mut detector, 850, KeyState::Up), Some(Press::Tap));
+    }
+}
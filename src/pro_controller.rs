@@ -0,0 +1,422 @@
+//! Calibration of the Wii U Pro Controller analog sticks.
+//!
+//! The sticks of a Pro Controller commonly drift: at rest, they report
+//! positions away from the nominal center, and their travel differs
+//! from the range advertised by the kernel. A [`StickCalibration`]
+//! stores the measured center and range of each axis, and normalizes
+//! the raw [`EventKind::ProControllerMove`] positions to -1 to 1.
+//!
+//! A [`StickCorrector`] applies a calibration to an event stream while
+//! refining it: the center is measured from the first positions at
+//! rest, follows slow drift afterwards, and the range grows to fit
+//! the observed travel. Use [`corrected_sticks`] to adapt a stream, and
+//! [`ProController::recalibrate_sticks`] to measure and save the
+//! center to the profile of the controller.
+use crate::event::{Event, EventKind};
+use crate::profile::{Profile, ProfileStore};
+use crate::{Device, Result};
+use futures::{future, Stream, StreamExt, TryStreamExt};
+use std::io;
+use std::time::SystemTime;
+
+/// The calibration of an axis, in the units of
+/// [`EventKind::ProControllerMove`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct AxisCalibration {
+    /// The position at rest.
+    pub center: i32,
+    /// The minimum position.
+    pub min: i32,
+    /// The maximum position.
+    pub max: i32,
+}
+
+impl AxisCalibration {
+    /// Converts the raw position to a value from -1 to 1, which is 0 at
+    /// the center.
+    pub fn normalize(&self, raw: i32) -> f32 {
+        let offset = raw - self.center;
+        // A broken calibration would divide by zero.
+        let range = if offset >= 0 {
+            self.max - self.center
+        } else {
+            self.center - self.min
+        };
+        (offset as f32 / range.max(1) as f32).clamp(-1.0, 1.0)
+    }
+}
+
+impl Default for AxisCalibration {
+    /// The range reported by the kernel driver.
+    fn default() -> Self {
+        Self {
+            center: 0,
+            min: -0x400,
+            max: 0x400,
+        }
+    }
+}
+
+/// The calibration of both analog sticks, ordered as the fields of
+/// [`EventKind::ProControllerMove`].
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub struct StickCalibration {
+    /// The calibration of the left stick x and y-axes, and of the
+    /// right stick x and y-axes.
+    pub axes: [AxisCalibration; 4],
+}
+
+impl StickCalibration {
+    /// The key of the calibration in [`Profile::values`].
+    const PROFILE_KEY: &'static str = "pro_controller.sticks";
+
+    /// Normalizes the raw positions of both sticks.
+    pub fn apply(&self, raw: [i32; 4]) -> [f32; 4] {
+        let mut values = [0.0; 4];
+        for ((value, axis), raw) in values.iter_mut().zip(&self.axes).zip(raw) {
+            *value = axis.normalize(raw);
+        }
+        values
+    }
+
+    /// Reads the calibration stored in the profile, if any.
+    pub fn from_profile(profile: &Profile) -> Result<Option<Self>> {
+        let value = match profile.values.get(Self::PROFILE_KEY) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid stick calibration: {}", value),
+            )
+        };
+        let numbers: Vec<i32> = value
+            .split_whitespace()
+            .map(|num| num.parse().map_err(|_| invalid()))
+            .collect::<Result<_>>()?;
+        if numbers.len() != 12 {
+            return Err(invalid());
+        }
+        let mut calibration = Self::default();
+        for (axis, values) in calibration.axes.iter_mut().zip(numbers.chunks(3)) {
+            *axis = AxisCalibration {
+                center: values[0],
+                min: values[1],
+                max: values[2],
+            };
+        }
+        Ok(Some(calibration))
+    }
+
+    /// Stores the calibration in the profile.
+    pub fn store(&self, profile: &mut Profile) {
+        let numbers: Vec<String> = self
+            .axes
+            .iter()
+            .flat_map(|axis| [axis.center, axis.min, axis.max])
+            .map(|num| num.to_string())
+            .collect();
+        profile
+            .values
+            .insert(Self::PROFILE_KEY.to_string(), numbers.join(" "));
+    }
+}
+
+/// The normalized positions of the analog sticks.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Sticks {
+    /// The left stick x and y-axis positions, from -1 to 1.
+    pub left: [f32; 2],
+    /// The right stick x and y-axis positions, from -1 to 1.
+    pub right: [f32; 2],
+    /// The time of the event.
+    pub time: SystemTime,
+}
+
+/// How a [`StickCorrector`] refines its calibration.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CorrectionConfig {
+    /// The maximum offset from the center of a stick at rest, in raw
+    /// units. Positions within it are reported as 0.
+    pub rest_tolerance: i32,
+    /// The weight given to each position at rest when following the
+    /// drift of the center, from 0 (disabled) to 1.
+    pub drift_smoothing: f32,
+}
+
+impl Default for CorrectionConfig {
+    fn default() -> Self {
+        Self {
+            // The flat value reported by the kernel driver.
+            rest_tolerance: 100,
+            drift_smoothing: 0.01,
+        }
+    }
+}
+
+/// Normalizes the analog stick positions of Pro Controller [`Event`]s,
+/// correcting their drift.
+#[derive(Clone, Debug)]
+pub struct StickCorrector {
+    calibration: StickCalibration,
+    config: CorrectionConfig,
+    // The center of each axis, with subunit precision.
+    centers: [f32; 4],
+    // Whether the center of each stick was measured.
+    centered: [bool; 2],
+}
+
+impl StickCorrector {
+    /// Creates a corrector with the given initial calibration.
+    pub fn new(calibration: StickCalibration, config: CorrectionConfig) -> Self {
+        Self {
+            calibration,
+            config,
+            centers: calibration.axes.map(|axis| axis.center as f32),
+            centered: [false; 2],
+        }
+    }
+
+    /// Returns the current calibration.
+    pub fn calibration(&self) -> StickCalibration {
+        self.calibration
+    }
+
+    /// Updates the corrector with the given event, and returns the
+    /// normalized positions, if it is a
+    /// [`EventKind::ProControllerMove`] event.
+    pub fn update(&mut self, event: &Event) -> Option<Sticks> {
+        let raw = match event.kind {
+            EventKind::ProControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            } => [left_x, left_y, right_x, right_y],
+            _ => return None,
+        };
+        let tolerance = self.config.rest_tolerance;
+        let mut values = [0.0; 4];
+        for stick in 0..2 {
+            let axes = stick * 2..stick * 2 + 2;
+            let at_rest = axes
+                .clone()
+                .all(|i| (raw[i] - self.calibration.axes[i].center).abs() <= tolerance);
+
+            for i in axes {
+                let axis = &mut self.calibration.axes[i];
+                if at_rest {
+                    // The first position at rest is the center, which then
+                    // follows the slow drift of the stick.
+                    let center = &mut self.centers[i];
+                    if self.centered[stick] {
+                        *center += (raw[i] as f32 - *center) * self.config.drift_smoothing;
+                    } else {
+                        *center = raw[i] as f32;
+                    }
+                    axis.center = center.round() as i32;
+                } else {
+                    axis.min = axis.min.min(raw[i]);
+                    axis.max = axis.max.max(raw[i]);
+                    values[i] = axis.normalize(raw[i]);
+                }
+            }
+            self.centered[stick] |= at_rest;
+        }
+        Some(Sticks {
+            left: [values[0], values[1]],
+            right: [values[2], values[3]],
+            time: event.time,
+        })
+    }
+}
+
+/// Adapts a stream of events into a stream of the stick positions
+/// normalized by `corrector`.
+pub fn corrected_sticks<S>(
+    events: S,
+    mut corrector: StickCorrector,
+) -> impl Stream<Item = Result<Sticks>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(corrector.update(&event))))
+}
+
+/// A Wii U Pro Controller.
+#[derive(Copy, Clone)]
+pub struct ProController<'a> {
+    device: &'a Device,
+}
+
+impl<'a> ProController<'a> {
+    /// The number of events to average when measuring the center.
+    const CENTER_SAMPLES: usize = 50;
+    /// The maximum spread of the positions at rest, in raw units.
+    const MAX_REST_SPREAD: i32 = 40;
+
+    /// Wraps the given device, which should be a Pro Controller.
+    pub fn new(device: &'a Device) -> Self {
+        Self { device }
+    }
+
+    /// Returns the underlying device.
+    pub fn device(&self) -> &'a Device {
+        self.device
+    }
+
+    /// Measures the center of both sticks, and saves it to the profile
+    /// of the controller in the [user store](ProfileStore::user). The
+    /// ranges of the saved calibration, if any, are kept.
+    ///
+    /// The positions are read from `events`, which must have
+    /// [`Channels::PRO_CONTROLLER`] open. Fails with
+    /// [`io::ErrorKind::InvalidData`] if the sticks are moved.
+    ///
+    /// [`Channels::PRO_CONTROLLER`]: crate::Channels::PRO_CONTROLLER
+    pub async fn recalibrate_sticks<S>(&self, events: &mut S) -> Result<StickCalibration>
+    where
+        S: Stream<Item = Result<Event>> + Unpin,
+    {
+        let mut samples = Vec::with_capacity(Self::CENTER_SAMPLES);
+        while samples.len() < Self::CENTER_SAMPLES {
+            let event = match events.next().await {
+                Some(event) => event?,
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+            if let EventKind::ProControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            } = event.kind
+            {
+                samples.push([left_x, left_y, right_x, right_y]);
+            }
+        }
+
+        let mut calibration = self.load_calibration()?.unwrap_or_default();
+        set_centers(&mut calibration, &samples)?;
+        self.save_calibration(&calibration)?;
+        Ok(calibration)
+    }
+
+    /// Saves the calibration to the profile of the controller in the
+    /// [user store](ProfileStore::user).
+    pub fn save_calibration(&self, calibration: &StickCalibration) -> Result<()> {
+        let store = ProfileStore::user()?;
+        let mac = self.device.mac_address()?;
+        let mut profile = match store.load(&mac)? {
+            Some(profile) => profile,
+            None => Profile::capture(self.device)?,
+        };
+        calibration.store(&mut profile);
+        store.save(&mac, &profile)
+    }
+
+    /// Loads the calibration from the profile of the controller in the
+    /// [user store](ProfileStore::user), if any.
+    pub fn load_calibration(&self) -> Result<Option<StickCalibration>> {
+        let mac = self.device.mac_address()?;
+        match ProfileStore::user()?.load(&mac)? {
+            Some(profile) => StickCalibration::from_profile(&profile),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Sets the center of each axis to the mean of the positions, which
+/// must be at rest.
+fn set_centers(calibration: &mut StickCalibration, samples: &[[i32; 4]]) -> Result<()> {
+    for (i, axis) in calibration.axes.iter_mut().enumerate() {
+        let positions = samples.iter().map(|sample| sample[i]);
+        let (min, max) = (positions.clone().min(), positions.clone().max());
+        if let (Some(min), Some(max)) = (min, max) {
+            if max - min > ProController::MAX_REST_SPREAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "sticks moved during calibration",
+                ));
+            }
+        }
+        let sum: i64 = positions.map(i64::from).sum();
+        axis.center = (sum / samples.len().max(1) as i64) as i32;
+        // Keep the center within the range.
+        axis.min = axis.min.min(axis.center - 1);
+        axis.max = axis.max.max(axis.center + 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_centers, AxisCalibration, CorrectionConfig, StickCalibration, StickCorrector};
+    use crate::event::{Event, EventKind};
+    use crate::profile::Profile;
+    use crate::Result;
+    use std::time::SystemTime;
+
+    fn moved(raw: [i32; 4]) -> Event {
+        Event::at(
+            SystemTime::UNIX_EPOCH,
+            EventKind::ProControllerMove {
+                left_x: raw[0],
+                left_y: raw[1],
+                right_x: raw[2],
+                right_y: raw[3],
+            },
+        )
+    }
+
+    #[test]
+    fn normalizes_asymmetric_range() {
+        let axis = AxisCalibration {
+            center: 20,
+            min: -980,
+            max: 520,
+        };
+        assert_eq!(axis.normalize(20), 0.0);
+        assert_eq!(axis.normalize(520), 1.0);
+        assert_eq!(axis.normalize(-480), -0.5);
+        assert_eq!(axis.normalize(2000), 1.0);
+    }
+
+    #[test]
+    fn recenters_drifting_stick() {
+        let mut corrector =
+            StickCorrector::new(StickCalibration::default(), CorrectionConfig::default());
+        // The left stick rests off center.
+        let sticks = corrector.update(&moved([60, -30, 0, 0])).unwrap();
+        assert_eq!(sticks.left, [0.0, 0.0]);
+        assert_eq!(corrector.calibration().axes[0].center, 60);
+
+        let sticks = corrector.update(&moved([60 + 0x400, -30, 0, 0])).unwrap();
+        assert_eq!(sticks.left, [1.0, 0.0]);
+        // The travel beyond the kernel range extends it.
+        assert_eq!(corrector.calibration().axes[0].max, 60 + 0x400);
+    }
+
+    #[test]
+    fn measures_centers() {
+        let mut calibration = StickCalibration::default();
+        set_centers(&mut calibration, &[[10, -4, 0, 2], [14, -6, 0, 2]]).unwrap();
+        let centers = calibration.axes.map(|axis| axis.center);
+        assert_eq!(centers, [12, -5, 0, 2]);
+
+        let moving = [[0, 0, 0, 0], [300, 0, 0, 0]];
+        assert!(set_centers(&mut calibration, &moving).is_err());
+    }
+
+    #[test]
+    fn stores_in_profile() -> Result<()> {
+        let mut profile = Profile::default();
+        assert_eq!(StickCalibration::from_profile(&profile)?, None);
+
+        let mut calibration = StickCalibration::default();
+        calibration.axes[3].center = -17;
+        calibration.store(&mut profile);
+        assert_eq!(StickCalibration::from_profile(&profile)?, Some(calibration));
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Persistent per-device configuration.
+//!
+//! A [`Profile`] stores the settings of a Wii Remote so that they
+//! survive reconnections and reboots. Profiles are kept in a
+//! [`ProfileStore`], keyed by the Bluetooth address of the device.
+//! See [`Device::load_profile`] and [`Device::save_profile`].
+use crate::quirks::QuirkOverride;
+use crate::{Device, Led, MotionPlusNormalization, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The settings of a device.
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct Profile {
+    /// The state of each LED light, from [`Led::One`] to [`Led::Four`].
+    pub leds: [bool; 4],
+    /// The Motion Plus sensor normalization values.
+    pub mp_normalization: MotionPlusNormalization,
+    /// Additional settings, such as calibration data, dead zones or
+    /// the name of a key-mapping profile.
+    ///
+    /// Keys and values cannot contain line breaks, nor start or end
+    /// with whitespace, and keys cannot contain `=`. Saving a profile
+    /// that breaks these rules fails.
+    pub values: BTreeMap<String, String>,
+}
+
+impl Profile {
+    /// Reads the current settings of the device. The additional
+    /// values are left empty.
+    pub fn capture(device: &Device) -> Result<Self> {
+        let mut leds = [false; 4];
+        for (ix, light) in [Led::One, Led::Two, Led::Three, Led::Four]
+            .into_iter()
+            .enumerate()
+        {
+            leds[ix] = device.led(light)?;
+        }
+        Ok(Self {
+            leds,
+            mp_normalization: device.mp_normalization(),
+            values: BTreeMap::new(),
+        })
+    }
+
+    /// Applies the settings to the device, including the
+    /// [`QuirkOverride`] stored in the additional values, if any.
+    pub fn apply(&self, device: &mut Device) -> Result<()> {
+        if let Some(quirk_override) = QuirkOverride::from_profile(self)? {
+            device.set_quirks(quirk_override.apply(device.quirks()));
+        }
+        for (light, &enabled) in [Led::One, Led::Two, Led::Three, Led::Four]
+            .into_iter()
+            .zip(&self.leds)
+        {
+            device.set_led(light, enabled)?;
+        }
+        device.set_mp_normalization(&self.mp_normalization);
+        Ok(())
+    }
+
+    /// Parses a profile from its textual representation.
+    fn parse(text: &str) -> Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid profile line: {}", line),
+            )
+        };
+        let numbers = |value: &str, line: &str| -> Result<Vec<i32>> {
+            value
+                .split_whitespace()
+                .map(|num| num.parse().map_err(|_| invalid(line)))
+                .collect()
+        };
+
+        let mut profile = Profile::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid(line))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                "leds" => {
+                    let leds = numbers(value, line)?;
+                    if leds.len() != profile.leds.len() {
+                        return Err(invalid(line));
+                    }
+                    for (led, state) in profile.leds.iter_mut().zip(leds) {
+                        *led = state != 0;
+                    }
+                }
+                "mp_normalization" => match numbers(value, line)?[..] {
+                    [x, y, z, factor] => {
+                        profile.mp_normalization = MotionPlusNormalization { x, y, z, factor }
+                    }
+                    _ => return Err(invalid(line)),
+                },
+                // Ignore settings written by newer versions.
+                _ => {
+                    if let Some(name) = key.strip_prefix("value.") {
+                        profile.values.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Checks that the additional values survive [`Profile::serialize`].
+    fn validate(&self) -> Result<()> {
+        let valid = |text: &str| text.trim() == text && !text.contains(['\n', '\r']);
+        match self
+            .values
+            .iter()
+            .find(|(key, value)| !valid(key) || key.contains('=') || !valid(value))
+        {
+            Some((key, _)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid profile value: {:?}", key),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the textual representation of the profile.
+    fn serialize(&self) -> String {
+        let leds: Vec<_> = self
+            .leds
+            .iter()
+            .map(|&enabled| (enabled as u8).to_string())
+            .collect();
+        let mp = &self.mp_normalization;
+        let mut text = format!(
+            "leds = {}\nmp_normalization = {} {} {} {}\n",
+            leds.join(" "),
+            mp.x,
+            mp.y,
+            mp.z,
+            mp.factor
+        );
+        for (key, value) in &self.values {
+            text.push_str(&format!("value.{} = {}\n", key, value));
+        }
+        text
+    }
+}
+
+/// A directory of [`Profile`]s, keyed by device address.
+#[derive(Clone, Debug)]
+pub struct ProfileStore {
+    dir: PathBuf,
+}
+
+impl ProfileStore {
+    /// Creates a store backed by the given directory.
+    ///
+    /// The directory is created when the first profile is saved.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Opens the store in the user configuration directory, that is
+    /// `$XDG_CONFIG_HOME/xwiimote/profiles` or, if unset,
+    /// `$HOME/.config/xwiimote/profiles`.
+    pub fn user() -> Result<Self> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "no user configuration directory")
+            })?;
+        Ok(Self::new(config_dir.join("xwiimote").join("profiles")))
+    }
+
+    fn path(&self, mac: &str) -> PathBuf {
+        // Colons are valid in file names, but awkward in shells.
+        self.dir.join(format!("{}.conf", mac.replace(':', "-")))
+    }
+
+    /// Reads the profile of the device with the given address, if any.
+    pub fn load(&self, mac: &str) -> Result<Option<Profile>> {
+        match fs::read_to_string(self.path(mac)) {
+            Ok(text) => Profile::parse(&text).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes the profile of the device with the given address.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if an additional
+    /// [value](Profile::values) cannot be stored.
+    pub fn save(&self, mac: &str, profile: &Profile) -> Result<()> {
+        profile.validate()?;
+        fs::create_dir_all(&self.dir)?;
+        // Write atomically, so a crash never leaves a truncated profile.
+        let path = self.path(mac);
+        let tmp_path = path.with_extension("conf.tmp");
+        fs::write(&tmp_path, profile.serialize())?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profile, ProfileStore};
+    use crate::{MotionPlusNormalization, Result};
+    use std::io;
+
+    fn profile() -> Profile {
+        let mut profile = Profile {
+            leds: [true, false, false, true],
+            mp_normalization: MotionPlusNormalization {
+                x: -12,
+                y: 4,
+                z: 0,
+                factor: 50,
+            },
+            ..Default::default()
+        };
+        profile
+            .values
+            .insert("stick.deadzone".to_string(), "0.1".to_string());
+        profile
+    }
+
+    #[test]
+    fn serialize_round_trip() -> Result<()> {
+        let profile = profile();
+        assert_eq!(Profile::parse(&profile.serialize())?, profile);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        assert!(Profile::parse("leds = 1 0").is_err());
+        assert!(Profile::parse("mp_normalization = a b c d").is_err());
+        assert!(Profile::parse("garbage").is_err());
+        assert!(Profile::parse("# comment\nunknown = 3").is_ok());
+    }
+
+    #[test]
+    fn rejects_unstorable_values() {
+        for (key, value) in [
+            ("a=b", "1"),
+            ("name", "two\nlines"),
+            (" key", "1"),
+            ("key", "1 "),
+        ] {
+            let mut profile = profile();
+            profile.values.insert(key.to_string(), value.to_string());
+            assert_eq!(
+                profile.validate().unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        assert!(profile().validate().is_ok());
+    }
+
+    #[test]
+    fn store_round_trip() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("xwiimote-profiles-{}", std::process::id()));
+        let store = ProfileStore::new(&dir);
+        let mac = "00:1f:32:aa:bb:cc";
+
+        assert_eq!(store.load(mac)?, None);
+        store.save(mac, &profile())?;
+        assert_eq!(store.load(mac)?, Some(profile()));
+
+        std::fs::remove_dir_all(dir)
+    }
+}
@@ -0,0 +1,160 @@
+//! Rumble intensity emulation by pulse-width modulation.
+//!
+//! The rumble motor is either on or off. Switching it on for a fraction
+//! of each short period makes it spin slower, which feels weaker. The
+//! pulses are timed and applied by the event loop, so they don't depend
+//! on the application polling.
+use crate::io_blocker::IoBlocker;
+use crate::timer::Timer;
+use crate::{bail_if, sys, Result};
+use std::sync::{Arc, Mutex};
+use std::task::{Wake, Waker};
+use std::time::{Duration, Instant};
+
+/// The interface of a device, used from the event loop thread.
+struct Handle(*mut xwiimote_sys::iface);
+
+// Safety: rumble requests are writes to the core input device, which
+// the kernel serializes. The device stops the pulses before closing
+// the core channel.
+unsafe impl Send for Handle {}
+
+impl Handle {
+    fn rumble(&self, enabled: bool) -> Result<()> {
+        let res_code = unsafe { sys::iface_rumble(self.0, enabled) };
+        bail_if!(res_code != 0);
+        Ok(())
+    }
+}
+
+/// Drives the rumble motor of a device at a partial intensity, until
+/// dropped, which turns the motor off.
+pub(crate) struct RumblePwm {
+    pulses: Arc<Pulses>,
+}
+
+impl RumblePwm {
+    /// Starts pulsing the motor of the device with the given interface,
+    /// whose core channel must be open in writable mode.
+    pub fn start(
+        handle: *mut xwiimote_sys::iface,
+        period: Duration,
+        intensity: f32,
+    ) -> Result<Self> {
+        let pulses = Arc::new(Pulses {
+            state: Mutex::new(State {
+                handle: Handle(handle),
+                period,
+                intensity,
+                on: false,
+                timer: Timer::new(IoBlocker::get().clone()),
+                stopped: false,
+            }),
+        });
+        pulses.step()?;
+        Ok(Self { pulses })
+    }
+
+    /// Changes the intensity, from 0 to 1, from the next pulse on.
+    pub fn set_intensity(&self, intensity: f32) {
+        self.pulses.state.lock().unwrap().intensity = intensity;
+    }
+}
+
+impl Drop for RumblePwm {
+    fn drop(&mut self) {
+        self.pulses.stop();
+    }
+}
+
+/// The pulses of a motor, whose timer wakes them to switch the motor.
+struct Pulses {
+    state: Mutex<State>,
+}
+
+struct State {
+    handle: Handle,
+    period: Duration,
+    intensity: f32,
+    // Whether the motor is on in the current phase of the period.
+    on: bool,
+    // Ends the current phase.
+    timer: Timer,
+    stopped: bool,
+}
+
+impl Pulses {
+    /// Switches the motor for the next phase of the period, and arranges
+    /// for the event loop to switch it again once the phase ends.
+    fn step(self: &Arc<Self>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            return Ok(());
+        }
+        let on = state.period.mul_f32(state.intensity.clamp(0.0, 1.0));
+        let phases = [(true, on), (false, state.period - on)];
+        // Alternate, skipping the empty phases of extreme intensities.
+        let (enabled, duration) = match phases[state.on as usize] {
+            (_, duration) if duration.is_zero() => phases[!state.on as usize],
+            phase => phase,
+        };
+        state.handle.rumble(enabled)?;
+        state.on = enabled;
+        let waker = Waker::from(Arc::clone(self));
+        state.timer.wake_at(Instant::now() + duration, &waker)
+    }
+
+    /// Stops the pulses, and turns the motor off.
+    fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            return;
+        }
+        state.stopped = true;
+        state.timer.cancel();
+        if let Err(err) = state.handle.rumble(false) {
+            log::warn!("failed to stop rumble intensity emulation: {}", err);
+        }
+    }
+}
+
+impl Wake for Pulses {
+    fn wake(self: Arc<Self>) {
+        if let Err(err) = self.step() {
+            log::warn!("rumble intensity emulation failed: {}", err);
+            self.stop();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-harness"))]
+mod tests {
+    use crate::harness::{connect, FakeIface};
+    use crate::{Channels, Result};
+    use std::io;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn pulses_partial_rumble() -> Result<()> {
+        let fake = FakeIface::new(Channels::CORE)?;
+        let mut device = connect(&fake)?;
+        device.set_rumble_period(Duration::from_millis(10));
+        device.set_rumble_intensity(0.5)?;
+
+        let mut seen = [false; 2];
+        let start = Instant::now();
+        while seen != [true; 2] && start.elapsed() < Duration::from_secs(1) {
+            seen[fake.rumble() as usize] = true;
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(seen, [true; 2]);
+
+        device.set_rumble_intensity(0.0)?;
+        assert!(!fake.rumble());
+
+        let err = device.set_rumble_intensity(f32::NAN).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+//! Python bindings, built with the `python` feature.
+//!
+//! [maturin] builds the `xwiimote` Python module from `pyproject.toml`,
+//! e.g. with `maturin develop --release`. The module exposes monitors,
+//! devices and their events, which are iterated with `for` or, without
+//! blocking the event loop, with `async for` from `asyncio`:
+//!
+//! ```python
+//! import asyncio
+//! import xwiimote
+//!
+//! async def main():
+//!     path = next(xwiimote.Monitor())
+//!     device = xwiimote.Device(path)
+//!     device.open(xwiimote.CORE | xwiimote.ACCELEROMETER)
+//!     async for event in device.events():
+//!         print(event)
+//!         if event.key == "Home":
+//!             break
+//!
+//! asyncio.run(main())
+//! ```
+//!
+//! The channels are the module constants named after [`Channels`], and
+//! errors are raised as `OSError`.
+//!
+//! [maturin]: https://www.maturin.rs
+use crate::event::{EventKind, KeyState};
+use crate::{Address, Channels, Led};
+use pyo3::exceptions::{PyStopAsyncIteration, PyValueError};
+use pyo3::prelude::*;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// Enumerates the sysfs paths of the connected devices, and optionally
+/// of the devices connected later on.
+#[pyclass(unsendable, module = "xwiimote")]
+struct Monitor {
+    inner: crate::Monitor,
+}
+
+#[pymethods]
+impl Monitor {
+    #[new]
+    #[pyo3(signature = (discover = false))]
+    fn new(discover: bool) -> PyResult<Self> {
+        Ok(Self {
+            inner: crate::Monitor::new(discover)?,
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Returns the path of the next device, without blocking. Iteration
+    /// ends once no device is available.
+    fn __next__(&mut self) -> PyResult<Option<PathBuf>> {
+        Ok(self.inner.next_address()?.map(|Address(syspath)| syspath))
+    }
+}
+
+/// A connected device.
+#[pyclass(unsendable, module = "xwiimote")]
+struct Device {
+    inner: crate::Device,
+}
+
+#[pymethods]
+impl Device {
+    #[new]
+    fn new(path: PathBuf) -> PyResult<Self> {
+        Ok(Self {
+            inner: crate::Device::connect(&Address::from(path))?,
+        })
+    }
+
+    /// Opens the given channels, in writable mode if `writable` is set.
+    #[pyo3(signature = (channels, writable = false))]
+    fn open(&mut self, channels: u32, writable: bool) -> PyResult<()> {
+        Ok(self.inner.open(channels_from(channels)?, writable)?)
+    }
+
+    /// Closes the given channels.
+    fn close(&mut self, channels: u32) -> PyResult<()> {
+        Ok(self.inner.close(channels_from(channels)?)?)
+    }
+
+    /// Returns the open channels.
+    fn opened(&self) -> u32 {
+        self.inner.all_open().bits()
+    }
+
+    /// Returns the device type identifier, e.g. `gen20`.
+    fn kind(&self) -> PyResult<String> {
+        Ok(self.inner.kind()?)
+    }
+
+    /// Returns the extension type identifier, e.g. `nunchuk`.
+    fn extension(&self) -> PyResult<String> {
+        Ok(self.inner.extension()?)
+    }
+
+    /// Returns the battery level, in percent.
+    fn battery(&self) -> PyResult<u8> {
+        Ok(self.inner.battery()?)
+    }
+
+    /// Turns the LED light with the given number, from 1 to 4, on or off.
+    fn set_led(&self, light: u8, enabled: bool) -> PyResult<()> {
+        let light = match light {
+            1 => Led::One,
+            2 => Led::Two,
+            3 => Led::Three,
+            4 => Led::Four,
+            _ => return Err(PyValueError::new_err("the light must be 1 to 4")),
+        };
+        Ok(self.inner.set_led(light, enabled)?)
+    }
+
+    /// Sets the rumble intensity, from 0 (off) to 1 (full speed).
+    fn rumble(&mut self, intensity: f32) -> PyResult<()> {
+        Ok(self.inner.set_rumble_intensity(intensity)?)
+    }
+
+    /// Returns the file descriptor that is readable once events are
+    /// available, e.g. for `select`.
+    fn fileno(&self) -> RawFd {
+        self.inner.fd()
+    }
+
+    /// Reads the next event without blocking, or returns `None` if no
+    /// event is available.
+    fn try_next_event(&mut self) -> PyResult<Option<Event>> {
+        Ok(self.inner.try_next_event()?.map(Event::from))
+    }
+
+    /// Returns an iterator over the events of the device, which ends
+    /// once the device is disconnected.
+    fn events(slf: Bound<'_, Self>) -> EventIterator {
+        EventIterator {
+            device: slf.unbind(),
+            done: false,
+        }
+    }
+}
+
+fn channels_from(bits: u32) -> PyResult<Channels> {
+    Channels::from_bits(bits).ok_or_else(|| PyValueError::new_err("unknown channels"))
+}
+
+/// Iterates over the events of a device, synchronously or with
+/// `async for`.
+#[pyclass(unsendable, module = "xwiimote")]
+struct EventIterator {
+    device: Py<Device>,
+    // Whether the device was disconnected.
+    done: bool,
+}
+
+impl EventIterator {
+    /// Reads the next event without blocking, and notes disconnections.
+    fn try_next(&mut self, py: Python<'_>) -> PyResult<Option<Event>> {
+        let event = self.device.borrow_mut(py).inner.try_next_event()?;
+        if let Some(crate::event::Event {
+            kind: EventKind::Disconnected,
+            ..
+        }) = event
+        {
+            self.done = true;
+        }
+        Ok(event.map(Event::from))
+    }
+}
+
+#[pymethods]
+impl EventIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Blocks until the next event is available.
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Event>> {
+        if self.done {
+            return Ok(None);
+        }
+        let fd = self.device.borrow(py).inner.fd();
+        loop {
+            if let Some(event) = self.try_next(py)? {
+                return Ok(Some(event));
+            }
+            // Let other Python threads run while waiting.
+            py.allow_threads(|| {
+                let mut poll_fd = libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                unsafe { libc::poll(&mut poll_fd, 1, -1) };
+            });
+            py.check_signals()?;
+        }
+    }
+
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Returns an `asyncio` future of the next event, completed by the
+    /// running event loop once the device is readable.
+    fn __anext__(mut slf: PyRefMut<'_, Self>) -> PyResult<PyObject> {
+        if slf.done {
+            return Err(PyStopAsyncIteration::new_err(()));
+        }
+        let py = slf.py();
+        let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+        let future = event_loop.call_method0("create_future")?;
+        match slf.try_next(py)? {
+            Some(event) => {
+                future.call_method1("set_result", (event,))?;
+            }
+            None => {
+                let fd = slf.device.borrow(py).inner.fd();
+                let ready = slf.into_pyobject(py)?.getattr("_ready")?;
+                event_loop.call_method1("add_reader", (fd, ready, &future))?;
+            }
+        }
+        Ok(future.unbind())
+    }
+
+    /// Completes the future of `__anext__` once an event is read.
+    fn _ready(&mut self, future: Bound<'_, PyAny>) -> PyResult<()> {
+        let py = future.py();
+        let fd = self.device.borrow(py).inner.fd();
+        let done = |future: &Bound<'_, PyAny>| -> PyResult<()> {
+            future
+                .call_method0("get_loop")?
+                .call_method1("remove_reader", (fd,))?;
+            Ok(())
+        };
+        // The awaiting task was cancelled.
+        if future.call_method0("done")?.is_truthy()? {
+            return done(&future);
+        }
+        match self.try_next(py) {
+            Ok(Some(event)) => {
+                done(&future)?;
+                future.call_method1("set_result", (event,))?;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                done(&future)?;
+                future.call_method1("set_exception", (err.into_value(py),))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An event received from a device.
+#[pyclass(frozen, get_all, module = "xwiimote")]
+struct Event {
+    /// The time of the event, in seconds since the Unix epoch.
+    time: f64,
+    /// The short uppercase name of the kind, e.g. `KEY` or `ACC`.
+    kind: &'static str,
+    /// The name of the key, for key events, e.g. `A`.
+    key: Option<String>,
+    /// The state of the key, for key events: `Up`, `Down` or
+    /// `AutoRepeat`.
+    state: Option<String>,
+    /// The values of accelerometer, Motion Plus and Balance Board events.
+    values: Vec<i32>,
+    /// The positions of the IR sources, for IR events.
+    ir: Option<Vec<Option<(i32, i32)>>>,
+    // The event, as formatted by `Display`.
+    text: String,
+}
+
+#[pymethods]
+impl Event {
+    fn __str__(&self) -> &str {
+        &self.text
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<xwiimote.Event {}>", self.text)
+    }
+}
+
+impl From<crate::event::Event> for Event {
+    fn from(event: crate::event::Event) -> Self {
+        fn key<K: std::fmt::Debug>(key: K, state: KeyState) -> Option<(String, String)> {
+            Some((format!("{:?}", key), format!("{:?}", state)))
+        }
+        let key = match event.kind {
+            EventKind::Key(k, state) => key(k, state),
+            EventKind::ProControllerKey(k, state) => key(k, state),
+            EventKind::ClassicControllerKey(k, state) => key(k, state),
+            EventKind::NunchukKey(k, state) => key(k, state),
+            EventKind::DrumsKey(k, state) => key(k, state),
+            EventKind::GuitarKey(k, state) => key(k, state),
+            _ => None,
+        };
+        let values = match event.kind {
+            EventKind::Accelerometer { x, y, z } | EventKind::MotionPlus { x, y, z } => {
+                vec![x, y, z]
+            }
+            EventKind::BalanceBoard(weights) => weights.to_vec(),
+            _ => Vec::new(),
+        };
+        let ir = match event.kind {
+            EventKind::Ir(sources) => Some(
+                sources
+                    .iter()
+                    .map(|source| source.map(|source| (source.x, source.y)))
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let (key, state) = key.unzip();
+        Self {
+            time: event
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            kind: event.kind.tag(),
+            key,
+            state,
+            values,
+            ir,
+            text: event.to_string(),
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "xwiimote")]
+pub(crate) fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Monitor>()?;
+    m.add_class::<Device>()?;
+    m.add_class::<EventIterator>()?;
+    m.add_class::<Event>()?;
+    for bit in 0..u32::BITS {
+        let channel = Channels::from_bits_truncate(1 << bit);
+        if !channel.is_empty() {
+            m.add(format!("{:?}", channel), channel.bits())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "test-harness"))]
+mod tests {
+    use crate::event::{Key, KeyState};
+    use crate::harness::{FakeEvent, FakeIface};
+    use crate::{Channels, Result};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn iterates_events_from_python() -> Result<()> {
+        use pyo3::ffi::c_str;
+        use pyo3::prelude::*;
+        use pyo3::types::PyDict;
+
+        let fake = FakeIface::new(Channels::CORE)?;
+        pyo3::prepare_freethreaded_python();
+        let kinds: Vec<String> = thread::scope(|scope| {
+            scope.spawn(|| {
+                // Arrives while the event loop waits for the device.
+                thread::sleep(Duration::from_millis(50));
+                fake.push(FakeEvent::key(Key::A, KeyState::Down));
+                fake.push(FakeEvent::gone());
+            });
+            Python::with_gil(|py| -> PyResult<_> {
+                let module = PyModule::new(py, "xwiimote")?;
+                crate::python::init(&module)?;
+                let globals = PyDict::new(py);
+                globals.set_item("xwiimote", module)?;
+                globals.set_item("path", &fake.address().0)?;
+                py.run(
+                    c_str!(
+                        "import asyncio
+device = xwiimote.Device(path)
+device.open(xwiimote.CORE)
+async def read():
+    return [event.kind async for event in device.events()]
+kinds = asyncio.run(read())"
+                    ),
+                    Some(&globals),
+                    None,
+                )?;
+                globals.get_item("kinds")?.unwrap().extract()
+            })
+        })
+        .unwrap();
+        assert_eq!(kinds, ["KEY", "DISCONNECTED"]);
+        Ok(())
+    }
+}
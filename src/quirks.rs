@@ -0,0 +1,464 @@
+//! Known quirks of remote models and kernel drivers.
+//!
+//! Remotes differ in their hardware: the Balance Board has no IR camera
+//! nor rumble motor, the RVL-CNT-01-TR has a built-in Motion Plus, and
+//! third-party remotes often need more time to initialize extensions.
+//! Older kernel drivers also lack support for some of them. A [`Device`]
+//! detects its [`Quirks`] when connected, and the rest of the library
+//! consults them, e.g. [`Device::open`] fails to open the IR camera of
+//! a remote without one. A [`QuirkOverride`] corrects wrong detections.
+//!
+//! Clones that misbehave further need
+//! [`ConnectOptions::clone_friendly`](crate::ConnectOptions::clone_friendly);
+//! [`CloneHeuristics`] tells whether a device is likely one of them.
+use crate::calibration::AccelCalibration;
+use crate::extension::ExtensionId;
+use crate::profile::Profile;
+use crate::{Device, Result};
+use bitflags::bitflags;
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+
+bitflags! {
+    /// The quirks of a device.
+    pub struct Quirks: u32 {
+        /// The device has no usable IR camera.
+        const NO_IR = 0x1;
+        /// The device has no rumble motor.
+        const NO_RUMBLE = 0x2;
+        /// The device has a built-in Motion Plus, which the kernel
+        /// reports as a regular extension.
+        const BUILTIN_MOTION_PLUS = 0x4;
+        /// The kernel driver doesn't support the Motion Plus.
+        const NO_MOTION_PLUS_DRIVER = 0x8;
+        /// Extensions take longer to initialize, and opening their
+        /// channels may fail the first times.
+        const SLOW_EXTENSION_INIT = 0x10;
+    }
+}
+
+impl Quirks {
+    /// The names of the quirks in profiles.
+    const NAMES: [(Quirks, &'static str); 5] = [
+        (Quirks::NO_IR, "no_ir"),
+        (Quirks::NO_RUMBLE, "no_rumble"),
+        (Quirks::BUILTIN_MOTION_PLUS, "builtin_motion_plus"),
+        (Quirks::NO_MOTION_PLUS_DRIVER, "no_motion_plus_driver"),
+        (Quirks::SLOW_EXTENSION_INIT, "slow_extension_init"),
+    ];
+
+    /// Returns the known quirks of the model with the given kernel.
+    pub fn of(model: RemoteModel, kernel: Option<KernelVersion>) -> Self {
+        let mut quirks = match model {
+            RemoteModel::Original | RemoteModel::Unknown => Quirks::empty(),
+            RemoteModel::Tr => Quirks::BUILTIN_MOTION_PLUS,
+            RemoteModel::BalanceBoard => Quirks::NO_IR | Quirks::NO_RUMBLE,
+            RemoteModel::ProController => Quirks::NO_IR,
+            RemoteModel::ThirdParty => Quirks::SLOW_EXTENSION_INIT,
+        };
+        // The Motion Plus is supported since the driver rework in 3.11.
+        if kernel.is_some_and(|kernel| kernel < KernelVersion::new(3, 11, 0)) {
+            quirks |= Quirks::NO_MOTION_PLUS_DRIVER;
+        }
+        quirks
+    }
+
+    /// Returns the known quirks of the extension with the given
+    /// identifier: copies of known models take longer to initialize.
+    pub fn of_extension(id: &ExtensionId) -> Self {
+        if id.is_likely_knockoff() {
+            Quirks::SLOW_EXTENSION_INIT
+        } else {
+            Quirks::empty()
+        }
+    }
+
+    /// Detects the model of the device and the running kernel, and
+    /// returns their quirks.
+    ///
+    /// Remotes identified by the kernel as a Wii Remote Plus, with the
+    /// `gen20` [device type](Device::kind), have a built-in Motion Plus
+    /// whatever their name, e.g. clones of the RVL-CNT-01-TR.
+    ///
+    /// Unlike [`Device::quirks`], overrides are not applied.
+    pub fn detect(device: &Device) -> Result<Self> {
+        let model = RemoteModel::detect(device)?;
+        let mut quirks = Self::of(model, KernelVersion::current().ok());
+        if device.kind().is_ok_and(|kind| kind == "gen20") {
+            quirks |= Quirks::BUILTIN_MOTION_PLUS;
+        }
+        Ok(quirks)
+    }
+}
+
+/// The hardware features of a device, as far as its [`Quirks`] tell.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Capabilities {
+    /// The device has a usable IR camera.
+    pub ir: bool,
+    /// The device has a rumble motor.
+    pub rumble: bool,
+    /// The kernel driver supports the Motion Plus, built-in or not.
+    pub motion_plus: bool,
+    /// The device has a built-in Motion Plus, like the Wii Remote Plus
+    /// (RVL-CNT-01-TR). It reports through [`Channels::MOTION_PLUS`]
+    /// like an external one, and extensions plugged into the remote are
+    /// passed through it; see the [`motion`](crate::motion) module.
+    ///
+    /// [`Channels::MOTION_PLUS`]: crate::Channels::MOTION_PLUS
+    pub builtin_motion_plus: bool,
+}
+
+impl From<Quirks> for Capabilities {
+    fn from(quirks: Quirks) -> Self {
+        Self {
+            ir: !quirks.contains(Quirks::NO_IR),
+            rumble: !quirks.contains(Quirks::NO_RUMBLE),
+            motion_plus: !quirks.contains(Quirks::NO_MOTION_PLUS_DRIVER),
+            builtin_motion_plus: quirks.contains(Quirks::BUILTIN_MOTION_PLUS),
+        }
+    }
+}
+
+/// The model of a device, as identified by its HID name and ids.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum RemoteModel {
+    /// An original Wii Remote (RVL-CNT-01).
+    Original,
+    /// A Wii Remote Plus (RVL-CNT-01-TR), with a built-in Motion Plus.
+    Tr,
+    /// A Wii Balance Board (RVL-WBC-01).
+    BalanceBoard,
+    /// A Wii U Pro Controller (RVL-CNT-01-UC).
+    ProController,
+    /// A remote not made by Nintendo.
+    ThirdParty,
+    /// A device whose model could not be identified.
+    Unknown,
+}
+
+impl RemoteModel {
+    /// The Nintendo USB vendor id, also used over Bluetooth.
+    const NINTENDO_VENDOR: u32 = 0x057e;
+
+    /// Identifies the model from the HID name and vendor id, as found
+    /// in the `HID_NAME` and `HID_ID` values of the `uevent` file of
+    /// the device.
+    pub fn identify(name: &str, vendor: u32) -> Self {
+        if vendor != Self::NINTENDO_VENDOR {
+            return RemoteModel::ThirdParty;
+        }
+        match name {
+            "Nintendo RVL-CNT-01" => RemoteModel::Original,
+            "Nintendo RVL-CNT-01-TR" => RemoteModel::Tr,
+            "Nintendo RVL-WBC-01" => RemoteModel::BalanceBoard,
+            "Nintendo RVL-CNT-01-UC" => RemoteModel::ProController,
+            // Clones often reuse the vendor id, but not the exact name.
+            _ if name.starts_with("Nintendo RVL-") => RemoteModel::Unknown,
+            _ => RemoteModel::ThirdParty,
+        }
+    }
+
+    /// Identifies the model of the device.
+    pub fn detect(device: &Device) -> Result<Self> {
+        let uevent = fs::read_to_string(device.syspath().join("uevent"))?;
+        let (name, vendor) = parse_uevent(&uevent)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing HID name or id"))?;
+        Ok(Self::identify(name, vendor))
+    }
+}
+
+/// The evidence that a device is a third-party clone.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CloneHeuristics {
+    /// The vendor id of the device is not Nintendo's.
+    pub foreign_vendor: bool,
+    /// The device uses the Nintendo vendor id, but its name is not the
+    /// name of any Nintendo model.
+    pub unknown_name: bool,
+    /// The accelerometer calibration block is invalid, or `None` if it
+    /// could not be read (e.g. without root privileges).
+    pub invalid_calibration: Option<bool>,
+    /// The plugged extension is a copy of a known model, or `None` if
+    /// no extension is plugged or its identifier could not be read.
+    /// See [`ExtensionId::is_likely_knockoff`].
+    pub knockoff_extension: Option<bool>,
+}
+
+impl CloneHeuristics {
+    /// Gathers the evidence from the given model and result of reading
+    /// the accelerometer calibration.
+    pub fn evaluate(model: RemoteModel, calibration: &Result<AccelCalibration>) -> Self {
+        let invalid_calibration = match calibration {
+            Ok(_) => Some(false),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => Some(true),
+            Err(_) => None,
+        };
+        Self {
+            foreign_vendor: model == RemoteModel::ThirdParty,
+            unknown_name: model == RemoteModel::Unknown,
+            invalid_calibration,
+            knockoff_extension: None,
+        }
+    }
+
+    /// Adds the evidence from the given result of reading the
+    /// identifier of the extension.
+    pub fn with_extension_id(self, id: &Result<ExtensionId>) -> Self {
+        Self {
+            knockoff_extension: id.as_ref().ok().map(ExtensionId::is_likely_knockoff),
+            ..self
+        }
+    }
+
+    /// Gathers the evidence from the device, including its extension.
+    ///
+    /// The calibration is read regardless of
+    /// [`Device::is_clone_friendly`].
+    pub fn detect(device: &Device) -> Result<Self> {
+        let model = RemoteModel::detect(device)?;
+        Ok(
+            Self::evaluate(model, &AccelCalibration::read_eeprom(device))
+                .with_extension_id(&device.extension_id()),
+        )
+    }
+
+    /// Checks whether any evidence points to a clone.
+    pub fn is_likely_clone(&self) -> bool {
+        self.foreign_vendor
+            || self.unknown_name
+            || self.invalid_calibration == Some(true)
+            || self.knockoff_extension == Some(true)
+    }
+}
+
+/// Returns the HID name and vendor id in the contents of a `uevent` file.
+fn parse_uevent(uevent: &str) -> Option<(&str, u32)> {
+    let mut name = None;
+    let mut vendor = None;
+    for line in uevent.lines() {
+        if let Some(value) = line.strip_prefix("HID_NAME=") {
+            name = Some(value);
+        } else if let Some(value) = line.strip_prefix("HID_ID=") {
+            // The bus type, vendor and product ids, e.g. `0005:0000057E:00000306`.
+            let vendor_id = value.split(':').nth(1)?;
+            vendor = u32::from_str_radix(vendor_id, 16).ok();
+        }
+    }
+    Some((name?, vendor?))
+}
+
+/// The version of a Linux kernel.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct KernelVersion {
+    /// The major version.
+    pub major: u32,
+    /// The minor version.
+    pub minor: u32,
+    /// The patch level.
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    /// Creates a version.
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses a kernel release, e.g. `6.1.0-18-amd64`.
+    pub fn parse(release: &str) -> Option<Self> {
+        let mut numbers = release.split('.').map(|part| {
+            let digits = part
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(part.len());
+            part[..digits].parse().ok()
+        });
+        let major = numbers.next()??;
+        let minor = numbers.next()??;
+        let patch = numbers.next().flatten().unwrap_or(0);
+        Some(Self::new(major, minor, patch))
+    }
+
+    /// Returns the version of the running kernel.
+    pub fn current() -> Result<Self> {
+        let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+        let res_code = unsafe { libc::uname(&mut name) };
+        crate::bail_if!(res_code == -1);
+        let release = unsafe { CStr::from_ptr(name.release.as_ptr()) };
+        Self::parse(&release.to_string_lossy())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid kernel release"))
+    }
+}
+
+/// Corrects the detected quirks of a device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct QuirkOverride {
+    /// The quirks to add.
+    pub add: Quirks,
+    /// The quirks to remove.
+    pub remove: Quirks,
+}
+
+impl QuirkOverride {
+    /// The key of the override in [`Profile::values`].
+    const PROFILE_KEY: &'static str = "quirks";
+
+    /// Applies the override to the given quirks.
+    pub fn apply(&self, quirks: Quirks) -> Quirks {
+        (quirks | self.add) - self.remove
+    }
+
+    /// Reads the override stored in the profile, if any.
+    ///
+    /// The override is a list of quirk names, each prefixed with `+` to
+    /// add it or `-` to remove it, e.g. `-no_ir +slow_extension_init`.
+    pub fn from_profile(profile: &Profile) -> Result<Option<Self>> {
+        let value = match profile.values.get(Self::PROFILE_KEY) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut quirk_override = Self {
+            add: Quirks::empty(),
+            remove: Quirks::empty(),
+        };
+        for item in value.split_whitespace() {
+            let (set, name) = if let Some(name) = item.strip_prefix('+') {
+                (&mut quirk_override.add, name)
+            } else if let Some(name) = item.strip_prefix('-') {
+                (&mut quirk_override.remove, name)
+            } else {
+                return Err(invalid_quirk(item));
+            };
+            let quirk = Quirks::NAMES
+                .iter()
+                .find(|(_, known)| *known == name)
+                .ok_or_else(|| invalid_quirk(item))?;
+            *set |= quirk.0;
+        }
+        Ok(Some(quirk_override))
+    }
+
+    /// Stores the override in the profile.
+    pub fn store(&self, profile: &mut Profile) {
+        let mut items = Vec::new();
+        for (quirk, name) in Quirks::NAMES {
+            if self.add.contains(quirk) {
+                items.push(format!("+{}", name));
+            }
+            if self.remove.contains(quirk) {
+                items.push(format!("-{}", name));
+            }
+        }
+        profile
+            .values
+            .insert(Self::PROFILE_KEY.to_string(), items.join(" "));
+    }
+}
+
+fn invalid_quirk(item: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid quirk override: {}", item),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_uevent, Capabilities, CloneHeuristics, KernelVersion, QuirkOverride, Quirks,
+        RemoteModel,
+    };
+    use crate::calibration::AccelCalibration;
+    use crate::extension::ExtensionId;
+    use crate::profile::Profile;
+    use crate::Result;
+    use std::io;
+
+    #[test]
+    fn identifies_models() {
+        let uevent = "DRIVER=wiimote\nHID_ID=0005:0000057E:00000330\n\
+                      HID_NAME=Nintendo RVL-CNT-01-TR\nHID_UNIQ=00:1f:32:aa:bb:cc\n";
+        let (name, vendor) = parse_uevent(uevent).unwrap();
+        assert_eq!(RemoteModel::identify(name, vendor), RemoteModel::Tr);
+        assert_eq!(
+            RemoteModel::identify("Nintendo RVL-CNT-01", 0x1234),
+            RemoteModel::ThirdParty
+        );
+        assert_eq!(parse_uevent("HID_NAME=x\n"), None);
+    }
+
+    #[test]
+    fn evaluates_clone_evidence() {
+        let genuine = CloneHeuristics::evaluate(RemoteModel::Tr, &Ok(AccelCalibration::NOMINAL));
+        assert!(!genuine.is_likely_clone());
+
+        let unreadable = Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        let unknown = CloneHeuristics::evaluate(RemoteModel::Original, &unreadable);
+        assert_eq!(unknown.invalid_calibration, None);
+        assert!(!unknown.is_likely_clone());
+
+        let mut block = [0; 10];
+        block[9] = 0x54;
+        let invalid =
+            CloneHeuristics::evaluate(RemoteModel::Original, &AccelCalibration::parse(&block));
+        assert!(invalid.is_likely_clone());
+        assert!(CloneHeuristics::evaluate(RemoteModel::Unknown, &unreadable).is_likely_clone());
+
+        let knockoff = ExtensionId([0xff, 0x00, 0xa4, 0x20, 0x00, 0x00]);
+        let with_knockoff = genuine.with_extension_id(&Ok(knockoff));
+        assert_eq!(with_knockoff.knockoff_extension, Some(true));
+        assert!(with_knockoff.is_likely_clone());
+        assert_eq!(Quirks::of_extension(&knockoff), Quirks::SLOW_EXTENSION_INIT);
+    }
+
+    #[test]
+    fn parses_kernel_release() {
+        assert_eq!(
+            KernelVersion::parse("6.1.0-18-amd64"),
+            Some(KernelVersion::new(6, 1, 0))
+        );
+        assert_eq!(
+            KernelVersion::parse("3.10-rc1"),
+            Some(KernelVersion::new(3, 10, 0))
+        );
+        assert_eq!(KernelVersion::parse("linux"), None);
+
+        let old = KernelVersion::parse("3.10.108");
+        let quirks = Quirks::of(RemoteModel::BalanceBoard, old);
+        assert_eq!(
+            quirks,
+            Quirks::NO_IR | Quirks::NO_RUMBLE | Quirks::NO_MOTION_PLUS_DRIVER
+        );
+
+        let capabilities = Capabilities::from(Quirks::of(RemoteModel::Tr, None));
+        assert!(capabilities.builtin_motion_plus && capabilities.motion_plus);
+        assert!(!Capabilities::from(quirks).rumble);
+    }
+
+    #[test]
+    fn overrides_round_trip() -> Result<()> {
+        let mut profile = Profile::default();
+        assert_eq!(QuirkOverride::from_profile(&profile)?, None);
+
+        let quirk_override = QuirkOverride {
+            add: Quirks::SLOW_EXTENSION_INIT,
+            remove: Quirks::NO_IR,
+        };
+        quirk_override.store(&mut profile);
+        assert_eq!(profile.values["quirks"], "-no_ir +slow_extension_init");
+        assert_eq!(QuirkOverride::from_profile(&profile)?, Some(quirk_override));
+        assert_eq!(
+            quirk_override.apply(Quirks::NO_IR | Quirks::NO_RUMBLE),
+            Quirks::NO_RUMBLE | Quirks::SLOW_EXTENSION_INIT
+        );
+
+        profile.values.insert("quirks".into(), "+unknown".into());
+        assert!(QuirkOverride::from_profile(&profile).is_err());
+        Ok(())
+    }
+}
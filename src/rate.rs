@@ -0,0 +1,162 @@
+//! Effective sample rates of the channels of a device.
+//!
+//! The high-rate channels should report about 100 events per second,
+//! but the actual rate depends on the Bluetooth link and on the other
+//! open channels. A [`RateMeter`] measures the rate of each channel
+//! over a sliding window of [`Event`]s; use [`sample_rates`] to adapt
+//! a stream.
+use crate::event::Event;
+use crate::{Channels, Result};
+use futures::{future, Stream, TryStreamExt};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// The parameters of a [`RateMeter`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RateConfig {
+    /// The duration of the window over which the rates are measured.
+    pub window: Duration,
+    /// The time between consecutive reports.
+    pub interval: Duration,
+}
+
+impl Default for RateConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The rates measured by a [`RateMeter`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct RateReport {
+    /// The rate of each channel that reported at least two events in
+    /// the window, in events per second, ordered by channel.
+    pub rates: Vec<(Channels, f32)>,
+    /// The time of the event that completed the report.
+    pub time: SystemTime,
+}
+
+impl RateReport {
+    /// Returns the rate of the given channel, in events per second.
+    pub fn rate(&self, channel: Channels) -> Option<f32> {
+        self.rates
+            .iter()
+            .find(|(reported, _)| *reported == channel)
+            .map(|(_, rate)| *rate)
+    }
+}
+
+/// Measures the rate of events of each channel.
+#[derive(Clone, Default, Debug)]
+pub struct RateMeter {
+    config: RateConfig,
+    times: HashMap<Channels, VecDeque<SystemTime>>,
+    next_report: Option<SystemTime>,
+}
+
+impl RateMeter {
+    /// Creates a meter with the given parameters.
+    pub fn new(config: RateConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Updates the meter with the given event, and returns a report if
+    /// one is due.
+    ///
+    /// Events not reported by a channel are ignored.
+    pub fn update(&mut self, event: &Event) -> Option<RateReport> {
+        let channel = event.kind.channel()?;
+        let times = self.times.entry(channel).or_default();
+        times.push_back(event.time);
+
+        if let Some(start) = event.time.checked_sub(self.config.window) {
+            for times in self.times.values_mut() {
+                while times.front().is_some_and(|&time| time < start) {
+                    times.pop_front();
+                }
+            }
+        }
+
+        let next_report = *self
+            .next_report
+            .get_or_insert_with(|| event.time + self.config.interval);
+        if event.time < next_report {
+            return None;
+        }
+        self.next_report = Some(next_report + self.config.interval);
+        Some(self.report(event.time))
+    }
+
+    /// Returns the current rate of the given channel, in events per
+    /// second, or `None` if it reported less than two events in the
+    /// window.
+    pub fn rate(&self, channel: Channels) -> Option<f32> {
+        let times = self.times.get(&channel)?;
+        let (first, last) = (times.front()?, times.back()?);
+        let span = last.duration_since(*first).ok()?;
+        if span.is_zero() {
+            return None;
+        }
+        Some((times.len() - 1) as f32 / span.as_secs_f32())
+    }
+
+    fn report(&self, time: SystemTime) -> RateReport {
+        let mut rates: Vec<_> = self
+            .times
+            .keys()
+            .filter_map(|&channel| Some((channel, self.rate(channel)?)))
+            .collect();
+        rates.sort_by_key(|(channel, _)| channel.bits());
+        RateReport { rates, time }
+    }
+}
+
+/// Adapts a stream of events into a stream of the reports of `meter`.
+pub fn sample_rates<S>(events: S, mut meter: RateMeter) -> impl Stream<Item = Result<RateReport>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(meter.update(&event))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateConfig, RateMeter};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use crate::Channels;
+    use std::time::{Duration, SystemTime};
+
+    fn at(ms: u64, kind: EventKind) -> Event {
+        Event::at(SystemTime::UNIX_EPOCH + Duration::from_millis(ms), kind)
+    }
+
+    #[test]
+    fn measures_each_channel() {
+        let mut meter = RateMeter::new(RateConfig::default());
+        let mut reports = Vec::new();
+        for i in 0..=300 {
+            let accel = at(
+                10_000 + i * 10,
+                EventKind::Accelerometer { x: 0, y: 0, z: 0 },
+            );
+            reports.extend(meter.update(&accel));
+            if i % 50 == 0 {
+                let key = at(10_000 + i * 10, EventKind::Key(Key::A, KeyState::Down));
+                reports.extend(meter.update(&key));
+            }
+        }
+
+        assert_eq!(reports.len(), 3);
+        let report = &reports[0];
+        assert_eq!(report.rate(Channels::ACCELEROMETER), Some(100.0));
+        assert_eq!(report.rate(Channels::CORE), Some(2.0));
+        assert_eq!(report.rate(Channels::IR), None);
+        assert_eq!(report.rates[0].0, Channels::CORE);
+    }
+}
@@ -0,0 +1,418 @@
+//! Raw HID output reports, sent through the hidraw node of a device.
+//!
+//! The kernel driver doesn't expose every feature of the Wii Remote,
+//! e.g. the speaker, the EEPROM or the registers of the IR camera. A
+//! [`Report`] is an output report with a validated length, built by
+//! the typed constructors of the known reports or from raw bytes, and
+//! sent with [`Device::send_report`]:
+//!
+//! ```no_run
+//! # use xwiimote::report::{IrMode, Report};
+//! # use xwiimote::Device;
+//! # fn enable_ir(device: &Device) -> std::io::Result<()> {
+//! for report in Report::ir_enable_sequence(IrMode::Extended) {
+//!     device.send_report(&report)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The answers of the device, e.g. to [`Report::status_request`], are
+//! input reports read from the hidraw node, see [`Device::hidraw_node`].
+//!
+//! The driver doesn't learn about the reports sent this way: it keeps
+//! configuring the device as it sees fit, e.g. when channels are
+//! opened, which may undo their effects.
+//!
+//! [`Device::send_report`]: crate::Device::send_report
+//! [`Device::hidraw_node`]: crate::Device::hidraw_node
+use crate::{bail_if, Leds, Result};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// The output reports of a Wii Remote, and the length of their payload.
+const OUTPUT_REPORTS: [(u8, usize); 11] = [
+    (0x10, 1),  // rumble
+    (0x11, 1),  // LEDs
+    (0x12, 2),  // data reporting mode
+    (0x13, 1),  // IR camera enable
+    (0x14, 1),  // speaker enable
+    (0x15, 1),  // status request
+    (0x16, 21), // write memory and registers
+    (0x17, 6),  // read memory and registers
+    (0x18, 21), // speaker data
+    (0x19, 1),  // speaker mute
+    (0x1a, 1),  // IR camera enable 2
+];
+
+/// The identifier of the rumble report.
+pub(crate) const RUMBLE_ID: u8 = 0x10;
+
+/// The identifier of the input report answering memory reads.
+const READ_DATA_ID: u8 = 0x21;
+
+/// The largest amount of data written by a single report.
+const MAX_WRITE: usize = 16;
+
+/// An address space of a Wii Remote, accessed by
+/// [`Report::write_memory`] and [`Report::read_memory`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum AddressSpace {
+    /// The EEPROM, which stores e.g. the accelerometer calibration and
+    /// the Mii data.
+    Eeprom,
+    /// The control registers of the peripherals, e.g. the speaker, the
+    /// IR camera and the extension.
+    Registers,
+}
+
+/// The data format of the IR camera, see [`Report::ir_enable_sequence`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum IrMode {
+    /// The positions of the sources, in 10 bytes.
+    Basic = 1,
+    /// The positions and sizes of the sources, in 12 bytes.
+    Extended = 3,
+    /// The positions, sizes and bounding boxes of the sources, in
+    /// 36 bytes split over two reports.
+    Full = 5,
+}
+
+/// An output report, made of its identifier and payload.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Report {
+    // The identifier followed by the payload, as written to hidraw.
+    bytes: Vec<u8>,
+}
+
+impl Report {
+    /// Creates a report with the given identifier and payload.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the identifier is
+    /// not an output report of the Wii Remote, or the payload doesn't
+    /// have the length of the report.
+    pub fn new(id: u8, payload: &[u8]) -> Result<Self> {
+        let len = OUTPUT_REPORTS
+            .iter()
+            .find(|&&(known, _)| known == id)
+            .map(|&(_, len)| len)
+            .ok_or_else(|| invalid_input(format!("unknown output report {:#04x}", id)))?;
+        if payload.len() != len {
+            return Err(invalid_input(format!(
+                "output report {:#04x} takes {} bytes, got {}",
+                id,
+                len,
+                payload.len()
+            )));
+        }
+        let mut bytes = Vec::with_capacity(1 + len);
+        bytes.push(id);
+        bytes.extend_from_slice(payload);
+        Ok(Self { bytes })
+    }
+
+    /// Creates a report of a known length.
+    fn known(id: u8, payload: &[u8]) -> Self {
+        Self::new(id, payload).expect("invalid known report")
+    }
+
+    /// Requests a status report, which contains the battery level and
+    /// whether an extension is plugged.
+    pub fn status_request() -> Self {
+        Self::known(0x15, &[0x00])
+    }
+
+    /// Turns on the given LED lights, and turns off the rest.
+    pub fn leds(leds: Leds) -> Self {
+        Self::known(0x11, &[leds.bits() << 4])
+    }
+
+    /// Toggles the rumble motor.
+    pub fn rumble(enabled: bool) -> Self {
+        Self::known(RUMBLE_ID, &[enabled as u8])
+    }
+
+    /// Enables or disables the IR camera. The camera must be configured
+    /// before reporting data; see [`Report::ir_enable_sequence`].
+    pub fn ir_camera(enabled: bool) -> [Self; 2] {
+        let flags = if enabled { 0x04 } else { 0x00 };
+        [Self::known(0x13, &[flags]), Self::known(0x1a, &[flags])]
+    }
+
+    /// Writes up to 16 bytes of data to the given address.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the data is empty
+    /// or too long, or the address exceeds 24 bits.
+    pub fn write_memory(space: AddressSpace, address: u32, data: &[u8]) -> Result<Self> {
+        if data.is_empty() || data.len() > MAX_WRITE {
+            return Err(invalid_input(format!(
+                "writes take 1 to {} bytes, got {}",
+                MAX_WRITE,
+                data.len()
+            )));
+        }
+        let mut payload = [0; 21];
+        payload[..4].copy_from_slice(&Self::address(space, address)?);
+        payload[4] = data.len() as u8;
+        payload[5..5 + data.len()].copy_from_slice(data);
+        Ok(Self::known(0x16, &payload))
+    }
+
+    /// Reads `size` bytes from the given address. The device answers
+    /// with input reports of 16 bytes each.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the address
+    /// exceeds 24 bits.
+    pub fn read_memory(space: AddressSpace, address: u32, size: u16) -> Result<Self> {
+        let mut payload = [0; 6];
+        payload[..4].copy_from_slice(&Self::address(space, address)?);
+        payload[4..].copy_from_slice(&size.to_be_bytes());
+        Ok(Self::known(0x17, &payload))
+    }
+
+    /// Encodes the address space flags followed by a 24-bit address.
+    fn address(space: AddressSpace, address: u32) -> Result<[u8; 4]> {
+        if address > 0xff_ffff {
+            return Err(invalid_input(format!(
+                "address {:#x} exceeds 24 bits",
+                address
+            )));
+        }
+        let mut bytes = address.to_be_bytes();
+        bytes[0] = match space {
+            AddressSpace::Eeprom => 0x00,
+            AddressSpace::Registers => 0x04,
+        };
+        Ok(bytes)
+    }
+
+    /// Returns the reports that enable and configure the IR camera, in
+    /// the order to send them.
+    ///
+    /// The camera is configured with the highest sensitivity suggested
+    /// by Wiibrew, which suits most sensor bars. Its data is included
+    /// in the input reports once the data reporting mode includes it,
+    /// which the kernel driver selects while the IR channel is open.
+    pub fn ir_enable_sequence(mode: IrMode) -> Vec<Self> {
+        let write = |address, data: &[u8]| {
+            Self::write_memory(AddressSpace::Registers, address, data)
+                .expect("invalid IR register write")
+        };
+        let mut reports = Self::ir_camera(true).to_vec();
+        reports.extend([
+            write(0xb0_0030, &[0x08]),
+            write(
+                0xb0_0000,
+                &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x90, 0x00, 0x41],
+            ),
+            write(0xb0_001a, &[0x40, 0x00]),
+            write(0xb0_0033, &[mode as u8]),
+            write(0xb0_0030, &[0x08]),
+        ]);
+        reports
+    }
+
+    /// Returns the report identifier.
+    pub fn id(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// Returns the payload, without the identifier.
+    pub fn payload(&self) -> &[u8] {
+        &self.bytes[1..]
+    }
+
+    /// Returns whether the report turns the rumble motor on, which
+    /// every output report does if the lowest bit of its payload is set.
+    pub fn rumble_bit(&self) -> bool {
+        self.bytes[1] & 0x01 != 0
+    }
+
+    /// Sets the rumble bit of the report; see [`Report::rumble_bit`].
+    pub fn with_rumble_bit(mut self, enabled: bool) -> Self {
+        self.bytes[1] = (self.bytes[1] & !0x01) | enabled as u8;
+        self
+    }
+
+    /// Writes the report to a hidraw node.
+    pub(crate) fn write_to(&self, out: &mut impl Write) -> Result<()> {
+        out.write_all(&self.bytes)
+    }
+}
+
+/// Collects the data answering a [`Report::read_memory`] of `size` bytes
+/// at `address` from the input reports of a hidraw node, which must be
+/// in non-blocking mode.
+///
+/// Fails with [`io::ErrorKind::NotFound`] if the address can't be read,
+/// e.g. because no extension is plugged, and with
+/// [`io::ErrorKind::TimedOut`] if the answer doesn't arrive in time.
+pub(crate) fn read_answer(
+    node: &mut (impl Read + AsRawFd),
+    address: u32,
+    size: u16,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut data = Vec::with_capacity(size.into());
+    let mut report = [0; 22];
+    while data.len() < size.into() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the device didn't answer the memory read",
+            ));
+        }
+        let mut pollfd = libc::pollfd {
+            fd: node.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let res_code = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as _) };
+        bail_if!(res_code == -1);
+        let len = match node.read(&mut report) {
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        };
+        // The core keys, the size and error, and the low bytes of the
+        // address are followed by up to 16 bytes of data.
+        if len < 6 || report[0] != READ_DATA_ID {
+            continue;
+        }
+        let offset = u16::from_be_bytes([report[4], report[5]]);
+        if offset != (address as u16).wrapping_add(data.len() as u16) {
+            continue; // the answer to another read
+        }
+        match report[3] & 0x0f {
+            0 => {}
+            7 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "the memory is write-only or not connected",
+                ))
+            }
+            8 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "the memory address doesn't exist",
+                ))
+            }
+            code => return Err(io::Error::other(format!("memory read error {}", code))),
+        }
+        let chunk = (report[3] >> 4) as usize + 1;
+        data.extend_from_slice(&report[6..len.min(6 + chunk)]);
+    }
+    data.truncate(size.into());
+    Ok(data)
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressSpace, IrMode, Report};
+    use crate::{Leds, Result};
+    use std::io;
+
+    #[test]
+    fn builds_known_reports() -> Result<()> {
+        assert_eq!(Report::status_request().payload(), [0x00]);
+        let leds = Report::leds(Leds::ONE | Leds::FOUR);
+        assert_eq!((leds.id(), leds.payload()), (0x11, &[0x90][..]));
+        assert!(Report::rumble(true).rumble_bit());
+        assert_eq!(leds.with_rumble_bit(true).payload(), [0x91]);
+
+        let write = Report::write_memory(AddressSpace::Registers, 0xa4_00f0, &[0x55])?;
+        assert_eq!(write.id(), 0x16);
+        assert_eq!(write.payload()[..6], [0x04, 0xa4, 0x00, 0xf0, 0x01, 0x55]);
+        let read = Report::read_memory(AddressSpace::Eeprom, 0x0016, 10)?;
+        assert_eq!(read.payload(), [0x00, 0x00, 0x00, 0x16, 0x00, 0x0a]);
+
+        let sequence = Report::ir_enable_sequence(IrMode::Full);
+        assert_eq!(sequence.len(), 7);
+        assert_eq!(
+            sequence[5].payload()[..6],
+            [0x04, 0xb0, 0x00, 0x33, 0x01, 0x05]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_reports() {
+        let kind = |res: Result<Report>| res.unwrap_err().kind();
+        assert_eq!(
+            kind(Report::new(0x20, &[0x00])),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            kind(Report::new(0x12, &[0x00])),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(Report::new(0x12, &[0x00, 0x33]).is_ok());
+        let space = AddressSpace::Registers;
+        assert_eq!(
+            kind(Report::write_memory(space, 0, &[0; 17])),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            kind(Report::write_memory(space, 0, &[])),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            kind(Report::read_memory(space, 1 << 24, 1)),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn reads_memory_answers() -> Result<()> {
+        use super::read_answer;
+        use crate::bail_if;
+        use std::fs::File;
+        use std::io::{Read, Write};
+        use std::os::unix::io::FromRawFd;
+        use std::time::Duration;
+
+        // Sequenced packets keep the boundaries of the reports, like hidraw.
+        let mut fds = [0; 2];
+        let flags = libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let res_code = unsafe { libc::socketpair(libc::AF_UNIX, flags, 0, fds.as_mut_ptr()) };
+        bail_if!(res_code == -1);
+        let (mut node, mut device) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        Report::status_request()
+            .with_rumble_bit(true)
+            .write_to(&mut node)?;
+        let mut request = [0; 8];
+        assert_eq!(device.read(&mut request)?, 2);
+        assert_eq!(request[..2], [0x15, 0x01]);
+
+        let answer = |size: u8, error: u8, offset: u16, data: &[u8]| {
+            let mut report = vec![0x21, 0x00, 0x00, (size - 1) << 4 | error];
+            report.extend(offset.to_be_bytes());
+            report.extend(data);
+            report.resize(22, 0);
+            report
+        };
+        // A status report, and the answer to an unrelated read.
+        device.write_all(&[0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64])?;
+        device.write_all(&answer(2, 0, 0x0016, &[1, 2]))?;
+        device.write_all(&answer(16, 0, 0x00f0, &[0xaa; 16]))?;
+        device.write_all(&answer(4, 0, 0x0100, &[0xbb; 4]))?;
+        let data = read_answer(&mut node, 0xa4_00f0, 20, Duration::from_secs(1))?;
+        assert_eq!(data, [[0xaa; 16], [0xbb; 16]].concat()[..20]);
+
+        device.write_all(&answer(6, 7, 0x00fa, &[]))?;
+        let err = read_answer(&mut node, 0xa4_00fa, 6, Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = read_answer(&mut node, 0xa4_00fa, 6, Duration::from_millis(10)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! Event loops driving the streams of monitors and devices.
+//!
+//! By default, all [`Monitor`](crate::Monitor)s and
+//! [event streams](crate::Device::events) share a global event loop,
+//! which runs on a dedicated thread for the lifetime of the process.
+//! A [`Runtime`] provides an isolated event loop instead, whose thread
+//! can be configured and which can be shut down, e.g. by libraries
+//! embedding this crate.
+//!
+//! Runtimes use edge-triggered notifications by default, which require
+//! streams to consume all available data before returning
+//! [`Poll::Pending`](std::task::Poll::Pending). The streams of this
+//! crate do so, but a [`Trigger::Level`] runtime is more forgiving to
+//! custom stream adapters driving the underlying files. A runtime only
+//! registers the files of the monitors and streams of this crate; the
+//! events of interest on them can't be changed from the outside.
+use crate::io_blocker::IoBlocker;
+use crate::Result;
+use std::io;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// How the event loop is notified of available data.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Trigger {
+    /// Notify once when new data arrives. Waiting futures must read all
+    /// available data when woken up, or they may block indefinitely.
+    #[default]
+    Edge,
+    /// Notify while data is available. Waiting futures are woken up
+    /// again if they didn't read all available data, at the cost of an
+    /// additional system call per wait.
+    Level,
+}
+
+/// An event loop running on a dedicated thread.
+///
+/// Dropping the runtime shuts it down. Streams created from a runtime
+/// stop receiving events once it is shut down, and should be dropped
+/// beforehand.
+pub struct Runtime {
+    blocker: Arc<IoBlocker>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Runtime {
+    /// Creates a runtime, spawning its event loop thread.
+    pub fn new() -> Result<Self> {
+        Self::with_trigger(Trigger::Edge)
+    }
+
+    /// Creates a runtime with the given notification mode, spawning
+    /// its event loop thread.
+    pub fn with_trigger(trigger: Trigger) -> Result<Self> {
+        let builder = thread::Builder::new().name("xwiimote-runtime".to_string());
+        Self::with_thread(builder, trigger, || {})
+    }
+
+    /// Creates a runtime with the given notification mode, whose event
+    /// loop thread is spawned by the given builder.
+    ///
+    /// The `on_start` function is called on the new thread before
+    /// running the event loop, e.g. to set its CPU affinity or priority.
+    pub fn with_thread<F>(builder: thread::Builder, trigger: Trigger, on_start: F) -> Result<Self>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let blocker = IoBlocker::new(trigger)?;
+        let loop_blocker = Arc::clone(&blocker);
+        let thread = builder.spawn(move || {
+            on_start();
+            loop_blocker.run()
+        })?;
+
+        Ok(Self {
+            blocker,
+            thread: Some(thread),
+        })
+    }
+
+    pub(crate) fn blocker(&self) -> &Arc<IoBlocker> {
+        &self.blocker
+    }
+
+    /// Stops the event loop and waits for its thread to exit.
+    ///
+    /// Returns the error that caused the event loop to fail, if any.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => {
+                self.blocker.shutdown()?;
+                thread
+                    .join()
+                    .map_err(|_| io::Error::other("event loop panicked"))?
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        // Errors can only be reported by `shutdown`.
+        let _ = self.stop();
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::{Runtime, Trigger};
+    use crate::Result;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn shutdown_joins_thread() -> Result<()> {
+        let started = Arc::new(AtomicBool::new(false));
+        let thread_started = Arc::clone(&started);
+        let runtime = Runtime::with_thread(thread::Builder::new(), Trigger::Level, move || {
+            thread_started.store(true, Ordering::Relaxed)
+        })?;
+
+        runtime.shutdown()?;
+        assert!(started.load(Ordering::Relaxed));
+        Ok(())
+    }
+
+    #[test]
+    fn runtimes_are_isolated() -> Result<()> {
+        // Registering the same file in two runtimes doesn't conflict.
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        assert_ne!(fd, -1);
+        let (first, second) = (Runtime::new()?, Runtime::new()?);
+        first.blocker().add_interest(fd, libc::EPOLLIN)?;
+        second.blocker().add_interest(fd, libc::EPOLLIN)?;
+
+        unsafe { libc::close(fd) };
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! System setup for access to the devices.
+//!
+//! By default only root can open the device nodes of a remote, so most
+//! first attempts fail with a permission error. The udev rules returned
+//! by [`generate_udev_rules`] grant the members of the `input` group
+//! access to the input, `hidraw` and LED nodes of the remotes, and to
+//! `/dev/uinput` for the `emulation` module.
+//! [`install_udev_rules`] installs them, and [`verify_setup`] diagnoses
+//! the common misconfigurations.
+use crate::{Address, Monitor, Result};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The group granted access to the devices by the generated rules.
+pub const GROUP: &str = "input";
+
+/// The path the rules are installed at by [`install_udev_rules`].
+pub const UDEV_RULES_PATH: &str = "/etc/udev/rules.d/70-xwiimote.rules";
+
+/// The HID bus and vendor of the remotes, as they appear in the name
+/// of their HID device, e.g. `0005:057E:0306.0001`.
+const HID_PREFIX: &str = "0005:057E:";
+
+/// Returns the udev rules granting the members of [`GROUP`] access to
+/// the devices.
+pub fn generate_udev_rules() -> String {
+    format!(
+        "\
+# Access to Nintendo Wii Remotes and peripherals driven by hid-wiimote,
+# for the members of the `{group}` group.
+SUBSYSTEM==\"input\", KERNEL==\"event*\", ATTRS{{name}}==\"Nintendo Wii Remote*\", \
+MODE=\"0660\", GROUP=\"{group}\"
+SUBSYSTEM==\"hidraw\", KERNELS==\"{hid}*\", MODE=\"0660\", GROUP=\"{group}\"
+SUBSYSTEM==\"leds\", KERNEL==\"{hid}*\", \
+RUN+=\"/bin/chgrp {group} /sys%p/brightness\", RUN+=\"/bin/chmod g+w /sys%p/brightness\"
+KERNEL==\"uinput\", SUBSYSTEM==\"misc\", MODE=\"0660\", GROUP=\"{group}\", \
+OPTIONS+=\"static_node=uinput\"
+",
+        group = GROUP,
+        hid = HID_PREFIX
+    )
+}
+
+/// Installs the rules of [`generate_udev_rules`] at [`UDEV_RULES_PATH`],
+/// and applies them to the connected devices.
+///
+/// This changes the configuration of the system and requires root, so
+/// applications should only call it once the user agreed to.
+pub fn install_udev_rules() -> Result<()> {
+    fs::write(UDEV_RULES_PATH, generate_udev_rules())?;
+    for args in [&["control", "--reload-rules"][..], &["trigger"]] {
+        let status = Command::new("udevadm").args(args).status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "`udevadm {}` failed with {}",
+                args.join(" "),
+                status
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A misconfiguration found by [`verify_setup`].
+#[non_exhaustive]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SetupIssue {
+    /// The `hid-wiimote` kernel driver is not loaded.
+    DriverMissing,
+    /// The rules of [`generate_udev_rules`] are not installed.
+    RulesMissing,
+    /// The process is not in [`GROUP`]. Users added to the group must
+    /// log in again.
+    NotInGroup,
+    /// The process can't read and write a device node of a connected
+    /// device.
+    NodeInaccessible(PathBuf),
+}
+
+impl fmt::Display for SetupIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DriverMissing => write!(
+                f,
+                "the hid-wiimote driver is not loaded, run `modprobe hid-wiimote`"
+            ),
+            Self::RulesMissing => {
+                write!(f, "the udev rules are not installed at {}", UDEV_RULES_PATH)
+            }
+            Self::NotInGroup => write!(
+                f,
+                "the user is not in the `{}` group, add it and log in again",
+                GROUP
+            ),
+            Self::NodeInaccessible(node) => {
+                write!(f, "cannot read and write {}", node.display())
+            }
+        }
+    }
+}
+
+/// Diagnoses the common misconfigurations that prevent the process from
+/// using the devices. Returns no issues if the setup is correct.
+///
+/// The device nodes are only checked for the connected devices.
+pub fn verify_setup() -> Result<Vec<SetupIssue>> {
+    let mut issues = Vec::new();
+    if !Path::new("/sys/bus/hid/drivers/wiimote").exists() {
+        issues.push(SetupIssue::DriverMissing);
+    }
+    if !Path::new(UDEV_RULES_PATH).exists() {
+        issues.push(SetupIssue::RulesMissing);
+    }
+    // Root can open every node.
+    if unsafe { libc::geteuid() } != 0 && !in_group(GROUP)? {
+        issues.push(SetupIssue::NotInGroup);
+    }
+    let mut monitor = Monitor::new(false)?;
+    while let Some(Address(syspath)) = monitor.next_address()? {
+        issues.extend(
+            crate::input::input_nodes(&syspath)?
+                .into_iter()
+                .filter(|node| !accessible(node))
+                .map(SetupIssue::NodeInaccessible),
+        );
+    }
+    Ok(issues)
+}
+
+/// Checks whether the process is in the group with the given name.
+fn in_group(name: &str) -> Result<bool> {
+    let name = CString::new(name).unwrap();
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        return Ok(false);
+    }
+    let gid = unsafe { (*group).gr_gid };
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    groups.truncate(count as usize);
+    Ok(unsafe { libc::getegid() } == gid || groups.contains(&gid))
+}
+
+/// Checks whether the process can read and write the given file.
+fn accessible(path: &Path) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accessible, generate_udev_rules, SetupIssue, GROUP};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn generates_rules() {
+        let rules = generate_udev_rules();
+        assert!(rules.lines().all(|line| !line.ends_with('\\')));
+        assert_eq!(
+            rules.lines().filter(|line| line.starts_with('#')).count(),
+            2
+        );
+        assert_eq!(rules.lines().count(), 6);
+        assert!(rules.contains("KERNELS==\"0005:057E:*\""));
+        assert!(rules.contains(&format!("GROUP=\"{}\"", GROUP)));
+
+        assert!(!accessible(Path::new("/nonexistent")));
+        let issue = SetupIssue::NodeInaccessible(PathBuf::from("/dev/input/event3"));
+        assert_eq!(issue.to_string(), "cannot read and write /dev/input/event3");
+    }
+}
@@ -0,0 +1,74 @@
+//! The event loop on platforms without `epoll`, built with the `stub`
+//! feature. No file can be watched and no timer fires, so the loop has
+//! nothing to do.
+use once_cell::sync::Lazy;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::task::Waker;
+use std::time::Instant;
+
+use crate::runtime::Trigger;
+use crate::Result;
+
+pub(crate) struct IoBlocker;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub(crate) struct TimerKey {
+    at: Instant,
+}
+
+impl TimerKey {
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+}
+
+impl IoBlocker {
+    pub const READ_EVENTS: libc::c_int = 0;
+
+    pub fn get() -> &'static Arc<Self> {
+        static BLOCKER: Lazy<Arc<IoBlocker>> = Lazy::new(|| Arc::new(IoBlocker));
+        &BLOCKER
+    }
+
+    pub fn new(_trigger: Trigger) -> Result<Arc<Self>> {
+        Ok(Arc::new(IoBlocker))
+    }
+
+    pub fn run(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn add_interest(&self, _fd: RawFd, _events: libc::c_int) -> Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn remove_interest(&self, _fd: RawFd, _events: libc::c_int) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_callback(&self, _fd: RawFd, _waker: &Waker) {}
+
+    pub fn add_timer(&self, _at: Instant, _waker: &Waker) -> Result<TimerKey> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn update_timer(&self, _key: TimerKey, _waker: &Waker) -> bool {
+        false
+    }
+
+    pub fn remove_timer(&self, _key: TimerKey) {}
+}
+
+pub(crate) fn register_external(_epfd: RawFd, _fd: RawFd, _token: u64) -> Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+pub(crate) fn deregister_external(_epfd: RawFd, _fd: RawFd) -> Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
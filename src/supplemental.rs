@@ -0,0 +1,300 @@
+//! Supplemental data read directly from the evdev nodes of a device.
+//!
+//! The library decodes a fixed set of axes for each channel. The kernel
+//! input devices of a [`Device`] may report more, e.g. on newer kernels
+//! or with patched drivers. [`SupplementalAxes`] reads the given axes
+//! from the evdev nodes returned by [`Device::evdev_nodes`], and [`merge`]
+//! interleaves them into an event stream as [`EventKind::InputAxis`]
+//! events. Events the kernel dropped because they were not read in time
+//! are reported as [`EventKind::Dropped`].
+//!
+//! Requires the `evdev` feature, and read access to the nodes in
+//! `/dev/input`.
+use crate::event::{Event, EventKind};
+use crate::io_blocker::IoBlocker;
+use crate::runtime::Runtime;
+use crate::{Device, Result};
+use ::evdev::raw_stream::RawDevice;
+use ::evdev::{AbsoluteAxisType, InputEvent, InputEventKind, Synchronization};
+use futures::stream::Fuse;
+use futures::{Stream, StreamExt};
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// A stream of the values of some absolute axes of a device, read from
+/// its evdev nodes.
+pub struct SupplementalAxes {
+    nodes: Vec<Node>,
+    blocker: Arc<IoBlocker>,
+    axes: Vec<u16>,
+    // Events read from a node, but not yet yielded.
+    pending: VecDeque<Event>,
+    // The sequence number of the next event.
+    sequence: u64,
+}
+
+/// An evdev node read by [`SupplementalAxes`].
+struct Node {
+    device: RawDevice,
+    // The number of events discarded since the last `SYN_DROPPED` event,
+    // until the next `SYN_REPORT` event.
+    dropped: Option<u32>,
+}
+
+impl SupplementalAxes {
+    /// Opens the evdev nodes of the device that report any of the given
+    /// axes. Fails with [`io::ErrorKind::NotFound`] if there are none.
+    pub fn open(device: &Device, axes: &[AbsoluteAxisType]) -> Result<Self> {
+        Self::with_blocker(device, axes, IoBlocker::get().clone())
+    }
+
+    /// Opens the evdev nodes like [`SupplementalAxes::open`], whose events
+    /// are received by the given runtime instead of the global one.
+    pub fn with_runtime(
+        device: &Device,
+        axes: &[AbsoluteAxisType],
+        runtime: &Runtime,
+    ) -> Result<Self> {
+        Self::with_blocker(device, axes, runtime.blocker().clone())
+    }
+
+    fn with_blocker(
+        device: &Device,
+        axes: &[AbsoluteAxisType],
+        blocker: Arc<IoBlocker>,
+    ) -> Result<Self> {
+        let mut stream = Self {
+            nodes: Vec::new(),
+            blocker,
+            axes: axes.iter().map(|axis| axis.0).collect(),
+            pending: VecDeque::new(),
+            sequence: 0,
+        };
+        for devnode in device.evdev_nodes()? {
+            let node = RawDevice::open(devnode)?;
+            let reports_axis = node
+                .supported_absolute_axes()
+                .is_some_and(|supported| axes.iter().any(|&axis| supported.contains(axis)));
+            if !reports_axis {
+                continue;
+            }
+
+            let fd = node.as_raw_fd();
+            let res_code = unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK)
+            };
+            crate::bail_if!(res_code == -1);
+            stream.blocker.add_interest(fd, IoBlocker::READ_EVENTS)?;
+            // Dropping the stream removes the interest of pushed nodes.
+            stream.nodes.push(Node {
+                device: node,
+                dropped: None,
+            });
+        }
+
+        if stream.nodes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no input device reports the given axes",
+            ));
+        }
+        Ok(stream)
+    }
+
+    /// Reads the available events of every node.
+    fn fill(&mut self, cx: &mut Context<'_>) -> Result<()> {
+        for Node { device, dropped } in &mut self.nodes {
+            let fd = device.as_raw_fd();
+            loop {
+                match device.fetch_events() {
+                    Ok(events) => {
+                        for event in events {
+                            let kind = match *dropped {
+                                Some(count) => resync(dropped, count, &event),
+                                None => decode(dropped, &self.axes, &event),
+                            };
+                            if let Some(kind) = kind {
+                                self.pending.push_back(Event {
+                                    time: event.timestamp(),
+                                    kind,
+                                    key_code: None,
+                                    sequence: Some(self.sequence),
+                                });
+                                self.sequence += 1;
+                            }
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        self.blocker.set_callback(fd, cx.waker());
+                        break;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes an event read from a node while it is consistent. Starts a
+/// drop on `SYN_DROPPED` events.
+fn decode(dropped: &mut Option<u32>, axes: &[u16], event: &InputEvent) -> Option<EventKind> {
+    match event.kind() {
+        InputEventKind::AbsAxis(axis) if axes.contains(&axis.0) => Some(EventKind::InputAxis {
+            code: axis.0,
+            value: event.value(),
+        }),
+        InputEventKind::Synchronization(Synchronization::SYN_DROPPED) => {
+            *dropped = Some(0);
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Discards the events read from a node after a drop, up to the next
+/// `SYN_REPORT` event, which ends the drop.
+fn resync(dropped: &mut Option<u32>, count: u32, event: &InputEvent) -> Option<EventKind> {
+    match event.kind() {
+        InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+            *dropped = None;
+            Some(EventKind::Dropped {
+                count_estimate: count,
+            })
+        }
+        _ => {
+            *dropped = Some(count + 1);
+            None
+        }
+    }
+}
+
+impl Stream for SupplementalAxes {
+    type Item = Result<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.pending.is_empty() {
+            if let Err(err) = self.fill(cx) {
+                return Poll::Ready(Some(Err(err)));
+            }
+        }
+        match self.pending.pop_front() {
+            Some(event) => Poll::Ready(Some(Ok(event))),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for SupplementalAxes {
+    fn drop(&mut self) {
+        for node in &self.nodes {
+            let _ = self
+                .blocker
+                .remove_interest(node.device.as_raw_fd(), IoBlocker::READ_EVENTS);
+        }
+    }
+}
+
+/// Interleaves the events of `axes` into `events`.
+///
+/// The merged stream ends when `events` ends, e.g. once the device is
+/// disconnected.
+pub fn merge<S, A>(events: S, axes: A) -> Merged<S, A>
+where
+    S: Stream<Item = Result<Event>> + Unpin,
+    A: Stream<Item = Result<Event>> + Unpin,
+{
+    Merged {
+        events,
+        axes: axes.fuse(),
+    }
+}
+
+/// The stream returned by [`merge`].
+pub struct Merged<S, A> {
+    events: S,
+    axes: Fuse<A>,
+}
+
+impl<S, A> Stream for Merged<S, A>
+where
+    S: Stream<Item = Result<Event>> + Unpin,
+    A: Stream<Item = Result<Event>> + Unpin,
+{
+    type Item = Result<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Poll::Ready(item) = self.events.poll_next_unpin(cx) {
+            return Poll::Ready(item);
+        }
+        match self.axes.poll_next_unpin(cx) {
+            Poll::Ready(Some(item)) => Poll::Ready(Some(item)),
+            // The device may outlive its supplemental axes.
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, merge, resync};
+    use crate::event::{Event, EventKind};
+    use ::evdev::{EventType, InputEvent, Synchronization};
+    use futures::channel::mpsc;
+    use futures::{executor, stream, StreamExt};
+    use std::time::SystemTime;
+
+    fn event(kind: EventKind) -> crate::Result<Event> {
+        Ok(Event::at(SystemTime::UNIX_EPOCH, kind))
+    }
+
+    #[test]
+    fn interleaves_until_events_end() {
+        let (sender, events) = mpsc::unbounded();
+        let axis = EventKind::InputAxis { code: 3, value: 7 };
+        let mut merged = merge(events, stream::iter([event(axis)]));
+
+        executor::block_on(async {
+            let item = merged.next().await.unwrap().unwrap();
+            assert!(matches!(
+                item.kind,
+                EventKind::InputAxis { code: 3, value: 7 }
+            ));
+
+            sender
+                .unbounded_send(event(EventKind::Disconnected))
+                .unwrap();
+            drop(sender);
+            let item = merged.next().await.unwrap().unwrap();
+            assert!(matches!(item.kind, EventKind::Disconnected));
+            assert!(merged.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn counts_events_discarded_after_drop() {
+        let sync = |code: Synchronization| InputEvent::new(EventType::SYNCHRONIZATION, code.0, 0);
+        let axis = InputEvent::new(EventType::ABSOLUTE, 3, 7);
+        let mut dropped = None;
+        assert!(matches!(
+            decode(&mut dropped, &[3], &axis),
+            Some(EventKind::InputAxis { code: 3, value: 7 })
+        ));
+        assert!(decode(&mut dropped, &[3], &sync(Synchronization::SYN_DROPPED)).is_none());
+        assert_eq!(dropped, Some(0));
+
+        for count in 0..2 {
+            assert!(resync(&mut dropped, count, &axis).is_none());
+        }
+        assert!(matches!(
+            resync(&mut dropped, 2, &sync(Synchronization::SYN_REPORT)),
+            Some(EventKind::Dropped { count_estimate: 2 })
+        ));
+        assert_eq!(dropped, None);
+    }
+}
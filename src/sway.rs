@@ -0,0 +1,276 @@
+//! Posturography metrics from the center of pressure on a Balance Board.
+//!
+//! The distribution of weight across the four sensors of a Balance
+//! Board locates the [`CenterOfPressure`] of the user. Its path while
+//! standing still is summarized by standard sway metrics: the path
+//! length, the area of the 95% confidence ellipse, and the velocity.
+//! A [`SwayAnalyzer`] computes them over windows of
+//! [`EventKind::BalanceBoard`] events; use [`sway`] to adapt a stream.
+use crate::event::{Event, EventKind};
+use crate::Result;
+use futures::{future, Stream, TryStreamExt};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+/// The distance between the left and right sensors, in millimeters.
+pub const SENSOR_SPACING_X_MM: f32 = 433.0;
+/// The distance between the front and back sensors, in millimeters.
+pub const SENSOR_SPACING_Y_MM: f32 = 238.0;
+
+/// The point of the board where the weight is centered.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CenterOfPressure {
+    /// The offset from the center of the board towards the right side,
+    /// in millimeters.
+    pub x_mm: f32,
+    /// The offset from the center of the board towards the front,
+    /// in millimeters.
+    pub y_mm: f32,
+    /// The time of the event.
+    pub time: SystemTime,
+}
+
+impl CenterOfPressure {
+    /// Locates the center of pressure from the weights of an
+    /// [`EventKind::BalanceBoard`] event. Returns `None` if the board
+    /// is empty.
+    pub fn from_weights(weights: [i32; 4], time: SystemTime) -> Option<Self> {
+        // The sensors are ordered top right, bottom right, top left and
+        // bottom left, with the power button at the bottom.
+        let [top_right, bottom_right, top_left, bottom_left] = weights.map(|w| w.max(0) as f32);
+        let total = top_right + bottom_right + top_left + bottom_left;
+        if total <= 0.0 {
+            return None;
+        }
+        let right = (top_right + bottom_right - top_left - bottom_left) / total;
+        let front = (top_right + top_left - bottom_right - bottom_left) / total;
+        Some(Self {
+            x_mm: right * SENSOR_SPACING_X_MM / 2.0,
+            y_mm: front * SENSOR_SPACING_Y_MM / 2.0,
+            time,
+        })
+    }
+}
+
+/// Sway metrics of a center of pressure path.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SwayMetrics {
+    /// The total length of the path, in millimeters.
+    pub path_length_mm: f32,
+    /// The area of the ellipse containing 95% of the points, assuming
+    /// they are normally distributed, in square millimeters.
+    pub ellipse_area_mm2: f32,
+    /// The mean velocity along the path, in millimeters per second.
+    pub mean_velocity_mm_s: f32,
+    /// The root mean square of the velocity between consecutive points,
+    /// in millimeters per second.
+    pub rms_velocity_mm_s: f32,
+    /// The time between the first and last point.
+    pub duration: Duration,
+    /// The time of the last point.
+    pub time: SystemTime,
+}
+
+impl SwayMetrics {
+    /// The 95th percentile of the chi-squared distribution with two
+    /// degrees of freedom.
+    const CHI_SQUARED_95: f32 = 5.991;
+
+    /// Computes the metrics of the given path, in chronological order.
+    /// Returns `None` if it spans no time.
+    pub fn compute(path: &[CenterOfPressure]) -> Option<Self> {
+        let (first, last) = (path.first()?, path.last()?);
+        let duration = last.time.duration_since(first.time).ok()?;
+        if duration.is_zero() {
+            return None;
+        }
+
+        let mut path_length = 0.0;
+        let mut squared_velocities = 0.0;
+        let mut steps = 0;
+        for pair in path.windows(2) {
+            let distance = (pair[1].x_mm - pair[0].x_mm).hypot(pair[1].y_mm - pair[0].y_mm);
+            path_length += distance;
+            if let Ok(elapsed) = pair[1].time.duration_since(pair[0].time) {
+                if !elapsed.is_zero() {
+                    squared_velocities += (distance / elapsed.as_secs_f32()).powi(2);
+                    steps += 1;
+                }
+            }
+        }
+
+        let n = path.len() as f32;
+        let mean_x = path.iter().map(|p| p.x_mm).sum::<f32>() / n;
+        let mean_y = path.iter().map(|p| p.y_mm).sum::<f32>() / n;
+        let (mut var_x, mut var_y, mut cov) = (0.0, 0.0, 0.0);
+        for point in path {
+            let (dx, dy) = (point.x_mm - mean_x, point.y_mm - mean_y);
+            var_x += dx * dx;
+            var_y += dy * dy;
+            cov += dx * dy;
+        }
+        let (var_x, var_y, cov) = (var_x / n, var_y / n, cov / n);
+        // The semi-axes are proportional to the square roots of the
+        // eigenvalues of the covariance matrix, whose product is its
+        // determinant.
+        let det = (var_x * var_y - cov * cov).max(0.0);
+
+        Some(Self {
+            path_length_mm: path_length,
+            ellipse_area_mm2: std::f32::consts::PI * Self::CHI_SQUARED_95 * det.sqrt(),
+            mean_velocity_mm_s: path_length / duration.as_secs_f32(),
+            rms_velocity_mm_s: (squared_velocities / steps.max(1) as f32).sqrt(),
+            duration,
+            time: last.time,
+        })
+    }
+}
+
+/// The windows over which sway metrics are computed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SwayConfig {
+    /// The duration of each window.
+    pub window: Duration,
+    /// The time between the end of consecutive windows. Windows overlap
+    /// if this is shorter than the window.
+    pub interval: Duration,
+}
+
+impl Default for SwayConfig {
+    /// Consecutive windows of 30 seconds, a common trial duration.
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(30),
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Computes [`SwayMetrics`] over windows of Balance Board [`Event`]s.
+#[derive(Clone, Default, Debug)]
+pub struct SwayAnalyzer {
+    config: SwayConfig,
+    path: VecDeque<CenterOfPressure>,
+    // The end of the next window.
+    next_end: Option<SystemTime>,
+}
+
+impl SwayAnalyzer {
+    /// Creates an analyzer with the given windows.
+    pub fn new(config: SwayConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Updates the analyzer with the given event, and returns the
+    /// metrics of the window completed by it, if any.
+    ///
+    /// Events where the board is empty are skipped.
+    pub fn update(&mut self, event: &Event) -> Option<SwayMetrics> {
+        let point = match event.kind {
+            EventKind::BalanceBoard(weights) => {
+                CenterOfPressure::from_weights(weights, event.time)?
+            }
+            _ => return None,
+        };
+        let next_end = *self
+            .next_end
+            .get_or_insert_with(|| point.time + self.config.window);
+
+        let mut metrics = None;
+        if point.time >= next_end {
+            let path = self.path.make_contiguous();
+            metrics = SwayMetrics::compute(path);
+            self.next_end = Some(next_end + self.config.interval);
+        }
+        self.path.push_back(point);
+        // Keep the points of the next window.
+        let start = self.next_end.unwrap() - self.config.window;
+        while self.path.front().is_some_and(|p| p.time < start) {
+            self.path.pop_front();
+        }
+        metrics
+    }
+}
+
+/// Adapts a stream of events into a stream of the metrics computed by
+/// `analyzer`.
+pub fn sway<S>(events: S, mut analyzer: SwayAnalyzer) -> impl Stream<Item = Result<SwayMetrics>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(analyzer.update(&event))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CenterOfPressure, SwayAnalyzer, SwayConfig, SwayMetrics, SENSOR_SPACING_X_MM};
+    use crate::event::{Event, EventKind};
+    use std::time::{Duration, SystemTime};
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn locates_center_of_pressure() {
+        let cop = CenterOfPressure::from_weights([1000, 1000, 0, 0], at(0)).unwrap();
+        assert_eq!((cop.x_mm, cop.y_mm), (SENSOR_SPACING_X_MM / 2.0, 0.0));
+        assert_eq!(CenterOfPressure::from_weights([0; 4], at(0)), None);
+    }
+
+    #[test]
+    fn square_path_metrics() {
+        // A 10 mm square, traversed at one side per second.
+        let corners = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ];
+        let path: Vec<_> = corners
+            .iter()
+            .enumerate()
+            .map(|(i, &(x_mm, y_mm))| CenterOfPressure {
+                x_mm,
+                y_mm,
+                time: at(i as u64 * 1000),
+            })
+            .collect();
+        let metrics = SwayMetrics::compute(&path).unwrap();
+
+        assert_eq!(metrics.path_length_mm, 40.0);
+        assert_eq!(metrics.mean_velocity_mm_s, 10.0);
+        assert_eq!(metrics.rms_velocity_mm_s, 10.0);
+        assert_eq!(metrics.duration, Duration::from_secs(4));
+        assert!(metrics.ellipse_area_mm2 > 0.0);
+        assert_eq!(SwayMetrics::compute(&path[..1]), None);
+    }
+
+    #[test]
+    fn reports_each_window() {
+        let mut analyzer = SwayAnalyzer::new(SwayConfig {
+            window: Duration::from_secs(1),
+            interval: Duration::from_millis(500),
+        });
+        let reports: Vec<_> = (0..250u64)
+            .filter_map(|i| {
+                let sway = if i % 2 == 0 { 1100 } else { 900 };
+                analyzer.update(&Event::at(
+                    at(i * 10),
+                    EventKind::BalanceBoard([sway, 1000, 2000 - sway, 1000]),
+                ))
+            })
+            .collect();
+
+        // Windows end at 1, 1.5 and 2 seconds.
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[1].time, at(1490));
+        assert!(reports
+            .iter()
+            .all(|m| m.duration == Duration::from_millis(990)));
+    }
+}
@@ -0,0 +1,61 @@
+//! The `xwiimote` library, as called by the crate.
+//!
+//! With the `test-harness` feature, calls on the interfaces of
+//! [`FakeIface`](crate::harness::FakeIface)s are routed to them, and
+//! the rest to the library. The items defined here take precedence
+//! over the glob import.
+pub(crate) use xwiimote_sys::*;
+
+#[cfg(feature = "test-harness")]
+use crate::harness;
+#[cfg(feature = "test-harness")]
+use libc::{c_char, c_int, c_uint};
+
+/// Defines a function that calls the method of the same name of the
+/// fake interface `dev`, or the library function if it is not fake.
+#[cfg(feature = "test-harness")]
+macro_rules! route {
+    ($(fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+        $(
+            pub(crate) unsafe fn $name(dev: *mut iface, $($arg: $ty),*) $(-> $ret)? {
+                match harness::find(dev) {
+                    Some(fake) => fake.$name($($arg),*),
+                    None => xwiimote_sys::$name(dev, $($arg),*),
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "test-harness")]
+route! {
+    fn iface_get_syspath() -> *const c_char;
+    fn iface_get_fd() -> c_int;
+    fn iface_watch(watch: bool) -> c_int;
+    fn iface_open(ifaces: c_uint) -> c_int;
+    fn iface_close(ifaces: c_uint);
+    fn iface_opened() -> c_uint;
+    fn iface_available() -> c_uint;
+    fn iface_dispatch(ev: *mut event, size: usize) -> c_int;
+    fn iface_rumble(on: bool) -> c_int;
+    fn iface_get_led(led: c_uint, state: *mut bool) -> c_int;
+    fn iface_set_led(led: c_uint, state: bool) -> c_int;
+    fn iface_get_battery(capacity: *mut u8) -> c_int;
+    fn iface_get_devtype(devtype: *mut *mut c_char) -> c_int;
+    fn iface_get_extension(extension: *mut *mut c_char) -> c_int;
+    fn iface_set_mp_normalization(x: i32, y: i32, z: i32, factor: i32);
+    fn iface_get_mp_normalization(x: *mut i32, y: *mut i32, z: *mut i32, factor: *mut i32);
+}
+
+/// Creates an interface for the device at the given sysfs path, which
+/// may be the [address](crate::harness::FakeIface::address) of a fake.
+#[cfg(feature = "test-harness")]
+pub(crate) unsafe fn iface_new(dev: *mut *mut iface, syspath: *const c_char) -> c_int {
+    match harness::find_at(std::ffi::CStr::from_ptr(syspath)) {
+        Some(fake) => {
+            *dev = fake;
+            0
+        }
+        None => xwiimote_sys::iface_new(dev, syspath),
+    }
+}
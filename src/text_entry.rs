@@ -0,0 +1,393 @@
+//! Text entry through an on-screen keyboard.
+//!
+//! Kiosk and home theater applications often need a few words of text,
+//! e.g. a search query, from users holding nothing but a remote. An
+//! [`OnScreenKeyboard`] drives a grid of key caps laid out by a
+//! [`KeyboardLayout`]: the D-pad or the IR pointer selects a cap, A
+//! types it, and B deletes the last character. The application draws
+//! the grid, highlighting the [selected](OnScreenKeyboard::selection)
+//! cap, and reacts to the [`TextEvent`]s. Use [`text_events`] to adapt
+//! an event stream.
+use crate::event::{Event, EventKind, Key, KeyState};
+use crate::ir::{Pointer, SensorBarConfig};
+use crate::Result;
+use futures::{future, Stream, TryStreamExt};
+
+/// A cap of an on-screen keyboard.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum KeyCap {
+    /// Types a character, in uppercase while shifted.
+    Char(char),
+    /// Types a space.
+    Space,
+    /// Deletes the last character.
+    Backspace,
+    /// Toggles uppercase for the next character.
+    Shift,
+    /// Submits the text.
+    Enter,
+}
+
+/// The rows of caps of an on-screen keyboard.
+///
+/// Rows may have different lengths; shorter rows are aligned to the
+/// left of the grid.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct KeyboardLayout {
+    rows: Vec<Vec<KeyCap>>,
+}
+
+impl KeyboardLayout {
+    /// Creates a layout with the given rows. Empty rows are dropped.
+    pub fn new(rows: Vec<Vec<KeyCap>>) -> Self {
+        let rows = rows.into_iter().filter(|row| !row.is_empty()).collect();
+        Self { rows }
+    }
+
+    /// Creates a layout with a row of character caps for each string,
+    /// followed by a row with the shift, space, backspace and enter caps.
+    pub fn from_rows(rows: &[&str]) -> Self {
+        let mut rows: Vec<_> = rows
+            .iter()
+            .map(|row| row.chars().map(KeyCap::Char).collect())
+            .collect();
+        rows.push(vec![
+            KeyCap::Shift,
+            KeyCap::Space,
+            KeyCap::Backspace,
+            KeyCap::Enter,
+        ]);
+        Self::new(rows)
+    }
+
+    /// The QWERTY layout, with a row of digits.
+    pub fn qwerty() -> Self {
+        Self::from_rows(&["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"])
+    }
+
+    /// An alphabetical layout, which is easier to scan for users not
+    /// used to a physical keyboard.
+    pub fn alphabetical() -> Self {
+        Self::from_rows(&["abcdefg", "hijklmn", "opqrstu", "vwxyz", "1234567890"])
+    }
+
+    /// Returns the rows of caps.
+    pub fn rows(&self) -> &[Vec<KeyCap>] {
+        &self.rows
+    }
+
+    /// Returns the number of caps of the longest row.
+    pub fn columns(&self) -> usize {
+        self.rows.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    /// Returns the cap at the given position, if any.
+    pub fn get(&self, row: usize, column: usize) -> Option<KeyCap> {
+        self.rows.get(row)?.get(column).copied()
+    }
+}
+
+impl Default for KeyboardLayout {
+    fn default() -> Self {
+        Self::qwerty()
+    }
+}
+
+/// The parameters of an [`OnScreenKeyboard`].
+#[derive(Clone, Debug)]
+pub struct KeyboardConfig {
+    /// The layout of the caps.
+    pub layout: KeyboardLayout,
+    /// The layout of the sensor bar, or `None` to only select caps
+    /// with the D-pad.
+    pub sensor_bar: Option<SensorBarConfig>,
+    /// The horizontal rotation of the remote, in radians, that sweeps
+    /// the pointer across the width of the grid. The caps are square.
+    pub reach: f32,
+    /// Whether the D-pad wraps around the edges of the grid.
+    pub wrap: bool,
+    /// The key that types the selected cap.
+    pub select: Key,
+    /// The key that deletes the last character.
+    pub delete: Key,
+    /// The key that submits the text.
+    pub submit: Key,
+    /// The key that cancels the entry.
+    pub cancel: Key,
+}
+
+impl Default for KeyboardConfig {
+    /// The QWERTY layout, selected with the D-pad or the pointer. A
+    /// types, B deletes, Plus submits and Home cancels.
+    fn default() -> Self {
+        Self {
+            layout: KeyboardLayout::default(),
+            sensor_bar: Some(SensorBarConfig::default()),
+            reach: 0.4,
+            wrap: true,
+            select: Key::A,
+            delete: Key::B,
+            submit: Key::Plus,
+            cancel: Key::Home,
+        }
+    }
+}
+
+/// A change caused by the input of an [`OnScreenKeyboard`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum TextEvent {
+    /// The selection moved to the cap at the given row and column.
+    Moved {
+        /// The row of the selected cap.
+        row: usize,
+        /// The column of the selected cap.
+        column: usize,
+    },
+    /// The character was appended to the text.
+    Inserted(char),
+    /// The last character of the text was deleted.
+    Deleted(char),
+    /// The shift state changed.
+    Shifted(bool),
+    /// The text was submitted. The keyboard is cleared.
+    Submitted(String),
+    /// The entry was cancelled. The keyboard is cleared.
+    Cancelled,
+}
+
+/// Turns the input of a remote into text.
+#[derive(Clone, Debug)]
+pub struct OnScreenKeyboard {
+    config: KeyboardConfig,
+    pointer: Option<Pointer>,
+    row: usize,
+    column: usize,
+    shifted: bool,
+    text: String,
+}
+
+impl OnScreenKeyboard {
+    /// Creates a keyboard with the given configuration. The first cap
+    /// is selected.
+    pub fn new(config: KeyboardConfig) -> Self {
+        Self {
+            pointer: config.sensor_bar.map(Pointer::new),
+            config,
+            row: 0,
+            column: 0,
+            shifted: false,
+            text: String::new(),
+        }
+    }
+
+    /// Returns the layout of the caps.
+    pub fn layout(&self) -> &KeyboardLayout {
+        &self.config.layout
+    }
+
+    /// Returns the row and column of the selected cap.
+    pub fn selection(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    /// Returns the selected cap, or `None` if the layout is empty.
+    pub fn selected(&self) -> Option<KeyCap> {
+        self.config.layout.get(self.row, self.column)
+    }
+
+    /// Returns the text entered so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text, e.g. to edit an existing value.
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+    }
+
+    /// Checks whether the next character is typed in uppercase.
+    pub fn is_shifted(&self) -> bool {
+        self.shifted
+    }
+
+    /// Updates the keyboard with the given event, and returns the change
+    /// it caused, if any.
+    pub fn update(&mut self, event: &Event) -> Option<TextEvent> {
+        match event.kind {
+            EventKind::Key(key, KeyState::Down | KeyState::AutoRepeat) => self.press(key),
+            EventKind::Ir(_) => {
+                let sample = self.pointer.as_mut()?.update(event)?;
+                self.point_at(sample.pose.yaw, sample.pose.pitch)
+            }
+            _ => None,
+        }
+    }
+
+    /// Selects the cap the remote points at, given the horizontal and
+    /// vertical angles between the camera axis and the center of the
+    /// grid, in radians, as in [`Pose`](crate::ir::Pose). Returns `None`
+    /// if the pointer is off the grid or on the selected cap.
+    pub fn point_at(&mut self, yaw: f32, pitch: f32) -> Option<TextEvent> {
+        let layout = &self.config.layout;
+        let cap_size = self.config.reach / layout.columns() as f32;
+        let column = layout.columns() as f32 / 2.0 - yaw / cap_size;
+        let row = layout.rows().len() as f32 / 2.0 - pitch / cap_size;
+        if column < 0.0 || row < 0.0 {
+            return None;
+        }
+        let (row, column) = (row as usize, column as usize);
+        layout.get(row, column)?;
+        self.select(row, column)
+    }
+
+    fn select(&mut self, row: usize, column: usize) -> Option<TextEvent> {
+        if (row, column) == (self.row, self.column) {
+            return None;
+        }
+        self.row = row;
+        self.column = column;
+        Some(TextEvent::Moved { row, column })
+    }
+
+    fn press(&mut self, key: Key) -> Option<TextEvent> {
+        let rows = self.config.layout.rows();
+        if rows.is_empty() {
+            return None;
+        }
+        let step = |position: usize, len: usize, forward: bool| match (forward, self.config.wrap) {
+            (true, _) if position + 1 < len => position + 1,
+            (true, true) => 0,
+            (false, _) if position > 0 => position - 1,
+            (false, true) => len - 1,
+            _ => position,
+        };
+        let (row, column) = match key {
+            Key::Left => (self.row, step(self.column, rows[self.row].len(), false)),
+            Key::Right => (self.row, step(self.column, rows[self.row].len(), true)),
+            Key::Up | Key::Down => {
+                let row = step(self.row, rows.len(), key == Key::Down);
+                (row, self.column.min(rows[row].len() - 1))
+            }
+            key if key == self.config.select => return self.type_selected(),
+            key if key == self.config.delete => return self.delete(),
+            key if key == self.config.submit => return Some(self.submit()),
+            key if key == self.config.cancel => {
+                self.clear();
+                return Some(TextEvent::Cancelled);
+            }
+            _ => return None,
+        };
+        self.select(row, column)
+    }
+
+    fn type_selected(&mut self) -> Option<TextEvent> {
+        let ch = match self.selected()? {
+            KeyCap::Char(ch) if self.shifted => {
+                self.shifted = false;
+                ch.to_uppercase().next().unwrap_or(ch)
+            }
+            KeyCap::Char(ch) => ch,
+            KeyCap::Space => ' ',
+            KeyCap::Backspace => return self.delete(),
+            KeyCap::Shift => {
+                self.shifted = !self.shifted;
+                return Some(TextEvent::Shifted(self.shifted));
+            }
+            KeyCap::Enter => return Some(self.submit()),
+        };
+        self.text.push(ch);
+        Some(TextEvent::Inserted(ch))
+    }
+
+    fn delete(&mut self) -> Option<TextEvent> {
+        self.text.pop().map(TextEvent::Deleted)
+    }
+
+    fn submit(&mut self) -> TextEvent {
+        let text = std::mem::take(&mut self.text);
+        self.clear();
+        TextEvent::Submitted(text)
+    }
+
+    /// Clears the text and the shift state. The selection is kept.
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.shifted = false;
+    }
+}
+
+/// Adapts a stream of events into a stream of the changes caused by
+/// them on `keyboard`.
+pub fn text_events<S>(
+    events: S,
+    mut keyboard: OnScreenKeyboard,
+) -> impl Stream<Item = Result<TextEvent>>
+where
+    S: Stream<Item = Result<Event>>,
+{
+    events.try_filter_map(move |event| future::ready(Ok(keyboard.update(&event))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyboardConfig, KeyboardLayout, OnScreenKeyboard, TextEvent};
+    use crate::event::{Event, EventKind, Key, KeyState};
+    use std::time::SystemTime;
+
+    fn press(keyboard: &mut OnScreenKeyboard, key: Key) -> Option<TextEvent> {
+        let event = Event::at(SystemTime::UNIX_EPOCH, EventKind::Key(key, KeyState::Down));
+        keyboard.update(&event)
+    }
+
+    #[test]
+    fn types_with_the_dpad() {
+        let mut keyboard = OnScreenKeyboard::new(KeyboardConfig {
+            layout: KeyboardLayout::from_rows(&["ab", "c"]),
+            ..Default::default()
+        });
+        // Shift is the first cap of the last row.
+        press(&mut keyboard, Key::Up);
+        assert_eq!(press(&mut keyboard, Key::A), Some(TextEvent::Shifted(true)));
+        press(&mut keyboard, Key::Down);
+        assert_eq!(
+            press(&mut keyboard, Key::Right),
+            Some(TextEvent::Moved { row: 0, column: 1 })
+        );
+        assert_eq!(press(&mut keyboard, Key::A), Some(TextEvent::Inserted('B')));
+        assert_eq!(press(&mut keyboard, Key::A), Some(TextEvent::Inserted('b')));
+        // The column is clamped to the shorter row.
+        assert_eq!(
+            press(&mut keyboard, Key::Down),
+            Some(TextEvent::Moved { row: 1, column: 0 })
+        );
+        press(&mut keyboard, Key::A);
+        assert_eq!(press(&mut keyboard, Key::B), Some(TextEvent::Deleted('c')));
+        assert_eq!(
+            press(&mut keyboard, Key::Plus),
+            Some(TextEvent::Submitted("Bb".to_string()))
+        );
+        assert_eq!(keyboard.text(), "");
+        assert_eq!(press(&mut keyboard, Key::B), None);
+    }
+
+    #[test]
+    fn selects_caps_with_the_pointer() {
+        let mut keyboard = OnScreenKeyboard::new(KeyboardConfig {
+            layout: KeyboardLayout::new(vec![vec![super::KeyCap::Space; 4]; 2]),
+            reach: 0.4,
+            ..Default::default()
+        });
+        // The caps are 0.1 rad wide, and a positive yaw points left.
+        assert_eq!(
+            keyboard.point_at(0.15, -0.05),
+            Some(TextEvent::Moved { row: 1, column: 0 })
+        );
+        assert_eq!(keyboard.point_at(0.12, -0.02), None);
+        assert_eq!(
+            keyboard.point_at(-0.05, 0.05),
+            Some(TextEvent::Moved { row: 0, column: 2 })
+        );
+        assert_eq!(keyboard.point_at(-0.25, 0.0), None);
+        assert_eq!(keyboard.selection(), (0, 2));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+use std::task::Waker;
+use std::time::Instant;
+
+use crate::io_blocker::{IoBlocker, TimerKey};
+use crate::Result;
+
+/// A one-shot deadline, whose expiration is signalled by the event loop
+/// of an [`IoBlocker`](crate::IoBlocker). Timers take no file
+/// descriptor, so every timed feature shares the event loop thread.
+pub(crate) struct Timer {
+    blocker: Arc<IoBlocker>,
+    key: Option<TimerKey>,
+}
+
+impl Timer {
+    /// Creates a disarmed timer.
+    pub fn new(blocker: Arc<IoBlocker>) -> Self {
+        Self { blocker, key: None }
+    }
+
+    /// Arranges for the waker to be called once the deadline passes,
+    /// replacing any previous deadline and waker.
+    ///
+    /// Re-arming the same deadline only replaces the waker, if it
+    /// differs, so repeatedly polling a pending future does not
+    /// allocate.
+    pub fn wake_at(&mut self, at: Instant, waker: &Waker) -> Result<()> {
+        if let Some(key) = self.key {
+            if key.at() == at && self.blocker.update_timer(key, waker) {
+                return Ok(());
+            }
+        }
+        self.cancel();
+        self.key = Some(self.blocker.add_timer(at, waker)?);
+        Ok(())
+    }
+
+    /// Disarms the timer.
+    pub fn cancel(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.blocker.remove_timer(key);
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timer;
+    use crate::runtime::{Runtime, Trigger};
+    use crate::Result;
+    use futures::executor;
+    use futures::future::poll_fn;
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+
+    /// Waits for the timer to expire at the given deadline.
+    fn wait(timer: &mut Timer, at: Instant) -> Result<()> {
+        executor::block_on(poll_fn(|cx| {
+            if Instant::now() >= at {
+                return Poll::Ready(Ok(()));
+            }
+            timer.wake_at(at, cx.waker())?;
+            Poll::Pending
+        }))
+    }
+
+    #[test]
+    fn expires_in_order() -> Result<()> {
+        for trigger in [Trigger::Edge, Trigger::Level] {
+            let runtime = Runtime::with_trigger(trigger)?;
+            let mut late = Timer::new(runtime.blocker().clone());
+            let mut early = Timer::new(runtime.blocker().clone());
+
+            // The late timer is set first, so the event loop must wake
+            // up early for the second one.
+            let start = Instant::now();
+            let noop = futures::task::noop_waker();
+            late.wake_at(start + Duration::from_secs(60), &noop)?;
+            wait(&mut early, start + Duration::from_millis(20))?;
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(20));
+            assert!(elapsed < Duration::from_secs(60));
+
+            late.cancel();
+            runtime.shutdown()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn past_deadline_expires() -> Result<()> {
+        let runtime = Runtime::new()?;
+        let mut timer = Timer::new(runtime.blocker().clone());
+        let noop = futures::task::noop_waker();
+        let past = Instant::now();
+        timer.wake_at(past, &noop)?;
+        // Wait with a new deadline, replacing the fired timer.
+        wait(&mut timer, Instant::now() + Duration::from_millis(5))?;
+        runtime.shutdown()
+    }
+}
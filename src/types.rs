@@ -0,0 +1,1185 @@
+//! Plain data types describing devices and their events.
+//!
+//! This module performs no I/O and doesn't depend on the `xwiimote`
+//! library, so recorded or forwarded events can be processed on any
+//! platform, e.g. to analyze logs captured from a Linux machine.
+//! The types are re-exported from the [`event`](crate::event) module
+//! and the crate root.
+use bitflags::bitflags;
+use num_derive::FromPrimitive;
+use std::fmt::{self, Write};
+use std::time::SystemTime;
+
+#[cfg(doc)]
+use crate::Device;
+
+// Channels
+
+bitflags! {
+    /// Represents the channels that can be opened on a [`Device`](crate::Device).
+    ///
+    /// The `xwiimote` library calls these interfaces.
+    pub struct Channels: u32 {
+        // todo: improve docs
+        /// Primary channel.
+        const CORE = 0x1;
+        /// Accelerometer channel.
+        const ACCELEROMETER = 0x2;
+        /// IR camera channel.
+        const IR = 0x4;
+        /// MotionPlus extension channel.
+        ///
+        /// Can be open together with [`Channels::NUNCHUK`] if the Nunchuk
+        /// is plugged into the Motion Plus pass-through port. See the
+        /// [`motion`](crate::motion) module for the consequences.
+        const MOTION_PLUS = 0x100;
+        /// Nunchuk extension channel.
+        const NUNCHUK = 0x200;
+        /// Classic controller channel.
+        const CLASSIC_CONTROLLER = 0x400;
+        /// Balance board channel.
+        const BALANCE_BOARD = 0x800;
+        /// ProController channel.
+        const PRO_CONTROLLER = 0x1000;
+        /// Drums channel.
+        const DRUMS = 0x2000;
+        /// Guitar channel.
+        const GUITAR = 0x4000;
+    }
+}
+
+impl Channels {
+    /// Returns the given interface bits that don't correspond to any
+    /// channel, such as those of extensions unknown to this library
+    /// or the flag used to open channels for writing.
+    pub(crate) fn unknown_bits(bits: u32) -> u32 {
+        bits & !Self::all().bits()
+    }
+}
+
+// Keys
+
+// We provide a key enumeration for each controller and extension type.
+// To avoid repetition, we use a macro to define the common key variants.
+// A matrix of the buttons reported by each device is given in `BUTTONS.md`.
+// This macro definition uses the TT munching technique.
+macro_rules! key_enum {
+    ($doc:expr, $name:ident {$($body:tt)*} ($variant:expr) $($tail:tt)*) => {
+        inner_key_enum! {
+            $doc:expr,
+            $name {
+                $($body)*  // Previously-built variants.
+                $variant,
+            }
+        }
+        $($tail)* // Unprocessed variants.
+    };
+    // There are no more variants, emit the enum definition.
+    ($doc:expr, $name:ident {$($body:tt)*}) => {
+        #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, FromPrimitive)]
+        #[doc = $doc]
+        pub enum $name {
+            /// Plus (+) button.
+            Plus = 6,
+            /// Minus (-) button.
+            Minus = 7,
+            $($body)*
+        }
+    };
+}
+
+macro_rules! regular_controller_key_enum {
+    ($doc:expr, $name:ident {$($body:tt)*}) => {
+        key_enum!{
+            $doc,
+            $name {
+                /// Left directional pad button.
+                Left = 0,
+                /// Right directional pad button.
+                Right = 1,
+                /// Up directional pad button.
+                Up = 2,
+                /// Down directional pad button.
+                Down = 3,
+                /// A button.
+                A = 4,
+                /// B button.
+                B = 5,
+                /// Home button.
+                Home = 8,
+                $($body)*
+            }
+        }
+    };
+}
+
+macro_rules! gamepad_key_enum {
+    ($doc:expr, $name:ident {$($body:tt)*}) => {
+        regular_controller_key_enum!{
+            $doc,
+            $name {
+                /// Joystick X-axis.
+                X = 11,
+                /// Joystick Y-axis.
+                Y = 12,
+                /// TL button.
+                TL = 13,
+                /// TR button.
+                TR = 14,
+                /// ZL button.
+                ZL = 15,
+                /// ZR button.
+                ZR = 16,
+                $($body)*
+            }
+        }
+    };
+}
+
+regular_controller_key_enum!(
+    "The keys of a Wii Remote",
+    Key {
+        /// 1 button.
+        One = 9,
+        /// 2 button.
+        Two = 10
+    }
+);
+
+gamepad_key_enum!(
+    "The keys of a Wii U Pro controller",
+    ProControllerKey {
+        /// Left thumb button.
+        ///
+        /// Reported if the left analog stick is pressed.
+        LeftThumb = 17,
+        /// Right thumb button.
+        ///
+        /// Reported if the right analog stick is pressed.
+        RightThumb = 18,
+    }
+);
+
+gamepad_key_enum!("The keys of a Classic controller", ClassicControllerKey {});
+
+/// The keys of a Nunchuk.
+// This is the only extension that doesn't have the + and - buttons.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, FromPrimitive)]
+pub enum NunchukKey {
+    /// C button.
+    C = 19,
+    /// Z button.
+    Z = 20,
+}
+
+key_enum!("The keys of a drums controller.", DrumsKey {});
+
+key_enum!("The keys of a guitar controller.",
+    GuitarKey {
+        /// The StarPower/Home button.
+        StarPower = 8, // same as Key::Home
+        /// The guitar strum bar, pushed up or down.
+        StrumBar = 21, // also 22
+        /// The guitar upper-most fret button.
+        HighestFretBar = 23,
+        /// The guitar second-upper fret button.
+        HighFretBar = 24,
+        /// The guitar mid fret button.
+        MidFretBar = 25,
+        /// The guitar second-lowest fret button.
+        LowFretBar = 26,
+        /// The guitar lowest fret button.
+        LowestFretBar = 27,
+    }
+);
+
+// Key decoding
+
+/// A key enumeration, decoded from the codes of a single event type.
+///
+/// The kernel reuses key codes across devices, e.g. both the Wii Remote
+/// Home button and the guitar StarPower button are reported with code 8.
+/// Hence each enumeration has its own decoding table, and the type of
+/// the event determines which table is used.
+pub trait KeyCode: Copy + Sized + 'static {
+    /// The key codes reported for this enumeration, and their keys.
+    /// A key may be reported with several codes.
+    const CODES: &'static [(u32, Self)];
+
+    /// Decodes the key with the given code, or returns `None` if the
+    /// code is not reported for this enumeration.
+    fn from_code(code: u32) -> Option<Self> {
+        Self::CODES
+            .iter()
+            .find(|(known, _)| *known == code)
+            .map(|&(_, key)| key)
+    }
+}
+
+macro_rules! key_codes {
+    ($name:ident { $($code:literal => $variant:ident),* $(,)? }) => {
+        impl KeyCode for $name {
+            const CODES: &'static [(u32, Self)] = &[$(($code, Self::$variant)),*];
+        }
+    };
+}
+
+key_codes!(Key {
+    0 => Left,
+    1 => Right,
+    2 => Up,
+    3 => Down,
+    4 => A,
+    5 => B,
+    6 => Plus,
+    7 => Minus,
+    8 => Home,
+    9 => One,
+    10 => Two,
+});
+
+key_codes!(ProControllerKey {
+    0 => Left,
+    1 => Right,
+    2 => Up,
+    3 => Down,
+    4 => A,
+    5 => B,
+    6 => Plus,
+    7 => Minus,
+    8 => Home,
+    11 => X,
+    12 => Y,
+    13 => TL,
+    14 => TR,
+    15 => ZL,
+    16 => ZR,
+    17 => LeftThumb,
+    18 => RightThumb,
+});
+
+key_codes!(ClassicControllerKey {
+    0 => Left,
+    1 => Right,
+    2 => Up,
+    3 => Down,
+    4 => A,
+    5 => B,
+    6 => Plus,
+    7 => Minus,
+    8 => Home,
+    11 => X,
+    12 => Y,
+    13 => TL,
+    14 => TR,
+    15 => ZL,
+    16 => ZR,
+});
+
+key_codes!(NunchukKey {
+    19 => C,
+    20 => Z,
+});
+
+key_codes!(DrumsKey {
+    6 => Plus,
+    7 => Minus,
+});
+
+key_codes!(GuitarKey {
+    6 => Plus,
+    7 => Minus,
+    8 => StarPower,
+    21 => StrumBar, // up
+    22 => StrumBar, // down
+    23 => HighestFretBar,
+    24 => HighFretBar,
+    25 => MidFretBar,
+    26 => LowFretBar,
+    27 => LowestFretBar,
+});
+
+/// The state of a key.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, FromPrimitive)]
+pub enum KeyState {
+    /// The key is released.
+    Up = 0,
+    /// The key is held down.
+    Down,
+    /// The key is [held down](`Self::Down`), and was reported as so in
+    /// the previous event for the same key.
+    AutoRepeat,
+}
+
+// Event kinds
+
+pub(crate) const MAX_IR_SOURCES: usize = 4;
+
+/// An IR source detected by the IR camera, as reported in [`EventKind::Ir`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct IrSource {
+    /// The x-axis position.
+    pub x: i32,
+    /// The y-axis position.
+    pub y: i32,
+}
+
+/// A pad of a drums controller, whose hits are reported in
+/// [`EventKind::DrumsMove`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum DrumsPad {
+    /// The left cymbal.
+    CymbalLeft,
+    /// The right cymbal.
+    CymbalRight,
+    /// The mid-left tom-tom.
+    TomLeft,
+    /// The mid-right tom-tom.
+    TomRight,
+    /// The right-most tom-tom.
+    TomFarRight,
+    /// The bass pedal.
+    Bass,
+    /// The hi-hat pedal.
+    HiHat,
+}
+
+impl DrumsPad {
+    /// The pads, in the order of the velocities of
+    /// [`EventKind::DrumsMove`].
+    pub const ALL: [DrumsPad; DRUMS_PADS] = [
+        DrumsPad::CymbalLeft,
+        DrumsPad::CymbalRight,
+        DrumsPad::TomLeft,
+        DrumsPad::TomRight,
+        DrumsPad::TomFarRight,
+        DrumsPad::Bass,
+        DrumsPad::HiHat,
+    ];
+}
+
+pub(crate) const DRUMS_PADS: usize = 7;
+
+/// An analog axis of a controller, as reported in [`EventKind::Axis`].
+#[non_exhaustive]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum AxisId {
+    /// The x-axis of the left stick of a Pro Controller, Classic
+    /// Controller or guitar, or of the Nunchuk stick.
+    LeftStickX,
+    /// The y-axis of the left stick of a Pro Controller, Classic
+    /// Controller or guitar, or of the Nunchuk stick.
+    LeftStickY,
+    /// The x-axis of the right stick of a Pro Controller or Classic
+    /// Controller.
+    RightStickX,
+    /// The y-axis of the right stick of a Pro Controller or Classic
+    /// Controller.
+    RightStickY,
+    /// The left analog trigger of a Classic Controller.
+    LeftTrigger,
+    /// The right analog trigger of a Classic Controller.
+    RightTrigger,
+    /// The whammy bar of a guitar.
+    WhammyBar,
+    /// The fret bar of a guitar.
+    FretBar,
+}
+
+impl AxisId {
+    /// Returns whether the axis ranges from 0 to 1, rather than from
+    /// -1 to 1.
+    pub fn is_unipolar(&self) -> bool {
+        matches!(
+            self,
+            Self::LeftTrigger | Self::RightTrigger | Self::WhammyBar | Self::FretBar
+        )
+    }
+}
+
+/// A change in the static data of a [`Device`], as reported
+/// in [`EventKind::Other`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct WatchEvent {
+    /// The channels available before the change.
+    pub available_before: Channels,
+    /// The channels available after the change.
+    pub available_after: Channels,
+}
+
+impl WatchEvent {
+    /// Returns the channels that became available, e.g. because
+    /// an extension was plugged.
+    pub fn added(&self) -> Channels {
+        self.available_after - self.available_before
+    }
+
+    /// Returns the channels that became unavailable, e.g. because
+    /// an extension was unplugged.
+    pub fn removed(&self) -> Channels {
+        self.available_before - self.available_after
+    }
+
+    /// Returns what changed, as far as the available channels tell.
+    pub fn kind(&self) -> WatchKind {
+        match (self.added(), self.removed()) {
+            (added, removed) if removed.is_empty() && !added.is_empty() => {
+                WatchKind::ExtensionPlugged(added)
+            }
+            (added, removed) if added.is_empty() && !removed.is_empty() => {
+                WatchKind::ExtensionUnplugged(removed)
+            }
+            (added, _) if added.is_empty() => WatchKind::DeviceInfoChanged,
+            _ => WatchKind::Unknown,
+        }
+    }
+}
+
+/// The change reported by a [`WatchEvent`].
+#[non_exhaustive]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum WatchKind {
+    /// An extension was plugged, making the given channels available.
+    ExtensionPlugged(Channels),
+    /// An extension was unplugged, and the given channels are no longer
+    /// available.
+    ExtensionUnplugged(Channels),
+    /// The available channels didn't change, but some other static data
+    /// of the [`Device`] may have, e.g. its extension while it is being
+    /// initialized. Check the data the application depends on.
+    DeviceInfoChanged,
+    /// Some channels became available and others unavailable, e.g. as an
+    /// extension was swapped for another between two reports. Check
+    /// every channel.
+    Unknown,
+}
+
+/// The type of an [`Event`], including its associated data.
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug)]
+pub enum EventKind {
+    /// The state of a Wii Remote controller key changed.
+    ///
+    /// Received only if [`Channels::CORE`] is open.
+    Key(Key, KeyState),
+    /// Provides the accelerometer data.
+    ///
+    /// Received only if [`Channels::ACCELEROMETER`] is open.
+    Accelerometer {
+        /// The x-axis acceleration.
+        x: i32,
+        /// The y-axis acceleration.
+        y: i32,
+        /// The z-axis acceleration.
+        z: i32,
+    },
+    /// Provides the IR camera data.
+    ///
+    /// The camera can track up to four IR sources. The index
+    /// of each source within the array is maintained across
+    /// events.
+    ///
+    /// Received only if [`Channels::IR`] is open.
+    Ir([Option<IrSource>; MAX_IR_SOURCES]),
+    /// Provides Balance Board weight data. Four sensors report
+    /// data for each of the edges of the board.
+    ///
+    /// Received only if [`Channels::BALANCE_BOARD`] is open.
+    BalanceBoard([i32; 4]),
+    /// Provides the Motion Plus extension gyroscope data.
+    ///
+    /// Received only if [`Channels::MOTION_PLUS`] is open.
+    MotionPlus {
+        /// The x-axis rotational speed.
+        x: i32,
+        /// The y-axis rotational speed.
+        y: i32,
+        /// The z-axis rotational speed.
+        z: i32,
+    },
+    /// The state of a Wii U Pro controller key changed.
+    ///
+    /// Received only if [`Channels::PRO_CONTROLLER`] is open.
+    ProControllerKey(ProControllerKey, KeyState),
+    /// Reports the movement of an analog stick from
+    /// a Wii U Pro controller.
+    ///
+    /// Received only if [`Channels::PRO_CONTROLLER`] is open.
+    ProControllerMove {
+        /// The left analog stick absolute x-axis position.
+        left_x: i32,
+        /// The left analog stick absolute y-axis position.
+        left_y: i32,
+        /// The right analog stick absolute x-axis position.
+        right_x: i32,
+        /// The right analog stick absolute y-axis position.
+        right_y: i32,
+    },
+    /// An extension was plugged or unplugged, or some other static
+    /// data that cannot be monitored separately changed.
+    ///
+    /// The payload describes the change in the available channels, and
+    /// [`WatchEvent::kind`] what it means. If no channel changed, the
+    /// application should check what changed by examining the
+    /// [`Device`] manually.
+    ///
+    /// Received only if the device is [watched](Device::set_watch).
+    Other(WatchEvent),
+    /// The kernel closed the given open channels, e.g. because their
+    /// extension was unplugged, or because of an error.
+    ///
+    /// Received after the event or error that closed them. See
+    /// [`Device::reopen_closed`] to reopen them once they are available.
+    ChannelClosed(Channels),
+    /// The state of a Classic controller key changed.
+    ///
+    /// Received only if [`Channels::CLASSIC_CONTROLLER`] is open.
+    ClassicControllerKey(ClassicControllerKey, KeyState),
+    /// Reports the movement of an analog stick from
+    /// a Classic controller.
+    ///
+    /// Received only if [`Channels::CLASSIC_CONTROLLER`] is open.
+    ClassicControllerMove {
+        /// The left analog stick x-axis absolute position.
+        left_x: i32,
+        /// The left analog stick y-axis absolute position.
+        left_y: i32,
+        /// The right analog stick x-axis absolute position.
+        right_x: i32,
+        /// The right analog stick y-axis absolute position.
+        right_y: i32,
+        /// The TL trigger absolute position, ranging from 0 to 63.
+        ///
+        /// Many controller do not have analog controllers, in
+        /// which case this value is either 0 or 63.
+        left_trigger: u8,
+        /// The TR trigger absolute position, ranging from 0 to 63.
+        ///
+        /// Many controller do not have analog controllers, in
+        /// which case this value is either 0 or 63.
+        right_trigger: u8,
+    },
+    /// The state of a Nunchuk key changed.
+    ///
+    /// Received only if [`Channels::NUNCHUK`] is open.
+    NunchukKey(NunchukKey, KeyState),
+    /// Reports the movement of an analog stick from a Nunchuk.
+    ///
+    /// If the Nunchuk is plugged into a Motion Plus in pass-through
+    /// mode, these events are interleaved with [`EventKind::MotionPlus`]
+    /// events and the accelerations have reduced precision. See the
+    /// [`motion`](crate::motion) module.
+    ///
+    /// Received only if [`Channels::NUNCHUK`] is open.
+    NunchukMove {
+        /// The x-axis absolute position.
+        x: i32,
+        /// The y-axis absolute position.
+        y: i32,
+        /// The x-axis acceleration.
+        x_acceleration: i32,
+        /// The y-axis acceleration.
+        y_acceleration: i32,
+    },
+    /// The state of a drums controller key changed.
+    ///
+    /// Received only if [`Channels::DRUMS`] is open.
+    DrumsKey(DrumsKey, KeyState),
+    /// Reports the movement of the analog stick, and the velocity
+    /// of the pads hit, from a drums controller.
+    ///
+    /// Received only if [`Channels::DRUMS`] is open.
+    DrumsMove {
+        /// The x-axis analog stick position.
+        x: i32,
+        /// The y-axis analog stick position.
+        y: i32,
+        /// The velocity of the hit of each pad, indexed by
+        /// `DrumsPad as usize`, from 1 (softest) to 7 (hardest),
+        /// or 0 if the pad wasn't hit.
+        velocities: [u8; DRUMS_PADS],
+    },
+    /// The state of a guitar controller key changed.
+    ///
+    /// Received only if [`Channels::GUITAR`] is open.
+    GuitarKey(GuitarKey, KeyState),
+    /// Reports the movement of an analog stick, the whammy bar,
+    /// or the fret bar from a guitar controller.
+    ///
+    /// Received only if [`Channels::GUITAR`] is open.
+    GuitarMove {
+        /// The x-axis analog stick position.
+        x: i32,
+        /// The y-axis analog stick position.
+        y: i32,
+        /// The whammy bar position.
+        whammy_bar: i32,
+        /// The fret bar absolute position.
+        fret_bar: i32,
+    },
+    /// Provides the value of an absolute axis of an input device of the
+    /// [`Device`], which the library doesn't decode. The `code` is one of
+    /// the `ABS_*` constants of `linux/input-event-codes.h`.
+    ///
+    /// Received only from the streams of the `supplemental` module,
+    /// which requires the `evdev` feature.
+    InputAxis {
+        /// The axis code.
+        code: u16,
+        /// The absolute position.
+        value: i32,
+    },
+    /// Provides the normalized position of an analog axis.
+    ///
+    /// Received only from the stream returned by
+    /// [`normalized_axes`](crate::axis::normalized_axes), which replaces
+    /// the movement events of every controller.
+    Axis {
+        /// The axis.
+        axis: AxisId,
+        /// The position, from -1 to 1, or from 0 to 1 if the axis
+        /// [is unipolar](AxisId::is_unipolar).
+        value: f32,
+    },
+    /// The inferred mode of the Motion Plus axes changed. Each axis is
+    /// either in fast mode, or in the precise slow mode.
+    ///
+    /// Received only from the stream returned by
+    /// [`with_gyro_modes`](crate::gyro::with_gyro_modes), before the
+    /// first [`EventKind::MotionPlus`] event in the new mode.
+    MotionPlusMode {
+        /// Whether the x-axis is in fast mode.
+        x_fast: bool,
+        /// Whether the y-axis is in fast mode.
+        y_fast: bool,
+        /// Whether the z-axis is in fast mode.
+        z_fast: bool,
+    },
+    /// The kernel dropped events because they were not read in time,
+    /// e.g. while the application was busy. Filters of motion data
+    /// should be reset, since the next event doesn't follow the last.
+    ///
+    /// Received only from the streams of the `supplemental` module, whose
+    /// evdev nodes report the drops; the `xwiimote` library doesn't.
+    Dropped {
+        /// The number of events discarded after the drop, until the
+        /// stream was consistent again. The kernel doesn't report those
+        /// dropped before, so this is a lower bound.
+        count_estimate: u32,
+    },
+    /// The device was disconnected, e.g. because it powered off
+    /// after a period of inactivity.
+    ///
+    /// This is the last event yielded by the stream. See
+    /// [`Device::set_keepalive`] to prevent idle disconnections.
+    Disconnected,
+}
+
+impl EventKind {
+    /// Returns the channel that reports events of this kind, or `None`
+    /// if they are not reported by a channel.
+    pub fn channel(&self) -> Option<Channels> {
+        let channel = match self {
+            EventKind::Key(..) => Channels::CORE,
+            EventKind::Accelerometer { .. } => Channels::ACCELEROMETER,
+            EventKind::Ir(_) => Channels::IR,
+            EventKind::BalanceBoard(_) => Channels::BALANCE_BOARD,
+            EventKind::MotionPlus { .. } | EventKind::MotionPlusMode { .. } => {
+                Channels::MOTION_PLUS
+            }
+            EventKind::ProControllerKey(..) | EventKind::ProControllerMove { .. } => {
+                Channels::PRO_CONTROLLER
+            }
+            EventKind::ClassicControllerKey(..) | EventKind::ClassicControllerMove { .. } => {
+                Channels::CLASSIC_CONTROLLER
+            }
+            EventKind::NunchukKey(..) | EventKind::NunchukMove { .. } => Channels::NUNCHUK,
+            EventKind::DrumsKey(..) | EventKind::DrumsMove { .. } => Channels::DRUMS,
+            EventKind::GuitarKey(..) | EventKind::GuitarMove { .. } => Channels::GUITAR,
+            EventKind::Other(_)
+            | EventKind::ChannelClosed(_)
+            | EventKind::Dropped { .. }
+            | EventKind::InputAxis { .. }
+            | EventKind::Axis { .. }
+            | EventKind::Disconnected => return None,
+        };
+        Some(channel)
+    }
+}
+
+/// An event received from an open channel to a [`Device`].
+#[derive(Copy, Clone, Debug)]
+pub struct Event {
+    /// The time at which the kernel generated the event.
+    pub time: SystemTime,
+    /// The event type.
+    pub kind: EventKind,
+    /// The raw key code reported by the kernel, for key events.
+    ///
+    /// Distinguishes keys reported with several codes, such as the
+    /// strum bar of a guitar being pushed up or down.
+    pub key_code: Option<u32>,
+    /// The position of the event in the stream that read it from the
+    /// device, counting from 0, or `None` for events made up by the
+    /// application.
+    ///
+    /// Each [`EventStream`](crate::event::EventStream), and
+    /// [`Device::try_next_event`], number their events separately.
+    /// Events emitted by [layers](crate::layer) keep the number of the
+    /// event they replace.
+    pub sequence: Option<u64>,
+}
+
+#[cfg(test)]
+impl Event {
+    /// Creates an event made up by a test, without a key code.
+    pub(crate) fn at(time: SystemTime, kind: EventKind) -> Self {
+        Self {
+            time,
+            kind,
+            key_code: None,
+            sequence: None,
+        }
+    }
+}
+
+// Formatting
+
+/// Writes the names of the channels separated by `|`, or `-` if empty.
+fn fmt_channels(channels: Channels, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if channels.is_empty() {
+        return f.write_char('-');
+    }
+    let mut separator = "";
+    for bit in 0..u32::BITS {
+        let channel = Channels::from_bits_truncate(1 << bit);
+        if !channel.is_empty() && channels.contains(channel) {
+            write!(f, "{}{:?}", separator, channel)?;
+            separator = "|";
+        }
+    }
+    Ok(())
+}
+
+impl EventKind {
+    /// Returns the short uppercase name of the kind in the [`Display`]
+    /// format.
+    ///
+    /// [`Display`]: fmt::Display
+    pub(crate) fn tag(&self) -> &'static str {
+        match self {
+            EventKind::Key(..) => "KEY",
+            EventKind::Accelerometer { .. } => "ACC",
+            EventKind::Ir(_) => "IR",
+            EventKind::BalanceBoard(_) => "BB",
+            EventKind::MotionPlus { .. } => "MP",
+            EventKind::ProControllerKey(..) => "PRO_KEY",
+            EventKind::ProControllerMove { .. } => "PRO_MOVE",
+            EventKind::Other(_) => "WATCH",
+            EventKind::ChannelClosed(_) => "CLOSED",
+            EventKind::ClassicControllerKey(..) => "CLASSIC_KEY",
+            EventKind::ClassicControllerMove { .. } => "CLASSIC_MOVE",
+            EventKind::NunchukKey(..) => "NUNCHUK_KEY",
+            EventKind::NunchukMove { .. } => "NUNCHUK_MOVE",
+            EventKind::DrumsKey(..) => "DRUMS_KEY",
+            EventKind::DrumsMove { .. } => "DRUMS_MOVE",
+            EventKind::GuitarKey(..) => "GUITAR_KEY",
+            EventKind::GuitarMove { .. } => "GUITAR_MOVE",
+            EventKind::InputAxis { .. } => "INPUT_AXIS",
+            EventKind::Axis { .. } => "AXIS",
+            EventKind::MotionPlusMode { .. } => "MP_MODE",
+            EventKind::Dropped { .. } => "DROPPED",
+            EventKind::Disconnected => "DISCONNECTED",
+        }
+    }
+
+    /// Writes the data of the event, each value preceded by a space.
+    fn fmt_values(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EventKind::Key(key, state) => write!(f, " {:?} {:?}", key, state),
+            EventKind::ProControllerKey(key, state) => write!(f, " {:?} {:?}", key, state),
+            EventKind::ClassicControllerKey(key, state) => write!(f, " {:?} {:?}", key, state),
+            EventKind::NunchukKey(key, state) => write!(f, " {:?} {:?}", key, state),
+            EventKind::DrumsKey(key, state) => write!(f, " {:?} {:?}", key, state),
+            EventKind::GuitarKey(key, state) => write!(f, " {:?} {:?}", key, state),
+            EventKind::Accelerometer { x, y, z } | EventKind::MotionPlus { x, y, z } => {
+                write!(f, " x={} y={} z={}", x, y, z)
+            }
+            EventKind::Ir(sources) => {
+                for (ix, source) in sources.iter().enumerate() {
+                    match source {
+                        Some(source) => write!(f, " {}={},{}", ix, source.x, source.y)?,
+                        None => write!(f, " {}=-", ix)?,
+                    }
+                }
+                Ok(())
+            }
+            EventKind::BalanceBoard(weights) => {
+                for (ix, weight) in weights.iter().enumerate() {
+                    write!(f, " {}={}", ix, weight)?;
+                }
+                Ok(())
+            }
+            EventKind::ProControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            } => write!(
+                f,
+                " lx={} ly={} rx={} ry={}",
+                left_x, left_y, right_x, right_y
+            ),
+            EventKind::Other(watch) => {
+                f.write_str(" before=")?;
+                fmt_channels(watch.available_before, f)?;
+                f.write_str(" after=")?;
+                fmt_channels(watch.available_after, f)
+            }
+            EventKind::ChannelClosed(channels) => {
+                f.write_char(' ')?;
+                fmt_channels(channels, f)
+            }
+            EventKind::ClassicControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+                left_trigger,
+                right_trigger,
+            } => write!(
+                f,
+                " lx={} ly={} rx={} ry={} lt={} rt={}",
+                left_x, left_y, right_x, right_y, left_trigger, right_trigger
+            ),
+            EventKind::NunchukMove {
+                x,
+                y,
+                x_acceleration,
+                y_acceleration,
+            } => write!(
+                f,
+                " x={} y={} ax={} ay={}",
+                x, y, x_acceleration, y_acceleration
+            ),
+            EventKind::GuitarMove {
+                x,
+                y,
+                whammy_bar,
+                fret_bar,
+            } => write!(
+                f,
+                " x={} y={} whammy={} fret={}",
+                x, y, whammy_bar, fret_bar
+            ),
+            EventKind::InputAxis { code, value } => write!(f, " code={} value={}", code, value),
+            EventKind::Axis { axis, value } => write!(f, " {:?}={:.3}", axis, value),
+            EventKind::MotionPlusMode {
+                x_fast,
+                y_fast,
+                z_fast,
+            } => {
+                let mode = |fast| if fast { "fast" } else { "slow" };
+                write!(
+                    f,
+                    " x={} y={} z={}",
+                    mode(x_fast),
+                    mode(y_fast),
+                    mode(z_fast)
+                )
+            }
+            EventKind::Dropped { count_estimate } => write!(f, " count>={}", count_estimate),
+            EventKind::DrumsMove { x, y, velocities } => {
+                write!(f, " x={} y={}", x, y)?;
+                for (pad, velocity) in DrumsPad::ALL.iter().zip(velocities) {
+                    if velocity != 0 {
+                        write!(f, " {:?}={}", pad, velocity)?;
+                    }
+                }
+                Ok(())
+            }
+            EventKind::Disconnected => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    /// Formats the kind as an uppercase name followed by the data of the
+    /// event, separated by spaces, e.g. `ACC x=12 y=-3 z=101`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.tag())?;
+        self.fmt_values(f)
+    }
+}
+
+/// The seconds since the Unix epoch, with millisecond precision.
+struct Seconds(SystemTime);
+
+impl fmt::Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = self.0.duration_since(SystemTime::UNIX_EPOCH);
+        let since = since.unwrap_or_default();
+        write!(f, "{}.{:03}s", since.as_secs(), since.subsec_millis())
+    }
+}
+
+impl fmt::Display for Event {
+    /// Formats the event as its time in seconds since the Unix epoch,
+    /// followed by its [kind](EventKind), e.g.
+    /// `1700000012.345s KEY A Down`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", Seconds(self.time), self.kind)
+    }
+}
+
+/// Formats the given events as a table, with a row per event and
+/// aligned columns for the time, the kind and the data of each event,
+/// e.g. to dump the last event of each channel periodically.
+pub fn fmt_table<'a>(events: impl IntoIterator<Item = &'a Event>) -> String {
+    /// The data of an event, without the leading space.
+    struct Values<'a>(&'a EventKind);
+
+    impl fmt::Display for Values<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt_values(f)
+        }
+    }
+
+    let rows: Vec<_> = events
+        .into_iter()
+        .map(|event| {
+            let values = Values(&event.kind).to_string();
+            (
+                Seconds(event.time).to_string(),
+                event.kind.tag(),
+                values.trim_start().to_string(),
+            )
+        })
+        .collect();
+    let header = ("time", "kind", "values");
+    let time_width = rows
+        .iter()
+        .map(|row| row.0.len())
+        .fold(header.0.len(), usize::max);
+    let tag_width = rows
+        .iter()
+        .map(|row| row.1.len())
+        .fold(header.1.len(), usize::max);
+
+    let mut table = String::new();
+    for (time, tag, values) in [(header.0, header.1, header.2)].into_iter().chain(
+        rows.iter()
+            .map(|(time, tag, values)| (time.as_str(), *tag, values.as_str())),
+    ) {
+        let row = format!("{:<time_width$} {:<tag_width$} {}", time, tag, values);
+        table.push_str(row.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fmt_table, Channels, ClassicControllerKey, DrumsKey, Event, EventKind, GuitarKey, IrSource,
+        Key, KeyCode, KeyState, NunchukKey, ProControllerKey, WatchEvent, WatchKind,
+    };
+    use proptest::prelude::*;
+    use std::time::{Duration, SystemTime};
+    use xwiimote_sys as sys;
+
+    /// The key codes reported by each device, as listed in `BUTTONS.md`.
+    fn reported_codes() -> [(&'static str, Vec<u32>); 6] {
+        let common = [sys::KEY_PLUS, sys::KEY_MINUS];
+        let regular = [
+            sys::KEY_LEFT,
+            sys::KEY_RIGHT,
+            sys::KEY_UP,
+            sys::KEY_DOWN,
+            sys::KEY_HOME,
+            sys::KEY_A,
+            sys::KEY_B,
+        ];
+        let gamepad = [
+            sys::KEY_X,
+            sys::KEY_Y,
+            sys::KEY_TR,
+            sys::KEY_TL,
+            sys::KEY_ZR,
+            sys::KEY_ZL,
+        ];
+        [
+            (
+                "wiimote",
+                [&common[..], &regular, &[sys::KEY_ONE, sys::KEY_TWO]].concat(),
+            ),
+            (
+                "pro",
+                [
+                    &common[..],
+                    &regular,
+                    &gamepad,
+                    &[sys::KEY_THUMBL, sys::KEY_THUMBR],
+                ]
+                .concat(),
+            ),
+            ("classic", [&common[..], &regular, &gamepad].concat()),
+            ("nunchuk", vec![sys::KEY_C, sys::KEY_Z]),
+            ("drums", common.to_vec()),
+            (
+                "guitar",
+                [
+                    &common[..],
+                    &[
+                        sys::KEY_HOME, // StarPower
+                        sys::KEY_FRET_FAR_UP,
+                        sys::KEY_FRET_UP,
+                        sys::KEY_FRET_MID,
+                        sys::KEY_FRET_LOW,
+                        sys::KEY_FRET_FAR_LOW,
+                        sys::KEY_STRUM_BAR_UP,
+                        sys::KEY_STRUM_BAR_DOWN,
+                    ],
+                ]
+                .concat(),
+            ),
+        ]
+    }
+
+    /// Returns the devices whose key enumeration decodes the code.
+    fn decoding_devices(code: u32) -> Vec<&'static str> {
+        [
+            ("wiimote", Key::from_code(code).is_some()),
+            ("pro", ProControllerKey::from_code(code).is_some()),
+            ("classic", ClassicControllerKey::from_code(code).is_some()),
+            ("nunchuk", NunchukKey::from_code(code).is_some()),
+            ("drums", DrumsKey::from_code(code).is_some()),
+            ("guitar", GuitarKey::from_code(code).is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(device, decoded)| decoded.then_some(device))
+        .collect()
+    }
+
+    /// Asserts that each code appears once in the decoding table.
+    fn assert_unique_codes<K: KeyCode>() {
+        for (ix, (code, _)) in K::CODES.iter().enumerate() {
+            assert!(!K::CODES[ix + 1..].iter().any(|(other, _)| other == code));
+        }
+    }
+
+    #[test]
+    fn decoding_tables_are_unambiguous() {
+        assert_unique_codes::<Key>();
+        assert_unique_codes::<ProControllerKey>();
+        assert_unique_codes::<ClassicControllerKey>();
+        assert_unique_codes::<NunchukKey>();
+        assert_unique_codes::<DrumsKey>();
+        assert_unique_codes::<GuitarKey>();
+        // Codes shared between devices are decoded per enumeration.
+        assert_eq!(GuitarKey::from_code(22), Some(GuitarKey::StrumBar));
+        assert_eq!(Key::from_code(8), Some(Key::Home));
+        assert_eq!(GuitarKey::from_code(8), Some(GuitarKey::StarPower));
+    }
+
+    #[test]
+    fn channels_match_library() {
+        assert_eq!(Channels::all().bits(), sys::IFACE_ALL);
+        assert_eq!(Channels::CORE.bits(), sys::IFACE_CORE);
+        assert_eq!(Channels::GUITAR.bits(), sys::IFACE_GUITAR);
+        // The writable flag is never mistaken for a channel.
+        assert!(Channels::from_bits_truncate(sys::IFACE_WRITABLE).is_empty());
+        assert_eq!(
+            Channels::unknown_bits(sys::IFACE_WRITABLE),
+            sys::IFACE_WRITABLE
+        );
+    }
+
+    #[test]
+    fn classifies_watch_events() {
+        let watch = |before, after| WatchEvent {
+            available_before: before,
+            available_after: after,
+        };
+        let core = Channels::CORE;
+        assert_eq!(
+            watch(core, core | Channels::NUNCHUK).kind(),
+            WatchKind::ExtensionPlugged(Channels::NUNCHUK)
+        );
+        assert_eq!(
+            watch(core | Channels::NUNCHUK, core).kind(),
+            WatchKind::ExtensionUnplugged(Channels::NUNCHUK)
+        );
+        assert_eq!(watch(core, core).kind(), WatchKind::DeviceInfoChanged);
+        assert_eq!(
+            watch(core | Channels::NUNCHUK, core | Channels::GUITAR).kind(),
+            WatchKind::Unknown
+        );
+    }
+
+    #[test]
+    fn formats_events() {
+        let event =
+            |millis, kind| Event::at(SystemTime::UNIX_EPOCH + Duration::from_millis(millis), kind);
+        let key = event(12_345, EventKind::Key(Key::A, KeyState::Down));
+        assert_eq!(key.to_string(), "12.345s KEY A Down");
+        let accel = event(
+            12_350,
+            EventKind::Accelerometer {
+                x: 12,
+                y: -3,
+                z: 101,
+            },
+        );
+        assert_eq!(accel.to_string(), "12.350s ACC x=12 y=-3 z=101");
+        let ir = EventKind::Ir([Some(IrSource { x: 1, y: 2 }), None, None, None]);
+        assert_eq!(ir.to_string(), "IR 0=1,2 1=- 2=- 3=-");
+        let watch = EventKind::Other(WatchEvent {
+            available_before: Channels::CORE,
+            available_after: Channels::CORE | Channels::NUNCHUK,
+        });
+        assert_eq!(watch.to_string(), "WATCH before=CORE after=CORE|NUNCHUK");
+
+        let disconnected = event(100_000, EventKind::Disconnected);
+        assert_eq!(
+            fmt_table([&key, &accel, &disconnected]),
+            "\
+time     kind         values
+12.345s  KEY          A Down
+12.350s  ACC          x=12 y=-3 z=101
+100.000s DISCONNECTED
+"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn known_channels_round_trip(bits in 0..=sys::IFACE_ALL) {
+            let bits = bits & sys::IFACE_ALL;
+            prop_assert_eq!(Channels::from_bits(bits).map(|channels| channels.bits()), Some(bits));
+        }
+
+        #[test]
+        fn unknown_channels_are_rejected(bits in any::<u32>()) {
+            prop_assume!(bits & !sys::IFACE_ALL != 0);
+            prop_assert!(Channels::from_bits(bits).is_none());
+        }
+
+        #[test]
+        fn unknown_channels_are_truncated(bits in any::<u32>()) {
+            let known = Channels::from_bits_truncate(bits);
+            prop_assert_eq!(known.bits(), bits & sys::IFACE_ALL);
+            prop_assert_eq!(known.bits() | Channels::unknown_bits(bits), bits);
+        }
+
+        #[test]
+        fn key_codes_match_buttons_matrix(code in 0..2 * sys::KEY_NUM) {
+            let expected: Vec<_> = reported_codes()
+                .into_iter()
+                .filter(|(_, codes)| codes.contains(&code))
+                .map(|(device, _)| device)
+                .collect();
+            prop_assert_eq!(decoding_devices(code), expected);
+        }
+    }
+}
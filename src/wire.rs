@@ -0,0 +1,567 @@
+//! A stable wire format for streaming events to other processes.
+//!
+//! Applications in other runtimes, e.g. Node.js or a browser behind a
+//! WebSocket bridge, can't link the crate. Instead a daemon reads the
+//! devices and streams their events over a TCP or Unix socket, encoded
+//! as [`WireEvent`]s: a schema that is independent of the Rust types,
+//! so it stays compatible as [`EventKind`] evolves.
+//!
+//! The protocol exchanges newline-delimited JSON [`Message`]s. Each side
+//! first sends a [`Message::Hello`] with its [`PROTOCOL_VERSION`], and
+//! [`WireConnection::handshake`] fails if the versions differ. Within a
+//! version, new event kinds and fields may be added: readers ignore
+//! unknown fields, and decode unknown kinds as [`WireKind::Unknown`] and
+//! unknown messages as [`Message::Unknown`].
+//!
+//! ```no_run
+//! # use xwiimote::wire::WireConnection;
+//! # use xwiimote::Device;
+//! # use futures::TryStreamExt;
+//! # use std::os::unix::net::UnixListener;
+//! # async fn serve(device: &Device) -> std::io::Result<()> {
+//! let listener = UnixListener::bind("/run/xwiimote.sock")?;
+//! let (client, _) = listener.accept()?;
+//! let mut connection = WireConnection::handshake(client, "my-daemon")?;
+//! let mut events = device.events()?;
+//! while let Some(event) = events.try_next().await? {
+//!     connection.send_event(Some("remote1"), &event)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requires the `wire` feature.
+use crate::event::{Event, EventKind, KeyState};
+use crate::Channels;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::time::UNIX_EPOCH;
+
+/// The version of the protocol. Both sides of a connection must use
+/// the same version.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A message of the protocol, sent as a single line of JSON.
+#[non_exhaustive]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// The first message sent by each side.
+    Hello {
+        /// The [`PROTOCOL_VERSION`] of the sender.
+        protocol: u32,
+        /// The name and version of the sender, e.g. `xwiimote/0.2.1`.
+        agent: String,
+    },
+    /// An event of a device.
+    Event(WireEvent),
+    /// A message type added by a later release.
+    #[serde(other)]
+    Unknown,
+}
+
+/// An event, as sent over the wire.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct WireEvent {
+    /// The device that produced the event, as labeled by the sender.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// The time of the event, in nanoseconds since the Unix epoch.
+    pub time_ns: u64,
+    /// The position of the event in the stream that read it, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// The kind and data of the event.
+    #[serde(flatten)]
+    pub kind: WireKind,
+}
+
+/// The state of a key, as sent over the wire.
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WireKeyState {
+    /// The key was released.
+    Up,
+    /// The key was pressed.
+    Down,
+    /// The key is held down.
+    AutoRepeat,
+}
+
+impl From<KeyState> for WireKeyState {
+    fn from(state: KeyState) -> Self {
+        match state {
+            KeyState::Up => Self::Up,
+            KeyState::Down => Self::Down,
+            KeyState::AutoRepeat => Self::AutoRepeat,
+        }
+    }
+}
+
+/// The kind and data of a [`WireEvent`], mirroring [`EventKind`]. Keys,
+/// channels and axes are sent by name, e.g. `"A"` or `"NUNCHUK"`.
+#[non_exhaustive]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WireKind {
+    /// See [`EventKind::Key`].
+    Key { key: String, state: WireKeyState },
+    /// See [`EventKind::Accelerometer`].
+    Accelerometer { x: i32, y: i32, z: i32 },
+    /// See [`EventKind::Ir`]. Missing sources are `null`.
+    Ir { sources: Vec<Option<[i32; 2]>> },
+    /// See [`EventKind::BalanceBoard`].
+    BalanceBoard { weights: [i32; 4] },
+    /// See [`EventKind::MotionPlus`].
+    MotionPlus { x: i32, y: i32, z: i32 },
+    /// See [`EventKind::MotionPlusMode`].
+    MotionPlusMode {
+        x_fast: bool,
+        y_fast: bool,
+        z_fast: bool,
+    },
+    /// See [`EventKind::ProControllerKey`].
+    ProControllerKey { key: String, state: WireKeyState },
+    /// See [`EventKind::ProControllerMove`].
+    ProControllerMove {
+        left_x: i32,
+        left_y: i32,
+        right_x: i32,
+        right_y: i32,
+    },
+    /// See [`EventKind::ClassicControllerKey`].
+    ClassicControllerKey { key: String, state: WireKeyState },
+    /// See [`EventKind::ClassicControllerMove`].
+    ClassicControllerMove {
+        left_x: i32,
+        left_y: i32,
+        right_x: i32,
+        right_y: i32,
+        left_trigger: u8,
+        right_trigger: u8,
+    },
+    /// See [`EventKind::NunchukKey`].
+    NunchukKey { key: String, state: WireKeyState },
+    /// See [`EventKind::NunchukMove`].
+    NunchukMove {
+        x: i32,
+        y: i32,
+        x_acceleration: i32,
+        y_acceleration: i32,
+    },
+    /// See [`EventKind::DrumsKey`].
+    DrumsKey { key: String, state: WireKeyState },
+    /// See [`EventKind::DrumsMove`].
+    DrumsMove { x: i32, y: i32, velocities: [u8; 7] },
+    /// See [`EventKind::GuitarKey`].
+    GuitarKey { key: String, state: WireKeyState },
+    /// See [`EventKind::GuitarMove`].
+    GuitarMove {
+        x: i32,
+        y: i32,
+        whammy_bar: i32,
+        fret_bar: i32,
+    },
+    /// See [`EventKind::InputAxis`].
+    InputAxis { code: u16, value: i32 },
+    /// See [`EventKind::Axis`].
+    Axis { axis: String, value: f32 },
+    /// See [`EventKind::Other`]. Lists the channels available before
+    /// and after the change.
+    Watch {
+        available_before: Vec<String>,
+        available_after: Vec<String>,
+    },
+    /// See [`EventKind::ChannelClosed`].
+    ChannelClosed { channels: Vec<String> },
+    /// See [`EventKind::Dropped`].
+    Dropped { count_estimate: u32 },
+    /// See [`EventKind::Disconnected`].
+    Disconnected,
+    /// An event kind added by a later release.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Returns the names of the given channels.
+fn channel_names(channels: Channels) -> Vec<String> {
+    (0..u32::BITS)
+        .map(|bit| Channels::from_bits_truncate(1 << bit))
+        .filter(|channel| !channel.is_empty() && channels.contains(*channel))
+        .map(|channel| format!("{:?}", channel))
+        .collect()
+}
+
+impl From<&EventKind> for WireKind {
+    fn from(kind: &EventKind) -> Self {
+        fn name(value: impl Debug) -> String {
+            format!("{:?}", value)
+        }
+        match *kind {
+            EventKind::Key(key, state) => Self::Key {
+                key: name(key),
+                state: state.into(),
+            },
+            EventKind::Accelerometer { x, y, z } => Self::Accelerometer { x, y, z },
+            EventKind::Ir(sources) => Self::Ir {
+                sources: sources
+                    .iter()
+                    .map(|source| source.map(|source| [source.x, source.y]))
+                    .collect(),
+            },
+            EventKind::BalanceBoard(weights) => Self::BalanceBoard { weights },
+            EventKind::MotionPlus { x, y, z } => Self::MotionPlus { x, y, z },
+            EventKind::MotionPlusMode {
+                x_fast,
+                y_fast,
+                z_fast,
+            } => Self::MotionPlusMode {
+                x_fast,
+                y_fast,
+                z_fast,
+            },
+            EventKind::ProControllerKey(key, state) => Self::ProControllerKey {
+                key: name(key),
+                state: state.into(),
+            },
+            EventKind::ProControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            } => Self::ProControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            },
+            EventKind::ClassicControllerKey(key, state) => Self::ClassicControllerKey {
+                key: name(key),
+                state: state.into(),
+            },
+            EventKind::ClassicControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+                left_trigger,
+                right_trigger,
+            } => Self::ClassicControllerMove {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+                left_trigger,
+                right_trigger,
+            },
+            EventKind::NunchukKey(key, state) => Self::NunchukKey {
+                key: name(key),
+                state: state.into(),
+            },
+            EventKind::NunchukMove {
+                x,
+                y,
+                x_acceleration,
+                y_acceleration,
+            } => Self::NunchukMove {
+                x,
+                y,
+                x_acceleration,
+                y_acceleration,
+            },
+            EventKind::DrumsKey(key, state) => Self::DrumsKey {
+                key: name(key),
+                state: state.into(),
+            },
+            EventKind::DrumsMove { x, y, velocities } => Self::DrumsMove { x, y, velocities },
+            EventKind::GuitarKey(key, state) => Self::GuitarKey {
+                key: name(key),
+                state: state.into(),
+            },
+            EventKind::GuitarMove {
+                x,
+                y,
+                whammy_bar,
+                fret_bar,
+            } => Self::GuitarMove {
+                x,
+                y,
+                whammy_bar,
+                fret_bar,
+            },
+            EventKind::InputAxis { code, value } => Self::InputAxis { code, value },
+            EventKind::Axis { axis, value } => Self::Axis {
+                axis: name(axis),
+                value,
+            },
+            EventKind::Other(watch) => Self::Watch {
+                available_before: channel_names(watch.available_before),
+                available_after: channel_names(watch.available_after),
+            },
+            EventKind::ChannelClosed(channels) => Self::ChannelClosed {
+                channels: channel_names(channels),
+            },
+            EventKind::Dropped { count_estimate } => Self::Dropped { count_estimate },
+            EventKind::Disconnected => Self::Disconnected,
+        }
+    }
+}
+
+impl WireEvent {
+    /// Encodes an event of the device with the given label, if any.
+    pub fn new(device: Option<&str>, event: &Event) -> Self {
+        let time_ns = event
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        Self {
+            device: device.map(str::to_string),
+            time_ns,
+            sequence: event.sequence,
+            kind: WireKind::from(&event.kind),
+        }
+    }
+}
+
+/// A connection to a peer speaking the protocol, over a TCP or Unix
+/// socket.
+pub struct WireConnection<S: Read + Write> {
+    stream: BufReader<S>,
+    peer_agent: String,
+    line: String,
+}
+
+impl<S: Read + Write> WireConnection<S> {
+    /// Sends a [`Message::Hello`] with the given agent name, and waits
+    /// for the hello of the peer.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the peer uses
+    /// another protocol version, or sends another message first.
+    pub fn handshake(stream: S, agent: &str) -> io::Result<Self> {
+        let mut connection = Self {
+            stream: BufReader::new(stream),
+            peer_agent: String::new(),
+            line: String::new(),
+        };
+        connection.send(&Message::Hello {
+            protocol: PROTOCOL_VERSION,
+            agent: agent.to_string(),
+        })?;
+        match connection.recv()? {
+            Some(Message::Hello { protocol, agent }) if protocol == PROTOCOL_VERSION => {
+                connection.peer_agent = agent;
+                Ok(connection)
+            }
+            Some(Message::Hello { protocol, agent }) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} speaks protocol version {}, expected {}",
+                    agent, protocol, PROTOCOL_VERSION
+                ),
+            )),
+            Some(_) | None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the peer did not send a hello",
+            )),
+        }
+    }
+
+    /// Returns the agent name sent by the peer.
+    pub fn peer_agent(&self) -> &str {
+        &self.peer_agent
+    }
+
+    /// Returns the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    /// Sends a message.
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.stream.get_mut().write_all(&line)
+    }
+
+    /// Sends an event of the device with the given label, if any.
+    pub fn send_event(&mut self, device: Option<&str>, event: &Event) -> io::Result<()> {
+        self.send(&Message::Event(WireEvent::new(device, event)))
+    }
+
+    /// Receives the next message, or returns `None` once the peer
+    /// closed the connection.
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        self.line.clear();
+        if self.stream.read_line(&mut self.line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&self.line)?))
+    }
+
+    /// Receives the next event, skipping other messages, or returns
+    /// `None` once the peer closed the connection.
+    pub fn recv_event(&mut self) -> io::Result<Option<WireEvent>> {
+        loop {
+            match self.recv()? {
+                Some(Message::Event(event)) => return Ok(Some(event)),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, WireConnection, WireEvent, WireKeyState, WireKind, PROTOCOL_VERSION};
+    use crate::event::{Event, EventKind, Key, KeyState, WatchEvent};
+    use crate::Channels;
+    use std::io;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn decode(line: &str) -> Message {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn encodes_events() {
+        let event = Event {
+            sequence: Some(3),
+            ..Event::at(
+                UNIX_EPOCH + Duration::from_nanos(1_500),
+                EventKind::Key(Key::A, KeyState::Down),
+            )
+        };
+        let message = Message::Event(WireEvent::new(Some("left"), &event));
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"type":"event","device":"left","time_ns":1500,"sequence":3,"kind":"key","key":"A","state":"down"}"#
+        );
+
+        let watch = EventKind::Other(WatchEvent {
+            available_before: Channels::CORE,
+            available_after: Channels::CORE | Channels::NUNCHUK,
+        });
+        assert_eq!(
+            WireKind::from(&watch),
+            WireKind::Watch {
+                available_before: vec!["CORE".into()],
+                available_after: vec!["CORE".into(), "NUNCHUK".into()],
+            }
+        );
+    }
+
+    /// Messages of protocol version 1, which must keep decoding.
+    #[test]
+    fn decodes_version_1() {
+        assert_eq!(
+            decode(r#"{"type":"hello","protocol":1,"agent":"xwiimote/0.2.1"}"#),
+            Message::Hello {
+                protocol: 1,
+                agent: "xwiimote/0.2.1".into(),
+            }
+        );
+        assert_eq!(
+            decode(r#"{"type":"event","time_ns":7,"kind":"accelerometer","x":1,"y":-2,"z":100}"#),
+            Message::Event(WireEvent {
+                device: None,
+                time_ns: 7,
+                sequence: None,
+                kind: WireKind::Accelerometer {
+                    x: 1,
+                    y: -2,
+                    z: 100
+                },
+            })
+        );
+        assert_eq!(
+            decode(r#"{"type":"event","time_ns":7,"kind":"ir","sources":[[1,2],null,null,null]}"#),
+            Message::Event(WireEvent {
+                device: None,
+                time_ns: 7,
+                sequence: None,
+                kind: WireKind::Ir {
+                    sources: vec![Some([1, 2]), None, None, None],
+                },
+            })
+        );
+        assert_eq!(
+            decode(
+                r#"{"type":"event","time_ns":7,"kind":"nunchuk_key","key":"C","state":"auto_repeat"}"#
+            ),
+            Message::Event(WireEvent {
+                device: None,
+                time_ns: 7,
+                sequence: None,
+                kind: WireKind::NunchukKey {
+                    key: "C".into(),
+                    state: WireKeyState::AutoRepeat,
+                },
+            })
+        );
+        assert_eq!(
+            decode(r#"{"type":"event","time_ns":7,"kind":"disconnected"}"#),
+            Message::Event(WireEvent {
+                device: None,
+                time_ns: 7,
+                sequence: None,
+                kind: WireKind::Disconnected,
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_later_additions() {
+        assert_eq!(
+            decode(r#"{"type":"bye","reason":"later"}"#),
+            Message::Unknown
+        );
+        let Message::Event(event) = decode(
+            r#"{"type":"event","time_ns":7,"kind":"speaker","volume":3,"added_later":true}"#,
+        ) else {
+            panic!("not an event");
+        };
+        assert_eq!(event.kind, WireKind::Unknown);
+        let Message::Event(event) =
+            decode(r#"{"type":"event","time_ns":7,"kind":"dropped","count_estimate":2,"x":0}"#)
+        else {
+            panic!("not an event");
+        };
+        assert_eq!(event.kind, WireKind::Dropped { count_estimate: 2 });
+    }
+
+    #[test]
+    fn handshakes() -> io::Result<()> {
+        let (server, client) = UnixStream::pair()?;
+        let peer = thread::spawn(move || -> io::Result<_> {
+            let mut connection = WireConnection::handshake(client, "client")?;
+            let event = connection.recv_event()?;
+            Ok((connection.peer_agent().to_string(), event))
+        });
+        let mut connection = WireConnection::handshake(server, "server")?;
+        assert_eq!(connection.peer_agent(), "client");
+        connection.send(&Message::Unknown)?;
+        let event = Event::at(UNIX_EPOCH, EventKind::Disconnected);
+        connection.send_event(None, &event)?;
+        let (agent, received) = peer.join().unwrap()?;
+        assert_eq!(agent, "server");
+        assert_eq!(received.unwrap().kind, WireKind::Disconnected);
+
+        // A peer speaking another version is refused.
+        let (server, mut client) = UnixStream::pair()?;
+        serde_json::to_writer(
+            &mut client,
+            &Message::Hello {
+                protocol: PROTOCOL_VERSION + 1,
+                agent: "future".into(),
+            },
+        )?;
+        io::Write::write_all(&mut client, b"\n")?;
+        let err = WireConnection::handshake(server, "server").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+}
@@ -3,6 +3,12 @@
 #![allow(non_snake_case)]
 #![allow(improper_ctypes)]
 
+#[cfg(not(feature = "stub"))]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+#[cfg(feature = "stub")]
+mod stub;
+#[cfg(feature = "stub")]
+pub use stub::*;
+
 // todo: add tests
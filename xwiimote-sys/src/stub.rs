@@ -0,0 +1,274 @@
+// Stand-in for the generated bindings, used with the `stub` feature.
+//
+// The types and constants mirror `xwiimote.h`, so dependent crates
+// compile unchanged on any Unix platform. Every function fails as if
+// the library reported `ENOSYS`, which `std::io::Error` maps to
+// `std::io::ErrorKind::Unsupported`.
+
+#![allow(clippy::missing_safety_doc)]
+
+use libc::{c_char, c_int, c_uint, timeval};
+use std::ptr;
+
+pub type event_types = c_uint;
+pub const EVENT_KEY: event_types = 0;
+pub const EVENT_ACCEL: event_types = 1;
+pub const EVENT_IR: event_types = 2;
+pub const EVENT_BALANCE_BOARD: event_types = 3;
+pub const EVENT_MOTION_PLUS: event_types = 4;
+pub const EVENT_PRO_CONTROLLER_KEY: event_types = 5;
+pub const EVENT_PRO_CONTROLLER_MOVE: event_types = 6;
+pub const EVENT_WATCH: event_types = 7;
+pub const EVENT_CLASSIC_CONTROLLER_KEY: event_types = 8;
+pub const EVENT_CLASSIC_CONTROLLER_MOVE: event_types = 9;
+pub const EVENT_NUNCHUK_KEY: event_types = 10;
+pub const EVENT_NUNCHUK_MOVE: event_types = 11;
+pub const EVENT_DRUMS_KEY: event_types = 12;
+pub const EVENT_DRUMS_MOVE: event_types = 13;
+pub const EVENT_GUITAR_KEY: event_types = 14;
+pub const EVENT_GUITAR_MOVE: event_types = 15;
+pub const EVENT_GONE: event_types = 16;
+pub const EVENT_NUM: event_types = 17;
+
+pub type event_keys = c_uint;
+pub const KEY_LEFT: event_keys = 0;
+pub const KEY_RIGHT: event_keys = 1;
+pub const KEY_UP: event_keys = 2;
+pub const KEY_DOWN: event_keys = 3;
+pub const KEY_A: event_keys = 4;
+pub const KEY_B: event_keys = 5;
+pub const KEY_PLUS: event_keys = 6;
+pub const KEY_MINUS: event_keys = 7;
+pub const KEY_HOME: event_keys = 8;
+pub const KEY_ONE: event_keys = 9;
+pub const KEY_TWO: event_keys = 10;
+pub const KEY_X: event_keys = 11;
+pub const KEY_Y: event_keys = 12;
+pub const KEY_TL: event_keys = 13;
+pub const KEY_TR: event_keys = 14;
+pub const KEY_ZL: event_keys = 15;
+pub const KEY_ZR: event_keys = 16;
+pub const KEY_THUMBL: event_keys = 17;
+pub const KEY_THUMBR: event_keys = 18;
+pub const KEY_C: event_keys = 19;
+pub const KEY_Z: event_keys = 20;
+pub const KEY_STRUM_BAR_UP: event_keys = 21;
+pub const KEY_STRUM_BAR_DOWN: event_keys = 22;
+pub const KEY_FRET_FAR_UP: event_keys = 23;
+pub const KEY_FRET_UP: event_keys = 24;
+pub const KEY_FRET_MID: event_keys = 25;
+pub const KEY_FRET_LOW: event_keys = 26;
+pub const KEY_FRET_FAR_LOW: event_keys = 27;
+pub const KEY_NUM: event_keys = 28;
+
+pub type drums_abs = c_uint;
+pub const DRUMS_ABS_PAD: drums_abs = 0;
+pub const DRUMS_ABS_CYMBAL_LEFT: drums_abs = 1;
+pub const DRUMS_ABS_CYMBAL_RIGHT: drums_abs = 2;
+pub const DRUMS_ABS_TOM_LEFT: drums_abs = 3;
+pub const DRUMS_ABS_TOM_RIGHT: drums_abs = 4;
+pub const DRUMS_ABS_TOM_FAR_RIGHT: drums_abs = 5;
+pub const DRUMS_ABS_BASS: drums_abs = 6;
+pub const DRUMS_ABS_HI_HAT: drums_abs = 7;
+pub const DRUMS_ABS_NUM: drums_abs = 8;
+
+pub type iface_type = c_uint;
+pub const IFACE_CORE: iface_type = 0x1;
+pub const IFACE_ACCEL: iface_type = 0x2;
+pub const IFACE_IR: iface_type = 0x4;
+pub const IFACE_MOTION_PLUS: iface_type = 0x100;
+pub const IFACE_NUNCHUK: iface_type = 0x200;
+pub const IFACE_CLASSIC_CONTROLLER: iface_type = 0x400;
+pub const IFACE_BALANCE_BOARD: iface_type = 0x800;
+pub const IFACE_PRO_CONTROLLER: iface_type = 0x1000;
+pub const IFACE_DRUMS: iface_type = 0x2000;
+pub const IFACE_GUITAR: iface_type = 0x4000;
+pub const IFACE_ALL: iface_type = 0x7f07;
+pub const IFACE_WRITABLE: iface_type = 0x10000;
+
+pub type led = c_uint;
+pub const LED1: led = 1;
+pub const LED2: led = 2;
+pub const LED3: led = 3;
+pub const LED4: led = 4;
+
+pub const ABS_NUM: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct event_key {
+    pub code: c_uint,
+    pub state: c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct event_abs {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union event_union {
+    pub key: event_key,
+    pub abs: [event_abs; ABS_NUM as usize],
+    pub reserved: [u8; 128],
+}
+
+impl Default for event_union {
+    fn default() -> Self {
+        Self { reserved: [0; 128] }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct event {
+    pub time: timeval,
+    pub type_: c_uint,
+    pub v: event_union,
+}
+
+impl Default for event {
+    fn default() -> Self {
+        Self {
+            time: timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_: 0,
+            v: Default::default(),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct iface {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct monitor {
+    _private: [u8; 0],
+}
+
+/// Sets `errno` to `ENOSYS`, and returns the negative error code.
+fn unsupported() -> c_int {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let location = unsafe { libc::__errno_location() };
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let location = unsafe { libc::__error() };
+    unsafe { *location = libc::ENOSYS };
+    -libc::ENOSYS
+}
+
+pub unsafe fn get_iface_name(_iface: c_uint) -> *const c_char {
+    ptr::null()
+}
+
+pub unsafe fn iface_new(_dev: *mut *mut iface, _syspath: *const c_char) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_ref(_dev: *mut iface) {}
+
+pub unsafe fn iface_unref(_dev: *mut iface) {}
+
+pub unsafe fn iface_get_syspath(_dev: *mut iface) -> *const c_char {
+    unsupported();
+    ptr::null()
+}
+
+pub unsafe fn iface_get_fd(_dev: *mut iface) -> c_int {
+    unsupported();
+    -1
+}
+
+pub unsafe fn iface_watch(_dev: *mut iface, _watch: bool) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_open(_dev: *mut iface, _ifaces: c_uint) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_close(_dev: *mut iface, _ifaces: c_uint) {}
+
+pub unsafe fn iface_opened(_dev: *mut iface) -> c_uint {
+    0
+}
+
+pub unsafe fn iface_available(_dev: *mut iface) -> c_uint {
+    0
+}
+
+pub unsafe fn iface_poll(_dev: *mut iface, _ev: *mut event) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_dispatch(_dev: *mut iface, _ev: *mut event, _size: usize) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_rumble(_dev: *mut iface, _on: bool) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_get_led(_dev: *mut iface, _led: c_uint, _state: *mut bool) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_set_led(_dev: *mut iface, _led: c_uint, _state: bool) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_get_battery(_dev: *mut iface, _capacity: *mut u8) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_get_devtype(_dev: *mut iface, _devtype: *mut *mut c_char) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_get_extension(_dev: *mut iface, _extension: *mut *mut c_char) -> c_int {
+    unsupported()
+}
+
+pub unsafe fn iface_set_mp_normalization(
+    _dev: *mut iface,
+    _x: i32,
+    _y: i32,
+    _z: i32,
+    _factor: i32,
+) {
+}
+
+pub unsafe fn iface_get_mp_normalization(
+    _dev: *mut iface,
+    _x: *mut i32,
+    _y: *mut i32,
+    _z: *mut i32,
+    _factor: *mut i32,
+) {
+}
+
+pub unsafe fn monitor_new(_poll: bool, _direct: bool) -> *mut monitor {
+    unsupported();
+    ptr::null_mut()
+}
+
+pub unsafe fn monitor_ref(_mon: *mut monitor) {}
+
+pub unsafe fn monitor_unref(_mon: *mut monitor) {}
+
+pub unsafe fn monitor_get_fd(_monitor: *mut monitor, _blocking: bool) -> c_int {
+    unsupported();
+    -1
+}
+
+pub unsafe fn monitor_poll(_monitor: *mut monitor) -> *mut c_char {
+    unsupported();
+    ptr::null_mut()
+}